@@ -0,0 +1,13 @@
+//
+// Copyright 2023 Signal Messenger, LLC
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+#![no_main]
+
+use calling_backend::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    dependency_descriptor::ParsedDescriptor::read(data).ok();
+});
@@ -105,6 +105,10 @@ impl Default for LevelsTracker {
 }
 
 impl LevelsTracker {
+    const HIGH: Level = 70;
+    const LOW: Level = 40;
+    const CHUNK_SIZE: usize = 5;
+
     pub fn push(&mut self, mut sample: Level) {
         self.floor = self.floor.update(sample);
         let threshold = self.floor.get().unwrap_or(0) + 10;
@@ -146,12 +150,14 @@ impl LevelsTracker {
         .count()
     }
 
-    pub fn more_active_than_most_active(&self, most_active: &LevelsTracker) -> bool {
-        const HIGH: Level = 70;
-        const LOW: Level = 40;
-        const CHUNK_SIZE: usize = 5;
+    /// Whether any of the latest chunk of samples is loud enough to count as speech,
+    /// even if it isn't enough to become the most active.
+    pub fn is_speaking(&self) -> bool {
+        self.count_latest_chunk_above_threshold(Self::CHUNK_SIZE, Self::LOW) > 0
+    }
 
-        if self.latest().unwrap_or(0) <= LOW {
+    pub fn more_active_than_most_active(&self, most_active: &LevelsTracker) -> bool {
+        if self.latest().unwrap_or(0) <= Self::LOW {
             trace!(
                 "The contender isn't active enough (latest sample = {:?})",
                 self.latest()
@@ -159,21 +165,22 @@ impl LevelsTracker {
             return false;
         }
 
-        if most_active.latest().unwrap_or(0) >= LOW {
+        if most_active.latest().unwrap_or(0) >= Self::LOW {
             trace!("The most active is still active (latest sample)");
             return false;
         }
 
-        let self_first_chunk = self.count_latest_chunk_above_threshold(CHUNK_SIZE, HIGH);
+        let self_first_chunk =
+            self.count_latest_chunk_above_threshold(Self::CHUNK_SIZE, Self::HIGH);
 
-        if self_first_chunk < CHUNK_SIZE {
+        if self_first_chunk < Self::CHUNK_SIZE {
             trace!("The contender isn't active enough (latest chunk)");
             // We're not active enough.
             return false;
         }
 
         let most_active_first_chunk =
-            most_active.count_latest_chunk_above_threshold(CHUNK_SIZE, HIGH);
+            most_active.count_latest_chunk_above_threshold(Self::CHUNK_SIZE, Self::HIGH);
 
         if most_active_first_chunk > 0 {
             trace!("The most active is still active (latest chunk)");
@@ -187,8 +194,9 @@ impl LevelsTracker {
             return false;
         }
 
-        let self_high_chunks = self.count_chunks_above_threshold(CHUNK_SIZE, HIGH);
-        let most_active_high_chunks = most_active.count_chunks_above_threshold(CHUNK_SIZE, HIGH);
+        let self_high_chunks = self.count_chunks_above_threshold(Self::CHUNK_SIZE, Self::HIGH);
+        let most_active_high_chunks =
+            most_active.count_chunks_above_threshold(Self::CHUNK_SIZE, Self::HIGH);
 
         if self_high_chunks <= most_active_high_chunks {
             trace!("The most active is more active (number of active chunk)");
@@ -304,4 +312,26 @@ mod test {
         assert_eq!(2, contender.count_chunks_above_threshold(5, 70));
         assert!(contender.more_active_than_most_active(&most_active));
     }
+
+    #[test]
+    fn test_audio_speaking() {
+        let mut levels = LevelsTracker::default();
+        assert!(!levels.is_speaking());
+
+        // Establishes the noise floor
+        levels.push(20);
+        assert!(!levels.is_speaking());
+
+        levels.push(60);
+        assert!(levels.is_speaking());
+
+        // Still counts for the rest of the chunk
+        for _ in 0..4 {
+            levels.push(20);
+        }
+        assert!(levels.is_speaking());
+
+        levels.push(20);
+        assert!(!levels.is_speaking());
+    }
 }
@@ -5,7 +5,7 @@
 
 use std::{
     cmp::min,
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     convert::{From, TryFrom},
     fmt::{self, Display, Formatter},
     sync::Arc,
@@ -15,11 +15,13 @@ use std::{
 use calling_common::{DataRate, DataSize, Duration, Instant, PixelSize, VideoHeight};
 use hex::ToHex;
 use log::*;
+use parking_lot::Mutex;
 use prost::Message;
+use strum_macros::EnumString;
 use thiserror::Error;
 
 use crate::{
-    audio, protos,
+    audio, dependency_descriptor, mixer, protos,
     rtp::{self, VideoRotation},
     vp8,
 };
@@ -42,8 +44,37 @@ const SEND_RATE_REALLOCATION_INTERVAL: Duration = Duration::from_millis(1000);
 /// The lower the value, the more CPU we use but the more responsive
 /// active speaker switching becomes.
 const ACTIVE_SPEAKER_CALCULATION_INTERVAL: Duration = Duration::from_millis(300);
+/// How many of the latest active speakers we rank and tell clients about.
+const RECENT_SPEAKER_COUNT: usize = 5;
+/// The RTP clock rate of all video.
+const VIDEO_RTP_TIMESTAMP_HZ: u64 = 90000;
+/// What we assume about a video layer's framerate before we've measured it.
+const ASSUMED_VIDEO_FRAMERATE: u32 = 30;
+/// A video layer that was arriving but hasn't had a packet for this long is considered frozen,
+/// and anyone receiving it is switched to another layer right away rather than at the next
+/// reallocation.
+const VIDEO_LAYER_FREEZE_THRESHOLD: Duration = Duration::from_millis(1000);
 /// This is how often we send stats down to the client
 const STATS_MESSAGE_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a client may send at its ingress limit before it's policed.
+/// This leaves room for key frames, which are much bigger than other frames.
+const INGRESS_POLICING_BURST: Duration = Duration::from_secs(1);
+/// A client that keeps violating its ingress policy for this long is considered abusive.
+const INGRESS_ABUSE_DURATION: Duration = Duration::from_secs(5);
+/// Violations further apart than this are not considered part of the same abuse.
+const INGRESS_ABUSE_GAP: Duration = Duration::from_secs(1);
+/// What we recommend clients send on each of their video layers, lowest first,
+/// when we don't think they're constrained.
+const RECOMMENDED_VIDEO_LAYER_RATES_KBPS: [u64; 3] = [150, 500, 1500];
+const FULL_VIDEO_FRAMERATE: u32 = 30;
+/// When there isn't enough room for a layer at its full rate, we recommend sending it
+/// at this framerate instead, as long as at least half its full rate is available.
+const REDUCED_VIDEO_FRAMERATE: u32 = 15;
+/// How much to raise the incoming video bandwidth estimate per receiver report with little loss.
+const INCOMING_VIDEO_BANDWIDTH_ESTIMATE_GROWTH: f64 = 1.4;
+/// The most reliable RtpData seqnums one message from a client may NACK.
+/// Any more are ignored.
+const MAX_DATA_NACKS_PER_MESSAGE: usize = 256;
 
 /// A wrapper around Vec<u8> to identify a Call.
 /// It comes from signaling, but isn't known by the clients.
@@ -182,6 +213,15 @@ impl LayerId {
         })
     }
 
+    fn video_layer_index(self) -> Option<usize> {
+        match self {
+            LayerId::Video0 => Some(0),
+            LayerId::Video1 => Some(1),
+            LayerId::Video2 => Some(2),
+            LayerId::Audio | LayerId::RtpData => None,
+        }
+    }
+
     fn from_video_layer_index(video_layer_index: usize) -> Option<Self> {
         Some(match video_layer_index {
             0 => LayerId::Video0,
@@ -200,6 +240,18 @@ impl LayerId {
     pub fn to_rtx_ssrc(self, demux_id: DemuxId) -> rtp::Ssrc {
         rtp::to_rtx_ssrc(self.to_ssrc(demux_id))
     }
+
+    /// The most a well-behaved client sends on this layer, based on the encoder
+    /// settings the clients use plus some room for RTP overhead.
+    fn max_expected_incoming_rate(self) -> DataRate {
+        match self {
+            LayerId::Audio => DataRate::from_kbps(100),
+            LayerId::RtpData => DataRate::from_kbps(100),
+            LayerId::Video0 => DataRate::from_kbps(500),
+            LayerId::Video1 => DataRate::from_kbps(1500),
+            LayerId::Video2 => DataRate::from_kbps(3000),
+        }
+    }
 }
 
 #[derive(Error, Debug, Eq, PartialEq)]
@@ -210,6 +262,8 @@ pub enum Error {
     UnauthorizedRtpSsrc(DemuxId, DemuxId),
     #[error("received RTP packet with invalid VP8 header")]
     InvalidVp8Header,
+    #[error("received RTP packet with missing or invalid dependency descriptor")]
+    InvalidDependencyDescriptor,
     #[error("received RTP packet with invalid layer ID")]
     InvalidRtpLayerId,
     #[error("unknown demux ID: {0:?}")]
@@ -218,11 +272,41 @@ pub enum Error {
     InvalidDemuxId(u32),
     #[error("received RTP leave")]
     Leave,
+    #[error("client removed for abusing its ingress policy")]
+    IngressAbuse,
+}
+
+/// Where the metadata (layers and key frames) of the video a client sends comes from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, EnumString)]
+pub enum VideoMetadataSource {
+    /// The VP8 payload descriptor and payload header, so the payload must be in cleartext.
+    #[default]
+    #[strum(serialize = "vp8")]
+    Vp8Payload,
+    /// The dependency descriptor RTP header extension, so the payload is opaque
+    /// and can be encrypted end-to-end (for example, with SFrame).
+    #[strum(serialize = "dependency-descriptor")]
+    DependencyDescriptor,
+}
+
+/// How to police the media each client sends to the call.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IngressPolicy {
+    /// Media over this multiple of the max expected rate of its layer violates the policy.
+    /// If None, incoming rates aren't policed.
+    pub max_rate_multiplier: Option<f64>,
+    /// Drop media that violates the policy rather than only counting it.
+    pub drop_excess: bool,
+    /// Remove clients that keep violating the policy, either by sending too much
+    /// or by sending with SSRCs they don't own.
+    pub disconnect_abusive_clients: bool,
 }
 
 /// Represents an RTP packet that should be sent to a particular client
 /// of the call, identified by DemuxId.
 type RtpToSend = (DemuxId, rtp::Packet<Vec<u8>>);
+/// An audio mixer shared by a call and whatever calls mix() on it.
+pub type SharedAudioMixer = Arc<Mutex<mixer::AudioMixer>>;
 /// Represents a KeyFrameRequest that should be sent to a particular client
 /// of the call, identified by DemuxId.
 type KeyFrameRequestToSend = (DemuxId, rtp::KeyFrameRequest);
@@ -242,6 +326,12 @@ pub struct Call {
     active_speaker_message_interval: Duration,
     initial_target_send_rate: DataRate,
     default_requested_max_send_rate: DataRate,
+    ingress_policy: IngressPolicy,
+
+    /// Only present if audio mixing is enabled. Shared with whatever calls mix().
+    audio_mixer: Option<SharedAudioMixer>,
+    /// Only present if RtpData is forwarded reliably
+    reliable_data_history_size: Option<usize>,
 
     /// Clients (AKA devices) that have joined the call
     clients: Vec<Client>,
@@ -257,6 +347,19 @@ pub struct Call {
     active_speaker_calculated: Instant,
     /// The last time an active speaker update was sent to the clients
     active_speaker_update_sent: Instant,
+    /// The latest active speakers still in the call, most recent first.
+    /// This only changes when the active speaker does, so it has the same hysteresis.
+    recent_speakers: Vec<DemuxId>,
+    /// How long each client has been heard speaking, including clients that have left
+    speaking_time_by_demux_id: HashMap<DemuxId, Duration>,
+
+    /// Senders whose video every client should see, most important first.
+    /// Set by admins' clients or through the signaling API.
+    spotlight: Vec<DemuxId>,
+    /// The last time the spotlight changed
+    spotlight_changed: Instant,
+    /// The last time a spotlight update was sent to the clients
+    spotlight_update_sent: Instant,
 
     /// The last time a status update was sent to the clients
     stats_update_sent: Instant,
@@ -289,12 +392,16 @@ pub struct SendRateAllocationInfo {
 }
 
 impl Call {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         loggable_call_id: LoggableCallId,
         creator_id: UserId,
         active_speaker_message_interval: Duration,
         initial_target_send_rate: DataRate,
         default_requested_max_send_rate: DataRate,
+        ingress_policy: IngressPolicy,
+        audio_mixer: Option<SharedAudioMixer>,
+        reliable_data_history_size: Option<usize>,
         now: Instant,
         system_now: SystemTime,
     ) -> Self {
@@ -306,6 +413,10 @@ impl Call {
             active_speaker_message_interval,
             initial_target_send_rate,
             default_requested_max_send_rate,
+            ingress_policy,
+
+            audio_mixer,
+            reliable_data_history_size,
 
             clients: Vec::new(),
             client_added_or_removed: now,
@@ -314,6 +425,12 @@ impl Call {
             active_speaker_ids: None,
             active_speaker_calculated: now - ACTIVE_SPEAKER_CALCULATION_INTERVAL, // easier than using None :)
             active_speaker_update_sent: now,
+            recent_speakers: Vec::new(),
+            speaking_time_by_demux_id: HashMap::new(),
+
+            spotlight: Vec::new(),
+            spotlight_changed: now,
+            spotlight_update_sent: now,
 
             stats_update_sent: now, // easier than using None :)
 
@@ -351,31 +468,55 @@ impl Call {
         &self.call_time
     }
 
+    pub fn speaking_time_by_demux_id(&self) -> &HashMap<DemuxId, Duration> {
+        &self.speaking_time_by_demux_id
+    }
+
     pub fn has_client(&self, demux_id: DemuxId) -> bool {
         self.clients
             .iter()
             .any(|client| client.demux_id == demux_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn add_client(
         &mut self,
         demux_id: DemuxId,
-        user_id: UserId, // only used for stats
+        user_id: UserId, // used for stats
+        is_admin: bool,  // admins may change call-wide state such as the spotlight
         active_speaker_id: String,
         resolution_request_id: u64,
+        video_metadata_source: VideoMetadataSource,
+        receive_mixed_audio: bool,
         now: Instant,
     ) {
         time_scope_us!("calling.call.add_client");
 
+        // Clients that ask for mixed audio get every speaker's audio if mixing is disabled.
+        let receives_mixed_audio = match &self.audio_mixer {
+            Some(audio_mixer) if receive_mixed_audio => {
+                audio_mixer.lock().add_listener(demux_id);
+                true
+            }
+            _ => false,
+        };
+
         let previous_client_count = self.clients.len();
-        self.clients.push(Client::new(
+        let mut client = Client::new(
             demux_id,
             user_id,
+            is_admin,
             active_speaker_id,
             resolution_request_id,
+            video_metadata_source,
+            receives_mixed_audio,
             self.default_requested_max_send_rate,
             now,
-        ));
+        );
+        // Without a history there's nothing to catch up on, so reliable RtpData is forwarded
+        // right away. Otherwise, it waits for catch_up_on_reliable_data.
+        client.caught_up_on_data = self.reliable_data_history_size.unwrap_or(0) == 0;
+        self.clients.push(client);
         // An update message to clients about clients will be sent at the next tick().
         let increment = now.saturating_duration_since(self.client_added_or_removed);
         match previous_client_count {
@@ -390,6 +531,17 @@ impl Call {
         self.update_padding_ssrcs();
     }
 
+    fn remove_abusive_client(&mut self, demux_id: DemuxId, now: Instant) -> Error {
+        info!(
+            "call: {} removing client: {} (abusive ingress)",
+            self.loggable_call_id(),
+            demux_id.as_u32()
+        );
+        event!("calling.call.ingress_policing.disconnect");
+        self.remove_client(demux_id, now);
+        Error::IngressAbuse
+    }
+
     pub fn remove_client(&mut self, demux_id: DemuxId, now: Instant) {
         time_scope_us!("calling.call.remove_client");
 
@@ -412,6 +564,19 @@ impl Call {
             self.reallocate_target_send_rates(now);
             self.update_padding_ssrcs();
 
+            if let Some(audio_mixer) = &self.audio_mixer {
+                audio_mixer.lock().remove_client(demux_id);
+            }
+
+            self.recent_speakers
+                .retain(|recent_speaker| *recent_speaker != demux_id);
+
+            if self.spotlight.contains(&demux_id) {
+                self.spotlight
+                    .retain(|spotlighted| *spotlighted != demux_id);
+                self.spotlight_changed = now;
+            }
+
             for client in &mut self.clients {
                 client.audio_forwarder_by_sender_demux_id.remove(&demux_id);
                 client.video_forwarder_by_sender_demux_id.remove(&demux_id);
@@ -463,9 +628,31 @@ impl Call {
     pub fn handle_rtp(
         &mut self,
         sender_demux_id: DemuxId,
-        incoming_rtp: rtp::Packet<&mut [u8]>,
+        mut incoming_rtp: rtp::Packet<&mut [u8]>,
         now: Instant,
     ) -> Result<Vec<RtpToSend>, Error> {
+        let (rtp_to_send, audio_mixer) =
+            self.handle_rtp_without_mixing(sender_demux_id, incoming_rtp.borrow_mut(), now)?;
+        if let Some(audio_mixer) = audio_mixer {
+            audio_mixer.lock().receive_audio(
+                sender_demux_id,
+                incoming_rtp.audio_level,
+                incoming_rtp.payload(),
+                now,
+            );
+        }
+        Ok(rtp_to_send)
+    }
+
+    /// Like handle_rtp, but if the packet is audio to be mixed, returns the mixer instead of
+    /// passing the audio to it. Decoding is slow, so this lets the caller do it after releasing
+    /// the call's lock.
+    pub fn handle_rtp_without_mixing(
+        &mut self,
+        sender_demux_id: DemuxId,
+        incoming_rtp: rtp::Packet<&mut [u8]>,
+        now: Instant,
+    ) -> Result<(Vec<RtpToSend>, Option<SharedAudioMixer>), Error> {
         if incoming_rtp.ssrc() == CLIENT_SERVER_DATA_SSRC
             && incoming_rtp.payload_type() == CLIENT_SERVER_DATA_PAYLOAD_TYPE
         {
@@ -500,25 +687,35 @@ impl Call {
             // if it didn't change.
             if proto.video_request != sender.video_request_proto {
                 if let Some(video_request_proto) = proto.video_request {
-                    sender.requested_height_by_demux_id = video_request_proto
+                    let requests: Vec<(DemuxId, VideoHeight, u32)> = video_request_proto
                         .requests
                         .iter()
                         .filter_map(|request| {
                             let raw_height = request.height?;
                             let height = VideoHeight::from(raw_height as u16);
+                            let priority = request.priority.unwrap_or(0);
 
                             if let Some(raw_demux_id) = request.demux_id {
                                 let demux_id = DemuxId::try_from(raw_demux_id).ok()?;
-                                Some((demux_id, height))
+                                Some((demux_id, height, priority))
                             } else if let Some(resolution_request_id) = request.short_device_id {
                                 demux_id_by_resolution_request_id
                                     .get(&resolution_request_id)
-                                    .map(|demux_id| (*demux_id, height))
+                                    .map(|demux_id| (*demux_id, height, priority))
                             } else {
                                 None
                             }
                         })
                         .collect();
+                    sender.requested_height_by_demux_id = requests
+                        .iter()
+                        .map(|(demux_id, height, _priority)| (*demux_id, *height))
+                        .collect();
+                    sender.requested_priority_by_demux_id = requests
+                        .iter()
+                        .filter(|(_demux_id, _height, priority)| *priority > 0)
+                        .map(|(demux_id, _height, priority)| (*demux_id, *priority))
+                        .collect();
                     sender.requested_max_send_rate = video_request_proto
                         .max_kbps
                         .map(|kbps| DataRate::from_kbps(kbps as u64))
@@ -534,31 +731,91 @@ impl Call {
                     self.allocate_video_layers(sender_demux_id, target_send_rate, now);
                 }
             }
-            // There's nothing to forward
-            return Ok(vec![]);
+
+            if let Some(spotlight_proto) = proto.spotlight {
+                if self.is_admin(sender_demux_id) {
+                    let demux_ids: Vec<DemuxId> = spotlight_proto
+                        .demux_ids
+                        .into_iter()
+                        .filter_map(|raw_demux_id| DemuxId::try_from(raw_demux_id).ok())
+                        .collect();
+                    self.set_spotlight(&demux_ids, now);
+                } else {
+                    event!("calling.call.spotlight.unauthorized");
+                }
+            }
+            // The only thing to send back is RtpData that was NACKed.
+            return Ok((
+                self.resend_reliable_data(sender_demux_id, &proto.data_nacks),
+                None,
+            ));
         }
 
         // Make sure to do this before processing audio level, etc.
         // Otherwise someone could fake the SSRC to change active speaker and that sort of thing.
         let authorized_sender_demux_id = DemuxId::from_ssrc(incoming_rtp.ssrc());
-        if authorized_sender_demux_id != sender_demux_id {
-            return Err(Error::UnauthorizedRtpSsrc(
-                authorized_sender_demux_id,
-                sender_demux_id,
-            ));
-        }
+        let layer_id = LayerId::from_ssrc(incoming_rtp.ssrc());
+        let ingress_policy = self.ingress_policy;
+        let reliable_data_history_size = self.reliable_data_history_size;
 
         let sender = self
             .find_client_mut(sender_demux_id)
             .ok_or(Error::UnknownDemuxId(sender_demux_id))?;
 
+        let layer_id = match layer_id {
+            Some(layer_id) if authorized_sender_demux_id == sender_demux_id => layer_id,
+            _ => {
+                event!("calling.call.ingress_policing.unauthorized_ssrc");
+                if sender.ingress_policer.record_violation(now)
+                    && ingress_policy.disconnect_abusive_clients
+                {
+                    return Err(self.remove_abusive_client(sender_demux_id, now));
+                }
+                return Err(if authorized_sender_demux_id != sender_demux_id {
+                    Error::UnauthorizedRtpSsrc(authorized_sender_demux_id, sender_demux_id)
+                } else {
+                    Error::InvalidRtpLayerId
+                });
+            }
+        };
+
+        if let Some(max_rate_multiplier) = ingress_policy.max_rate_multiplier {
+            if !sender.ingress_policer.admit(
+                layer_id,
+                incoming_rtp.size(),
+                max_rate_multiplier,
+                now,
+            ) {
+                if ingress_policy.drop_excess {
+                    event!("calling.call.ingress_policing.dropped");
+                } else {
+                    event!("calling.call.ingress_policing.flagged");
+                }
+                if sender.ingress_policer.record_violation(now)
+                    && ingress_policy.disconnect_abusive_clients
+                {
+                    return Err(self.remove_abusive_client(sender_demux_id, now));
+                }
+                if ingress_policy.drop_excess {
+                    return Ok((vec![], None));
+                }
+            }
+        }
+
         let incoming_rtp = incoming_rtp.borrow();
-        let incoming_vp8 = if incoming_rtp.payload_type() == rtp::VP8_PAYLOAD_TYPE {
+        let incoming_video_header = if incoming_rtp.payload_type() == rtp::VP8_PAYLOAD_TYPE {
             time_scope_us!("calling.call.handle_rtp.vp8_header");
-            let incoming_vp8 = sender
-                .parse_vp8_header_and_update_incoming_video_rate_and_resolution(&incoming_rtp, now)
-                .ok_or(Error::InvalidVp8Header)?;
-            Some(incoming_vp8)
+            let invalid_header_error = match sender.video_metadata_source {
+                VideoMetadataSource::Vp8Payload => Error::InvalidVp8Header,
+                VideoMetadataSource::DependencyDescriptor => Error::InvalidDependencyDescriptor,
+            };
+            let incoming_video_header = sender
+                .parse_video_header_and_update_incoming_video_rate_and_resolution(
+                    &incoming_rtp,
+                    now,
+                )
+                .ok_or(invalid_header_error)?;
+            Some(incoming_video_header)
         } else {
             None
         };
@@ -567,10 +824,29 @@ impl Call {
         if let Some(audio_level) = incoming_rtp.audio_level {
             time_scope_us!("calling.call.handle_rtp.audio_level");
             sender.incoming_audio_levels.push(audio_level);
+            sender.incoming_audio_last_received = Some(now);
             // Active speaker is recalculated in tick()
         }
 
-        let layer_id = LayerId::from_ssrc(incoming_rtp.ssrc()).ok_or(Error::InvalidRtpLayerId)?;
+        // Every receiver gets the same reliable RtpData, and the history keeps a copy.
+        let reliable_data_rtp = match (layer_id, reliable_data_history_size) {
+            (LayerId::RtpData, Some(history_size)) => {
+                match sender.data_history.push(&incoming_rtp, history_size) {
+                    Some(reliable_data_rtp) => Some(reliable_data_rtp),
+                    None => {
+                        event!("calling.call.reliable_data.duplicate");
+                        return Ok((vec![], None));
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let audio_mixer = if layer_id == LayerId::Audio {
+            self.audio_mixer.clone()
+        } else {
+            None
+        };
 
         time_scope_us!("calling.call.handle_rtp.forwarding");
 
@@ -582,21 +858,114 @@ impl Call {
             if let Some(rtp_to_forward) = match layer_id {
                 LayerId::Audio => {
                     let is_silence = incoming_rtp.audio_level == Some(0);
-                    if is_silence {
+                    // Receivers of mixed audio get it from the mixer instead.
+                    if is_silence || receiver.receives_mixed_audio {
                         None
                     } else {
                         receiver.forward_audio_rtp(&incoming_rtp)
                     }
                 }
-                LayerId::RtpData => receiver.forward_data_rtp(&incoming_rtp),
+                LayerId::RtpData => match &reliable_data_rtp {
+                    // Until a receiver catches up, it gets this with the rest of the history
+                    // (see catch_up_on_reliable_data).
+                    Some(reliable_data_rtp) if receiver.caught_up_on_data => {
+                        Some(reliable_data_rtp.clone())
+                    }
+                    Some(_) => None,
+                    None => receiver.forward_data_rtp(&incoming_rtp),
+                },
                 LayerId::Video0 | LayerId::Video1 | LayerId::Video2 => {
-                    receiver.forward_video_rtp(&incoming_rtp, incoming_vp8.as_ref())
+                    receiver.forward_video_rtp(&incoming_rtp, incoming_video_header.as_ref())
                 }
             } {
                 rtp_to_send.push((receiver.demux_id, rtp_to_forward));
             }
         }
-        Ok(rtp_to_send)
+        Ok((rtp_to_send, audio_mixer))
+    }
+
+    /// Resends the reliable RtpData the receiver NACKed that's still in the senders' histories.
+    /// Seqnums that are repeated, out of range, or beyond MAX_DATA_NACKS_PER_MESSAGE are ignored.
+    fn resend_reliable_data(
+        &self,
+        receiver_demux_id: DemuxId,
+        data_nacks: &[protos::device_to_sfu::DataNackMessage],
+    ) -> Vec<RtpToSend> {
+        if self.reliable_data_history_size.is_none() || data_nacks.is_empty() {
+            return vec![];
+        }
+
+        let mut rtp_to_send = vec![];
+        let mut nacked = HashSet::new();
+        let mut unavailable_count = 0;
+        let mut ignored_count = 0;
+        for data_nack in data_nacks {
+            let sender_demux_id = data_nack
+                .demux_id
+                .and_then(|raw_demux_id| DemuxId::try_from(raw_demux_id).ok())
+                .filter(|sender_demux_id| *sender_demux_id != receiver_demux_id);
+            let sender =
+                sender_demux_id.and_then(|sender_demux_id| self.find_client(sender_demux_id));
+            for seqnum in &data_nack.seqnums {
+                let seqnum = match rtp::TruncatedSequenceNumber::try_from(*seqnum) {
+                    Ok(seqnum)
+                        if nacked.len() < MAX_DATA_NACKS_PER_MESSAGE
+                            && nacked.insert((sender_demux_id, seqnum)) =>
+                    {
+                        seqnum
+                    }
+                    _ => {
+                        ignored_count += 1;
+                        continue;
+                    }
+                };
+                match sender.and_then(|sender| sender.data_history.get(seqnum)) {
+                    // Resent as RTX so it's never encrypted twice with the same SSRC and seqnum.
+                    // The RTX seqnum is assigned when it's sent.
+                    Some(rtp) => rtp_to_send.push((receiver_demux_id, rtp.to_rtx(0))),
+                    None => unavailable_count += 1,
+                }
+            }
+        }
+        if !rtp_to_send.is_empty() {
+            event!("calling.call.reliable_data.resent", rtp_to_send.len());
+        }
+        if unavailable_count > 0 {
+            event!("calling.call.reliable_data.unavailable", unavailable_count);
+        }
+        if ignored_count > 0 {
+            event!("calling.call.reliable_data.nack_ignored", ignored_count);
+        }
+        rtp_to_send
+    }
+
+    /// Once a client's connection is established, sends it the history of every other client's
+    /// reliable RtpData, oldest first, and starts forwarding it reliable RtpData as it arrives.
+    /// This only does anything the first time it's called for a client.
+    pub fn catch_up_on_reliable_data(&mut self, receiver_demux_id: DemuxId) -> Vec<RtpToSend> {
+        if self.reliable_data_history_size.is_none() {
+            return vec![];
+        }
+        match self.find_client_mut(receiver_demux_id) {
+            Some(receiver) if !receiver.caught_up_on_data => {
+                receiver.caught_up_on_data = true;
+            }
+            _ => {
+                return vec![];
+            }
+        }
+
+        let rtp_to_send: Vec<RtpToSend> = self
+            .clients
+            .iter()
+            .filter(|sender| sender.demux_id != receiver_demux_id)
+            .flat_map(|sender| sender.data_history.packets())
+            .map(|rtp| (receiver_demux_id, rtp.clone()))
+            .collect();
+        if !rtp_to_send.is_empty() {
+            event!("calling.call.reliable_data.caught_up", rtp_to_send.len());
+        }
+        rtp_to_send
     }
 
     /// Update state that only needs to be updated regularly, such as
@@ -606,16 +975,60 @@ impl Call {
     pub fn tick(&mut self, now: Instant) -> (Vec<RtpToSend>, Vec<KeyFrameRequestToSend>) {
         time_scope_us!("calling.call.tick");
 
+        let mut frozen_ssrcs: Vec<rtp::Ssrc> = vec![];
         for sender in &mut self.clients {
-            sender.incoming_video0.rate_tracker.update(now);
-            sender.incoming_video1.rate_tracker.update(now);
-            sender.incoming_video2.rate_tracker.update(now);
+            let sender_demux_id = sender.demux_id;
+            let stalled = [
+                sender.incoming_video0.is_stalled(now),
+                sender.incoming_video1.is_stalled(now),
+                sender.incoming_video2.is_stalled(now),
+            ];
+            let incoming_videos = [
+                (LayerId::Video0, &mut sender.incoming_video0),
+                (LayerId::Video1, &mut sender.incoming_video1),
+                (LayerId::Video2, &mut sender.incoming_video2),
+            ];
+            // A sender that stops all of its video (such as by muting) hasn't frozen, and its
+            // rates wind down as usual. This is decided before any stalled layer forgets its rate.
+            let still_sending_video =
+                incoming_videos
+                    .iter()
+                    .zip(stalled)
+                    .any(|((_, incoming_video), stalled)| {
+                        !stalled && incoming_video.rate().unwrap_or_default() > DataRate::ZERO
+                    });
+            for ((layer_id, incoming_video), stalled) in incoming_videos.into_iter().zip(stalled) {
+                if stalled && still_sending_video {
+                    incoming_video.freeze();
+                    frozen_ssrcs.push(layer_id.to_ssrc(sender_demux_id));
+                } else if stalled {
+                    incoming_video.last_received = None;
+                }
+                incoming_video.rate_tracker.update(now);
+                incoming_video.framerate_tracker.update(now);
+            }
         }
+        self.fall_back_from_frozen_video_layers(&frozen_ssrcs, now);
 
         let mut new_active_speaker: Option<DemuxId> = None;
         if now > self.active_speaker_calculated + ACTIVE_SPEAKER_CALCULATION_INTERVAL {
             time_scope_us!("calling.call.tick.calculate_active_speaker");
 
+            let since_calculated = now.saturating_duration_since(self.active_speaker_calculated);
+            for client in &self.clients {
+                // The levels stay where they were when audio stops, so only count clients that
+                // were still sending audio since the last calculation.
+                let received_audio_since_calculated = client
+                    .incoming_audio_last_received
+                    .map_or(false, |received| received > self.active_speaker_calculated);
+                if received_audio_since_calculated && client.incoming_audio_levels.is_speaking() {
+                    *self
+                        .speaking_time_by_demux_id
+                        .entry(client.demux_id)
+                        .or_default() += since_calculated;
+                }
+            }
+
             self.active_speaker_calculated = now;
             new_active_speaker = self.calculate_active_speaker(now);
             if new_active_speaker.is_some() {
@@ -692,6 +1105,47 @@ impl Call {
         Ok(())
     }
 
+    /// Adjust how much video we think the given client can send us according to
+    /// how much of what it sent was lost, as of the latest receiver report.
+    pub fn set_incoming_fraction_lost(
+        &mut self,
+        sender_demux_id: DemuxId,
+        fraction_lost: u8,
+    ) -> Result<(), Error> {
+        let sender = self
+            .find_client_mut(sender_demux_id)
+            .ok_or(Error::UnknownDemuxId(sender_demux_id))?;
+        sender.update_incoming_video_bandwidth_estimate(fraction_lost);
+        Ok(())
+    }
+
+    pub fn spotlight(&self) -> &[DemuxId] {
+        &self.spotlight
+    }
+
+    /// Replaces the senders whose video is given to every client before anything else.
+    /// Unknown and repeated DemuxIds are ignored.
+    pub fn set_spotlight(&mut self, demux_ids: &[DemuxId], now: Instant) {
+        let mut spotlight: Vec<DemuxId> = Vec::with_capacity(demux_ids.len());
+        for demux_id in demux_ids {
+            if self.has_client(*demux_id) && !spotlight.contains(demux_id) {
+                spotlight.push(*demux_id);
+            }
+        }
+        if spotlight != self.spotlight {
+            info!(
+                "call: {} spotlight changed to {:?}",
+                self.loggable_call_id(),
+                spotlight
+            );
+            self.spotlight = spotlight;
+            self.spotlight_changed = now;
+            // An update message to clients about the spotlight will be sent at the next tick().
+            // Everyone has to make room for the spotlight right away.
+            self.reallocate_target_send_rates(now);
+        }
+    }
+
     pub fn set_outgoing_queue_drain_rate(
         &mut self,
         receiver_demux_id: DemuxId,
@@ -704,6 +1158,18 @@ impl Call {
         Ok(())
     }
 
+    pub fn set_reception_summary(
+        &mut self,
+        receiver_demux_id: DemuxId,
+        reception_summary: rtp::ReceptionSummary,
+    ) -> Result<(), Error> {
+        let receiver = self
+            .find_client_mut(receiver_demux_id)
+            .ok_or(Error::UnknownDemuxId(receiver_demux_id))?;
+        receiver.reception_summary = Some(reception_summary);
+        Ok(())
+    }
+
     pub fn get_send_rate_allocation_info(
         &self,
     ) -> impl Iterator<Item = SendRateAllocationInfo> + '_ {
@@ -716,6 +1182,49 @@ impl Call {
         })
     }
 
+    /// Reallocates for the receivers forwarded (or about to be forwarded) any of the given
+    /// layers, which have stopped arriving while others from the same sender haven't.
+    /// That switches them to another layer, and the key frame request for it goes out with
+    /// the rest in tick().
+    fn fall_back_from_frozen_video_layers(&mut self, frozen_ssrcs: &[rtp::Ssrc], now: Instant) {
+        for frozen_ssrc in frozen_ssrcs {
+            let sender_demux_id = DemuxId::from_ssrc(*frozen_ssrc);
+            let affected_receivers: Vec<(DemuxId, DataRate)> = self
+                .clients
+                .iter()
+                .filter(|receiver| {
+                    receiver
+                        .video_forwarder_by_sender_demux_id
+                        .get(&sender_demux_id)
+                        .map_or(false, |forwarder| {
+                            forwarder.forwarding_ssrc() == Some(*frozen_ssrc)
+                                || forwarder.switching_ssrc() == Some(*frozen_ssrc)
+                        })
+                })
+                .map(|receiver| (receiver.demux_id, receiver.target_send_rate))
+                .collect();
+            if affected_receivers.is_empty() {
+                continue;
+            }
+
+            debug!(
+                "call: {} video layer {} from {:?} froze for {} receivers",
+                self.loggable_call_id,
+                frozen_ssrc,
+                sender_demux_id,
+                affected_receivers.len()
+            );
+            event!("calling.call.video_layer_frozen");
+            if let Some(sender) = self.find_client_mut(sender_demux_id) {
+                sender.video_freezes += 1;
+            }
+
+            for (receiver_demux_id, target_send_rate) in affected_receivers {
+                self.allocate_video_layers(receiver_demux_id, target_send_rate, now);
+            }
+        }
+    }
+
     fn reallocate_target_send_rates_if_its_been_too_long(&mut self, now: Instant) {
         let receivers: Vec<(DemuxId, DataRate)> = self
             .clients
@@ -760,7 +1269,7 @@ impl Call {
         let active_speaker_demux_id = self
             .active_speaker_ids
             .as_ref()
-            .map(|(demux_id, _)| demux_id);
+            .map(|(demux_id, _)| *demux_id);
 
         // We have to collect these because we can't get a mutable ref to the receiver while getting
         // immutable refs to the senders.
@@ -773,29 +1282,22 @@ impl Call {
                     return None;
                 }
 
-                let mut requested_height = receiver
-                    .requested_height_by_demux_id
-                    .get(&sender.demux_id)
-                    .copied()
-                    .unwrap_or_else(|| VideoHeight::from(1));
-
-                // Override the requested height for the active speaker to support early requests
-                // from the SFU for higher video layers before the client's UI updates.
-                if Some(&sender.demux_id) == active_speaker_demux_id
-                    && receiver.active_speaker_height > requested_height.as_u16()
-                {
-                    requested_height = VideoHeight::from(receiver.active_speaker_height);
-                }
-
+                let spotlighted = self.spotlight.contains(&sender.demux_id);
                 Some(AllocatableVideo {
                     sender_demux_id: sender.demux_id,
-                    layers: [
-                        sender.incoming_video0.as_allocatable_layer(),
-                        sender.incoming_video1.as_allocatable_layer(),
-                        sender.incoming_video2.as_allocatable_layer(),
-                    ],
-                    requested_height,
+                    layers: sender.allocatable_video_layers(),
+                    requested_height: receiver.requested_height(
+                        sender.demux_id,
+                        active_speaker_demux_id,
+                        spotlighted,
+                    ),
                     interesting: sender.became_active_speaker,
+                    priority: receiver
+                        .requested_priority_by_demux_id
+                        .get(&sender.demux_id)
+                        .copied()
+                        .unwrap_or(0),
+                    spotlighted,
                 })
             })
             .collect();
@@ -842,7 +1344,7 @@ impl Call {
                 .entry(sender_demux_id)
                 .or_insert_with(|| {
                     let outgoing_ssrc = LayerId::Video0.to_ssrc(sender_demux_id);
-                    Vp8SimulcastRtpForwarder::new(outgoing_ssrc)
+                    SimulcastRtpForwarder::new(outgoing_ssrc)
                 });
             forwarder.set_desired_ssrc(desired_incoming_ssrc);
         }
@@ -880,6 +1382,12 @@ impl Call {
         self.send_key_frame_requests_if_its_been_too_long(now)
     }
 
+    /// Only admins' clients may change call-wide state such as the spotlight.
+    fn is_admin(&self, demux_id: DemuxId) -> bool {
+        self.find_client(demux_id)
+            .map_or(false, |client| client.is_admin)
+    }
+
     fn find_client(&self, demux_id: DemuxId) -> Option<&Client> {
         self.clients
             .iter()
@@ -892,6 +1400,49 @@ impl Call {
             .find(|client| client.demux_id == demux_id)
     }
 
+    /// Recommends which of the sender's video layers are worth sending and how much to
+    /// spend on each, based on what the other clients are asking for and being sent,
+    /// and how much we think the sender can send us.
+    fn recommend_video_layers(&self, sender: &Client) -> [Option<RecommendedVideoLayer>; 3] {
+        let active_speaker_demux_id = self
+            .active_speaker_ids
+            .as_ref()
+            .map(|(demux_id, _)| *demux_id);
+        let layers = sender.allocatable_video_layers();
+        let spotlighted = self.spotlight.contains(&sender.demux_id);
+
+        let top_layer_index = self
+            .clients
+            .iter()
+            .filter(|receiver| receiver.demux_id != sender.demux_id)
+            .filter_map(|receiver| {
+                if receiver.target_send_rate < receiver.ideal_send_rate {
+                    // The receiver can't get everything it asked for, so only count what it's being sent.
+                    let forwarder = receiver
+                        .video_forwarder_by_sender_demux_id
+                        .get(&sender.demux_id)?;
+                    let ssrc = forwarder
+                        .switching_ssrc()
+                        .or_else(|| forwarder.forwarding_ssrc())?;
+                    LayerId::from_ssrc(ssrc)?.video_layer_index()
+                } else {
+                    requested_video_layer_index(
+                        &layers,
+                        receiver.requested_height(
+                            sender.demux_id,
+                            active_speaker_demux_id,
+                            spotlighted,
+                        ),
+                    )
+                }
+            })
+            .max()
+            // Keep the base layer going so video can start quickly for anyone who asks.
+            .unwrap_or(0);
+
+        recommend_video_layers(top_layer_index, sender.incoming_video_bandwidth_estimate)
+    }
+
     fn send_update_proto_to_all_clients(
         &mut self,
         active_speaker_just_changed: bool,
@@ -915,20 +1466,58 @@ impl Call {
                 update.speaker = Some(protos::sfu_to_device::Speaker {
                     long_device_id: Some(active_speaker_id.clone()),
                     demux_id: Some(demux_id.as_u32()),
+                    recent_demux_ids: self
+                        .recent_speakers
+                        .iter()
+                        .map(|demux_id| demux_id.as_u32())
+                        .collect(),
                 });
             }
             self.active_speaker_update_sent = now;
         }
 
+        // New clients need to know about the spotlight too, but only if there is one.
+        if self.spotlight_changed > self.spotlight_update_sent
+            || (update.device_joined_or_left.is_some() && !self.spotlight.is_empty())
+        {
+            update.spotlight = Some(protos::sfu_to_device::Spotlight {
+                demux_ids: self
+                    .spotlight
+                    .iter()
+                    .map(|demux_id| demux_id.as_u32())
+                    .collect(),
+            });
+            self.spotlight_update_sent = now;
+        }
+
         let send_stats = now >= self.stats_update_sent + STATS_MESSAGE_INTERVAL;
-        if update.device_joined_or_left.is_some() || update.speaker.is_some() || send_stats {
+        if update.device_joined_or_left.is_some()
+            || update.speaker.is_some()
+            || update.spotlight.is_some()
+            || send_stats
+        {
             let raw_demux_ids: Vec<u32> = self
                 .clients
                 .iter()
                 .map(|client| client.demux_id.as_u32())
                 .collect();
 
-            for client in &mut self.clients {
+            // Recommendations are only sent along with stats because they're O(n^2) to calculate.
+            let send_recommendations: Vec<_> = self
+                .clients
+                .iter()
+                .map(|sender| {
+                    if send_stats {
+                        Some(send_recommendation_proto(
+                            &self.recommend_video_layers(sender),
+                        ))
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+
+            for (client, send_recommendation) in self.clients.iter_mut().zip(send_recommendations) {
                 let (demux_ids_with_video, allocated_heights) = client
                     .video_forwarder_by_sender_demux_id
                     .iter()
@@ -964,6 +1553,7 @@ impl Call {
                         allocated_send_rate_kbps: Some(client.allocated_send_rate.as_kbps() as u32),
                     });
                 }
+                update.send_recommendation = send_recommendation;
 
                 let mut update_rtp_payload: Vec<u8> = Vec::with_capacity(update.encoded_len());
                 update
@@ -1016,6 +1606,10 @@ impl Call {
                 .unwrap()
                 .became_active_speaker = Some(now);
             self.active_speaker_ids = Some(most_active_ids);
+            self.recent_speakers
+                .retain(|demux_id| *demux_id != most_active_demux_id);
+            self.recent_speakers.insert(0, most_active_demux_id);
+            self.recent_speakers.truncate(RECENT_SPEAKER_COUNT);
             Some(most_active_demux_id)
         } else {
             None
@@ -1088,7 +1682,19 @@ impl Call {
     pub fn get_stats(&self) -> CallStats {
         CallStats {
             loggable_call_id: self.loggable_call_id.clone(),
-            clients: self.clients.iter().map(Client::get_stats).collect(),
+            clients: self
+                .clients
+                .iter()
+                .map(|client| {
+                    let mut stats = client.get_stats();
+                    stats.speaking_time = self
+                        .speaking_time_by_demux_id
+                        .get(&client.demux_id)
+                        .copied()
+                        .unwrap_or_default();
+                    stats
+                })
+                .collect(),
         }
     }
 }
@@ -1098,8 +1704,11 @@ struct Client {
     // Immutable
     demux_id: DemuxId,
     user_id: UserId, // only used for stats
+    is_admin: bool,
     active_speaker_id: String,
     resolution_request_id: u64,
+    video_metadata_source: VideoMetadataSource,
+    receives_mixed_audio: bool,
 
     // Updated by incoming video packets
     incoming_video0: IncomingVideoState,
@@ -1109,11 +1718,17 @@ struct Client {
 
     // Updated by incoming audio packets
     incoming_audio_levels: audio::LevelsTracker,
+    incoming_audio_last_received: Option<Instant>,
     became_active_speaker: Option<Instant>,
 
+    // Updated by all incoming packets
+    ingress_policer: IngressPolicer,
+
     // Updated by incoming video requests
     video_request_proto: Option<protos::device_to_sfu::VideoRequestMessage>,
     requested_height_by_demux_id: HashMap<DemuxId, VideoHeight>,
+    // Missing means 0.
+    requested_priority_by_demux_id: HashMap<DemuxId, u32>,
     active_speaker_height: u16,
 
     // Updated by Call::set_target_send_rate
@@ -1122,6 +1737,13 @@ struct Client {
     outgoing_queue_drain_rate: DataRate,
     requested_max_send_rate: DataRate,
     send_rate_allocated: Instant,
+    // Updated by Call::set_reception_summary
+    reception_summary: Option<rtp::ReceptionSummary>,
+    // Updated by Call::set_incoming_fraction_lost
+    // None means we don't think the client is constrained.
+    incoming_video_bandwidth_estimate: Option<DataRate>,
+    // Updated by Call::tick when one of our video layers freezes while being forwarded.
+    video_freezes: usize,
 
     // Updated by send rate allocation, which is affected by
     // incoming video requests, target send rate,
@@ -1141,8 +1763,11 @@ struct Client {
     // (where n is the number of clients in the group call).
     // So we need to be careful what we store here.
     audio_forwarder_by_sender_demux_id: HashMap<DemuxId, SingleSsrcRtpForwarder>,
-    video_forwarder_by_sender_demux_id: HashMap<DemuxId, Vp8SimulcastRtpForwarder>,
+    video_forwarder_by_sender_demux_id: HashMap<DemuxId, SimulcastRtpForwarder>,
     data_forwarder_by_sender_demux_id: HashMap<DemuxId, SingleSsrcRtpForwarder>,
+    // Only used if RtpData is forwarded reliably
+    data_history: ReliableDataHistory,
+    caught_up_on_data: bool,
     allocated_height_by_sender_demux_id: HashMap<DemuxId, VideoHeight>,
 
     // Update with each proto send from server to client
@@ -1150,19 +1775,26 @@ struct Client {
 }
 
 impl Client {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         demux_id: DemuxId,
         user_id: UserId,
+        is_admin: bool,
         active_speaker_id: String,
         resolution_request_id: u64,
+        video_metadata_source: VideoMetadataSource,
+        receives_mixed_audio: bool,
         requested_max_send_rate: DataRate,
         now: Instant,
     ) -> Self {
         Self {
             demux_id,
             user_id,
+            is_admin,
             active_speaker_id,
             resolution_request_id,
+            video_metadata_source,
+            receives_mixed_audio,
 
             incoming_video0: IncomingVideoState::default(),
             incoming_video1: IncomingVideoState::default(),
@@ -1170,16 +1802,23 @@ impl Client {
             video_rotation: VideoRotation::None,
 
             incoming_audio_levels: audio::LevelsTracker::default(),
+            incoming_audio_last_received: None,
             became_active_speaker: None,
 
+            ingress_policer: IngressPolicer::default(),
+
             video_request_proto: None,
             requested_height_by_demux_id: HashMap::new(),
+            requested_priority_by_demux_id: HashMap::new(),
             active_speaker_height: 0,
 
             target_send_rate: DataRate::default(),
             outgoing_queue_drain_rate: DataRate::default(),
             requested_max_send_rate,
             send_rate_allocated: now,
+            reception_summary: None,
+            incoming_video_bandwidth_estimate: None,
+            video_freezes: 0,
 
             requested_base_rate: DataRate::default(),
             ideal_send_rate: DataRate::default(),
@@ -1190,18 +1829,30 @@ impl Client {
             audio_forwarder_by_sender_demux_id: HashMap::new(),
             video_forwarder_by_sender_demux_id: HashMap::new(),
             data_forwarder_by_sender_demux_id: HashMap::new(),
+            data_history: ReliableDataHistory::default(),
+            caught_up_on_data: false,
             allocated_height_by_sender_demux_id: HashMap::new(),
 
             next_server_to_client_data_rtp_seqnum: 1,
         }
     }
 
-    fn parse_vp8_header_and_update_incoming_video_rate_and_resolution(
+    fn parse_video_header_and_update_incoming_video_rate_and_resolution(
         &mut self,
         incoming_rtp: &rtp::Packet<&[u8]>,
         now: Instant,
-    ) -> Option<vp8::ParsedHeader> {
-        let incoming_vp8 = vp8::ParsedHeader::read(incoming_rtp.payload()).ok()?;
+    ) -> Option<IncomingVideoHeader> {
+        let incoming_video_header = match self.video_metadata_source {
+            VideoMetadataSource::Vp8Payload => {
+                IncomingVideoHeader::Vp8(vp8::ParsedHeader::read(incoming_rtp.payload()).ok()?)
+            }
+            VideoMetadataSource::DependencyDescriptor => IncomingVideoHeader::DependencyDescriptor(
+                dependency_descriptor::ParsedDescriptor::read(
+                    incoming_rtp.dependency_descriptor()?,
+                )
+                .ok()?,
+            ),
+        };
         let incoming_layer_id = LayerId::from_ssrc(incoming_rtp.ssrc());
         let incoming_video = match incoming_layer_id {
             Some(LayerId::Video0) => &mut self.incoming_video0,
@@ -1212,10 +1863,16 @@ impl Client {
             }
         };
 
+        incoming_video.last_received = Some(now);
         incoming_video.rate_tracker.push(incoming_rtp.size(), now);
+        incoming_video.framerate_tracker.push(
+            incoming_video_header.frame_id(),
+            incoming_rtp.timestamp,
+            now,
+        );
 
         let old_resolution = incoming_video.original_resolution;
-        if let Some(resolution) = incoming_vp8.resolution {
+        if let Some(resolution) = incoming_video_header.resolution() {
             incoming_video.original_resolution = Some(resolution);
         }
         let new_resolution = incoming_video.original_resolution;
@@ -1247,7 +1904,7 @@ impl Client {
                 _ => unreachable!("checked above"),
             }
         }
-        Some(incoming_vp8)
+        Some(incoming_video_header)
     }
 
     fn forward_audio_rtp(
@@ -1270,27 +1927,46 @@ impl Client {
     fn forward_video_rtp(
         &mut self,
         incoming_rtp: &rtp::Packet<&[u8]>,
-        incoming_vp8: Option<&vp8::ParsedHeader>,
+        incoming_video_header: Option<&IncomingVideoHeader>,
     ) -> Option<rtp::Packet<Vec<u8>>> {
-        let incoming_vp8 = incoming_vp8?;
+        let incoming_video_header = incoming_video_header?;
 
         let sender_demux_id = DemuxId::from_ssrc(incoming_rtp.ssrc());
         let forwarder = self
             .video_forwarder_by_sender_demux_id
             .get_mut(&sender_demux_id)?;
 
-        let (outgoing_ssrc, outgoing) = forwarder.forward_vp8_rtp(incoming_rtp, incoming_vp8)?;
-        let mut outgoing_rtp = incoming_rtp.rewrite(
-            outgoing_ssrc,
-            outgoing.seqnum,
-            outgoing.timestamp as rtp::TruncatedTimestamp,
-        );
-        vp8::modify_header(
-            outgoing_rtp.payload_mut(),
-            outgoing.picture_id as vp8::TruncatedPictureId,
-            outgoing.tl0_pic_idx as vp8::TruncatedTl0PicIdx,
-        );
-        Some(outgoing_rtp)
+        match incoming_video_header {
+            IncomingVideoHeader::Vp8(incoming_vp8) => {
+                let (outgoing_ssrc, outgoing) =
+                    forwarder.forward_vp8_rtp(incoming_rtp, incoming_vp8)?;
+                let mut outgoing_rtp = incoming_rtp.rewrite(
+                    outgoing_ssrc,
+                    outgoing.seqnum,
+                    outgoing.timestamp as rtp::TruncatedTimestamp,
+                );
+                vp8::modify_header(
+                    outgoing_rtp.payload_mut(),
+                    outgoing.picture_id as vp8::TruncatedPictureId,
+                    outgoing.tl0_pic_idx as vp8::TruncatedTl0PicIdx,
+                );
+                Some(outgoing_rtp)
+            }
+            IncomingVideoHeader::DependencyDescriptor(incoming_descriptor) => {
+                let (outgoing_ssrc, outgoing) = forwarder
+                    .forward_dependency_descriptor_rtp(incoming_rtp, incoming_descriptor)?;
+                let mut outgoing_rtp = incoming_rtp.rewrite(
+                    outgoing_ssrc,
+                    outgoing.seqnum,
+                    outgoing.timestamp as rtp::TruncatedTimestamp,
+                );
+                dependency_descriptor::modify_frame_number(
+                    outgoing_rtp.dependency_descriptor_mut()?,
+                    outgoing.picture_id as dependency_descriptor::TruncatedFrameNumber,
+                );
+                Some(outgoing_rtp)
+            }
+        }
     }
 
     fn forward_data_rtp(
@@ -1309,6 +1985,72 @@ impl Client {
         Some(outgoing_rtp)
     }
 
+    fn allocatable_video_layers(&self) -> [AllocatableVideoLayer; 3] {
+        [
+            self.incoming_video0.as_allocatable_layer(),
+            self.incoming_video1.as_allocatable_layer(),
+            self.incoming_video2.as_allocatable_layer(),
+        ]
+    }
+
+    /// The height this client wants of the sender's video.
+    fn requested_height(
+        &self,
+        sender_demux_id: DemuxId,
+        active_speaker_demux_id: Option<DemuxId>,
+        spotlighted: bool,
+    ) -> VideoHeight {
+        if spotlighted {
+            // Everyone gets the biggest layer the sender has, whatever they asked for.
+            return VideoHeight::from(u16::MAX);
+        }
+
+        let requested_height = self
+            .requested_height_by_demux_id
+            .get(&sender_demux_id)
+            .copied()
+            .unwrap_or_else(|| VideoHeight::from(1));
+
+        // Override the requested height for the active speaker to support early requests
+        // from the SFU for higher video layers before the client's UI updates.
+        if Some(sender_demux_id) == active_speaker_demux_id
+            && self.active_speaker_height > requested_height.as_u16()
+        {
+            VideoHeight::from(self.active_speaker_height)
+        } else {
+            requested_height
+        }
+    }
+
+    /// Estimates the client's uplink bandwidth from the loss in the receiver reports we send it,
+    /// dropping the estimate once it's more than all the layers need.
+    fn update_incoming_video_bandwidth_estimate(&mut self, fraction_lost: u8) {
+        let incoming_video_rate: DataRate = [
+            &self.incoming_video0,
+            &self.incoming_video1,
+            &self.incoming_video2,
+        ]
+        .iter()
+        .filter_map(|incoming_video| incoming_video.rate())
+        .sum();
+        if incoming_video_rate == DataRate::ZERO {
+            // There's no video to estimate with.
+            return;
+        }
+
+        let loss = fraction_lost as f64 / 256.0;
+        if loss > 0.1 {
+            self.incoming_video_bandwidth_estimate = Some(incoming_video_rate * (1.0 - 0.5 * loss));
+        } else if loss < 0.02 {
+            let unconstrained_rate =
+                DataRate::from_kbps(RECOMMENDED_VIDEO_LAYER_RATES_KBPS.iter().sum());
+            self.incoming_video_bandwidth_estimate = self
+                .incoming_video_bandwidth_estimate
+                .map(|estimate| estimate * INCOMING_VIDEO_BANDWIDTH_ESTIMATE_GROWTH)
+                .filter(|estimate| *estimate < unconstrained_rate);
+        }
+    }
+
     fn get_stats(&self) -> ClientStats {
         ClientStats {
             demux_id: self.demux_id,
@@ -1325,6 +2067,33 @@ impl Client {
             allocated_send_rate: self.allocated_send_rate,
             outgoing_queue_drain_rate: self.outgoing_queue_drain_rate,
             max_requested_height: self.requested_height_by_demux_id.values().max().copied(),
+            reception_summary: self.reception_summary,
+            speaking_time: Duration::ZERO, // Filled in by Call::get_stats
+            video_freezes: self.video_freezes,
+        }
+    }
+}
+
+/// The video metadata of an incoming packet, from wherever its sender puts it.
+#[derive(Debug)]
+enum IncomingVideoHeader {
+    Vp8(vp8::ParsedHeader),
+    DependencyDescriptor(dependency_descriptor::ParsedDescriptor),
+}
+
+impl IncomingVideoHeader {
+    fn resolution(&self) -> Option<PixelSize> {
+        match self {
+            Self::Vp8(vp8) => vp8.resolution,
+            Self::DependencyDescriptor(descriptor) => descriptor.resolution,
+        }
+    }
+
+    /// Changes with each frame, if the sender provides it.
+    fn frame_id(&self) -> Option<u16> {
+        match self {
+            Self::Vp8(vp8) => vp8.picture_id,
+            Self::DependencyDescriptor(descriptor) => Some(descriptor.frame_number),
         }
     }
 }
@@ -1332,10 +2101,13 @@ impl Client {
 #[derive(Default)]
 struct IncomingVideoState {
     rate_tracker: IncomingDataRateTracker,
+    framerate_tracker: IncomingFramerateTracker,
     /// The resolution of the video, ignoring rotation.
     original_resolution: Option<PixelSize>,
     /// The height of the video, taking rotation into account.
     height: Option<VideoHeight>,
+    /// None until packets arrive, and again once they stall.
+    last_received: Option<Instant>,
 }
 
 impl IncomingVideoState {
@@ -1343,6 +2115,10 @@ impl IncomingVideoState {
         self.rate_tracker.rate()
     }
 
+    pub fn framerate(&self) -> Option<u32> {
+        self.framerate_tracker.framerate()
+    }
+
     fn apply_rotation(&mut self, rotation: VideoRotation) {
         if let Some(resolution) = self.original_resolution {
             let height = match rotation {
@@ -1360,10 +2136,29 @@ impl IncomingVideoState {
         self.height = None;
     }
 
+    /// True if frames were arriving but stopped. It's up to the caller to clear last_received
+    /// so that each stall is only reported once. A layer too sparse to have a framerate
+    /// (such as a paused screen share) doesn't stall.
+    fn is_stalled(&self, now: Instant) -> bool {
+        self.last_received.map_or(false, |last_received| {
+            now.saturating_duration_since(last_received) >= VIDEO_LAYER_FREEZE_THRESHOLD
+                && self.framerate().unwrap_or(0) > 0
+        })
+    }
+
+    /// Forgets the rate and framerate of a stalled layer so nothing is allocated from it
+    /// until it resumes.
+    fn freeze(&mut self) {
+        self.last_received = None;
+        self.rate_tracker = IncomingDataRateTracker::default();
+        self.framerate_tracker = IncomingFramerateTracker::default();
+    }
+
     fn as_allocatable_layer(&self) -> AllocatableVideoLayer {
         AllocatableVideoLayer {
             incoming_rate: self.rate().unwrap_or_default(),
             incoming_height: self.height.unwrap_or_default(),
+            incoming_framerate: self.framerate(),
         }
     }
 }
@@ -1418,11 +2213,155 @@ impl IncomingDataRateTracker {
     }
 }
 
-// This is spatial layers, not temporal layers
-#[derive(Clone, Debug)]
-struct AllocatableVideoLayer {
+/// Measures the framerate of a video layer from the RTP timestamps of its frames,
+/// which reflect when the sender captured them rather than how they were delayed on the way.
+#[derive(Default)]
+pub struct IncomingFramerateTracker {
+    // When each frame started arriving and its RTP timestamp.
+    // Oldest is at the back. The newest is at the front.
+    history: VecDeque<(Instant, rtp::TruncatedTimestamp)>,
+    latest_frame_id: Option<u16>,
+    framerate: Option<u32>,
+}
+
+impl IncomingFramerateTracker {
+    const MAX_DURATION: Duration = Duration::from_millis(5000);
+    const MIN_DURATION: Duration = Duration::from_millis(500);
+
+    fn framerate(&self) -> Option<u32> {
+        self.framerate
+    }
+
+    /// Counts a new frame when the frame ID changes, or when the timestamp changes
+    /// if there is no frame ID.
+    /// Old values don't get pushed off unless update() is called periodically.
+    fn push(&mut self, frame_id: Option<u16>, timestamp: rtp::TruncatedTimestamp, time: Instant) {
+        let is_new_frame = match (frame_id, self.latest_frame_id, self.history.front()) {
+            (Some(frame_id), Some(latest_frame_id), _) => frame_id != latest_frame_id,
+            (None, _, Some((_, latest_timestamp))) => timestamp != *latest_timestamp,
+            _ => true,
+        };
+        if is_new_frame {
+            self.history.push_front((time, timestamp));
+            self.latest_frame_id = frame_id;
+        }
+    }
+
+    fn update(&mut self, now: Instant) {
+        let deadline = now - Self::MAX_DURATION;
+        while matches!(self.history.back(), Some((oldest, _)) if *oldest < deadline) {
+            self.history.pop_back();
+        }
+
+        let (oldest, newest) = match (self.history.back(), self.history.front()) {
+            (Some(oldest), Some(newest)) => (oldest, newest),
+            _ => {
+                // Wait for more info
+                self.framerate = None;
+                return;
+            }
+        };
+        let duration = now.saturating_duration_since(oldest.0);
+        if duration < Self::MIN_DURATION {
+            // Wait for more info
+            self.framerate = None;
+            return;
+        }
+
+        // If frames stop coming, the time since the oldest frame arrived keeps growing,
+        // so a stalled layer's framerate drops even though its timestamps look fine.
+        let timestamp_span = newest.1.wrapping_sub(oldest.1) as u64;
+        let arrival_span = duration.as_millis() as u64 * VIDEO_RTP_TIMESTAMP_HZ / 1000;
+        let frame_intervals = (self.history.len() - 1) as u64;
+        self.framerate = Some(
+            (frame_intervals * VIDEO_RTP_TIMESTAMP_HZ / std::cmp::max(timestamp_span, arrival_span))
+                as u32,
+        );
+    }
+}
+
+struct TokenBucket {
+    tokens: DataSize,
+    refilled: Instant,
+}
+
+/// Polices how much a client sends on each layer with a token bucket per layer,
+/// and keeps track of how long the client has been violating its ingress policy.
+#[derive(Default)]
+struct IngressPolicer {
+    // Indexed by IngressPolicer::bucket_index()
+    buckets: [Option<TokenBucket>; 5],
+    // When the current stretch of violations started and the last violation in it
+    violations: Option<(Instant, Instant)>,
+}
+
+impl IngressPolicer {
+    fn bucket_index(layer_id: LayerId) -> usize {
+        match layer_id {
+            LayerId::Audio => 0,
+            LayerId::Video0 => 1,
+            LayerId::Video1 => 2,
+            LayerId::Video2 => 3,
+            LayerId::RtpData => 4,
+        }
+    }
+
+    /// Returns false if the packet would exceed the limit of its layer.
+    /// Packets that aren't admitted don't count against the limit.
+    fn admit(
+        &mut self,
+        layer_id: LayerId,
+        size: DataSize,
+        max_rate_multiplier: f64,
+        now: Instant,
+    ) -> bool {
+        let max_rate = layer_id.max_expected_incoming_rate() * max_rate_multiplier;
+        let capacity = max_rate * INGRESS_POLICING_BURST;
+        let bucket = self.buckets[Self::bucket_index(layer_id)].get_or_insert(TokenBucket {
+            tokens: capacity,
+            refilled: now,
+        });
+        let refill = max_rate * now.saturating_duration_since(bucket.refilled);
+        bucket.tokens = min(bucket.tokens + refill, capacity);
+        bucket.refilled = now;
+        if size > bucket.tokens {
+            return false;
+        }
+        bucket.tokens -= size;
+        true
+    }
+
+    /// Returns true if the client has been violating its policy long enough to be abusive.
+    fn record_violation(&mut self, now: Instant) -> bool {
+        let started = match self.violations {
+            Some((started, last)) if now.saturating_duration_since(last) <= INGRESS_ABUSE_GAP => {
+                started
+            }
+            _ => now,
+        };
+        self.violations = Some((started, now));
+        now.saturating_duration_since(started) >= INGRESS_ABUSE_DURATION
+    }
+}
+
+// This is spatial layers, not temporal layers
+#[derive(Clone, Debug)]
+struct AllocatableVideoLayer {
     incoming_rate: DataRate,
     incoming_height: VideoHeight,
+    // None until we've measured it
+    incoming_framerate: Option<u32>,
+}
+
+impl AllocatableVideoLayer {
+    /// Roughly how good the layer looks, in pixels per second.
+    /// We only know the height, but all the layers have the same aspect ratio,
+    /// so height squared is proportional to pixels.
+    fn quality(&self) -> u64 {
+        let height = self.incoming_height.as_u16() as u64;
+        let framerate = self.incoming_framerate.unwrap_or(ASSUMED_VIDEO_FRAMERATE) as u64;
+        height * height * framerate
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1434,6 +2373,10 @@ struct AllocatableVideo {
     requested_height: VideoHeight,
     // AKA became active speaker
     interesting: Option<Instant>,
+    // Chosen by the receiver. Higher priority videos get their higher layers first.
+    priority: u32,
+    // Gets all of its layers before any other video gets any
+    spotlighted: bool,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -1455,7 +2398,7 @@ fn ideal_video_layer_index(video: &AllocatableVideo) -> Option<usize> {
         layer.incoming_height >= requested_height && has_rate(layer)
     };
 
-    if requested_height == VideoHeight::from(0) {
+    let ideal_layer_index = if requested_height == VideoHeight::from(0) {
         // Nothing was requested, so nothing is ideal.
         None
     } else if let Some(first_layer_which_has_enough) =
@@ -1474,7 +2417,17 @@ fn ideal_video_layer_index(video: &AllocatableVideo) -> Option<usize> {
         // None of the layers have enough height and rate, so just take the
         // highest layer that has any height and rate.
         video.layers.iter().rposition(has_height_and_rate)
-    }
+    };
+
+    // A higher layer isn't better if it has stalled or dropped frames, so
+    // take a lower layer when it looks better (more pixels per second).
+    // Ties go to the higher layer.
+    ideal_layer_index.map(|ideal_layer_index| {
+        (0..=ideal_layer_index)
+            .filter(|index| has_height_and_rate(&video.layers[*index]))
+            .max_by_key(|index| (video.layers[*index].quality(), *index))
+            .unwrap_or(ideal_layer_index)
+    })
 }
 
 fn ideal_send_rate(videos: &[AllocatableVideo], max_requested_send_rate: DataRate) -> DataRate {
@@ -1509,6 +2462,98 @@ fn requested_base_rate(videos: &[AllocatableVideo], max_requested_send_rate: Dat
     min(allocatable, max_requested_send_rate)
 }
 
+/// The layer the receiver wants, by the height it requested, even if we aren't receiving
+/// that layer (so that the sender will turn it on).
+fn requested_video_layer_index(
+    layers: &[AllocatableVideoLayer; 3],
+    requested_height: VideoHeight,
+) -> Option<usize> {
+    if requested_height == VideoHeight::from(0) {
+        return None;
+    }
+    let has_rate = |layer: &AllocatableVideoLayer| layer.incoming_rate.as_bps() > 0;
+    if let Some(first_layer_which_has_enough) = layers
+        .iter()
+        .position(|layer| has_rate(layer) && layer.incoming_height >= requested_height)
+    {
+        Some(first_layer_which_has_enough)
+    } else if let Some(highest_layer_with_rate) = layers.iter().rposition(has_rate) {
+        // None of the layers we're receiving are big enough, so try the next one up.
+        Some(min(highest_layer_with_rate + 1, layers.len() - 1))
+    } else {
+        // We aren't receiving any video, so we don't know how big the layers are.
+        // Start with the base layer and go up from there.
+        Some(0)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct RecommendedVideoLayer {
+    target_rate: DataRate,
+    max_framerate: u32,
+}
+
+/// Enables the layers up to and including top_layer_index, lowest first,
+/// until the incoming bandwidth estimate (if any) runs out.
+fn recommend_video_layers(
+    top_layer_index: usize,
+    incoming_bandwidth_estimate: Option<DataRate>,
+) -> [Option<RecommendedVideoLayer>; 3] {
+    let mut recommended = [None, None, None];
+    let mut remaining_rate = incoming_bandwidth_estimate;
+    for (layer_index, full_rate_kbps) in RECOMMENDED_VIDEO_LAYER_RATES_KBPS
+        .iter()
+        .enumerate()
+        .take(top_layer_index + 1)
+    {
+        let full_rate = DataRate::from_kbps(*full_rate_kbps);
+        match remaining_rate {
+            Some(rate) if rate < full_rate => {
+                // Send fewer frames rather than turning the layer off if there's enough room
+                // to make it worthwhile. We always keep the base layer.
+                let half_rate = full_rate * 0.5;
+                if layer_index == 0 || rate >= half_rate {
+                    recommended[layer_index] = Some(RecommendedVideoLayer {
+                        target_rate: std::cmp::max(rate, half_rate),
+                        max_framerate: REDUCED_VIDEO_FRAMERATE,
+                    });
+                }
+                break;
+            }
+            _ => {
+                recommended[layer_index] = Some(RecommendedVideoLayer {
+                    target_rate: full_rate,
+                    max_framerate: FULL_VIDEO_FRAMERATE,
+                });
+                remaining_rate = remaining_rate.map(|rate| rate - full_rate);
+            }
+        }
+    }
+    recommended
+}
+
+fn send_recommendation_proto(
+    layers: &[Option<RecommendedVideoLayer>; 3],
+) -> protos::sfu_to_device::SendRecommendation {
+    protos::sfu_to_device::SendRecommendation {
+        layers: layers
+            .iter()
+            .map(|layer| match layer {
+                Some(layer) => protos::sfu_to_device::send_recommendation::Layer {
+                    enabled: Some(true),
+                    target_kbps: Some(layer.target_rate.as_kbps() as u32),
+                    max_framerate: Some(layer.max_framerate),
+                },
+                None => protos::sfu_to_device::send_recommendation::Layer {
+                    enabled: Some(false),
+                    target_kbps: None,
+                    max_framerate: None,
+                },
+            })
+            .collect(),
+    }
+}
+
 fn allocate_send_rate(
     target_send_rate: DataRate,
     ideal_send_rate: DataRate,
@@ -1525,71 +2570,107 @@ fn allocate_send_rate(
     let mut allocated_by_sender_demux_id: HashMap<DemuxId, AllocatedVideo> = HashMap::new();
     let mut allocated_rate = DataRate::ZERO;
 
-    // Biggest first and then (for the same size), most recently interesting first
-    videos.sort_by_key(|video| std::cmp::Reverse((video.requested_height, video.interesting)));
+    // Highest priority first, then biggest first and then (for the same size), most recently
+    // interesting first
+    videos.sort_by_key(|video| {
+        std::cmp::Reverse((video.priority, video.requested_height, video.interesting))
+    });
+
+    let mut allocate_layer = |video: &AllocatableVideo, layer_index: usize| {
+        let layer = &video.layers[layer_index];
+        trace!(
+            "Allocating {:?}.{} = ({}, {:?})",
+            video.sender_demux_id,
+            layer_index,
+            layer.incoming_rate.as_kbps(),
+            layer.incoming_height
+        );
+        if layer.incoming_height == VideoHeight::from(0) && layer.incoming_rate.as_bps() == 0 {
+            trace!("Skipped layer with nothing coming in.");
+            return;
+        }
 
-    // We try to get the lowest layers for each one before trying to get the higher layer for any one.
-    // In the future we may want to allow clients to prioritize a video to a degree
-    // that it gets all of its layers first.
-    for layer_index in 0..=2 {
-        trace!("Allocating layer {}", layer_index);
-        for video in &videos {
-            let layer = &video.layers[layer_index];
+        let ideal_layer_index = ideal_video_layer_index(video);
+        if ideal_layer_index.is_none() || ideal_layer_index.unwrap() < layer_index {
             trace!(
-                "Allocating {:?}.{} = ({}, {:?})",
-                video.sender_demux_id,
-                layer_index,
-                layer.incoming_rate.as_kbps(),
-                layer.incoming_height
+                "Skipped layer that's not requested (ideal layer index: {:?}).",
+                ideal_layer_index
             );
-            if layer.incoming_height == VideoHeight::from(0) && layer.incoming_rate.as_bps() == 0 {
-                trace!("Skipped layer with nothing coming in.");
-                continue;
-            }
+            return;
+        }
 
-            let ideal_layer_index = ideal_video_layer_index(video);
-            if ideal_layer_index.is_none() || ideal_layer_index.unwrap() < layer_index {
-                trace!(
-                    "Skipped layer that's not requested (ideal layer index: {:?}).",
-                    ideal_layer_index
-                );
-                continue;
-            }
+        let layer_rate = layer.incoming_rate;
+        let lower_layer_rate = allocated_by_sender_demux_id
+            .get(&video.sender_demux_id)
+            .map(|allocated| allocated.rate)
+            .unwrap_or_default();
+        let rate_increase = layer_rate.saturating_sub(lower_layer_rate);
+        let increased_allocated_rate = allocated_rate + rate_increase;
+        if increased_allocated_rate > allocatable_rate {
+            trace!(
+                "Skipped layer that's too big ({}/{} allocated and {}={}-{} increase)",
+                allocated_rate.as_kbps(),
+                allocatable_rate.as_kbps(),
+                rate_increase.as_kbps(),
+                layer_rate.as_kbps(),
+                lower_layer_rate.as_kbps()
+            );
+            return;
+        }
 
-            let layer_rate = layer.incoming_rate;
-            let lower_layer_rate = allocated_by_sender_demux_id
-                .get(&video.sender_demux_id)
-                .map(|allocated| allocated.rate)
-                .unwrap_or_default();
-            let rate_increase = layer_rate.saturating_sub(lower_layer_rate);
-            let increased_allocated_rate = allocated_rate + rate_increase;
-            if increased_allocated_rate > allocatable_rate {
-                trace!(
-                    "Skipped layer that's too big ({}/{} allocated and {}={}-{} increase)",
-                    allocated_rate.as_kbps(),
-                    allocatable_rate.as_kbps(),
-                    rate_increase.as_kbps(),
-                    layer_rate.as_kbps(),
-                    lower_layer_rate.as_kbps()
-                );
-                continue;
-            }
+        allocated_by_sender_demux_id.insert(
+            video.sender_demux_id,
+            AllocatedVideo {
+                sender_demux_id: video.sender_demux_id,
+                layer_index,
+                rate: layer.incoming_rate,
+                height: layer.incoming_height,
+            },
+        );
+        allocated_rate = increased_allocated_rate;
+        trace!(
+            "Allocated layer.  New allocated_rate: {:?}",
+            allocated_rate.as_kbps()
+        );
+    };
 
-            allocated_by_sender_demux_id.insert(
-                video.sender_demux_id,
-                AllocatedVideo {
-                    sender_demux_id: video.sender_demux_id,
-                    layer_index,
-                    rate: layer.incoming_rate,
-                    height: layer.incoming_height,
-                },
-            );
-            allocated_rate = increased_allocated_rate;
+    // Spotlighted videos get all of their layers before any other video gets any.
+    let (spotlighted_videos, other_videos): (Vec<_>, Vec<_>) =
+        videos.iter().partition(|video| video.spotlighted);
+    for video in spotlighted_videos {
+        trace!("Allocating spotlighted {:?}", video.sender_demux_id);
+        for layer_index in 0..=2 {
+            allocate_layer(video, layer_index);
+        }
+    }
+
+    // For everyone else, we try to get the lowest layers for each one before trying to get
+    // the higher layer for any one.
+    trace!("Allocating layer 0");
+    for &video in &other_videos {
+        allocate_layer(video, 0);
+    }
+
+    // But the higher layers go to the higher priority videos first.
+    // The videos are sorted by priority, so each priority is a contiguous run.
+    let mut start = 0;
+    while start < other_videos.len() {
+        let priority = other_videos[start].priority;
+        let end = other_videos[start..]
+            .iter()
+            .position(|video| video.priority != priority)
+            .map_or(other_videos.len(), |len| start + len);
+        for layer_index in 1..=2 {
             trace!(
-                "Allocated layer.  New allocated_rate: {:?}",
-                allocated_rate.as_kbps()
+                "Allocating layer {} with priority {}",
+                layer_index,
+                priority
             );
+            for &video in &other_videos[start..end] {
+                allocate_layer(video, layer_index);
+            }
         }
+        start = end;
     }
 
     allocated_by_sender_demux_id
@@ -1646,27 +2727,80 @@ impl SingleSsrcRtpForwarder {
     }
 }
 
+/// When RtpData is forwarded reliably, every receiver gets the sender's packets with the
+/// sender's seqnums, so any gap a receiver sees is one it can NACK.
+/// The latest are kept to resend and to catch up receivers that join later.
+#[derive(Default)]
+struct ReliableDataHistory {
+    // Used to expand the seqnums receivers NACK.
+    max_seqnum: rtp::FullSequenceNumber,
+    seqnum_reuse_detector: rtp::SequenceNumberReuseDetector,
+    // Oldest first.
+    packets: BTreeMap<rtp::FullSequenceNumber, rtp::Packet<Vec<u8>>>,
+}
+
+impl ReliableDataHistory {
+    /// Returns the packet to forward, or None if it has already been forwarded
+    /// (or is too old to know).
+    fn push(
+        &mut self,
+        incoming_rtp: &rtp::Packet<&[u8]>,
+        max_len: usize,
+    ) -> Option<rtp::Packet<Vec<u8>>> {
+        let seqnum = incoming_rtp.seqnum();
+        match self.seqnum_reuse_detector.remember_used(seqnum) {
+            rtp::SequenceNumberReuse::NotUsedBefore => {}
+            rtp::SequenceNumberReuse::UsedBefore
+            | rtp::SequenceNumberReuse::TooOldToKnow { .. } => {
+                return None;
+            }
+        }
+        self.max_seqnum = std::cmp::max(self.max_seqnum, seqnum);
+
+        let outgoing_rtp =
+            incoming_rtp.rewrite(incoming_rtp.ssrc(), seqnum, incoming_rtp.timestamp);
+        if max_len > 0 {
+            self.packets.insert(seqnum, outgoing_rtp.clone());
+            while self.packets.len() > max_len {
+                self.packets.pop_first();
+            }
+        }
+        Some(outgoing_rtp)
+    }
+
+    fn get(&self, seqnum: rtp::TruncatedSequenceNumber) -> Option<&rtp::Packet<Vec<u8>>> {
+        let mut max_seqnum = self.max_seqnum;
+        let seqnum = rtp::expand_seqnum(seqnum, &mut max_seqnum);
+        self.packets.get(&seqnum)
+    }
+
+    fn packets(&self) -> impl Iterator<Item = &rtp::Packet<Vec<u8>>> {
+        self.packets.values()
+    }
+}
+
 // State to allow forwarding a set of N video SSRCs as 1 video SSRC by
 // changing the seqnums and VP8 picture IDs and VP8 TL0 Picture Indexes
-// to make it appear that it's one stream rather than N.
-struct Vp8SimulcastRtpForwarder {
+// (or dependency descriptor frame numbers) to make it appear that it's
+// one stream rather than N.
+struct SimulcastRtpForwarder {
     // The outgoing SSRC.  It never changes.
     outgoing_ssrc: rtp::Ssrc,
-    forwarding: Vp8SimulcastRtpForwardingState,
-    switching: Vp8SimulcastRtpSwitchingState,
+    forwarding: SimulcastRtpForwardingState,
+    switching: SimulcastRtpSwitchingState,
     // We have to keep track of the max outgoing IDs
     // to know what to make the "first" when we switch.
     // (generally, the max + 1).  And we have to retain
     // that outside of the forwarding state below so we
     // retain it across various pause/forward cycles.
-    max_outgoing: Vp8RewrittenIds,
+    max_outgoing: RewrittenIds,
 }
-enum Vp8SimulcastRtpSwitchingState {
+enum SimulcastRtpSwitchingState {
     DoNotSwitch,
     SwitchAtNextKeyFrame(rtp::Ssrc),
 }
 
-enum Vp8SimulcastRtpForwardingState {
+enum SimulcastRtpForwardingState {
     Paused,
     Forwarding {
         incoming_ssrc: rtp::Ssrc,
@@ -1677,29 +2811,32 @@ enum Vp8SimulcastRtpForwardingState {
         // maintain the relative relationship that they did in the
         // unmodified stream of packets.
         // "first" here means "first since latest switch".
-        first_incoming: Vp8RewrittenIds,
-        first_outgoing: Vp8RewrittenIds,
+        first_incoming: RewrittenIds,
+        first_outgoing: RewrittenIds,
 
         // We have to keep track of the max incoming IDs
         // to be able to expand the IDs from truncated to full.
         // otherwise, rollover would mess up the "max outgoing"
         // below.
-        max_incoming: Vp8RewrittenIds,
+        max_incoming: RewrittenIds,
     },
 }
 
-// These are the IDs that we rewrite when forwarding VP8.
+// These are the IDs that we rewrite when forwarding video.
 // These is a convenience for keep track of all 4 together,
-// which is a common thing in Vp8SimulcastRtpForwarder.
+// which is a common thing in SimulcastRtpForwarder.
+// For VP8, these are the seqnum, timestamp, picture ID, and TL0 PIC IDX.
+// When forwarding with a dependency descriptor, the frame number takes
+// the place of the picture ID and the TL0 PIC IDX stays 0.
 #[derive(Default, Debug, Clone, Eq, PartialEq)]
-struct Vp8RewrittenIds {
+struct RewrittenIds {
     seqnum: rtp::FullSequenceNumber,
     timestamp: rtp::FullTimestamp,
     picture_id: vp8::FullPictureId,
     tl0_pic_idx: vp8::FullTl0PicIdx,
 }
 
-impl Vp8RewrittenIds {
+impl RewrittenIds {
     fn new(
         seqnum: rtp::FullSequenceNumber,
         timestamp: rtp::FullTimestamp,
@@ -1743,18 +2880,18 @@ impl Vp8RewrittenIds {
     }
 }
 
-impl Vp8SimulcastRtpForwarder {
+impl SimulcastRtpForwarder {
     fn new(outgoing_ssrc: rtp::Ssrc) -> Self {
         Self {
             outgoing_ssrc,
-            forwarding: Vp8SimulcastRtpForwardingState::Paused,
-            switching: Vp8SimulcastRtpSwitchingState::DoNotSwitch,
-            max_outgoing: Vp8RewrittenIds::default(),
+            forwarding: SimulcastRtpForwardingState::Paused,
+            switching: SimulcastRtpSwitchingState::DoNotSwitch,
+            max_outgoing: RewrittenIds::default(),
         }
     }
 
     fn switching_ssrc(&self) -> Option<rtp::Ssrc> {
-        if let Vp8SimulcastRtpSwitchingState::SwitchAtNextKeyFrame(switch_ssrc) = self.switching {
+        if let SimulcastRtpSwitchingState::SwitchAtNextKeyFrame(switch_ssrc) = self.switching {
             Some(switch_ssrc)
         } else {
             None
@@ -1762,7 +2899,7 @@ impl Vp8SimulcastRtpForwarder {
     }
 
     fn forwarding_ssrc(&self) -> Option<rtp::Ssrc> {
-        if let Vp8SimulcastRtpForwardingState::Forwarding {
+        if let SimulcastRtpForwardingState::Forwarding {
             incoming_ssrc: forward_ssrc,
             ..
         } = self.forwarding
@@ -1774,10 +2911,9 @@ impl Vp8SimulcastRtpForwarder {
     }
 
     fn needs_key_frame(&self) -> Option<rtp::Ssrc> {
-        if let Vp8SimulcastRtpSwitchingState::SwitchAtNextKeyFrame(switching_ssrc) = self.switching
-        {
+        if let SimulcastRtpSwitchingState::SwitchAtNextKeyFrame(switching_ssrc) = self.switching {
             Some(switching_ssrc)
-        } else if let Vp8SimulcastRtpForwardingState::Forwarding {
+        } else if let SimulcastRtpForwardingState::Forwarding {
             incoming_ssrc: forwarding_ssrc,
             needs_key_frame: true,
             ..
@@ -1801,15 +2937,15 @@ impl Vp8SimulcastRtpForwarder {
                     self.outgoing_ssrc
                 );
                 self.switching =
-                    Vp8SimulcastRtpSwitchingState::SwitchAtNextKeyFrame(desired_incoming_ssrc);
+                    SimulcastRtpSwitchingState::SwitchAtNextKeyFrame(desired_incoming_ssrc);
             }
         } else {
             if self.forwarding_ssrc().is_some() {
                 trace!("Stop forwarding to SSRC {}", self.outgoing_ssrc);
             }
 
-            self.forwarding = Vp8SimulcastRtpForwardingState::Paused;
-            self.switching = Vp8SimulcastRtpSwitchingState::DoNotSwitch;
+            self.forwarding = SimulcastRtpForwardingState::Paused;
+            self.switching = SimulcastRtpSwitchingState::DoNotSwitch;
         }
     }
 
@@ -1818,7 +2954,7 @@ impl Vp8SimulcastRtpForwarder {
         // Don't pause because packets arriving out of order would not get delivered
         // and we'd perhaps need to request a new key frame yet again.
         // Plus, pausing messes up the congestion controller.
-        if let Vp8SimulcastRtpForwardingState::Forwarding {
+        if let SimulcastRtpForwardingState::Forwarding {
             needs_key_frame, ..
         } = &mut self.forwarding
         {
@@ -1832,26 +2968,79 @@ impl Vp8SimulcastRtpForwarder {
         &mut self,
         incoming_rtp: &rtp::Packet<&[u8]>,
         incoming_vp8: &vp8::ParsedHeader,
-    ) -> Option<(rtp::Ssrc, Vp8RewrittenIds)> {
+    ) -> Option<(rtp::Ssrc, RewrittenIds)> {
         let incoming_picture_id = incoming_vp8.picture_id?;
         let incoming_tl0_pic_idx = incoming_vp8.tl0_pic_idx?;
 
-        if self.switching_ssrc() == Some(incoming_rtp.ssrc()) && incoming_vp8.is_key_frame {
+        self.forward_rtp(
+            incoming_rtp,
+            incoming_vp8.is_key_frame,
+            (
+                incoming_picture_id as vp8::FullPictureId,
+                incoming_tl0_pic_idx as vp8::FullTl0PicIdx,
+            ),
+            |max_incoming| {
+                (
+                    vp8::expand_picture_id(incoming_picture_id, &mut max_incoming.picture_id),
+                    vp8::expand_tl0_pic_idx(incoming_tl0_pic_idx, &mut max_incoming.tl0_pic_idx),
+                )
+            },
+        )
+    }
+
+    // Selects a new seqnum and frame number.  If None is returned, that means
+    // don't forward the packet.
+    fn forward_dependency_descriptor_rtp(
+        &mut self,
+        incoming_rtp: &rtp::Packet<&[u8]>,
+        incoming_descriptor: &dependency_descriptor::ParsedDescriptor,
+    ) -> Option<(rtp::Ssrc, RewrittenIds)> {
+        let incoming_frame_number = incoming_descriptor.frame_number;
+
+        self.forward_rtp(
+            incoming_rtp,
+            incoming_descriptor.is_key_frame,
+            (
+                incoming_frame_number as dependency_descriptor::FullFrameNumber,
+                0,
+            ),
+            |max_incoming| {
+                (
+                    dependency_descriptor::expand_frame_number(
+                        incoming_frame_number,
+                        &mut max_incoming.picture_id,
+                    ),
+                    0,
+                )
+            },
+        )
+    }
+
+    // The incoming picture ID and TL0 PIC IDX are given both as a base for future values
+    // (when switching) and as a way to expand them given the max incoming values.
+    fn forward_rtp(
+        &mut self,
+        incoming_rtp: &rtp::Packet<&[u8]>,
+        is_key_frame: bool,
+        (incoming_picture_id, incoming_tl0_pic_idx): (u64, u64),
+        expand_picture_id_and_tl0_pic_idx: impl FnOnce(&mut RewrittenIds) -> (u64, u64),
+    ) -> Option<(rtp::Ssrc, RewrittenIds)> {
+        if self.switching_ssrc() == Some(incoming_rtp.ssrc()) && is_key_frame {
             trace!(
                 "Begin forwarding from SSRC {} to SSRC {} because we have a key frame.",
                 incoming_rtp.ssrc(),
                 self.outgoing_ssrc
             );
 
-            let first_incoming = Vp8RewrittenIds::new(
+            let first_incoming = RewrittenIds::new(
                 incoming_rtp.seqnum(),
                 // These are OK to expand without one of the expand_X functions because
                 // they are only used as a base for future values.
                 // In other words, we are only tracking the ROC since the switching point,
                 // and that is now, so the ROC is 0.
                 incoming_rtp.timestamp as rtp::FullTimestamp,
-                incoming_picture_id as vp8::FullPictureId,
-                incoming_tl0_pic_idx as vp8::FullTl0PicIdx,
+                incoming_picture_id,
+                incoming_tl0_pic_idx,
             );
             // We make two simplifying assumptions here:
             // 1. The first packet we received is the first packet of the key frame.
@@ -1866,20 +3055,20 @@ impl Vp8SimulcastRtpForwarder {
             // previous frame was (probably) incomplete.  That's why there's a 2 for the seqnum.
             let first_outgoing = self
                 .max_outgoing
-                .checked_add(&Vp8RewrittenIds::new(2, 1, 1, 1))?;
+                .checked_add(&RewrittenIds::new(2, 1, 1, 1))?;
 
-            self.forwarding = Vp8SimulcastRtpForwardingState::Forwarding {
+            self.forwarding = SimulcastRtpForwardingState::Forwarding {
                 incoming_ssrc: incoming_rtp.ssrc(),
                 first_incoming: first_incoming.clone(),
                 first_outgoing: first_outgoing.clone(),
                 max_incoming: first_incoming,
                 needs_key_frame: false,
             };
-            self.switching = Vp8SimulcastRtpSwitchingState::DoNotSwitch;
+            self.switching = SimulcastRtpSwitchingState::DoNotSwitch;
             self.max_outgoing = first_outgoing;
         }
 
-        if let Vp8SimulcastRtpForwardingState::Forwarding {
+        if let SimulcastRtpForwardingState::Forwarding {
             incoming_ssrc,
             first_incoming,
             first_outgoing,
@@ -1889,18 +3078,17 @@ impl Vp8SimulcastRtpForwarder {
         } = &mut self.forwarding
         {
             if *incoming_ssrc == incoming_rtp.ssrc() {
-                let incoming = Vp8RewrittenIds::new(
-                    incoming_rtp.seqnum(),
-                    rtp::expand_timestamp(incoming_rtp.timestamp, &mut max_incoming.timestamp),
-                    vp8::expand_picture_id(incoming_picture_id, &mut max_incoming.picture_id),
-                    vp8::expand_tl0_pic_idx(incoming_tl0_pic_idx, &mut max_incoming.tl0_pic_idx),
-                );
+                let timestamp =
+                    rtp::expand_timestamp(incoming_rtp.timestamp, &mut max_incoming.timestamp);
+                let (picture_id, tl0_pic_idx) = expand_picture_id_and_tl0_pic_idx(max_incoming);
+                let incoming =
+                    RewrittenIds::new(incoming_rtp.seqnum(), timestamp, picture_id, tl0_pic_idx);
                 // If the sub fails, it's because the incoming packet predates the switch (before the key frame)
                 let outgoing =
                     first_outgoing.checked_add(&incoming.checked_sub(first_incoming)?)?;
                 self.max_outgoing = self.max_outgoing.max(&outgoing);
 
-                if incoming_vp8.is_key_frame {
+                if is_key_frame {
                     *needs_key_frame = false;
                 }
                 trace!(
@@ -1942,6 +3130,11 @@ pub struct ClientStats {
     pub allocated_send_rate: DataRate,
     pub outgoing_queue_drain_rate: DataRate,
     pub max_requested_height: Option<VideoHeight>,
+    /// From the latest RTCP receiver report or sender report sent by the client.
+    pub reception_summary: Option<rtp::ReceptionSummary>,
+    pub speaking_time: Duration,
+    /// How many times one of the client's video layers froze while being forwarded.
+    pub video_freezes: usize,
 }
 
 #[cfg(test)]
@@ -2042,6 +3235,51 @@ mod call_tests {
         assert_eq!(Some(DataRate::from_bps(1000)), tracker.rate());
     }
 
+    #[test]
+    fn test_framerate_tracker() {
+        let now = Instant::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        // 90kHz
+        let ts = |millis: u32| millis * 90;
+
+        let mut tracker = IncomingFramerateTracker::default();
+        assert_eq!(None, tracker.framerate());
+
+        // 10 frames per second, with 2 packets per frame
+        for i in 0..10u16 {
+            let millis = i as u64 * 100;
+            tracker.push(Some(i), ts(millis as u32), at(millis));
+            tracker.push(Some(i), ts(millis as u32), at(millis + 5));
+        }
+        tracker.update(at(100));
+        // We ignore values until 500ms have passed
+        assert_eq!(None, tracker.framerate());
+        tracker.update(at(900));
+        assert_eq!(Some(10), tracker.framerate());
+
+        // Bunched-up arrival doesn't matter, only the RTP timestamps do.
+        tracker.push(Some(10), ts(1000), at(1200));
+        tracker.push(Some(11), ts(1100), at(1200));
+        tracker.push(Some(12), ts(1200), at(1200));
+        tracker.update(at(1200));
+        assert_eq!(Some(10), tracker.framerate());
+
+        // Without a frame ID, a new timestamp means a new frame.
+        let mut tracker = IncomingFramerateTracker::default();
+        for i in 0..=30u32 {
+            tracker.push(None, ts(i * 1000 / 30), at(i as u64 * 1000 / 30));
+            tracker.push(None, ts(i * 1000 / 30), at(i as u64 * 1000 / 30));
+        }
+        tracker.update(at(1000));
+        assert_eq!(Some(30), tracker.framerate());
+
+        // If the frames stop coming, the framerate decays and eventually goes away.
+        tracker.update(at(3000));
+        assert_eq!(Some(10), tracker.framerate());
+        tracker.update(at(6001));
+        assert_eq!(None, tracker.framerate());
+    }
+
     #[test]
     fn test_forward_audio() {
         let full = (rtp::TruncatedSequenceNumber::MAX as rtp::FullSequenceNumber) + 1;
@@ -2154,8 +3392,8 @@ mod call_tests {
 
             fn forward(
                 &self,
-                forwarder: &mut Vp8SimulcastRtpForwarder,
-            ) -> Option<(rtp::Ssrc, Vp8RewrittenIds)> {
+                forwarder: &mut SimulcastRtpForwarder,
+            ) -> Option<(rtp::Ssrc, RewrittenIds)> {
                 forwarder.forward_vp8_rtp(&self.rtp.borrow(), &self.vp8)
             }
         }
@@ -2167,10 +3405,10 @@ mod call_tests {
                         timestamp: rtp::FullTimestamp,
                         picture_id: vp8::FullPictureId,
                         tl0_pic_idx: vp8::FullTl0PicIdx|
-         -> Option<(rtp::Ssrc, Vp8RewrittenIds)> {
+         -> Option<(rtp::Ssrc, RewrittenIds)> {
             Some((
                 outgoing_ssrc,
-                Vp8RewrittenIds {
+                RewrittenIds {
                     seqnum,
                     timestamp,
                     picture_id,
@@ -2179,7 +3417,7 @@ mod call_tests {
             ))
         };
 
-        let mut forwarder = Vp8SimulcastRtpForwarder::new(outgoing_ssrc);
+        let mut forwarder = SimulcastRtpForwarder::new(outgoing_ssrc);
 
         // Nothing desired yet.  Don't send key frame requests and don't forward packets.
         let layer0 = Incoming::start_with_key_frame(0, 320, 180);
@@ -2345,6 +3583,7 @@ mod call_tests {
             AllocatableVideoLayer {
                 incoming_rate: DataRate::from_kbps(incoming_rate_kbps),
                 incoming_height,
+                incoming_framerate: None,
             }
         }
 
@@ -2357,6 +3596,8 @@ mod call_tests {
                 layers: [layers[0].clone(), layers[1].clone(), layers[2].clone()],
                 requested_height: VideoHeight::from(0),
                 interesting: None,
+                priority: 0,
+                spotlighted: false,
             }
         }
 
@@ -2366,6 +3607,19 @@ mod call_tests {
             video
         }
 
+        fn prioritize(priority: u32, video: &AllocatableVideo) -> AllocatableVideo {
+            let mut video: AllocatableVideo = video.clone();
+            video.priority = priority;
+            video
+        }
+
+        fn spotlight(video: &AllocatableVideo) -> AllocatableVideo {
+            let mut video: AllocatableVideo = video.clone();
+            video.requested_height = VideoHeight::from(u16::MAX);
+            video.spotlighted = true;
+            video
+        }
+
         fn interesting(secs_ago: u64, video: &AllocatableVideo) -> AllocatableVideo {
             let mut video: AllocatableVideo = video.clone();
             video.interesting = Some(Instant::now() - Duration::from_secs(secs_ago));
@@ -2776,44 +4030,285 @@ mod call_tests {
                 no_max
             )
         );
-    }
-
-    fn create_call(call_id: &[u8], now: Instant, system_now: SystemTime) -> Call {
-        let creator_id = UserId::from(b"creator_id".to_vec());
-        let active_speaker_message_interval = Duration::from_secs(1);
-        let initial_target_send_rate = DataRate::from_kbps(600);
-        let default_requested_max_send_rate = DataRate::from_kbps(20000);
-        Call::new(
-            LoggableCallId::from(call_id),
-            creator_id,
-            active_speaker_message_interval,
-            initial_target_send_rate,
-            default_requested_max_send_rate,
-            now,
-            system_now,
-        )
-    }
 
-    fn demux_id_from_unshifted(demux_id_without_shifting: u32) -> DemuxId {
-        DemuxId::from_ssrc(demux_id_without_shifting << 4)
-    }
+        // Spotlighted videos get all of their layers before anyone else gets any.
+        assert_eq!(
+            (4000, vec![(3, 1, 800), (4, 1, 800)]),
+            allocate(
+                2500,
+                0,
+                &[
+                    &request(VideoHeight::from(720), &video3),
+                    &request(VideoHeight::from(720), &video4)
+                ],
+                no_max
+            )
+        );
+        assert_eq!(
+            (4000, vec![(3, 2, 2000), (4, 0, 200)]),
+            allocate(
+                2500,
+                0,
+                &[
+                    &spotlight(&video3),
+                    &request(VideoHeight::from(720), &video4)
+                ],
+                no_max
+            )
+        );
+        assert_eq!(
+            (2200, vec![(3, 2, 2000), (4, 0, 200)]),
+            allocate(
+                2500,
+                0,
+                &[
+                    &spotlight(&video3),
+                    &request(VideoHeight::from(180), &video4)
+                ],
+                no_max
+            )
+        );
+        assert_eq!(
+            (4000, vec![(3, 1, 800), (4, 1, 800)]),
+            allocate(1700, 0, &[&spotlight(&video3), &spotlight(&video4)], no_max)
+        );
 
-    fn add_client(
-        call: &mut Call,
-        user_id: &str,
-        demux_id_without_shifting: u32,
-        now: Instant,
-    ) -> DemuxId {
-        let demux_id = demux_id_from_unshifted(demux_id_without_shifting);
-        let user_id = UserId::from(user_id.as_bytes().to_vec());
-        let active_speaker_id = format!("{}_active_speaker_id", demux_id_without_shifting);
-        let resolution_request_id = 10_000_000_000u64 + (demux_id_without_shifting as u64);
-        call.add_client(
-            demux_id,
-            user_id,
-            active_speaker_id,
-            resolution_request_id,
-            now,
+        // Higher priority videos get their higher layers first, but only after everyone gets a base layer.
+        let video3_720 = request(VideoHeight::from(720), &video3);
+        let video4_720 = request(VideoHeight::from(720), &video4);
+        assert_eq!(
+            (4000, vec![(3, 0, 200), (4, 2, 2000)]),
+            allocate(2500, 0, &[&video3_720, &prioritize(1, &video4_720)], no_max)
+        );
+        assert_eq!(
+            (4000, vec![(3, 2, 2000), (4, 0, 200)]),
+            allocate(
+                2500,
+                0,
+                &[&prioritize(2, &video3_720), &prioritize(1, &video4_720)],
+                no_max
+            )
+        );
+        // What doesn't fit for the higher priority video goes to the lower priority one.
+        assert_eq!(
+            (4000, vec![(3, 1, 800), (4, 1, 800)]),
+            allocate(1700, 0, &[&video3_720, &prioritize(1, &video4_720)], no_max)
+        );
+        // Without enough for every base layer, priority beats size.
+        assert_eq!(
+            (2200, vec![(3, 0, 200)]),
+            allocate(
+                300,
+                0,
+                &[&video3_720, &request(VideoHeight::from(180), &video4)],
+                no_max
+            )
+        );
+        assert_eq!(
+            (2200, vec![(4, 0, 200)]),
+            allocate(
+                300,
+                0,
+                &[
+                    &video3_720,
+                    &prioritize(1, &request(VideoHeight::from(180), &video4))
+                ],
+                no_max
+            )
+        );
+        // Each priority gets its turn in order.
+        assert_eq!(
+            (4800, vec![(2, 1, 800), (3, 1, 800), (4, 0, 200)]),
+            allocate(
+                2000,
+                0,
+                &[
+                    &prioritize(2, &request(VideoHeight::from(720), &video2)),
+                    &prioritize(1, &video3_720),
+                    &video4_720
+                ],
+                no_max
+            )
+        );
+
+        // A high layer that has stalled loses to a smooth lower layer.
+        fn at_framerate(framerate: u32, layer: &AllocatableVideoLayer) -> AllocatableVideoLayer {
+            let mut layer = layer.clone();
+            layer.incoming_framerate = Some(framerate);
+            layer
+        }
+        let smooth_layer0 = at_framerate(30, &layer0);
+        let smooth_layer1 = at_framerate(30, &layer1);
+        let smooth_layer2 = at_framerate(30, &layer2);
+        let choppy_layer2 = at_framerate(15, &layer2);
+        let stalled_layer2 = at_framerate(1, &layer2);
+        let smooth_video3_720 = request(
+            VideoHeight::from(720),
+            &video(DemuxId(3), [&smooth_layer0, &smooth_layer1, &smooth_layer2]),
+        );
+        let choppy_video3_720 = request(
+            VideoHeight::from(720),
+            &video(DemuxId(3), [&smooth_layer0, &smooth_layer1, &choppy_layer2]),
+        );
+        let stalled_video3_720 = request(
+            VideoHeight::from(720),
+            &video(
+                DemuxId(3),
+                [&smooth_layer0, &smooth_layer1, &stalled_layer2],
+            ),
+        );
+        assert_eq!(
+            (2000, vec![(3, 2, 2000)]),
+            allocate(2500, 0, &[&smooth_video3_720], no_max)
+        );
+        // 720*720*15 > 360*360*30
+        assert_eq!(
+            (2000, vec![(3, 2, 2000)]),
+            allocate(2500, 0, &[&choppy_video3_720], no_max)
+        );
+        // 720*720*1 < 360*360*30
+        assert_eq!(
+            (800, vec![(3, 1, 800)]),
+            allocate(2500, 0, &[&stalled_video3_720], no_max)
+        );
+        // The bandwidth saved goes to others.
+        assert_eq!(
+            (2800, vec![(3, 1, 800), (4, 2, 2000)]),
+            allocate(2800, 0, &[&stalled_video3_720, &video4_720], no_max)
+        );
+    }
+
+    #[test]
+    fn test_requested_video_layer_index() {
+        fn layer(incoming_rate_kbps: u64, incoming_height: u16) -> AllocatableVideoLayer {
+            AllocatableVideoLayer {
+                incoming_rate: DataRate::from_kbps(incoming_rate_kbps),
+                incoming_height: VideoHeight::from(incoming_height),
+                incoming_framerate: None,
+            }
+        }
+        let all = [layer(150, 180), layer(500, 360), layer(1500, 720)];
+        let lowest_only = [layer(150, 180), layer(0, 0), layer(0, 0)];
+        let nothing = [layer(0, 0), layer(0, 0), layer(0, 0)];
+        let requested =
+            |layers, height| requested_video_layer_index(layers, VideoHeight::from(height));
+
+        assert_eq!(None, requested(&all, 0));
+        assert_eq!(Some(0), requested(&all, 1));
+        assert_eq!(Some(0), requested(&all, 180));
+        assert_eq!(Some(1), requested(&all, 181));
+        assert_eq!(Some(2), requested(&all, 720));
+        assert_eq!(Some(2), requested(&all, 1080));
+
+        assert_eq!(None, requested(&lowest_only, 0));
+        assert_eq!(Some(0), requested(&lowest_only, 180));
+        assert_eq!(Some(1), requested(&lowest_only, 720));
+
+        assert_eq!(None, requested(&nothing, 0));
+        assert_eq!(Some(0), requested(&nothing, 180));
+    }
+
+    #[test]
+    fn test_recommend_video_layers() {
+        fn full(target_kbps: u64) -> Option<RecommendedVideoLayer> {
+            Some(RecommendedVideoLayer {
+                target_rate: DataRate::from_kbps(target_kbps),
+                max_framerate: FULL_VIDEO_FRAMERATE,
+            })
+        }
+        fn reduced(target_kbps: u64) -> Option<RecommendedVideoLayer> {
+            Some(RecommendedVideoLayer {
+                target_rate: DataRate::from_kbps(target_kbps),
+                max_framerate: REDUCED_VIDEO_FRAMERATE,
+            })
+        }
+        let recommend = |top_layer_index, estimate_kbps: Option<u64>| {
+            recommend_video_layers(top_layer_index, estimate_kbps.map(DataRate::from_kbps))
+        };
+
+        // Unconstrained
+        assert_eq!([full(150), None, None], recommend(0, None));
+        assert_eq!([full(150), full(500), None], recommend(1, None));
+        assert_eq!([full(150), full(500), full(1500)], recommend(2, None));
+        assert_eq!([full(150), full(500), None], recommend(1, Some(10000)));
+
+        // Enough for the lower layers, with reduced framerate for the top one
+        assert_eq!(
+            [full(150), full(500), reduced(1000)],
+            recommend(2, Some(1650))
+        );
+        assert_eq!(
+            [full(150), full(500), reduced(750)],
+            recommend(2, Some(1400))
+        );
+
+        // Not enough to make the top layer worthwhile
+        assert_eq!([full(150), full(500), None], recommend(2, Some(1399)));
+        assert_eq!([full(150), None, None], recommend(2, Some(399)));
+
+        // The base layer is always on
+        assert_eq!([reduced(100), None, None], recommend(2, Some(100)));
+        assert_eq!([reduced(75), None, None], recommend(2, Some(10)));
+    }
+
+    fn create_call(call_id: &[u8], now: Instant, system_now: SystemTime) -> Call {
+        let creator_id = UserId::from(b"creator_id".to_vec());
+        let active_speaker_message_interval = Duration::from_secs(1);
+        let initial_target_send_rate = DataRate::from_kbps(600);
+        let default_requested_max_send_rate = DataRate::from_kbps(20000);
+        Call::new(
+            LoggableCallId::from(call_id),
+            creator_id,
+            active_speaker_message_interval,
+            initial_target_send_rate,
+            default_requested_max_send_rate,
+            IngressPolicy::default(),
+            None,
+            None,
+            now,
+            system_now,
+        )
+    }
+
+    fn demux_id_from_unshifted(demux_id_without_shifting: u32) -> DemuxId {
+        DemuxId::from_ssrc(demux_id_without_shifting << 4)
+    }
+
+    fn add_client(
+        call: &mut Call,
+        user_id: &str,
+        demux_id_without_shifting: u32,
+        now: Instant,
+    ) -> DemuxId {
+        add_client_with_video_metadata_source(
+            call,
+            user_id,
+            demux_id_without_shifting,
+            VideoMetadataSource::default(),
+            now,
+        )
+    }
+
+    fn add_client_with_video_metadata_source(
+        call: &mut Call,
+        user_id: &str,
+        demux_id_without_shifting: u32,
+        video_metadata_source: VideoMetadataSource,
+        now: Instant,
+    ) -> DemuxId {
+        let demux_id = demux_id_from_unshifted(demux_id_without_shifting);
+        let user_id = UserId::from(user_id.as_bytes().to_vec());
+        let active_speaker_id = format!("{}_active_speaker_id", demux_id_without_shifting);
+        let resolution_request_id = 10_000_000_000u64 + (demux_id_without_shifting as u64);
+        call.add_client(
+            demux_id,
+            user_id,
+            false,
+            active_speaker_id,
+            resolution_request_id,
+            video_metadata_source,
+            false,
+            now,
         );
         demux_id
     }
@@ -2871,6 +4366,49 @@ mod call_tests {
         create_rtp(sender_demux_id, layer_id, seqnum, &payload[..])
     }
 
+    fn create_dependency_descriptor_video_rtp(
+        sender_demux_id: DemuxId,
+        layer_id: LayerId,
+        frame_number: u16,
+        seqnum: rtp::FullSequenceNumber,
+        key_frame_size: Option<PixelSize>,
+    ) -> rtp::Packet<Vec<u8>> {
+        let ssrc = layer_id.to_ssrc(sender_demux_id);
+        let timestamp = seqnum as rtp::TruncatedTimestamp;
+        let dependency_descriptor = write_dependency_descriptor(frame_number, key_frame_size);
+        // Simulate big (encrypted) video packets, which aren't valid VP8.
+        let payload = vec![0xE2; 1200];
+        rtp::Packet::with_empty_tag_and_dependency_descriptor(
+            108,
+            seqnum,
+            timestamp,
+            ssrc,
+            &dependency_descriptor,
+            &payload,
+        )
+    }
+
+    fn write_dependency_descriptor(
+        frame_number: u16,
+        key_frame_size: Option<PixelSize>,
+    ) -> Vec<u8> {
+        // A whole frame using template 0
+        let mut descriptor = vec![0b1100_0000];
+        descriptor.extend_from_slice(&frame_number.to_be_bytes());
+        if let Some(size) = key_frame_size {
+            // A template dependency structure with one template, one decode target, no chains,
+            // and the resolution, in 56 bits.
+            let structure: u64 = (0b10000 << 51) // template_dependency_structure_present_flag
+                | (0b11 << 38) // next_layer_idc: no more templates
+                | (0b10 << 36) // DTI: switch
+                | (1 << 33) // resolutions_present_flag
+                | ((size.width as u64 - 1) << 17)
+                | ((size.height as u64 - 1) << 1);
+            descriptor.extend_from_slice(&structure.to_be_bytes()[1..]);
+        }
+        descriptor
+    }
+
     fn write_vp8_header(
         picture_id: u16,
         tl0_pic_idx: u8,
@@ -2919,6 +4457,7 @@ mod call_tests {
     fn create_sfu_to_device(
         joined_or_left: bool,
         active_speaker_ids: Option<(&str, DemuxId)>,
+        recent_speakers: &[DemuxId],
         all_demux_ids: &[DemuxId],
     ) -> protos::SfuToDevice {
         protos::SfuToDevice {
@@ -2931,6 +4470,7 @@ mod call_tests {
                 protos::sfu_to_device::Speaker {
                     long_device_id: Some(device_id.to_owned()),
                     demux_id: Some(demux_id.as_u32()),
+                    recent_demux_ids: recent_speakers.iter().map(|id| id.as_u32()).collect(),
                 }
             }),
             current_devices: Some(protos::sfu_to_device::CurrentDevices {
@@ -2960,16 +4500,19 @@ mod call_tests {
                 short_device_id: Some(10_000_000_000 + (demux_id_without_shifting as u64)),
                 height: Some(height as u32),
                 demux_id: None,
+                priority: None,
             },
             IdentifiedBy::DemuxId => VideoRequest {
                 short_device_id: None,
                 height: Some(height as u32),
                 demux_id: Some(demux_id_from_unshifted(demux_id_without_shifting).as_u32()),
+                priority: None,
             },
             IdentifiedBy::Both => VideoRequest {
                 short_device_id: Some(10_000_000_000 + (demux_id_without_shifting as u64)),
                 height: Some(height as u32),
                 demux_id: Some(demux_id_from_unshifted(demux_id_without_shifting).as_u32()),
+                priority: None,
             },
         };
 
@@ -2995,6 +4538,7 @@ mod call_tests {
             short_device_id: None,
             height: Some(request_height as u32),
             demux_id: Some(demux_id_from_unshifted(demux_id_without_shifting).as_u32()),
+            priority: None,
         };
 
         create_server_to_client_rtp(
@@ -3025,6 +4569,19 @@ mod call_tests {
         )
     }
 
+    fn create_spotlight_rtp(demux_ids: &[DemuxId]) -> rtp::Packet<Vec<u8>> {
+        create_server_to_client_rtp(
+            1,
+            encode_proto(protos::DeviceToSfu {
+                spotlight: Some(protos::device_to_sfu::SpotlightMessage {
+                    demux_ids: demux_ids.iter().map(|demux_id| demux_id.as_u32()).collect(),
+                }),
+                ..Default::default()
+            })
+            .as_slice(),
+        )
+    }
+
     fn create_leave_rtp() -> rtp::Packet<Vec<u8>> {
         create_server_to_client_rtp(
             1,
@@ -3096,6 +4653,220 @@ mod call_tests {
         );
     }
 
+    #[test]
+    fn forward_data_reliably() {
+        let now = Instant::now();
+        let system_now = SystemTime::now();
+        let create_data_nack_rtp = |sender_demux_id: DemuxId, seqnums: Vec<u32>| {
+            create_server_to_client_rtp(
+                1,
+                encode_proto(protos::DeviceToSfu {
+                    data_nacks: vec![protos::device_to_sfu::DataNackMessage {
+                        demux_id: Some(sender_demux_id.as_u32()),
+                        seqnums,
+                    }],
+                    ..Default::default()
+                })
+                .as_slice(),
+            )
+        };
+
+        let mut call = create_call(b"call_id", now, system_now);
+        call.reliable_data_history_size = Some(2);
+        let sender_demux_id = add_client(&mut call, "sender", 1, now);
+        let receiver1_demux_id = add_client(&mut call, "receiver1", 2, now);
+
+        // receiver1 isn't connected yet, so it gets this when it catches up.
+        let mut sender_rtp1 = create_data_rtp(sender_demux_id, 1000);
+        let rtp_to_send = call
+            .handle_rtp(sender_demux_id, sender_rtp1.borrow_mut(), now)
+            .unwrap();
+        assert_eq!(0, rtp_to_send.len());
+
+        assert_eq!(
+            vec![(receiver1_demux_id, sender_rtp1)],
+            call.catch_up_on_reliable_data(receiver1_demux_id)
+        );
+        assert_eq!(0, call.catch_up_on_reliable_data(receiver1_demux_id).len());
+        assert_eq!(0, call.catch_up_on_reliable_data(sender_demux_id).len());
+
+        // The sender's seqnums are kept, gaps and all, and duplicates are dropped.
+        let sender_rtps: Vec<rtp::Packet<Vec<u8>>> = [1001, 1003, 1002]
+            .iter()
+            .map(|seqnum| create_data_rtp(sender_demux_id, *seqnum))
+            .collect();
+        for sender_rtp in &sender_rtps {
+            let rtp_to_send = call
+                .handle_rtp(sender_demux_id, sender_rtp.clone().borrow_mut(), now)
+                .unwrap();
+            assert_eq!(vec![(receiver1_demux_id, sender_rtp.clone())], rtp_to_send);
+        }
+        let rtp_to_send = call
+            .handle_rtp(sender_demux_id, sender_rtps[1].clone().borrow_mut(), now)
+            .unwrap();
+        assert_eq!(0, rtp_to_send.len());
+
+        // Only the latest 2 can be resent, each once, and seqnums that don't fit aren't.
+        let mut nack =
+            create_data_nack_rtp(sender_demux_id, vec![1002, 1000, 1001, 1003, 1002, 66538]);
+        let rtp_to_send = call
+            .handle_rtp(receiver1_demux_id, nack.borrow_mut(), now)
+            .unwrap();
+        assert_eq!(
+            vec![
+                (receiver1_demux_id, sender_rtps[2].to_rtx(0)),
+                (receiver1_demux_id, sender_rtps[1].to_rtx(0)),
+            ],
+            rtp_to_send
+        );
+        let (_, resent) = &rtp_to_send[0];
+        assert!(resent.is_rtx());
+        assert_eq!(sender_rtps[2].ssrc(), resent.ssrc());
+        assert_eq!(sender_rtps[2].seqnum(), resent.seqnum());
+
+        // Too many NACKs in one message are ignored.
+        let mut too_many_nacks = create_data_nack_rtp(
+            sender_demux_id,
+            (0..MAX_DATA_NACKS_PER_MESSAGE as u32)
+                .chain([1003])
+                .collect(),
+        );
+        let rtp_to_send = call
+            .handle_rtp(receiver1_demux_id, too_many_nacks.borrow_mut(), now)
+            .unwrap();
+        assert_eq!(0, rtp_to_send.len());
+
+        // Without history on, NACKs are ignored.
+        call.reliable_data_history_size = None;
+        let rtp_to_send = call
+            .handle_rtp(receiver1_demux_id, nack.borrow_mut(), now)
+            .unwrap();
+        assert_eq!(0, rtp_to_send.len());
+        call.reliable_data_history_size = Some(2);
+
+        // Someone who joins gets the history once they're connected, and nothing before.
+        let receiver2_demux_id = add_client(&mut call, "receiver2", 3, now);
+        let mut receiver1_rtp1 = create_data_rtp(receiver1_demux_id, 500);
+        let rtp_to_send = call
+            .handle_rtp(receiver1_demux_id, receiver1_rtp1.borrow_mut(), now)
+            .unwrap();
+        assert_eq!(vec![(sender_demux_id, receiver1_rtp1.clone())], rtp_to_send);
+        assert_eq!(
+            vec![
+                (receiver2_demux_id, sender_rtps[2].clone()),
+                (receiver2_demux_id, sender_rtps[1].clone()),
+                (receiver2_demux_id, receiver1_rtp1),
+            ],
+            call.catch_up_on_reliable_data(receiver2_demux_id)
+        );
+
+        // Without any history, there's nothing to catch up on, so RtpData is forwarded right away.
+        call.reliable_data_history_size = Some(0);
+        let receiver3_demux_id = add_client(&mut call, "receiver3", 4, now);
+        let mut sender_rtp2 = create_data_rtp(sender_demux_id, 1004);
+        let rtp_to_send = call
+            .handle_rtp(sender_demux_id, sender_rtp2.borrow_mut(), now)
+            .unwrap();
+        assert_eq!(
+            vec![
+                (receiver1_demux_id, sender_rtp2.clone()),
+                (receiver2_demux_id, sender_rtp2.clone()),
+                (receiver3_demux_id, sender_rtp2),
+            ],
+            rtp_to_send
+        );
+        assert_eq!(0, call.catch_up_on_reliable_data(receiver3_demux_id).len());
+    }
+
+    #[test]
+    fn police_ingress_rates() {
+        let now = Instant::now();
+        let system_now = SystemTime::now();
+
+        let mut call = create_call(b"call_id", now, system_now);
+        call.ingress_policy = IngressPolicy {
+            max_rate_multiplier: Some(1.0),
+            drop_excess: true,
+            disconnect_abusive_clients: false,
+        };
+        let sender_demux_id = add_client(&mut call, "sender", 1, now);
+        let _receiver_demux_id = add_client(&mut call, "receiver", 2, now);
+
+        let forwarded_count = |call: &mut Call, seqnum: u64, now: Instant| {
+            (seqnum..seqnum + 20)
+                .filter(|seqnum| {
+                    let mut rtp = create_rtp(sender_demux_id, LayerId::Audio, *seqnum, &[1; 1000]);
+                    !call
+                        .handle_rtp(sender_demux_id, rtp.borrow_mut(), now)
+                        .unwrap()
+                        .is_empty()
+                })
+                .count()
+        };
+
+        // A burst of up to a second at the max expected rate is allowed.
+        let packet_size = create_rtp(sender_demux_id, LayerId::Audio, 1, &[1; 1000]).size();
+        let burst_size = LayerId::Audio.max_expected_incoming_rate() * INGRESS_POLICING_BURST;
+        let burst_count = (burst_size.as_bytes() / packet_size.as_bytes()) as usize;
+        assert_eq!(burst_count, forwarded_count(&mut call, 1, now));
+
+        // The limit refills over time.
+        let at_200ms = now + Duration::from_millis(200);
+        assert_eq!(burst_count / 5, forwarded_count(&mut call, 21, at_200ms));
+
+        // Other layers have their own limits.
+        let mut data = create_data_rtp(sender_demux_id, 1);
+        assert_eq!(
+            1,
+            call.handle_rtp(sender_demux_id, data.borrow_mut(), at_200ms)
+                .unwrap()
+                .len()
+        );
+
+        // If excess isn't dropped, it's still forwarded.
+        call.ingress_policy.drop_excess = false;
+        assert_eq!(20, forwarded_count(&mut call, 41, at_200ms));
+        assert!(call.has_client(sender_demux_id));
+    }
+
+    #[test]
+    fn disconnect_abusive_clients() {
+        let now = Instant::now();
+        let system_now = SystemTime::now();
+
+        let mut call = create_call(b"call_id", now, system_now);
+        call.ingress_policy.disconnect_abusive_clients = true;
+        let sender_demux_id = add_client(&mut call, "sender", 1, now);
+        let abuser_demux_id = add_client(&mut call, "abuser", 2, now);
+
+        let mut rtp = create_audio_rtp(sender_demux_id, 1);
+        let mut send_with_unauthorized_ssrc =
+            |call: &mut Call, now| call.handle_rtp(abuser_demux_id, rtp.borrow_mut(), now);
+        assert_eq!(
+            Err(Error::UnauthorizedRtpSsrc(sender_demux_id, abuser_demux_id)),
+            send_with_unauthorized_ssrc(&mut call, now)
+        );
+
+        // A gap between violations starts over.
+        let mut at = now + Duration::from_secs(2);
+        for _ in 0..10 {
+            assert_eq!(
+                Err(Error::UnauthorizedRtpSsrc(sender_demux_id, abuser_demux_id)),
+                send_with_unauthorized_ssrc(&mut call, at)
+            );
+            at += Duration::from_millis(500);
+        }
+        assert!(call.has_client(abuser_demux_id));
+
+        // But violations that keep going long enough are abuse.
+        assert_eq!(
+            Err(Error::IngressAbuse),
+            send_with_unauthorized_ssrc(&mut call, at)
+        );
+        assert!(!call.has_client(abuser_demux_id));
+        assert!(call.has_client(sender_demux_id));
+    }
+
     #[test]
     fn forward_audio() {
         let now = Instant::now();
@@ -3184,6 +4955,96 @@ mod call_tests {
         );
     }
 
+    #[test]
+    fn forward_mixed_audio() {
+        let now = Instant::now();
+        let system_now = SystemTime::now();
+
+        let encoder = audiopus::coder::Encoder::new(
+            audiopus::SampleRate::Hz48000,
+            audiopus::Channels::Mono,
+            audiopus::Application::Voip,
+        )
+        .unwrap();
+        let mut payload = [0u8; 1275];
+        let payload_size = encoder.encode(&[1000i16; 960], &mut payload).unwrap();
+        let payload = &payload[..payload_size];
+
+        let add_client_receiving_mixed_audio = |call: &mut Call, demux_id_without_shifting| {
+            let demux_id = demux_id_from_unshifted(demux_id_without_shifting);
+            call.add_client(
+                demux_id,
+                UserId::from(b"mixed".to_vec()),
+                false,
+                format!("{}_active_speaker_id", demux_id_without_shifting),
+                10_000_000_000u64 + (demux_id_without_shifting as u64),
+                VideoMetadataSource::default(),
+                true,
+                now,
+            );
+            demux_id
+        };
+
+        // Without a mixer, asking for mixed audio gets every speaker's audio.
+        let mut call = create_call(b"call_id", now, system_now);
+        let sender_demux_id = add_client(&mut call, "sender", 1, now);
+        let receiver_demux_id = add_client_receiving_mixed_audio(&mut call, 2);
+        let mut rtp = create_rtp(sender_demux_id, LayerId::Audio, 1, payload);
+        rtp.audio_level = Some(50);
+        let rtp_to_send = call
+            .handle_rtp(sender_demux_id, rtp.borrow_mut(), now)
+            .unwrap();
+        assert_eq!(vec![(receiver_demux_id, rtp.clone())], rtp_to_send);
+
+        let mut call = create_call(b"call_id", now, system_now);
+        let audio_mixer = Arc::new(Mutex::new(mixer::AudioMixer::new(3)));
+        call.audio_mixer = Some(audio_mixer.clone());
+        let sender_demux_id = add_client(&mut call, "sender", 1, now);
+        let receiver1_demux_id = add_client(&mut call, "receiver1", 2, now);
+        let receiver2_demux_id = add_client_receiving_mixed_audio(&mut call, 3);
+
+        // The audio is left for the caller to mix.
+        let (rtp_to_send, mixer_to_use) = call
+            .handle_rtp_without_mixing(sender_demux_id, rtp.borrow_mut(), now)
+            .unwrap();
+        assert_eq!(vec![(receiver1_demux_id, rtp.clone())], rtp_to_send);
+        assert!(Arc::ptr_eq(&audio_mixer, &mixer_to_use.unwrap()));
+        assert!(audio_mixer.lock().mix(now).is_empty());
+
+        // Mixing happens separately from handling packets.
+        let mut rtp = create_rtp(sender_demux_id, LayerId::Audio, 2, payload);
+        rtp.audio_level = Some(50);
+        let rtp_to_send = call
+            .handle_rtp(sender_demux_id, rtp.borrow_mut(), now)
+            .unwrap();
+        assert_eq!(vec![(receiver1_demux_id, rtp.clone())], rtp_to_send);
+        let mixed_rtp_to_send = audio_mixer.lock().mix(now + Duration::from_millis(20));
+        assert_eq!(1, mixed_rtp_to_send.len());
+        let (mixed_receiver_demux_id, mixed_rtp) = &mixed_rtp_to_send[0];
+        assert_eq!(receiver2_demux_id, *mixed_receiver_demux_id);
+        assert_eq!(mixer::MIXED_AUDIO_SSRC, mixed_rtp.ssrc());
+        assert_eq!(rtp::OPUS_PAYLOAD_TYPE, mixed_rtp.payload_type());
+
+        // Only the audio is mixed.
+        let mut data_rtp = create_rtp(sender_demux_id, LayerId::RtpData, 1, &[]);
+        let (_, mixer_to_use) = call
+            .handle_rtp_without_mixing(sender_demux_id, data_rtp.borrow_mut(), now)
+            .unwrap();
+        assert!(mixer_to_use.is_none());
+
+        call.remove_client(receiver2_demux_id, now);
+        let mut rtp = create_rtp(sender_demux_id, LayerId::Audio, 3, payload);
+        rtp.audio_level = Some(50);
+        let rtp_to_send = call
+            .handle_rtp(sender_demux_id, rtp.borrow_mut(), now)
+            .unwrap();
+        assert_eq!(vec![(receiver1_demux_id, rtp.clone())], rtp_to_send);
+        assert!(audio_mixer
+            .lock()
+            .mix(now + Duration::from_millis(40))
+            .is_empty());
+    }
+
     fn forward_video_by_identifier(identifier: IdentifiedBy) {
         let now = Instant::now();
         let system_now = SystemTime::now();
@@ -3553,6 +5414,124 @@ mod call_tests {
         forward_video_by_identifier(IdentifiedBy::Both);
     }
 
+    #[test]
+    fn forward_video_with_dependency_descriptor() {
+        let now = Instant::now();
+        let system_now = SystemTime::now();
+        let at = |millis| now + Duration::from_millis(millis);
+
+        let mut call = create_call(b"call_id", now, system_now);
+        let sender_demux_id = add_client_with_video_metadata_source(
+            &mut call,
+            "sender",
+            1,
+            VideoMetadataSource::DependencyDescriptor,
+            now,
+        );
+
+        // The sender has to use the dependency descriptor, even with a valid VP8 payload.
+        let mut rtp = create_video_rtp(sender_demux_id, LayerId::Video0, 101, 11, 1, None);
+        assert_eq!(
+            Err(Error::InvalidDependencyDescriptor),
+            call.handle_rtp(sender_demux_id, rtp.borrow_mut(), at(1))
+        );
+
+        let mut frame_number = 1001;
+        let mut seqnum = 2;
+        let size = PixelSize {
+            width: 320,
+            height: 240,
+        };
+        // We need at least 2 packets to get the incoming rate working.
+        for _ in 0..2 {
+            let mut rtp = create_dependency_descriptor_video_rtp(
+                sender_demux_id,
+                LayerId::Video0,
+                frame_number,
+                seqnum,
+                Some(size),
+            );
+            let rtp_to_send = call
+                .handle_rtp(sender_demux_id, rtp.borrow_mut(), at(1))
+                .unwrap();
+            assert_eq!(0, rtp_to_send.len());
+            frame_number += 1;
+            seqnum += 1;
+        }
+
+        // The resolution comes from the template dependency structure.
+        call.tick(at(501));
+        assert!(call.clients[0].incoming_video0.rate().is_some());
+        assert_eq!(
+            Some(VideoHeight::from(size.height)),
+            call.clients[0].incoming_video0.height
+        );
+
+        let receiver_demux_id = add_client(&mut call, "receiver", 2, at(502));
+
+        // Not a key frame, so it's not forwarded yet.
+        let mut rtp = create_dependency_descriptor_video_rtp(
+            sender_demux_id,
+            LayerId::Video0,
+            frame_number,
+            seqnum,
+            None,
+        );
+        let rtp_to_send = call
+            .handle_rtp(sender_demux_id, rtp.borrow_mut(), at(503))
+            .unwrap();
+        assert_eq!(0, rtp_to_send.len());
+        frame_number += 1;
+        seqnum += 1;
+
+        // A key frame is forwarded with the frame number rewritten and the payload untouched.
+        let mut rtp = create_dependency_descriptor_video_rtp(
+            sender_demux_id,
+            LayerId::Video0,
+            frame_number,
+            seqnum,
+            Some(size),
+        );
+        let rtp_to_send = call
+            .handle_rtp(sender_demux_id, rtp.borrow_mut(), at(504))
+            .unwrap();
+        let rewritten_frame_number = 1;
+        let rewritten_timestamp = 1;
+        let rewritten_seqnum = 2;
+        let mut rewritten_rtp = create_dependency_descriptor_video_rtp(
+            sender_demux_id,
+            LayerId::Video0,
+            rewritten_frame_number,
+            rewritten_timestamp,
+            Some(size),
+        );
+        rewritten_rtp.set_seqnum_in_header(rewritten_seqnum);
+        assert_eq!(vec![(receiver_demux_id, rewritten_rtp)], rtp_to_send);
+        frame_number += 1;
+        seqnum += 1;
+
+        // And so are the frames after it.
+        let mut rtp = create_dependency_descriptor_video_rtp(
+            sender_demux_id,
+            LayerId::Video0,
+            frame_number,
+            seqnum,
+            None,
+        );
+        let rtp_to_send = call
+            .handle_rtp(sender_demux_id, rtp.borrow_mut(), at(505))
+            .unwrap();
+        let mut rewritten_rtp = create_dependency_descriptor_video_rtp(
+            sender_demux_id,
+            LayerId::Video0,
+            rewritten_frame_number + 1,
+            rewritten_timestamp + 1,
+            None,
+        );
+        rewritten_rtp.set_seqnum_in_header(rewritten_seqnum + 1);
+        assert_eq!(vec![(receiver_demux_id, rewritten_rtp)], rtp_to_send);
+    }
+
     #[test]
     fn send_updates_when_someone_joins_or_leaves() {
         let now = Instant::now();
@@ -3568,6 +5547,7 @@ mod call_tests {
             true,
             Some(("1_active_speaker_id", demux_id1)),
             &[demux_id1],
+            &[demux_id1],
         ));
 
         let (rtp_to_send, _outgoing_key_frame_requests) = call.tick(at(100));
@@ -3584,6 +5564,7 @@ mod call_tests {
         let expected_update_payload_both_clients = encode_proto(create_sfu_to_device(
             true,
             Some(("1_active_speaker_id", demux_id1)),
+            &[demux_id1],
             &[demux_id1, demux_id2],
         ));
 
@@ -3611,6 +5592,7 @@ mod call_tests {
         let expected_update_payload_just_client2 = encode_proto(create_sfu_to_device(
             true,
             Some(("1_active_speaker_id", demux_id1)), // Is it okay that the active speaker left?
+            &[],
             &[demux_id2],
         ));
 
@@ -3640,6 +5622,7 @@ mod call_tests {
         let expected_update_payload = encode_proto(create_sfu_to_device(
             true,
             Some((active_speaker_id, demux_id1)),
+            &[demux_id1],
             &[demux_id1, demux_id2],
         ));
         assert_eq!(
@@ -3673,6 +5656,7 @@ mod call_tests {
         let expected_update_payload = encode_proto(create_sfu_to_device(
             false,
             Some((active_speaker_id, demux_id2)),
+            &[demux_id2, demux_id1],
             &[demux_id1, demux_id2],
         ));
         assert_eq!(
@@ -3711,6 +5695,7 @@ mod call_tests {
             false,
             Some((active_speaker_id, demux_id1)),
             &[demux_id1, demux_id2],
+            &[demux_id1, demux_id2],
         ));
         assert_eq!(
             Some((demux_id1, active_speaker_id.to_owned())),
@@ -3797,6 +5782,106 @@ mod call_tests {
         );
     }
 
+    #[test]
+    fn rank_recent_speakers_and_track_speaking_time() {
+        let now = Instant::now();
+        let system_now = SystemTime::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        let get_recent_speakers =
+            |from_server: &[RtpToSend], receiver_demux_id: DemuxId| -> Option<Vec<DemuxId>> {
+                let (_demux_id, rtp) = from_server
+                    .iter()
+                    .find(|(demux_id, _rtp)| *demux_id == receiver_demux_id)?;
+                let proto = protos::SfuToDevice::decode(rtp.payload()).ok()?;
+                Some(
+                    proto
+                        .speaker?
+                        .recent_demux_ids
+                        .into_iter()
+                        .map(|demux_id| DemuxId::try_from(demux_id).unwrap())
+                        .collect(),
+                )
+            };
+        let speaking_time = |call: &Call, demux_id: DemuxId| {
+            call.get_stats()
+                .clients
+                .iter()
+                .find(|client| client.demux_id == demux_id)
+                .unwrap()
+                .speaking_time
+        };
+
+        let mut call = create_call(b"call_id", now, system_now);
+        let demux_id1 = add_client(&mut call, "1", 1, at(1));
+        let demux_id2 = add_client(&mut call, "2", 2, at(2));
+        let demux_id3 = add_client(&mut call, "3", 3, at(3));
+        // Everyone else is quiet while someone speaks.
+        let speak = |call: &mut Call, speaker_demux_id: DemuxId, start_millis: u64| {
+            for seqnum in 1..100 {
+                for demux_id in [demux_id1, demux_id2, demux_id3] {
+                    let mut rtp = create_audio_rtp(demux_id, seqnum);
+                    // We can't just send 100 every time or that becomes the noise floor
+                    rtp.audio_level = Some(if demux_id == speaker_demux_id {
+                        seqnum as u8
+                    } else {
+                        0
+                    });
+                    let _rtp_to_send =
+                        call.handle_rtp(demux_id, rtp.borrow_mut(), at(start_millis + seqnum));
+                }
+            }
+        };
+
+        // If there is no audio activity from anyone, we choose the first client as the active speaker
+        let (rtp_to_send, _outgoing_key_frame_requests) = call.tick(at(301));
+        assert_eq!(
+            Some(vec![demux_id1]),
+            get_recent_speakers(&rtp_to_send, demux_id1)
+        );
+        // But nobody has said anything.
+        assert_eq!(Duration::ZERO, speaking_time(&call, demux_id1));
+
+        speak(&mut call, demux_id3, 301);
+        let (rtp_to_send, _outgoing_key_frame_requests) = call.tick(at(602));
+        assert_eq!(
+            Some(vec![demux_id3, demux_id1]),
+            get_recent_speakers(&rtp_to_send, demux_id2)
+        );
+        assert_eq!(Duration::from_millis(301), speaking_time(&call, demux_id3));
+        assert_eq!(Duration::ZERO, speaking_time(&call, demux_id1));
+
+        speak(&mut call, demux_id2, 602);
+        let (rtp_to_send, _outgoing_key_frame_requests) = call.tick(at(903));
+        assert_eq!(
+            Some(vec![demux_id2, demux_id3, demux_id1]),
+            get_recent_speakers(&rtp_to_send, demux_id1)
+        );
+        // demux_id3 went quiet.
+        assert_eq!(Duration::from_millis(301), speaking_time(&call, demux_id3));
+        assert_eq!(Duration::from_millis(301), speaking_time(&call, demux_id2));
+
+        // Speaking again moves you to the front rather than adding you again.
+        speak(&mut call, demux_id3, 903);
+        let (rtp_to_send, _outgoing_key_frame_requests) = call.tick(at(1204));
+        assert_eq!(
+            Some(vec![demux_id3, demux_id2, demux_id1]),
+            get_recent_speakers(&rtp_to_send, demux_id1)
+        );
+        assert_eq!(Duration::from_millis(602), speaking_time(&call, demux_id3));
+
+        // Audio stopping while loud (such as a muted or disconnected client) isn't speaking.
+        let (_rtp_to_send, _outgoing_key_frame_requests) = call.tick(at(1505));
+        assert_eq!(Duration::from_millis(602), speaking_time(&call, demux_id3));
+
+        // Leaving the call leaves the list, but speaking time is remembered for the end of the call.
+        call.remove_client(demux_id3, at(1300));
+        assert_eq!(vec![demux_id2, demux_id1], call.recent_speakers);
+        assert_eq!(
+            Some(&Duration::from_millis(602)),
+            call.speaking_time_by_demux_id().get(&demux_id3)
+        );
+    }
+
     #[test]
     fn send_key_frame_request_on_active_speaker_change() {
         let now = Instant::now();
@@ -4182,6 +6267,315 @@ mod call_tests {
         );
     }
 
+    #[test]
+    fn send_recommendations() {
+        // (enabled, target_kbps, max_framerate)
+        type LayerRecommendation = (bool, Option<u32>, Option<u32>);
+
+        let now = Instant::now();
+        let system_now = SystemTime::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        let get_send_recommendation = |from_server: &[RtpToSend],
+                                       receiver_demux_id: DemuxId|
+         -> Option<Vec<LayerRecommendation>> {
+            let (_demux_id, rtp) = from_server
+                .iter()
+                .find(|(demux_id, _rtp)| *demux_id == receiver_demux_id)?;
+            let proto = protos::SfuToDevice::decode(rtp.payload()).ok()?;
+            Some(
+                proto
+                    .send_recommendation?
+                    .layers
+                    .iter()
+                    .map(|layer| {
+                        (
+                            layer.enabled.unwrap_or(false),
+                            layer.target_kbps,
+                            layer.max_framerate,
+                        )
+                    })
+                    .collect(),
+            )
+        };
+        let send_video = |call: &mut Call, sender_demux_id: DemuxId, time: Instant| {
+            for seqnum in 0..10 {
+                let mut to_server = create_video_rtp(
+                    sender_demux_id,
+                    LayerId::Video0,
+                    1,
+                    1,
+                    seqnum * 2,
+                    Some(PixelSize {
+                        width: 320,
+                        height: 240,
+                    }),
+                );
+                call.handle_rtp(sender_demux_id, to_server.borrow_mut(), time)
+                    .unwrap();
+            }
+        };
+
+        let mut call = create_call(b"call_id", now, system_now);
+        let demux_id1 = add_client(&mut call, "1", 1, at(1));
+        let demux_id2 = add_client(&mut call, "2", 2, at(2));
+
+        // Updates sent without stats don't include recommendations.
+        let (from_server, _outgoing_key_frame_requests) = call.tick(at(3));
+        assert_eq!(None, get_send_recommendation(&from_server, demux_id1));
+
+        // Nobody has asked for any video, so only the base layer is recommended.
+        let (from_server, _outgoing_key_frame_requests) = call.tick(at(1003));
+        assert_eq!(
+            Some(vec![
+                (true, Some(150), Some(30)),
+                (false, None, None),
+                (false, None, None),
+            ]),
+            get_send_recommendation(&from_server, demux_id2)
+        );
+
+        // demux_id1 asks for more than demux_id2 is sending, so the layers above are recommended.
+        let mut resolution_request = create_resolution_request_rtp(2, 480, IdentifiedBy::DemuxId);
+        call.handle_rtp(demux_id1, resolution_request.borrow_mut(), at(1004))
+            .unwrap();
+        send_video(&mut call, demux_id2, at(1005));
+        call.set_target_send_rate(demux_id1, DataRate::from_kbps(5000))
+            .unwrap();
+        let (from_server, _outgoing_key_frame_requests) = call.tick(at(2006));
+        assert_eq!(
+            Some(vec![
+                (true, Some(150), Some(30)),
+                (true, Some(500), Some(30)),
+                (false, None, None),
+            ]),
+            get_send_recommendation(&from_server, demux_id2)
+        );
+
+        // Heavy loss from demux_id2 means only the base layer fits, at a reduced framerate.
+        let incoming_rate = call
+            .find_client(demux_id2)
+            .unwrap()
+            .incoming_video0
+            .rate()
+            .unwrap();
+        call.set_incoming_fraction_lost(demux_id2, 128).unwrap();
+        let estimate = incoming_rate * 0.75;
+        assert_eq!(
+            Some(estimate),
+            call.find_client(demux_id2)
+                .unwrap()
+                .incoming_video_bandwidth_estimate
+        );
+        let (from_server, _outgoing_key_frame_requests) = call.tick(at(3006));
+        assert_eq!(
+            Some(vec![
+                (
+                    true,
+                    Some(std::cmp::max(estimate, DataRate::from_kbps(75)).as_kbps() as u32),
+                    Some(15)
+                ),
+                (false, None, None),
+                (false, None, None),
+            ]),
+            get_send_recommendation(&from_server, demux_id2)
+        );
+
+        // Once loss goes away, the estimate recovers.
+        for _ in 0..20 {
+            call.set_incoming_fraction_lost(demux_id2, 0).unwrap();
+        }
+        assert_eq!(
+            None,
+            call.find_client(demux_id2)
+                .unwrap()
+                .incoming_video_bandwidth_estimate
+        );
+
+        assert_eq!(
+            Err(Error::UnknownDemuxId(demux_id_from_unshifted(3))),
+            call.set_incoming_fraction_lost(demux_id_from_unshifted(3), 0)
+        );
+    }
+
+    #[test]
+    fn fall_back_from_frozen_video_layer() {
+        let now = Instant::now();
+        let system_now = SystemTime::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        let send_video = |call: &mut Call,
+                          sender_demux_id: DemuxId,
+                          layer_ids: &[LayerId],
+                          millis: std::ops::RangeInclusive<u64>| {
+            for millis in millis.step_by(100) {
+                for (layer_index, layer_id) in layer_ids.iter().enumerate() {
+                    let height = 240 * (layer_index as u16 + 1);
+                    let mut to_server = create_video_rtp(
+                        sender_demux_id,
+                        *layer_id,
+                        millis as u16,
+                        1,
+                        millis,
+                        Some(PixelSize {
+                            width: height * 4 / 3,
+                            height,
+                        }),
+                    );
+                    call.handle_rtp(sender_demux_id, to_server.borrow_mut(), at(millis))
+                        .unwrap();
+                }
+            }
+        };
+        let forwarder_ssrcs =
+            |call: &Call, receiver_demux_id: DemuxId, sender_demux_id: DemuxId| {
+                let forwarder = &call
+                    .find_client(receiver_demux_id)
+                    .unwrap()
+                    .video_forwarder_by_sender_demux_id[&sender_demux_id];
+                (forwarder.forwarding_ssrc(), forwarder.switching_ssrc())
+            };
+        let video_freezes = |call: &Call, demux_id: DemuxId| {
+            call.get_stats()
+                .clients
+                .iter()
+                .find(|client| client.demux_id == demux_id)
+                .unwrap()
+                .video_freezes
+        };
+
+        let mut call = create_call(b"call_id", now, system_now);
+        let demux_id1 = add_client(&mut call, "1", 1, at(1));
+        let demux_id2 = add_client(&mut call, "2", 2, at(2));
+        let layer_ids = [LayerId::Video0, LayerId::Video1, LayerId::Video2];
+
+        let mut resolution_request = create_resolution_request_rtp(1, 720, IdentifiedBy::DemuxId);
+        call.handle_rtp(demux_id2, resolution_request.borrow_mut(), at(3))
+            .unwrap();
+        call.set_target_send_rate(demux_id2, DataRate::from_kbps(5000))
+            .unwrap();
+        send_video(&mut call, demux_id1, &layer_ids, 100..=1000);
+        call.tick(at(1100));
+        assert_eq!(
+            (None, Some(LayerId::Video2.to_ssrc(demux_id1))),
+            forwarder_ssrcs(&call, demux_id2, demux_id1)
+        );
+
+        // The top layer stops while the others keep going.
+        send_video(&mut call, demux_id1, &layer_ids[..2], 1100..=2000);
+        let (_rtp_to_send, outgoing_key_frame_requests) = call.tick(at(2000));
+        assert_eq!(
+            (None, Some(LayerId::Video1.to_ssrc(demux_id1))),
+            forwarder_ssrcs(&call, demux_id2, demux_id1)
+        );
+        assert!(outgoing_key_frame_requests.contains(&(
+            demux_id1,
+            rtp::KeyFrameRequest {
+                ssrc: LayerId::Video1.to_ssrc(demux_id1),
+            }
+        )));
+        assert_eq!(1, video_freezes(&call, demux_id1));
+
+        // Stopping all video isn't a freeze, and there's nothing to fall back to.
+        call.tick(at(3000));
+        assert_eq!(
+            (None, Some(LayerId::Video1.to_ssrc(demux_id1))),
+            forwarder_ssrcs(&call, demux_id2, demux_id1)
+        );
+        assert_eq!(1, video_freezes(&call, demux_id1));
+    }
+
+    #[test]
+    fn spotlight_updates() {
+        let now = Instant::now();
+        let system_now = SystemTime::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        let get_spotlight =
+            |from_server: &[RtpToSend], receiver_demux_id: DemuxId| -> Option<Vec<DemuxId>> {
+                let (_demux_id, rtp) = from_server
+                    .iter()
+                    .find(|(demux_id, _rtp)| *demux_id == receiver_demux_id)?;
+                let proto = protos::SfuToDevice::decode(rtp.payload()).ok()?;
+                Some(
+                    proto
+                        .spotlight?
+                        .demux_ids
+                        .into_iter()
+                        .map(|demux_id| DemuxId::try_from(demux_id).unwrap())
+                        .collect(),
+                )
+            };
+
+        let mut call = create_call(b"call_id", now, system_now);
+        let demux_id1 = demux_id_from_unshifted(1);
+        call.add_client(
+            demux_id1,
+            UserId::from(b"admin".to_vec()),
+            true,
+            "1_active_speaker_id".to_owned(),
+            10_000_000_001,
+            VideoMetadataSource::default(),
+            false,
+            at(1),
+        );
+        let demux_id2 = add_client(&mut call, "creator_id", 2, at(2));
+
+        // There's no spotlight to tell anyone about yet.
+        let (from_server, _outgoing_key_frame_requests) = call.tick(at(3));
+        assert_eq!(None, get_spotlight(&from_server, demux_id1));
+        assert_eq!(None, get_spotlight(&from_server, demux_id2));
+
+        // Only admins' clients can change the spotlight, even if the call's creator isn't one.
+        assert_eq!(
+            Ok(vec![]),
+            call.handle_rtp(
+                demux_id2,
+                create_spotlight_rtp(&[demux_id2]).borrow_mut(),
+                at(4)
+            )
+        );
+        assert!(call.spotlight().is_empty());
+
+        // Repeated and unknown DemuxIds are ignored.
+        assert_eq!(
+            Ok(vec![]),
+            call.handle_rtp(
+                demux_id1,
+                create_spotlight_rtp(&[demux_id2, demux_id2, demux_id_from_unshifted(3)])
+                    .borrow_mut(),
+                at(5)
+            )
+        );
+        assert_eq!(&[demux_id2], call.spotlight());
+
+        let (from_server, _outgoing_key_frame_requests) = call.tick(at(6));
+        assert_eq!(
+            Some(vec![demux_id2]),
+            get_spotlight(&from_server, demux_id1)
+        );
+        assert_eq!(
+            Some(vec![demux_id2]),
+            get_spotlight(&from_server, demux_id2)
+        );
+
+        // Nothing changed, so nothing is sent.
+        let (from_server, _outgoing_key_frame_requests) = call.tick(at(7));
+        assert_eq!(None, get_spotlight(&from_server, demux_id1));
+
+        // New clients find out about the spotlight.
+        let demux_id3 = add_client(&mut call, "3", 3, at(8));
+        let (from_server, _outgoing_key_frame_requests) = call.tick(at(9));
+        assert_eq!(
+            Some(vec![demux_id2]),
+            get_spotlight(&from_server, demux_id3)
+        );
+
+        // Leaving the call leaves the spotlight.
+        call.remove_client(demux_id2, at(10));
+        assert!(call.spotlight().is_empty());
+        let (from_server, _outgoing_key_frame_requests) = call.tick(at(11));
+        assert_eq!(Some(vec![]), get_spotlight(&from_server, demux_id1));
+        assert_eq!(Some(vec![]), get_spotlight(&from_server, demux_id3));
+    }
+
     #[test]
     fn test_leave_message() {
         let now = Instant::now();
@@ -4207,6 +6601,7 @@ mod call_tests {
         let expected_update_payload = encode_proto(create_sfu_to_device(
             true,
             Some(("1_active_speaker_id", demux_id1)),
+            &[],
             &[demux_id2],
         ));
         assert_eq!(
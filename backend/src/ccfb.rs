@@ -0,0 +1,506 @@
+//
+// Copyright 2023 Signal Messenger, LLC
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Implementation of RTP Control Protocol (RTCP) Feedback for Congestion Control
+//! https://datatracker.ietf.org/doc/html/rfc8888
+//!
+//! Unlike transport-cc, the feedback refers to packets by SSRC and RTP seqnum.
+//! So the Sender maps those back to the transport-cc seqnums we use to identify
+//! sent packets, and the rest of congestion control works the same either way.
+//! The feedback also carries the ECN codepoint each packet arrived with, so that
+//! packets marked as having experienced congestion can be treated like losses.
+
+use std::collections::{BTreeMap, HashMap};
+
+use byteorder::{ReadBytesExt, BE};
+use calling_common::{expand_truncated_counter, Duration, Instant, TwoGenerationCache};
+use log::*;
+
+use crate::{
+    rtp::{expand_seqnum, FullSequenceNumber, Ssrc, TruncatedSequenceNumber},
+    transportcc::{self as tcc, RemoteInstant},
+};
+
+/// The arrival time offset is in units of 1/1024 seconds.
+const ARRIVAL_TIME_OFFSET_TICKS_PER_SECOND: u64 = 1024;
+/// The arrival time offset was too large to represent.
+const ARRIVAL_TIME_OFFSET_OVERRANGE: u16 = 0x1FFE;
+/// The arrival time offset is not known.
+const ARRIVAL_TIME_OFFSET_UNAVAILABLE: u16 = 0x1FFF;
+const ARRIVAL_TIME_OFFSET_MASK: u16 = 0x1FFF;
+const RECEIVED_BIT: u16 = 1 << 15;
+const ECN_SHIFT: u16 = 13;
+
+/// If there's a gap larger than this between received seqnums, start a new block
+/// rather than reporting every seqnum in between as not received.
+const MAX_SEQNUM_GAP_IN_BLOCK: FullSequenceNumber = 64;
+/// Like transport-cc, keep feedback packets from getting too big.
+const MAX_FEEDBACK_LEN: usize = 1100;
+
+/// The ECN codepoint of a packet (the low 2 bits of the IPv4 TOS or IPv6 traffic class).
+/// See https://datatracker.ietf.org/doc/html/rfc3168#section-5
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Ecn {
+    #[default]
+    NotEct = 0b00,
+    Ect1 = 0b01,
+    Ect0 = 0b10,
+    CongestionExperienced = 0b11,
+}
+
+impl Ecn {
+    /// Takes the ECN codepoint out of an IPv4 TOS or IPv6 traffic class.
+    pub fn from_tos(tos: u8) -> Self {
+        Self::from_bits(tos as u16)
+    }
+
+    fn from_bits(bits: u16) -> Self {
+        match bits & 0b11 {
+            0b00 => Self::NotEct,
+            0b01 => Self::Ect1,
+            0b10 => Self::Ect0,
+            _ => Self::CongestionExperienced,
+        }
+    }
+}
+
+/// One metric block of the feedback, about one packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PacketReport {
+    pub ssrc: Ssrc,
+    pub seqnum: TruncatedSequenceNumber,
+    /// None if the packet wasn't received.
+    /// Otherwise, how long before the report timestamp it was received
+    /// (None if that isn't known) and its ECN codepoint.
+    pub arrival: Option<(Option<Duration>, Ecn)>,
+}
+
+/// A packet (identified by its transport-cc seqnum) and whether it was received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PacketStatus {
+    pub tcc_seqnum: tcc::FullSequenceNumber,
+    /// None if the packet wasn't received.
+    /// If it was received but the arrival time isn't known, the RemoteInstant is None.
+    pub arrival: Option<(Option<RemoteInstant>, Ecn)>,
+}
+
+// The state for sending with RFC 8888 feedback, which maps the packets we send
+// to the transport-cc seqnums used to identify them.
+pub struct Sender {
+    tcc_seqnum_by_ssrc_and_seqnum:
+        TwoGenerationCache<(Ssrc, FullSequenceNumber), tcc::FullSequenceNumber>,
+    max_sent_seqnum_by_ssrc: HashMap<Ssrc, FullSequenceNumber>,
+    max_report_timestamp: u64,
+}
+
+impl Sender {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            // Same as tcc::Sender
+            tcc_seqnum_by_ssrc_and_seqnum: TwoGenerationCache::new(Duration::from_secs(10), now),
+            max_sent_seqnum_by_ssrc: HashMap::new(),
+            max_report_timestamp: 0,
+        }
+    }
+
+    pub fn remember_sent(
+        &mut self,
+        ssrc: Ssrc,
+        seqnum: FullSequenceNumber,
+        tcc_seqnum: tcc::FullSequenceNumber,
+        now: Instant,
+    ) {
+        let max_sent_seqnum = self.max_sent_seqnum_by_ssrc.entry(ssrc).or_default();
+        *max_sent_seqnum = std::cmp::max(*max_sent_seqnum, seqnum);
+        self.tcc_seqnum_by_ssrc_and_seqnum
+            .insert((ssrc, seqnum), tcc_seqnum, now);
+    }
+
+    // Packets we don't remember sending (or that were already reported received) are ignored.
+    // Feedback can overlap, so packets reported as not received are remembered in case later
+    // feedback reports them received (until they age out).
+    pub fn process_feedback(&mut self, feedback: &[u8]) -> Vec<PacketStatus> {
+        let (report_timestamp, reports) = if let Some(parsed) = read_feedback(feedback) {
+            parsed
+        } else {
+            warn!("Failed to parse RFC 8888 feedback");
+            return vec![];
+        };
+        let report_timestamp = expand_truncated_counter(
+            report_timestamp,
+            &mut self.max_report_timestamp,
+            u32::BITS as usize,
+        );
+        // The report timestamp is in units of 1/65536 seconds.
+        let report_time = RemoteInstant::from_micros((report_timestamp * 1_000_000) >> 16);
+
+        reports
+            .into_iter()
+            .filter_map(|report| {
+                // Expand against a copy so that feedback can't move our max seqnum.
+                let mut max_sent_seqnum = *self.max_sent_seqnum_by_ssrc.get(&report.ssrc)?;
+                let seqnum = expand_seqnum(report.seqnum, &mut max_sent_seqnum);
+                let key = (report.ssrc, seqnum);
+                let tcc_seqnum = if report.arrival.is_some() {
+                    self.tcc_seqnum_by_ssrc_and_seqnum.remove(&key)?
+                } else {
+                    *self.tcc_seqnum_by_ssrc_and_seqnum.get(&key)?
+                };
+                let arrival = report.arrival.map(|(arrival_time_offset, ecn)| {
+                    let arrival = arrival_time_offset.and_then(|arrival_time_offset| {
+                        report_time.checked_sub(arrival_time_offset)
+                    });
+                    (arrival, ecn)
+                });
+                Some(PacketStatus {
+                    tcc_seqnum,
+                    arrival,
+                })
+            })
+            .collect()
+    }
+}
+
+// The state for receiving with RFC 8888 feedback, which keeps track of packets received
+// and then occasionally triggers sending a feedback message.
+#[derive(Default)]
+pub struct Receiver {
+    // This contains all the packets that have not been reported.
+    // It is cleared whenever feedback is sent.
+    // We need a map to ignore seqnums received more than once
+    // and for it to be sorted to properly construct the feedback.
+    unreported_arrival_by_ssrc_and_seqnum: BTreeMap<(Ssrc, FullSequenceNumber), (Instant, Ecn)>,
+}
+
+impl Receiver {
+    pub fn new() -> Self {
+        Self {
+            unreported_arrival_by_ssrc_and_seqnum: BTreeMap::new(),
+        }
+    }
+
+    pub fn remember_received(
+        &mut self,
+        ssrc: Ssrc,
+        seqnum: FullSequenceNumber,
+        ecn: Ecn,
+        arrival: Instant,
+    ) {
+        // If a seqnum arrives more than once, ignore the subsequent ones.
+        self.unreported_arrival_by_ssrc_and_seqnum
+            .entry((ssrc, seqnum))
+            .or_insert((arrival, ecn));
+    }
+
+    /// Returns feedback payloads for everything received since the last time this was called.
+    /// The report timestamp is the compact NTP timestamp (see RFC 3550) of now.
+    pub fn send_feedback(&mut self, report_timestamp: u32, now: Instant) -> Vec<Vec<u8>> {
+        let arrivals = std::mem::take(&mut self.unreported_arrival_by_ssrc_and_seqnum);
+        write_feedback(report_timestamp, now, arrivals.into_iter())
+    }
+}
+
+// The arrivals must be sorted by SSRC and then seqnum.
+pub fn write_feedback(
+    report_timestamp: u32,
+    now: Instant,
+    arrivals: impl Iterator<Item = ((Ssrc, FullSequenceNumber), (Instant, Ecn))>,
+) -> Vec<Vec<u8>> {
+    let mut payloads = vec![];
+    let mut payload = vec![];
+    // (ssrc, first seqnum, metrics)
+    let mut block: Option<(Ssrc, FullSequenceNumber, Vec<u16>)> = None;
+    for ((ssrc, seqnum), (arrival, ecn)) in arrivals {
+        if let Some((block_ssrc, block_first_seqnum, metrics)) = &mut block {
+            let next_seqnum = *block_first_seqnum + metrics.len() as FullSequenceNumber;
+            if *block_ssrc == ssrc
+                && seqnum >= next_seqnum
+                && seqnum - next_seqnum <= MAX_SEQNUM_GAP_IN_BLOCK
+                && metrics.len() < u16::MAX as usize
+            {
+                // Not received
+                metrics.resize(metrics.len() + (seqnum - next_seqnum) as usize, 0);
+                metrics.push(encode_metric(now, arrival, ecn));
+                continue;
+            }
+            write_block(&mut payload, *block_ssrc, *block_first_seqnum, metrics);
+            if payload.len() > MAX_FEEDBACK_LEN {
+                payload.extend_from_slice(&report_timestamp.to_be_bytes());
+                payloads.push(std::mem::take(&mut payload));
+            }
+        }
+        block = Some((ssrc, seqnum, vec![encode_metric(now, arrival, ecn)]));
+    }
+    if let Some((block_ssrc, block_first_seqnum, metrics)) = block {
+        write_block(&mut payload, block_ssrc, block_first_seqnum, &metrics);
+    }
+    if !payload.is_empty() {
+        payload.extend_from_slice(&report_timestamp.to_be_bytes());
+        payloads.push(payload);
+    }
+    payloads
+}
+
+fn encode_metric(now: Instant, arrival: Instant, ecn: Ecn) -> u16 {
+    let arrival_time_offset = now.saturating_duration_since(arrival);
+    let ticks =
+        (arrival_time_offset.as_micros() as u64) * ARRIVAL_TIME_OFFSET_TICKS_PER_SECOND / 1_000_000;
+    let ticks = std::cmp::min(ticks, ARRIVAL_TIME_OFFSET_OVERRANGE as u64) as u16;
+    RECEIVED_BIT | ((ecn as u16) << ECN_SHIFT) | ticks
+}
+
+fn write_block(
+    payload: &mut Vec<u8>,
+    ssrc: Ssrc,
+    first_seqnum: FullSequenceNumber,
+    metrics: &[u16],
+) {
+    payload.extend_from_slice(&ssrc.to_be_bytes());
+    payload.extend_from_slice(&(first_seqnum as TruncatedSequenceNumber).to_be_bytes());
+    payload.extend_from_slice(&(metrics.len() as u16).to_be_bytes());
+    for metric in metrics {
+        payload.extend_from_slice(&metric.to_be_bytes());
+    }
+    if metrics.len() % 2 == 1 {
+        // Pad to a multiple of 4 bytes.
+        payload.extend_from_slice(&[0, 0]);
+    }
+}
+
+/// Returns the report timestamp and the reports in the feedback.
+pub fn read_feedback(payload: &[u8]) -> Option<(u32, Vec<PacketReport>)> {
+    let (mut blocks, mut report_timestamp) = payload.split_at(payload.len().checked_sub(4)?);
+    let report_timestamp = report_timestamp.read_u32::<BE>().ok()?;
+
+    let mut reports = vec![];
+    while !blocks.is_empty() {
+        let ssrc = blocks.read_u32::<BE>().ok()?;
+        let begin_seqnum = blocks.read_u16::<BE>().ok()?;
+        let report_count = blocks.read_u16::<BE>().ok()?;
+        for i in 0..report_count {
+            let metric = blocks.read_u16::<BE>().ok()?;
+            let arrival = if metric & RECEIVED_BIT == 0 {
+                None
+            } else {
+                let ecn = Ecn::from_bits(metric >> ECN_SHIFT);
+                let ticks = metric & ARRIVAL_TIME_OFFSET_MASK;
+                let arrival_time_offset = if ticks == ARRIVAL_TIME_OFFSET_OVERRANGE
+                    || ticks == ARRIVAL_TIME_OFFSET_UNAVAILABLE
+                {
+                    None
+                } else {
+                    Some(Duration::from_micros(
+                        ticks as u64 * 1_000_000 / ARRIVAL_TIME_OFFSET_TICKS_PER_SECOND,
+                    ))
+                };
+                Some((arrival_time_offset, ecn))
+            };
+            reports.push(PacketReport {
+                ssrc,
+                seqnum: begin_seqnum.wrapping_add(i),
+                arrival,
+            });
+        }
+        if report_count % 2 == 1 {
+            // Skip the padding.
+            blocks.read_u16::<BE>().ok()?;
+        }
+    }
+    Some((report_timestamp, reports))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_and_read_feedback() {
+        let now = Instant::now();
+        let before = |millis| now - Duration::from_millis(millis);
+        let payloads = write_feedback(
+            0x12345678,
+            now,
+            vec![
+                ((1, 10), (before(1000), Ecn::NotEct)),
+                ((1, 12), (before(500), Ecn::Ect0)),
+                ((1, 500), (before(10), Ecn::CongestionExperienced)),
+                ((2, 65535), (before(9000), Ecn::NotEct)),
+                ((2, 65536), (before(0), Ecn::Ect1)),
+            ]
+            .into_iter(),
+        );
+        assert_eq!(1, payloads.len());
+        assert_eq!(
+            Some((
+                0x12345678,
+                vec![
+                    PacketReport {
+                        ssrc: 1,
+                        seqnum: 10,
+                        arrival: Some((Some(Duration::from_millis(1000)), Ecn::NotEct))
+                    },
+                    PacketReport {
+                        ssrc: 1,
+                        seqnum: 11,
+                        arrival: None
+                    },
+                    PacketReport {
+                        ssrc: 1,
+                        seqnum: 12,
+                        arrival: Some((Some(Duration::from_millis(500)), Ecn::Ect0))
+                    },
+                    // The gap is too large, so this is a new block.
+                    PacketReport {
+                        ssrc: 1,
+                        seqnum: 500,
+                        arrival: Some((
+                            Some(Duration::from_micros(9765)),
+                            Ecn::CongestionExperienced
+                        ))
+                    },
+                    // Too long ago to represent
+                    PacketReport {
+                        ssrc: 2,
+                        seqnum: 65535,
+                        arrival: Some((None, Ecn::NotEct))
+                    },
+                    PacketReport {
+                        ssrc: 2,
+                        seqnum: 0,
+                        arrival: Some((Some(Duration::ZERO), Ecn::Ect1))
+                    },
+                ]
+            )),
+            read_feedback(&payloads[0])
+        );
+
+        // Truncated
+        assert_eq!(None, read_feedback(&payloads[0][..payloads[0].len() - 6]));
+        assert_eq!(None, read_feedback(&[]));
+    }
+
+    #[test]
+    fn test_write_feedback_splits_large_feedback() {
+        let now = Instant::now();
+        let arrivals = (0..2000).map(|i| ((1, i * 100), (now, Ecn::NotEct)));
+        let payloads = write_feedback(1, now, arrivals);
+        assert!(payloads.len() > 1);
+        let mut read_count = 0;
+        for payload in payloads {
+            assert!(payload.len() <= MAX_FEEDBACK_LEN + 12 + 4);
+            read_count += read_feedback(&payload).unwrap().1.len();
+        }
+        assert_eq!(2000, read_count);
+    }
+
+    #[test]
+    fn test_receiver() {
+        let now = Instant::now();
+        let mut receiver = Receiver::new();
+        assert_eq!(Vec::<Vec<u8>>::new(), receiver.send_feedback(1, now));
+
+        receiver.remember_received(1, 2, Ecn::CongestionExperienced, now);
+        receiver.remember_received(1, 1, Ecn::Ect0, now);
+        // Received again (later) is ignored.
+        receiver.remember_received(1, 1, Ecn::NotEct, now + Duration::from_millis(100));
+        let payloads = receiver.send_feedback(1, now + Duration::from_millis(1000));
+        assert_eq!(1, payloads.len());
+        let (_, reports) = read_feedback(&payloads[0]).unwrap();
+        assert_eq!(
+            vec![
+                PacketReport {
+                    ssrc: 1,
+                    seqnum: 1,
+                    arrival: Some((Some(Duration::from_millis(1000)), Ecn::Ect0))
+                },
+                PacketReport {
+                    ssrc: 1,
+                    seqnum: 2,
+                    arrival: Some((
+                        Some(Duration::from_millis(1000)),
+                        Ecn::CongestionExperienced
+                    ))
+                },
+            ],
+            reports
+        );
+        assert_eq!(Vec::<Vec<u8>>::new(), receiver.send_feedback(1, now));
+    }
+
+    #[test]
+    fn test_sender() {
+        let now = Instant::now();
+        let mut sender = Sender::new(now);
+        sender.remember_sent(1, 65535, 101, now);
+        sender.remember_sent(1, 65536, 102, now);
+        sender.remember_sent(1, 65537, 103, now);
+        sender.remember_sent(2, 7, 104, now);
+
+        // 1 second and some fraction of a second after the remote epoch.
+        let report_timestamp: u32 = (1 << 16) | (1 << 15);
+        let mut feedback = vec![];
+        write_block(
+            &mut feedback,
+            1,
+            65535,
+            &[
+                RECEIVED_BIT | ((Ecn::Ect0 as u16) << ECN_SHIFT) | 512,
+                0,
+                RECEIVED_BIT
+                    | ((Ecn::CongestionExperienced as u16) << ECN_SHIFT)
+                    | ARRIVAL_TIME_OFFSET_UNAVAILABLE,
+                // Never sent
+                RECEIVED_BIT,
+            ],
+        );
+        // Unknown SSRC
+        write_block(&mut feedback, 3, 7, &[RECEIVED_BIT]);
+        feedback.extend_from_slice(&report_timestamp.to_be_bytes());
+        assert_eq!(
+            vec![
+                PacketStatus {
+                    tcc_seqnum: 101,
+                    arrival: Some((Some(RemoteInstant::from_millis(1000)), Ecn::Ect0))
+                },
+                PacketStatus {
+                    tcc_seqnum: 102,
+                    arrival: None
+                },
+                PacketStatus {
+                    tcc_seqnum: 103,
+                    arrival: Some((None, Ecn::CongestionExperienced))
+                },
+            ],
+            sender.process_feedback(&feedback)
+        );
+
+        // Already reported received, but not received can be repeated.
+        assert_eq!(
+            vec![PacketStatus {
+                tcc_seqnum: 102,
+                arrival: None
+            }],
+            sender.process_feedback(&feedback)
+        );
+
+        // Not received in one report, but received in the next.
+        let mut feedback = vec![];
+        write_block(&mut feedback, 1, 65536, &[RECEIVED_BIT | 256]);
+        feedback.extend_from_slice(&report_timestamp.to_be_bytes());
+        assert_eq!(
+            vec![PacketStatus {
+                tcc_seqnum: 102,
+                arrival: Some((Some(RemoteInstant::from_millis(1250)), Ecn::NotEct))
+            }],
+            sender.process_feedback(&feedback)
+        );
+        assert_eq!(
+            Vec::<PacketStatus>::new(),
+            sender.process_feedback(&feedback)
+        );
+
+        assert_eq!(Vec::<PacketStatus>::new(), sender.process_feedback(&[1, 2]));
+    }
+}
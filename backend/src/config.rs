@@ -30,10 +30,30 @@ pub struct Config {
     pub ice_candidate_port: u16,
 
     /// The port to use for ICE candidates when connected over TCP. Clients
-    /// will connect to the calling backend using this port.
+    /// will connect to the calling backend using this port. The io_uring
+    /// packet server doesn't support TCP, and requires this to be 0.
     #[clap(long, default_value = "10000")]
     pub ice_candidate_port_tcp: u16,
 
+    /// The port to use for ICE candidates when connected over TCP wrapped in TLS,
+    /// for clients behind firewalls that only allow TLS (typically 443). Requires
+    /// tls-certificate-path and tls-private-key-path. If unset, TLS is not offered.
+    #[clap(long)]
+    pub ice_candidate_port_tls: Option<u16>,
+
+    /// Path to the PEM-encoded certificate chain to present on the TLS port.
+    #[clap(long)]
+    pub tls_certificate_path: Option<String>,
+
+    /// Path to the PEM-encoded private key for the TLS certificate.
+    #[clap(long)]
+    pub tls_private_key_path: Option<String>,
+
+    /// If set, TLS connections that negotiate HTTP/1.1 (via ALPN) are forwarded to
+    /// the signaling port, so that the signaling server can share the TLS port.
+    #[clap(long)]
+    pub tls_forward_http_to_signaling: bool,
+
     /// The IP address to share for direct access to the signaling_server. If
     /// defined, then the signaling_server will be used, otherwise the
     /// http_server will be used for testing.
@@ -72,6 +92,13 @@ pub struct Config {
     #[clap(long, default_value = "5000")]
     pub default_requested_max_send_rate_kbps: u64,
 
+    /// Mark outgoing UDP packets as ECN-capable (ECT(0)), so routers can mark them as having
+    /// experienced congestion rather than dropping them. Clients using RFC 8888 feedback report
+    /// those marks, and congestion control treats them like losses. Not supported by the generic
+    /// packet server.
+    #[clap(long)]
+    pub send_ecn_capable: bool,
+
     /// Timer tick period for operating on the Sfu state (ms).
     #[clap(long, default_value = "100")]
     pub tick_interval_ms: u64,
@@ -81,6 +108,7 @@ pub struct Config {
     /// It will push out other, lower-priority, streams to prioritize draining.
     /// The lower the value here, the higher the rate and the
     /// higher priority put on draining the outgoing queue.
+    /// Video that has been queued for longer than this is dropped.
     #[clap(long, default_value = "500")]
     pub outgoing_queue_drain_ms: u64,
 
@@ -105,6 +133,45 @@ pub struct Config {
     #[clap(long, default_value = "30")]
     pub inactivity_timeout_secs: u64,
 
+    /// Number of shards to partition calls across. Each shard owns its calls and their
+    /// connections, so packets for unrelated calls don't contend for the same lock.
+    /// If not defined, uses one shard per CPU.
+    #[clap(long)]
+    pub sfu_shard_count: Option<usize>,
+
+    /// The number of ICE binding requests accepted per second from each address,
+    /// with bursts of up to a second's worth. Requests over the limit are dropped
+    /// before their HMAC is verified.
+    #[clap(long, default_value = "20")]
+    pub ice_binding_requests_per_second_per_address: u32,
+
+    /// Police the media each client sends against this multiple of the most a
+    /// well-behaved client sends on each layer. If not defined, incoming rates
+    /// aren't policed.
+    #[clap(long)]
+    pub ingress_max_rate_multiplier: Option<f64>,
+
+    /// Drop incoming media over the ingress limit rather than only counting it.
+    #[clap(long)]
+    pub ingress_drop_excess: bool,
+
+    /// Remove clients that keep exceeding the ingress limit or keep sending
+    /// with SSRCs they don't own.
+    #[clap(long)]
+    pub ingress_disconnect_abusive_clients: bool,
+
+    /// Mix the audio of this many of the loudest speakers of a call into one stream for
+    /// each client that asks for mixed audio. If not defined, audio isn't mixed and
+    /// every client receives every speaker's audio.
+    #[clap(long)]
+    pub audio_mixing_max_speakers: Option<usize>,
+
+    /// Forward RtpData reliably: forward each client's RtpData with its own seqnums and without
+    /// duplicates, resend what other clients NACK, and give clients that connect this many of
+    /// each client's latest RtpData packets. If not defined, RtpData is forwarded as is.
+    #[clap(long)]
+    pub reliable_data_history_size: Option<usize>,
+
     #[clap(flatten)]
     pub metrics: MetricsOptions,
 }
@@ -128,6 +195,7 @@ pub struct MetricsOptions {
 pub struct MediaPorts {
     pub udp: u16,
     pub tcp: u16,
+    pub tls: Option<u16>,
 }
 
 pub struct ServerMediaAddress {
@@ -155,6 +223,7 @@ impl ServerMediaAddress {
             ports: MediaPorts {
                 udp: config.ice_candidate_port,
                 tcp: config.ice_candidate_port_tcp,
+                tls: config.ice_candidate_port_tls,
             },
         }
     }
@@ -175,17 +244,29 @@ pub(crate) fn default_test_config() -> Config {
         signaling_port: 8080,
         ice_candidate_port: 10000,
         ice_candidate_port_tcp: 10000,
+        ice_candidate_port_tls: None,
+        tls_certificate_path: None,
+        tls_private_key_path: None,
+        tls_forward_http_to_signaling: false,
         max_clients_per_call: 8,
         initial_target_send_rate_kbps: 1500,
         min_target_send_rate_kbps: 100,
         max_target_send_rate_kbps: 30000,
         default_requested_max_send_rate_kbps: 20000,
+        send_ecn_capable: false,
         tick_interval_ms: 100,
         outgoing_queue_drain_ms: 500,
         diagnostics_interval_secs: None,
         active_speaker_message_interval_ms: 1000,
         inactivity_check_interval_secs: 5,
         inactivity_timeout_secs: 30,
+        sfu_shard_count: Some(4),
+        ice_binding_requests_per_second_per_address: 20,
+        ingress_max_rate_multiplier: None,
+        ingress_drop_excess: false,
+        ingress_disconnect_abusive_clients: false,
+        audio_mixing_max_speakers: None,
+        reliable_data_history_size: None,
         metrics: Default::default(),
     }
 }
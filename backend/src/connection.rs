@@ -5,11 +5,14 @@
 
 use calling_common::{DataRate, DataSize, Duration, Instant};
 use log::*;
-use std::net::IpAddr::{V4, V6};
+use std::{
+    collections::HashMap,
+    net::IpAddr::{V4, V6},
+};
 use thiserror::Error;
 
 use crate::{
-    googcc, ice,
+    ccfb, googcc, ice,
     pacer::{self, Pacer, Scheduler},
     packet_server::SocketLocator,
     rtp,
@@ -34,6 +37,22 @@ const ACK_CALCULATION_INTERVAL: Duration = Duration::from_millis(100);
 
 const RECEIVER_REPORT_INTERVAL: Duration = Duration::from_secs(5);
 
+/// How many RTTs a path can go without receiving anything before we consider it silent,
+/// and fail over from it if it's the outgoing path.
+const PATH_SILENCE_TIMEOUT_RTTS: u32 = 4;
+
+/// The least time a path can go without receiving anything before we consider it silent,
+/// however low the RTT. This is long enough to cover audio DTX and RTCP intervals when the
+/// client isn't sending video.
+const MIN_PATH_SILENCE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// How long the client's consent to receive media over a path lasts after its last ICE binding
+/// request over that path (RFC 7675).
+const CONSENT_EXPIRATION: Duration = Duration::from_secs(30);
+
+/// How many validated paths we remember per connection. The oldest is forgotten beyond this.
+const MAX_CANDIDATE_PATHS: usize = 8;
+
 pub type PacketToSend = Vec<u8>;
 
 #[derive(Error, Debug, Eq, PartialEq)]
@@ -48,7 +67,7 @@ pub enum Error {
     ReceivedInvalidRtcp,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AddressType {
     UdpV4,
     UdpV6,
@@ -56,6 +75,70 @@ pub enum AddressType {
     TcpV6,
 }
 
+impl AddressType {
+    fn from_locator(addr: SocketLocator) -> Self {
+        match addr {
+            // addr.ip().to_canonical().is_ipv6());
+            // can't use this because it's not yet stable, do a little bit of the work ourselves
+            SocketLocator::Udp(addr) => match addr.ip() {
+                V4(_) => AddressType::UdpV4,
+                V6(addr) => {
+                    if addr.to_ipv4_mapped().is_none() {
+                        AddressType::UdpV6
+                    } else {
+                        AddressType::UdpV4
+                    }
+                }
+            },
+            SocketLocator::Tcp { is_ipv6, .. } => {
+                if is_ipv6 {
+                    AddressType::TcpV6
+                } else {
+                    AddressType::TcpV4
+                }
+            }
+        }
+    }
+
+    fn is_tcp(self) -> bool {
+        matches!(self, AddressType::TcpV4 | AddressType::TcpV6)
+    }
+}
+
+/// A path to the client (one of its candidates) that it has validated with an ICE binding request.
+struct CandidatePath {
+    address_type: AddressType,
+    /// The last time a valid packet of any kind was received over this path.
+    last_received: Instant,
+    /// The last time a valid ICE binding request was received over this path.
+    consent_received: Instant,
+    /// The RTT reported by the client while this path was the outgoing path.
+    rtt: Option<Duration>,
+}
+
+impl CandidatePath {
+    /// `default_rtt` is used if the client hasn't reported an RTT for this path.
+    fn is_receiving(&self, default_rtt: Duration, now: Instant) -> bool {
+        let silence_timeout = std::cmp::max(
+            self.rtt.unwrap_or(default_rtt) * PATH_SILENCE_TIMEOUT_RTTS,
+            MIN_PATH_SILENCE_TIMEOUT,
+        );
+        now.saturating_duration_since(self.last_received) <= silence_timeout
+    }
+
+    fn has_consent(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.consent_received) < CONSENT_EXPIRATION
+    }
+}
+
+/// See Connection::path_stats().
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PathStats {
+    pub validated_paths: usize,
+    /// How many times the outgoing path has changed, either by nomination or by failover.
+    pub switches: u32,
+}
+
 /// The state of a connection to a client.
 /// Combines the ICE and SRTP/SRTCP state.
 /// Takes care of transport auth, crypto, ACKs, NACKs,
@@ -77,22 +160,38 @@ pub struct Connection {
     /// See Connection::outgoing_addr().
     outgoing_addr: Option<SocketLocator>,
     outgoing_addr_type: Option<AddressType>,
+    /// Every address the client has validated, so we can fail over to another one if the
+    /// outgoing path stops working before the client nominates a new one.
+    paths: HashMap<SocketLocator, CandidatePath>,
+    path_switches: u32,
+    /// Set when the client's consent to receive on the outgoing address expired and there was
+    /// nowhere else to send. Nothing is sent until the client sends a binding request again.
+    consent_lost_addr: Option<SocketLocator>,
 }
 
 struct Ice {
-    // Immutable
+    // Changed only by an ICE restart
     /// Username expected by server in binding requests from clients.
     request_username: Vec<u8>,
     /// Username expected by clients in binding responses from server.
     response_username: Vec<u8>,
     /// Used to verify the HMAC in requests and generate HMACS in response.
     pwd: Vec<u8>,
+    /// The credentials from before the last ICE restart, which are still accepted so that
+    /// the client can keep using its old candidate pair until the new one is nominated.
+    previous: Option<IceCredentials>,
 
     // Mutable
     /// The last time a valid ice binding request from the client was received.
     binding_request_received: Option<Instant>,
 }
 
+struct IceCredentials {
+    request_username: Vec<u8>,
+    response_username: Vec<u8>,
+    pwd: Vec<u8>,
+}
+
 struct Rtp {
     // Immutable
     /// The SSRC used for sending transport-CC ACKs.
@@ -114,6 +213,9 @@ struct Rtp {
 struct CongestionControl {
     controller: googcc::CongestionController,
     pacer: Pacer,
+    /// For video the pacer dropped.  They're handled with the next RTCP from the client
+    /// as if the client sent them.
+    key_frame_requests_for_dropped_video: Vec<rtp::KeyFrameRequest>,
 }
 
 pub type DhePublicKey = [u8; 32];
@@ -125,7 +227,9 @@ impl Connection {
         ice_response_username: Vec<u8>,
         ice_pwd: Vec<u8>,
         srtp_master_key_material: rtp::MasterKeyMaterial,
+        srtp_protection_profile: rtp::SrtpProtectionProfile,
         ack_ssrc: rtp::Ssrc,
+        congestion_control_feedback: rtp::CongestionControlFeedback,
         googcc_config: googcc::Config,
         inactivity_timeout: Duration,
         now: Instant,
@@ -133,8 +237,11 @@ impl Connection {
         let (decrypt, encrypt) =
             rtp::KeysAndSalts::derive_client_and_server_from_master_key_material(
                 &srtp_master_key_material,
+                srtp_protection_profile,
             );
-        let rtp_endpoint = rtp::Endpoint::new(decrypt, encrypt, now, RTCP_SENDER_SSRC, ack_ssrc);
+        let mut rtp_endpoint =
+            rtp::Endpoint::new(decrypt, encrypt, now, RTCP_SENDER_SSRC, ack_ssrc);
+        rtp_endpoint.set_congestion_control_feedback(congestion_control_feedback);
         Self {
             created: now,
 
@@ -144,6 +251,7 @@ impl Connection {
                 request_username: ice_request_username,
                 response_username: ice_response_username,
                 pwd: ice_pwd,
+                previous: None,
 
                 binding_request_received: None,
             },
@@ -159,11 +267,16 @@ impl Connection {
                     media_send_rate: googcc_config.initial_target_send_rate,
                     padding_send_rate: googcc_config.initial_target_send_rate,
                     padding_ssrc: None,
+                    max_video_queue_delay: None,
                 }),
                 controller: googcc::CongestionController::new(googcc_config, now),
+                key_frame_requests_for_dropped_video: vec![],
             },
             outgoing_addr: None,
             outgoing_addr_type: None,
+            paths: HashMap::new(),
+            path_switches: 0,
+            consent_lost_addr: None,
         }
     }
 
@@ -173,7 +286,57 @@ impl Connection {
         &self.ice.request_username
     }
 
+    /// The username used before the last ICE restart, which is still accepted.
+    pub fn previous_ice_request_username(&self) -> Option<&[u8]> {
+        self.ice
+            .previous
+            .as_ref()
+            .map(|previous| &previous.request_username[..])
+    }
+
+    /// Switches to new ICE credentials, such as when a client changes networks, while keeping
+    /// the SRTP and congestion control state. The current credentials remain valid until the
+    /// next restart, so media keeps flowing over the current candidate pair until the client
+    /// nominates a new one.
+    ///
+    /// Returns the request username that is no longer accepted, if any.
+    pub fn restart_ice(
+        &mut self,
+        ice_request_username: Vec<u8>,
+        ice_response_username: Vec<u8>,
+        ice_pwd: Vec<u8>,
+    ) -> Option<Vec<u8>> {
+        event!("calling.sfu.ice.restart");
+        let previous = IceCredentials {
+            request_username: std::mem::replace(
+                &mut self.ice.request_username,
+                ice_request_username,
+            ),
+            response_username: std::mem::replace(
+                &mut self.ice.response_username,
+                ice_response_username,
+            ),
+            pwd: std::mem::replace(&mut self.ice.pwd, ice_pwd),
+        };
+        self.ice
+            .previous
+            .replace(previous)
+            .map(|expired| expired.request_username)
+    }
+
+    /// Switches to SRTP keys from a new DHE exchange without rejoining, keeping the same
+    /// protection profile. See rtp::Endpoint::rekey for how the switch happens.
+    pub fn rekey(&mut self, srtp_master_key_material: rtp::MasterKeyMaterial, now: Instant) {
+        let (decrypt, encrypt) =
+            rtp::KeysAndSalts::derive_client_and_server_from_master_key_material(
+                &srtp_master_key_material,
+                self.rtp.endpoint.srtp_protection_profile(),
+            );
+        self.rtp.endpoint.rekey(decrypt, encrypt, now);
+    }
+
     /// All packets except for ICE binding responses should be sent to this address, if there is one.
+    /// There isn't one before the client nominates one, or after it stops consenting to receive.
     pub fn outgoing_addr(&self) -> Option<SocketLocator> {
         self.outgoing_addr
     }
@@ -182,6 +345,18 @@ impl Connection {
         self.outgoing_addr_type
     }
 
+    /// Whether we stopped sending because the client stopped consenting to receive.
+    pub fn consent_lost(&self) -> bool {
+        self.consent_lost_addr.is_some()
+    }
+
+    pub fn path_stats(&self) -> PathStats {
+        PathStats {
+            validated_paths: self.paths.len(),
+            switches: self.path_switches,
+        }
+    }
+
     pub fn set_dequeue_scheduler(&mut self, dequeue_scheduler: Option<Box<Scheduler>>) {
         self.congestion_control.pacer.dequeue_scheduler = dequeue_scheduler;
     }
@@ -196,56 +371,48 @@ impl Connection {
         binding_request: ice::BindingRequest,
         now: Instant,
     ) -> Result<PacketToSend, Error> {
+        // This should never fail because sfu.rs should never call us with an invalid username.
+        // But defense in depth is good too.
+        let (response_username, pwd) = if binding_request.username() == self.ice.request_username {
+            (&self.ice.response_username, &self.ice.pwd)
+        } else {
+            match &self.ice.previous {
+                Some(previous) if binding_request.username() == previous.request_username => {
+                    (&previous.response_username, &previous.pwd)
+                }
+                _ => {
+                    return Err(Error::ReceivedIceWithInvalidUsername(
+                        binding_request.username().to_vec(),
+                    ));
+                }
+            }
+        };
         let verified_binding_request = binding_request
-            .verify_hmac(&self.ice.pwd)
+            .verify_hmac(pwd)
             .map_err(|_| Error::ReceivedIceWithInvalidHmac(binding_request.hmac().to_vec()))?;
+        let response = verified_binding_request.to_binding_response(response_username, pwd);
 
-        // This should never happen because sfu.rs should never call us with an invalid username.
-        // But defense in depth is good too.
-        if verified_binding_request.username() != self.ice.request_username {
-            return Err(Error::ReceivedIceWithInvalidUsername(
-                verified_binding_request.username().to_vec(),
-            ));
-        }
         // The client may send ICE binding requests from many different addresses
         // (probably different network interfaces).
         // At any given time, only one will be nominated, which means the client
         // wants to receive using that address.
         // Other addresses are only being checked as "backup".  We should send
-        // back responses for those, but not switch to sending to them.
+        // back responses for those, but not switch to sending to them unless the
+        // nominated one stops working (see fail_over_if_outgoing_path_is_unusable()).
         // Over time, the nominated address may change, and we switch to the new
         // one whenever it does.
+        self.validate_path(sender_addr, now);
         if verified_binding_request.nominated() && self.outgoing_addr != Some(sender_addr) {
             event!("calling.sfu.ice.outgoing_addr_switch");
-            self.outgoing_addr = Some(sender_addr);
-            self.outgoing_addr_type = Some(match sender_addr {
-                // addr.ip().to_canonical().is_ipv6());
-                // can't use this because it's not yet stable, do a little bit of the work ourselves
-                SocketLocator::Udp(addr) => match addr.ip() {
-                    V4(_) => AddressType::UdpV4,
-                    V6(addr) => {
-                        if addr.to_ipv4_mapped().is_none() {
-                            AddressType::UdpV6
-                        } else {
-                            AddressType::UdpV4
-                        }
-                    }
-                },
-                SocketLocator::Tcp { is_ipv6, .. } => {
-                    if is_ipv6 {
-                        AddressType::TcpV6
-                    } else {
-                        AddressType::TcpV4
-                    }
-                }
-            });
+            self.switch_outgoing_addr(sender_addr);
+        } else if self.consent_lost_addr.is_some() {
+            // The request also renews consent, so we can resume sending on this path.
+            event!("calling.sfu.ice.consent_restored");
+            self.switch_outgoing_addr(sender_addr);
         }
         self.ice.binding_request_received = Some(now);
 
-        Ok(
-            verified_binding_request
-                .to_binding_response(&self.ice.response_username, &self.ice.pwd),
-        )
+        Ok(response)
     }
 
     // This effectively overrides the DHE, which is more convenient for tests.
@@ -260,17 +427,144 @@ impl Connection {
             rtp::Endpoint::new(decrypt, encrypt, now, RTCP_SENDER_SSRC, self.rtp.ack_ssrc);
     }
 
+    fn validate_path(&mut self, addr: SocketLocator, now: Instant) {
+        if let Some(path) = self.paths.get_mut(&addr) {
+            path.last_received = now;
+            path.consent_received = now;
+            return;
+        }
+        if self.paths.len() >= MAX_CANDIDATE_PATHS {
+            let outgoing_addr = self.outgoing_addr;
+            if let Some(oldest_addr) = self
+                .paths
+                .iter()
+                .filter(|(addr, _)| Some(**addr) != outgoing_addr)
+                .min_by_key(|(_, path)| path.last_received)
+                .map(|(addr, _)| *addr)
+            {
+                self.paths.remove(&oldest_addr);
+            }
+        }
+        self.paths.insert(
+            addr,
+            CandidatePath {
+                address_type: AddressType::from_locator(addr),
+                last_received: now,
+                consent_received: now,
+                rtt: None,
+            },
+        );
+    }
+
+    fn switch_outgoing_addr(&mut self, addr: SocketLocator) {
+        let previous_addr = self.outgoing_addr.or(self.consent_lost_addr.take());
+        if matches!(previous_addr, Some(previous_addr) if previous_addr != addr) {
+            self.path_switches = self.path_switches.saturating_add(1);
+        }
+        self.outgoing_addr = Some(addr);
+        self.outgoing_addr_type = Some(AddressType::from_locator(addr));
+    }
+
+    /// Records that a valid (decrypted) packet arrived from the given address. If it isn't the
+    /// outgoing address, and nothing has arrived over the outgoing path for an RTT, the client
+    /// has evidently moved to the new path, so we follow it.
+    pub fn handle_packet_received_on_path(&mut self, sender_addr: SocketLocator, now: Instant) {
+        let Some(path) = self.paths.get_mut(&sender_addr) else {
+            // Only ICE binding requests validate a path.
+            return;
+        };
+        path.last_received = now;
+        if !path.has_consent(now) {
+            return;
+        }
+        let Some(outgoing_addr) = self.outgoing_addr else {
+            return;
+        };
+        if outgoing_addr == sender_addr {
+            return;
+        }
+        let (outgoing_last_received, outgoing_rtt) = match self.paths.get(&outgoing_addr) {
+            Some(outgoing_path) => (outgoing_path.last_received, outgoing_path.rtt),
+            None => (self.created, None),
+        };
+        let rtt = outgoing_rtt.unwrap_or_else(|| self.rtt());
+        if now.saturating_duration_since(outgoing_last_received) > rtt {
+            event!("calling.sfu.ice.path_failover.follow_client");
+            debug!(
+                "outgoing path {} silent for {:?}, following the client to {}",
+                outgoing_addr,
+                now.saturating_duration_since(outgoing_last_received),
+                sender_addr
+            );
+            self.switch_outgoing_addr(sender_addr);
+        }
+    }
+
+    /// If nothing has arrived over the outgoing path for a while, or the client's consent to
+    /// receive on it has expired, switches to the best path that is still receiving, preferring
+    /// a lower RTT, then UDP, then the most recently used. If there is none and consent has
+    /// expired, stops sending.
+    fn fail_over_if_outgoing_path_is_unusable(&mut self, now: Instant) {
+        let Some(outgoing_addr) = self.outgoing_addr else {
+            return;
+        };
+        let default_rtt = self.rtt();
+        let outgoing_path = self.paths.get(&outgoing_addr);
+        let consent_expired = !matches!(outgoing_path, Some(path) if path.has_consent(now));
+        let silent = !matches!(outgoing_path, Some(path) if path.is_receiving(default_rtt, now));
+        if !consent_expired && !silent {
+            return;
+        }
+
+        let best_addr = self
+            .paths
+            .iter()
+            .filter(|(addr, path)| {
+                **addr != outgoing_addr
+                    && path.is_receiving(default_rtt, now)
+                    && path.has_consent(now)
+            })
+            .min_by_key(|(_, path)| {
+                (
+                    path.rtt.is_none(),
+                    path.rtt,
+                    path.address_type.is_tcp(),
+                    std::cmp::Reverse(path.last_received),
+                )
+            })
+            .map(|(addr, _)| *addr);
+        if let Some(best_addr) = best_addr {
+            if consent_expired {
+                event!("calling.sfu.ice.path_failover.consent_expired");
+            } else {
+                event!("calling.sfu.ice.path_failover.silent");
+            }
+            debug!(
+                "outgoing path {} is unusable, failing over to {}",
+                outgoing_addr, best_addr
+            );
+            self.switch_outgoing_addr(best_addr);
+        } else if consent_expired {
+            event!("calling.sfu.ice.consent_lost");
+            info!("consent to send to {} expired", outgoing_addr);
+            self.outgoing_addr = None;
+            self.outgoing_addr_type = None;
+            self.consent_lost_addr = Some(outgoing_addr);
+        }
+    }
+
     /// Decrypts an incoming RTP packet and returns it.
     /// Also remembers that we may need to send ACKs and NACKs
     /// at the next call to tick().
     pub fn handle_rtp_packet<'packet>(
         &mut self,
         incoming_packet: &'packet mut [u8],
+        ecn: ccfb::Ecn,
         now: Instant,
     ) -> Result<rtp::Packet<&'packet mut [u8]>, Error> {
         let rtp_endpoint = &mut self.rtp.endpoint;
         rtp_endpoint
-            .receive_rtp(incoming_packet, now)
+            .receive_rtp_with_ecn(incoming_packet, ecn, now)
             .ok_or(Error::ReceivedInvalidRtp)
     }
 
@@ -291,10 +585,28 @@ impl Connection {
             .receive_rtcp(incoming_packet, now)
             .ok_or(Error::ReceivedInvalidRtcp)?;
 
+        if let Some(rtt) = rtcp.reception_summary.and_then(|summary| summary.rtt) {
+            self.congestion_control
+                .controller
+                .remember_reported_rtt(rtt);
+            // The RTT is measured from a report we sent over the outgoing path.
+            if let Some(path) = self
+                .outgoing_addr
+                .and_then(|outgoing_addr| self.paths.get_mut(&outgoing_addr))
+            {
+                path.rtt = Some(rtt);
+            }
+        }
         let new_target_send_rate = self
             .congestion_control
             .controller
-            .recalculate_target_send_rate(rtcp.acks);
+            .recalculate_target_send_rate(
+                rtcp.acks,
+                &rtcp.losses,
+                rtcp.late_ack_count,
+                rtcp.congestion_experienced_count,
+                now,
+            );
         // TODO: Adjust the ACK interval like WebRTC does.  Something like this:
         // ack_interval = (DataSize::from_bytes(68) / (new_target_send_rate * 0.05)).clamp(Duration::from_millis(50), Duration::from_millis(250));
         // WebRTC sends this initially every 100ms
@@ -307,9 +619,12 @@ impl Connection {
             for rtp::Nack { ssrc, seqnums } in rtcp.nacks {
                 for seqnum in seqnums {
                     if let Some(rtx) = rtp_endpoint.resend_rtp(ssrc, seqnum, now) {
-                        // TODO: Consider sending through the pacer.
-                        rtp_endpoint.remember_sent_for_tcc(&rtx, now);
-                        outgoing_rtx.push((rtx.into_serialized(), outgoing_addr));
+                        // RTX goes through the pacer ahead of video but behind audio.
+                        // It comes back out here if it doesn't need to be queued.
+                        if let Some(rtx) = self.congestion_control.pacer.enqueue(rtx, now) {
+                            rtp_endpoint.remember_sent_for_tcc(&rtx, None, now);
+                            outgoing_rtx.push((rtx.into_serialized(), outgoing_addr));
+                        }
                     } else {
                         debug!("Ignoring NACK for (SSRC, seqnum) that is either too old or invalid: ({}, {})", ssrc, seqnum);
                     }
@@ -317,10 +632,15 @@ impl Connection {
             }
         }
 
+        let mut incoming_key_frame_requests = rtcp.key_frame_requests;
+        incoming_key_frame_requests
+            .append(&mut self.congestion_control.key_frame_requests_for_dropped_video);
+
         Ok(HandleRtcpResult {
-            incoming_key_frame_requests: rtcp.key_frame_requests,
+            incoming_key_frame_requests,
             outgoing_rtx,
             new_target_send_rate,
+            reception_summary: rtcp.reception_summary,
         })
     }
 
@@ -331,9 +651,11 @@ impl Connection {
     // results of calling this across many connections.
     // So we use (packet, addr) for convenience.
     pub fn tick(&mut self, packets_to_send: &mut Vec<(PacketToSend, SocketLocator)>, now: Instant) {
+        self.fail_over_if_outgoing_path_is_unusable(now);
         self.send_acks_if_its_been_too_long(packets_to_send, now);
         self.send_nacks_if_its_been_too_long(packets_to_send, now);
         self.send_receiver_report_if_its_been_too_long(packets_to_send, now);
+        self.start_probing_if_its_time(now);
     }
 
     /// If an ICE binding request has been received, a Connection is inactive if it's been more
@@ -365,7 +687,7 @@ impl Connection {
                     if let Some(outgoing_rtp) =
                         self.congestion_control.pacer.enqueue(outgoing_rtp, now)
                     {
-                        rtp_endpoint.remember_sent_for_tcc(&outgoing_rtp, now);
+                        rtp_endpoint.remember_sent_for_tcc(&outgoing_rtp, None, now);
                         rtp_to_send.push((outgoing_rtp.into_serialized(), outgoing_addr));
                     }
                 } else {
@@ -380,13 +702,30 @@ impl Connection {
     /// or generates padding (if necessary).
     pub fn dequeue_outgoing_rtp(&mut self, now: Instant) -> Option<(PacketToSend, SocketLocator)> {
         let rtp_endpoint = &mut self.rtp.endpoint;
+        // The client would NACK the dropped video, and resending it would only move the
+        // backlog ahead of the rest of the video.  So forget it and get a key frame instead.
+        let congestion_control = &mut self.congestion_control;
+        for dropped_video in congestion_control.pacer.drop_stale_video(now) {
+            rtp_endpoint.forget_sent(dropped_video.ssrc(), dropped_video.seqnum());
+            let key_frame_request = rtp::KeyFrameRequest {
+                ssrc: dropped_video.ssrc(),
+            };
+            if !congestion_control
+                .key_frame_requests_for_dropped_video
+                .contains(&key_frame_request)
+            {
+                congestion_control
+                    .key_frame_requests_for_dropped_video
+                    .push(key_frame_request);
+            }
+        }
         let generate_padding = |padding_ssrc| rtp_endpoint.send_padding(padding_ssrc, now);
-        let outgoing_rtp = self
+        let (outgoing_rtp, probe_cluster_id) = self
             .congestion_control
             .pacer
             .dequeue(generate_padding, now)?;
         let outgoing_addr = self.outgoing_addr?;
-        rtp_endpoint.remember_sent_for_tcc(&outgoing_rtp, now);
+        rtp_endpoint.remember_sent_for_tcc(&outgoing_rtp, probe_cluster_id, now);
         Some((outgoing_rtp.into_serialized(), outgoing_addr))
     }
 
@@ -427,7 +766,7 @@ impl Connection {
 
         let rtp_endpoint = &mut self.rtp.endpoint;
         if let Some(outgoing_addr) = self.outgoing_addr {
-            for ack_packet in rtp_endpoint.send_acks() {
+            for ack_packet in rtp_endpoint.send_acks(now) {
                 packets_to_send.push((ack_packet, outgoing_addr));
             }
 
@@ -477,15 +816,43 @@ impl Connection {
             if let Some(receiver_report_packet) = self.rtp.endpoint.send_receiver_report() {
                 packets_to_send.push((receiver_report_packet, outgoing_addr));
             }
+            if let Some(extended_report_packet) =
+                self.rtp.endpoint.send_receiver_reference_time(now)
+            {
+                packets_to_send.push((extended_report_packet, outgoing_addr));
+            }
 
             self.rtp.receiver_report_sent = Some(now);
         }
     }
 
+    fn start_probing_if_its_time(&mut self, now: Instant) {
+        if self.outgoing_addr.is_none() || self.congestion_control.pacer.is_probing() {
+            return;
+        }
+        // The probe clusters are sent by dequeue_outgoing_rtp() and the acks for them
+        // are used by the controller to (maybe) raise the target send rate.
+        for probe_send_rate in self.congestion_control.controller.probe_send_rates(now) {
+            self.congestion_control
+                .pacer
+                .start_probe_cluster(probe_send_rate, now);
+        }
+    }
+
+    /// The worst loss (out of 256) of what the client sent us, if a receiver report was
+    /// sent since the last call.
+    pub fn take_incoming_fraction_lost(&mut self) -> Option<u8> {
+        self.rtp.endpoint.take_incoming_fraction_lost()
+    }
+
     pub fn outgoing_queue_size(&self) -> DataSize {
         self.congestion_control.pacer.queued_size()
     }
 
+    pub fn outgoing_queue_stats(&self) -> pacer::Stats {
+        self.congestion_control.pacer.stats()
+    }
+
     pub fn rtp_endpoint_stats(&self) -> rtp::EndpointStats {
         self.rtp.endpoint.stats()
     }
@@ -515,6 +882,7 @@ pub struct HandleRtcpResult {
     // So we use (packet, addr) for convenience.
     pub outgoing_rtx: Vec<(PacketToSend, SocketLocator)>,
     pub new_target_send_rate: Option<DataRate>,
+    pub reception_summary: Option<rtp::ReceptionSummary>,
 }
 
 #[cfg(test)]
@@ -539,8 +907,10 @@ mod connection_tests {
             ice_request_username.to_vec(),
             ice_response_username.to_vec(),
             ice_pwd.to_vec(),
-            zeroize::Zeroizing::new([0u8; 56]),
+            zeroize::Zeroizing::new(vec![0u8; rtp::MASTER_KEY_MATERIAL_LEN]),
+            rtp::SrtpProtectionProfile::default(),
             ack_ssrc,
+            rtp::CongestionControlFeedback::default(),
             googcc_config,
             inactivity_timeout,
             now,
@@ -550,21 +920,21 @@ mod connection_tests {
     fn new_srtp_keys(seed: u8) -> (rtp::KeysAndSalts, rtp::KeysAndSalts) {
         let decrypt = rtp::KeysAndSalts {
             rtp: rtp::KeyAndSalt {
-                key: [seed + 1; rtp::SRTP_KEY_LEN].into(),
+                key: [seed + 1; rtp::SRTP_AES_128_KEY_LEN].into(),
                 salt: [seed + 2; rtp::SRTP_SALT_LEN],
             },
             rtcp: rtp::KeyAndSalt {
-                key: [seed + 3; rtp::SRTP_KEY_LEN].into(),
+                key: [seed + 3; rtp::SRTP_AES_128_KEY_LEN].into(),
                 salt: [seed + 4; rtp::SRTP_SALT_LEN],
             },
         };
         let encrypt = rtp::KeysAndSalts {
             rtp: rtp::KeyAndSalt {
-                key: [seed + 5; rtp::SRTP_KEY_LEN].into(),
+                key: [seed + 5; rtp::SRTP_AES_128_KEY_LEN].into(),
                 salt: [seed + 6; rtp::SRTP_SALT_LEN],
             },
             rtcp: rtp::KeyAndSalt {
-                key: [seed + 7; rtp::SRTP_KEY_LEN].into(),
+                key: [seed + 7; rtp::SRTP_AES_128_KEY_LEN].into(),
                 salt: [seed + 8; rtp::SRTP_SALT_LEN],
             },
         };
@@ -651,6 +1021,7 @@ mod connection_tests {
             encrypted_rtcp,
             &encrypt.rtcp.key,
             &encrypt.rtcp.salt,
+            &mut HashMap::new(),
         )?;
         let acks = rtcp
             .tcc_feedbacks
@@ -769,6 +1140,262 @@ mod connection_tests {
         assert!(connection.inactive(now));
     }
 
+    #[test]
+    fn test_ice_restart() {
+        let now = Instant::now();
+        let wifi_addr = SocketLocator::Udp("192.0.2.4:5".parse().unwrap());
+        let cellular_addr = SocketLocator::Udp("198.51.100.9:10".parse().unwrap());
+
+        let mut connection = new_connection(now);
+        let old_request_username = connection.ice.request_username.clone();
+        let old_pwd = connection.ice.pwd.clone();
+        handle_ice_binding_request(&mut connection, wifi_addr, 1, true, now).unwrap();
+        assert_eq!(Some(wifi_addr), connection.outgoing_addr());
+
+        assert_eq!(
+            None,
+            connection.restart_ice(
+                b"server2:client2".to_vec(),
+                b"client2:server2".to_vec(),
+                b"the_new_pwd_should_be_long".to_vec(),
+            )
+        );
+        assert_eq!(
+            Some(&old_request_username[..]),
+            connection.previous_ice_request_username()
+        );
+
+        // The old candidate pair keeps working until the new one is nominated.
+        let old_request = ice::create_binding_request_packet(
+            &2u128.to_be_bytes(),
+            &old_request_username,
+            &old_pwd,
+            false,
+        );
+        assert_eq!(
+            ice::create_binding_response_packet(
+                &2u128.to_be_bytes(),
+                b"client:server",
+                &old_pwd,
+                false
+            ),
+            connection
+                .handle_ice_binding_request(
+                    wifi_addr,
+                    ice::BindingRequest::parse(&old_request).unwrap(),
+                    now
+                )
+                .unwrap()
+        );
+        assert_eq!(Some(wifi_addr), connection.outgoing_addr());
+
+        // The old password doesn't work with the new username.
+        assert!(matches!(
+            connection.handle_ice_binding_request(
+                cellular_addr,
+                ice::BindingRequest::parse(&ice::create_binding_request_packet(
+                    &3u128.to_be_bytes(),
+                    b"server2:client2",
+                    &old_pwd,
+                    true,
+                ))
+                .unwrap(),
+                now,
+            ),
+            Err(Error::ReceivedIceWithInvalidHmac(_))
+        ));
+
+        // Nominating the new candidate pair switches to it.
+        let actual_response =
+            handle_ice_binding_request(&mut connection, cellular_addr, 4, true, now).unwrap();
+        assert_eq!(
+            ice::create_binding_response_packet(
+                &4u128.to_be_bytes(),
+                b"client2:server2",
+                b"the_new_pwd_should_be_long",
+                true
+            ),
+            actual_response
+        );
+        assert_eq!(Some(cellular_addr), connection.outgoing_addr());
+
+        // After another restart, the original credentials are no longer accepted.
+        assert_eq!(
+            Some(old_request_username.clone()),
+            connection.restart_ice(
+                b"server3:client3".to_vec(),
+                b"client3:server3".to_vec(),
+                b"the_newest_pwd_should_be_long".to_vec(),
+            )
+        );
+        assert_eq!(
+            Err(Error::ReceivedIceWithInvalidUsername(
+                old_request_username.clone()
+            )),
+            connection.handle_ice_binding_request(
+                wifi_addr,
+                ice::BindingRequest::parse(&old_request).unwrap(),
+                now
+            )
+        );
+    }
+
+    #[test]
+    fn test_fail_over_to_surviving_path() {
+        let now = Instant::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        let udp_addr = SocketLocator::Udp("192.0.2.4:5".parse().unwrap());
+        let tcp_addr = SocketLocator::Tcp {
+            id: 1,
+            is_ipv6: false,
+        };
+        let other_udp_addr = SocketLocator::Udp("198.51.100.9:10".parse().unwrap());
+
+        let mut connection = new_connection(now);
+        handle_ice_binding_request(&mut connection, udp_addr, 1, true, now).unwrap();
+        handle_ice_binding_request(&mut connection, tcp_addr, 2, false, now).unwrap();
+        handle_ice_binding_request(&mut connection, other_udp_addr, 3, false, now).unwrap();
+        assert_eq!(Some(udp_addr), connection.outgoing_addr());
+        assert_eq!(
+            PathStats {
+                validated_paths: 3,
+                switches: 0
+            },
+            connection.path_stats()
+        );
+
+        // Nothing happens while the outgoing path is still receiving.
+        let mut packets_to_send = vec![];
+        connection.handle_packet_received_on_path(udp_addr, at(900));
+        handle_ice_binding_request(&mut connection, tcp_addr, 4, false, at(1500)).unwrap();
+        handle_ice_binding_request(&mut connection, other_udp_addr, 5, false, at(1500)).unwrap();
+        connection.tick(&mut packets_to_send, at(1500));
+        assert_eq!(Some(udp_addr), connection.outgoing_addr());
+
+        // Once it stops, we fail over to a surviving path, preferring UDP.
+        connection.tick(&mut packets_to_send, at(1901));
+        assert_eq!(Some(other_udp_addr), connection.outgoing_addr());
+        assert_eq!(Some(AddressType::UdpV4), connection.outgoing_addr_type());
+        assert_eq!(1, connection.path_stats().switches);
+
+        // Nominating a path still switches to it.
+        handle_ice_binding_request(&mut connection, tcp_addr, 6, true, at(2000)).unwrap();
+        assert_eq!(Some(tcp_addr), connection.outgoing_addr());
+        assert_eq!(2, connection.path_stats().switches);
+
+        // But if every other path is silent too, we stay put.
+        connection.tick(&mut packets_to_send, at(5000));
+        assert_eq!(Some(tcp_addr), connection.outgoing_addr());
+    }
+
+    #[test]
+    fn test_fail_over_waits_longer_with_high_rtt() {
+        let now = Instant::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        let udp_addr = SocketLocator::Udp("192.0.2.4:5".parse().unwrap());
+        let other_udp_addr = SocketLocator::Udp("198.51.100.9:10".parse().unwrap());
+
+        let mut connection = new_connection(now);
+        handle_ice_binding_request(&mut connection, udp_addr, 1, true, now).unwrap();
+        handle_ice_binding_request(&mut connection, other_udp_addr, 2, false, now).unwrap();
+        connection.paths.get_mut(&udp_addr).unwrap().rtt = Some(Duration::from_millis(500));
+
+        // With a 500ms RTT, a second of silence isn't enough...
+        let mut packets_to_send = vec![];
+        connection.handle_packet_received_on_path(udp_addr, at(900));
+        handle_ice_binding_request(&mut connection, other_udp_addr, 3, false, at(2500)).unwrap();
+        connection.tick(&mut packets_to_send, at(2500));
+        assert_eq!(Some(udp_addr), connection.outgoing_addr());
+
+        // ...but four RTTs is.
+        connection.tick(&mut packets_to_send, at(2901));
+        assert_eq!(Some(other_udp_addr), connection.outgoing_addr());
+    }
+
+    #[test]
+    fn test_fail_over_follows_client_media() {
+        let now = Instant::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        let wifi_addr = SocketLocator::Udp("192.0.2.4:5".parse().unwrap());
+        let cellular_addr = SocketLocator::Udp("198.51.100.9:10".parse().unwrap());
+        let unvalidated_addr = SocketLocator::Udp("203.0.113.1:15".parse().unwrap());
+
+        let mut connection = new_connection(now);
+        handle_ice_binding_request(&mut connection, wifi_addr, 1, true, now).unwrap();
+        handle_ice_binding_request(&mut connection, cellular_addr, 2, false, now).unwrap();
+        let rtt = connection.rtt();
+
+        // Media on the backup path while the outgoing path is also receiving isn't enough.
+        connection.handle_packet_received_on_path(wifi_addr, at(10));
+        connection.handle_packet_received_on_path(cellular_addr, at(20));
+        assert_eq!(Some(wifi_addr), connection.outgoing_addr());
+
+        // Addresses that haven't passed an ICE check are never used.
+        connection.handle_packet_received_on_path(unvalidated_addr, at(10) + rtt * 2);
+        assert_eq!(Some(wifi_addr), connection.outgoing_addr());
+
+        // But once the outgoing path has been silent for an RTT, we follow the client.
+        connection.handle_packet_received_on_path(cellular_addr, at(11) + rtt);
+        assert_eq!(Some(cellular_addr), connection.outgoing_addr());
+        assert_eq!(
+            PathStats {
+                validated_paths: 2,
+                switches: 1
+            },
+            connection.path_stats()
+        );
+    }
+
+    #[test]
+    fn test_consent_expiration() {
+        let now = Instant::now();
+        let at = |secs| now + Duration::from_secs(secs);
+        let client_addr = SocketLocator::Udp("192.0.2.4:5".parse().unwrap());
+        let backup_addr = SocketLocator::Udp("198.51.100.9:10".parse().unwrap());
+
+        let mut connection = new_connection(now);
+        let (decrypt, encrypt) = new_srtp_keys(0);
+        connection.set_srtp_keys(decrypt, encrypt.clone(), now);
+        handle_ice_binding_request(&mut connection, client_addr, 1, true, now).unwrap();
+
+        // Media keeps the path alive, but doesn't renew consent.
+        let mut packets_to_send = vec![];
+        for secs in 1..30 {
+            connection.handle_packet_received_on_path(client_addr, at(secs));
+            connection.tick(&mut packets_to_send, at(secs));
+        }
+        assert_eq!(Some(client_addr), connection.outgoing_addr());
+        assert!(!connection.consent_lost());
+
+        connection.handle_packet_received_on_path(client_addr, at(30));
+        connection.tick(&mut packets_to_send, at(30));
+        assert_eq!(None, connection.outgoing_addr());
+        assert!(connection.consent_lost());
+
+        // Nothing is sent without consent.
+        let rtp = decrypt_rtp(&new_encrypted_rtp(1, None, &encrypt), &encrypt);
+        let mut rtp_to_send = vec![];
+        connection.send_or_enqueue_rtp(rtp.clone(), &mut rtp_to_send, at(30));
+        assert_eq!(0, rtp_to_send.len());
+        packets_to_send.clear();
+        connection.tick(&mut packets_to_send, at(40));
+        assert_eq!(0, packets_to_send.len());
+
+        // A binding request renews consent, even if it isn't nominated.
+        handle_ice_binding_request(&mut connection, client_addr, 2, false, at(41)).unwrap();
+        assert_eq!(Some(client_addr), connection.outgoing_addr());
+        assert!(!connection.consent_lost());
+        assert_eq!(0, connection.path_stats().switches);
+
+        // With a backup path that still has consent, we fail over instead.
+        handle_ice_binding_request(&mut connection, backup_addr, 3, false, at(70)).unwrap();
+        connection.handle_packet_received_on_path(client_addr, at(71));
+        connection.tick(&mut packets_to_send, at(71));
+        assert_eq!(Some(backup_addr), connection.outgoing_addr());
+        assert!(!connection.consent_lost());
+        assert_eq!(1, connection.path_stats().switches);
+    }
+
     #[test]
     fn test_receive_srtp() {
         let now = Instant::now();
@@ -781,7 +1408,7 @@ mod connection_tests {
         assert_eq!(
             expected_decrypted_rtp.to_owned(),
             connection
-                .handle_rtp_packet(&mut encrypted_rtp.into_serialized(), now)
+                .handle_rtp_packet(&mut encrypted_rtp.into_serialized(), ccfb::Ecn::NotEct, now)
                 .unwrap()
                 .to_owned()
         );
@@ -789,7 +1416,11 @@ mod connection_tests {
         let encrypted_rtp = new_encrypted_rtp(2, None, &encrypt);
         assert_eq!(
             Err(Error::ReceivedInvalidRtp),
-            connection.handle_rtp_packet(&mut encrypted_rtp.into_serialized(), now)
+            connection.handle_rtp_packet(
+                &mut encrypted_rtp.into_serialized(),
+                ccfb::Ecn::NotEct,
+                now
+            )
         );
 
         let encrypted_rtp = new_encrypted_rtx_rtp(5, 2, None, &decrypt);
@@ -797,7 +1428,7 @@ mod connection_tests {
         assert_eq!(
             expected_decrypted_rtp.borrow().to_owned(),
             connection
-                .handle_rtp_packet(&mut encrypted_rtp.into_serialized(), now)
+                .handle_rtp_packet(&mut encrypted_rtp.into_serialized(), ccfb::Ecn::NotEct, now)
                 .unwrap()
                 .to_owned()
         );
@@ -820,6 +1451,7 @@ mod connection_tests {
                     media_send_rate: send_rate,
                     padding_send_rate: send_rate,
                     padding_ssrc: None,
+                    max_video_queue_delay: None,
                 },
                 now,
             );
@@ -868,6 +1500,7 @@ mod connection_tests {
                         media_send_rate: padding_send_rate,
                         padding_send_rate,
                         padding_ssrc,
+                        max_video_queue_delay: None,
                     },
                     now,
                 );
@@ -987,7 +1620,7 @@ mod connection_tests {
             &decrypt.rtcp.salt,
         )
         .unwrap();
-        let result = connection.handle_rtcp_packet(&mut nacks2, now).unwrap();
+        let result = connection.handle_rtcp_packet(&mut nacks2, at(40)).unwrap();
         let mut expected_rtx = unencrypted_rtp.to_rtx(2);
         expected_rtx
             .encrypt_in_place(&encrypt.rtp.key, &encrypt.rtp.salt)
@@ -996,13 +1629,99 @@ mod connection_tests {
         expected_rtx2
             .encrypt_in_place(&encrypt.rtp.key, &encrypt.rtp.salt)
             .unwrap();
+        // The second one is paced.
         assert_eq!(
-            vec![
-                (expected_rtx.into_serialized(), client_addr),
-                (expected_rtx2.into_serialized(), client_addr)
-            ],
+            vec![(expected_rtx.into_serialized(), client_addr)],
             result.outgoing_rtx
         );
+        assert_eq!(
+            expected_rtx2.size(),
+            connection.outgoing_queue_stats().rtx.queued_size
+        );
+        assert_eq!(
+            Some((expected_rtx2.into_serialized(), client_addr)),
+            connection.dequeue_outgoing_rtp(at(60))
+        );
+        assert_eq!(DataSize::ZERO, connection.outgoing_queue_size());
+    }
+
+    #[test]
+    fn test_stale_video_is_not_resent() {
+        let now = Instant::now();
+        let at = |ms| now + Duration::from_millis(ms);
+
+        let mut connection = new_connection(now);
+        let (decrypt, encrypt) = new_srtp_keys(0);
+        connection.set_srtp_keys(decrypt.clone(), encrypt.clone(), now);
+        let client_addr = SocketLocator::Udp("192.0.2.4:5".parse().unwrap());
+        handle_ice_binding_request(&mut connection, client_addr, 1, true, now).unwrap();
+        // About one packet every 70ms
+        connection.configure_congestion_control(
+            googcc::Request {
+                base: DataRate::from_kbps(5),
+                ideal: DataRate::from_kbps(5),
+            },
+            pacer::Config {
+                media_send_rate: DataRate::from_kbps(5),
+                padding_send_rate: DataRate::ZERO,
+                padding_ssrc: None,
+                max_video_queue_delay: Some(Duration::from_millis(100)),
+            },
+            now,
+        );
+
+        let unencrypted_rtps: Vec<rtp::Packet<Vec<u8>>> = (1..=3)
+            .map(|seqnum| decrypt_rtp(&new_encrypted_rtp(seqnum, Some(seqnum), &encrypt), &encrypt))
+            .collect();
+        let mut rtp_to_send = vec![];
+        for unencrypted_rtp in &unencrypted_rtps {
+            connection.send_or_enqueue_rtp(unencrypted_rtp.clone(), &mut rtp_to_send, at(0));
+        }
+        assert_eq!(1, rtp_to_send.len());
+        assert_eq!(2, connection.outgoing_queue_stats().video.queued_packets);
+
+        // Nothing was dequeued before the rest became stale.
+        assert_eq!(None, connection.dequeue_outgoing_rtp(at(200)));
+        assert_eq!(2, connection.outgoing_queue_stats().video.dropped_packets);
+        assert_eq!(DataSize::ZERO, connection.outgoing_queue_size());
+
+        // Only the one that was sent is resent, and a key frame is requested in place of the rest.
+        let mut nacks = rtp::ControlPacket::serialize_and_encrypt(
+            rtp::RTCP_TYPE_GENERIC_FEEDBACK,
+            rtp::RTCP_FORMAT_NACK,
+            RTCP_SENDER_SSRC,
+            rtp::write_nack(
+                unencrypted_rtps[0].ssrc(),
+                unencrypted_rtps.iter().map(|rtp| rtp.seqnum()),
+            ),
+            1,
+            &decrypt.rtcp.key,
+            &decrypt.rtcp.salt,
+        )
+        .unwrap();
+        let result = connection.handle_rtcp_packet(&mut nacks, at(200)).unwrap();
+        assert_eq!(1, result.outgoing_rtx.len());
+        assert_eq!(
+            vec![rtp::KeyFrameRequest {
+                ssrc: unencrypted_rtps[0].ssrc()
+            }],
+            result.incoming_key_frame_requests
+        );
+
+        // The key frame is only requested once.
+        let mut nacks = rtp::ControlPacket::serialize_and_encrypt(
+            rtp::RTCP_TYPE_GENERIC_FEEDBACK,
+            rtp::RTCP_FORMAT_NACK,
+            RTCP_SENDER_SSRC,
+            rtp::write_nack(unencrypted_rtps[0].ssrc(), vec![2, 3].into_iter()),
+            2,
+            &decrypt.rtcp.key,
+            &decrypt.rtcp.salt,
+        )
+        .unwrap();
+        let result = connection.handle_rtcp_packet(&mut nacks, at(300)).unwrap();
+        assert_eq!(0, result.outgoing_rtx.len());
+        assert_eq!(0, result.incoming_key_frame_requests.len());
     }
 
     #[test]
@@ -1017,6 +1736,7 @@ mod connection_tests {
         connection
             .handle_rtp_packet(
                 &mut new_encrypted_rtp(1, Some(101), &decrypt).into_serialized(),
+                ccfb::Ecn::NotEct,
                 at(1),
             )
             .unwrap();
@@ -1024,6 +1744,7 @@ mod connection_tests {
         connection
             .handle_rtp_packet(
                 &mut new_encrypted_rtp(3, Some(103), &decrypt).into_serialized(),
+                ccfb::Ecn::NotEct,
                 at(3),
             )
             .unwrap();
@@ -1038,9 +1759,9 @@ mod connection_tests {
         handle_ice_binding_request(&mut connection, client_addr, 1, true, at(5)).unwrap();
         assert_eq!(Some(client_addr), connection.outgoing_addr());
 
-        // Now we can send ACKs, NACKs, and receiver reports.
+        // Now we can send ACKs, NACKs, receiver reports, and receiver reference times.
         connection.tick(&mut packets_to_send, at(6));
-        assert_eq!(3, packets_to_send.len());
+        assert_eq!(4, packets_to_send.len());
 
         let expected_acks = vec![
             (101u64, tcc::RemoteInstant::from_millis(1)),
@@ -1064,10 +1785,10 @@ mod connection_tests {
 
         // We resend NACKs but not acks or receiver reports.
         connection.tick(&mut packets_to_send, at(1000));
-        assert_eq!(4, packets_to_send.len());
-        assert_eq!(client_addr, packets_to_send[3].1);
+        assert_eq!(5, packets_to_send.len());
+        assert_eq!(client_addr, packets_to_send[4].1);
         let (actual_acks, actual_nacks) =
-            decrypt_rtcp(&mut packets_to_send[3].0, &encrypt).unwrap();
+            decrypt_rtcp(&mut packets_to_send[4].0, &encrypt).unwrap();
         assert_eq!(expected_nacks, actual_nacks);
         assert_eq!(0, actual_acks.len());
 
@@ -1075,11 +1796,12 @@ mod connection_tests {
         connection
             .handle_rtp_packet(
                 &mut new_encrypted_rtp(2, Some(102), &decrypt).into_serialized(),
+                ccfb::Ecn::NotEct,
                 at(10002),
             )
             .unwrap();
         connection.tick(&mut packets_to_send, at(1000));
-        assert_eq!(4, packets_to_send.len());
+        assert_eq!(5, packets_to_send.len());
     }
 
     #[test]
@@ -1100,6 +1822,7 @@ mod connection_tests {
             &mut encrypted_rtcp,
             &encrypt.rtcp.key,
             &encrypt.rtcp.salt,
+            &mut HashMap::new(),
         )
         .unwrap();
 
@@ -1159,7 +1882,7 @@ mod connection_tests {
                 RTCP_SENDER_SSRC,
                 tcc::write_feedback(10000, &mut 0, now, vec![(seqnum, received)].into_iter())
                     .collect::<Vec<_>>(),
-                1,
+                seqnum as u32,
                 &decrypt.rtcp.key,
                 &decrypt.rtcp.salt,
             )
@@ -0,0 +1,447 @@
+//
+// Copyright 2023 Signal Messenger, LLC
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Reads the parts of the dependency descriptor RTP header extension that we need to forward
+//! video without looking at the payload, which lets clients encrypt the payload end-to-end
+//! (for example, with SFrame).
+//!
+//! See https://aomediacodec.github.io/av1-rtp-spec/#dependency-descriptor-rtp-header-extension
+
+use calling_common::{expand_truncated_counter, PixelSize};
+use thiserror::Error;
+
+pub type TruncatedFrameNumber = u16;
+pub type FullFrameNumber = u64;
+
+const MANDATORY_FIELDS_LEN: usize = 3;
+const FRAME_NUMBER_RANGE: std::ops::Range<usize> = 1..3;
+
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct ParsedDescriptor {
+    pub start_of_frame: bool,
+    pub end_of_frame: bool,
+
+    /// Incremented with each video frame.
+    /// Used to indicate frame order and gaps, and referred to (relatively) by frame dependencies.
+    /// Must be rewritten when forwarding simulcast.
+    pub frame_number: TruncatedFrameNumber,
+
+    /// True for the first packet of a frame that carries a template dependency structure.
+    /// Senders only attach a new structure to key frames because it resets the receiver's
+    /// view of the dependencies.
+    pub is_key_frame: bool,
+
+    /// The render resolution of the highest spatial layer, if the structure includes it.
+    /// Only included with the template dependency structure.
+    pub resolution: Option<PixelSize>,
+}
+
+#[derive(Error, Eq, PartialEq, Debug, Copy, Clone)]
+pub enum DependencyDescriptorError {
+    #[error("The dependency descriptor ended early.")]
+    TooShort,
+}
+
+/// Reads big-endian bit fields, the way the spec writes f(n).
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    bit_index: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            bit_index: 0,
+        }
+    }
+
+    fn read_bit(&mut self) -> Result<bool, DependencyDescriptorError> {
+        let byte = self
+            .bytes
+            .get(self.bit_index / 8)
+            .ok_or(DependencyDescriptorError::TooShort)?;
+        let bit = (byte >> (7 - (self.bit_index % 8))) & 1;
+        self.bit_index += 1;
+        Ok(bit == 1)
+    }
+
+    /// f(n)
+    fn read_bits(&mut self, n: usize) -> Result<u32, DependencyDescriptorError> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | (self.read_bit()? as u32);
+        }
+        Ok(value)
+    }
+
+    /// ns(n): a non-symmetric unsigned value in the range 0..n.
+    fn read_non_symmetric(&mut self, n: u32) -> Result<u32, DependencyDescriptorError> {
+        let width = u32::BITS - n.leading_zeros();
+        let m = (1 << width) - n;
+        let v = self.read_bits(width.saturating_sub(1) as usize)?;
+        if v < m {
+            return Ok(v);
+        }
+        let extra_bit = self.read_bits(1)?;
+        Ok((v << 1) - m + extra_bit)
+    }
+}
+
+impl ParsedDescriptor {
+    pub fn read(descriptor: &[u8]) -> Result<Self, DependencyDescriptorError> {
+        let mut reader = BitReader::new(descriptor);
+        let start_of_frame = reader.read_bit()?;
+        let end_of_frame = reader.read_bit()?;
+        let _frame_dependency_template_id = reader.read_bits(6)?;
+        let frame_number = reader.read_bits(16)? as TruncatedFrameNumber;
+
+        let mut parsed = Self {
+            start_of_frame,
+            end_of_frame,
+            frame_number,
+            ..Default::default()
+        };
+
+        if descriptor.len() > MANDATORY_FIELDS_LEN {
+            let template_dependency_structure_present = reader.read_bit()?;
+            let _active_decode_targets_present = reader.read_bit()?;
+            let _custom_dtis = reader.read_bit()?;
+            let _custom_fdiffs = reader.read_bit()?;
+            let _custom_chains = reader.read_bit()?;
+            // The rest of the extended fields (the active decode targets and the custom values)
+            // come after the structure, and we don't need them.
+            if template_dependency_structure_present {
+                parsed.is_key_frame = start_of_frame;
+                parsed.resolution = read_template_dependency_structure(&mut reader)?;
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Returns the render resolution of the highest spatial layer, if present.
+fn read_template_dependency_structure(
+    reader: &mut BitReader,
+) -> Result<Option<PixelSize>, DependencyDescriptorError> {
+    let _template_id_offset = reader.read_bits(6)?;
+    let decode_target_count = reader.read_bits(5)? + 1;
+
+    // template_layers()
+    let mut template_count = 0;
+    let mut max_spatial_id = 0;
+    loop {
+        template_count += 1;
+        match reader.read_bits(2)? {
+            // Same spatial and temporal layer, or the next temporal layer
+            0 | 1 => {}
+            // The next spatial layer
+            2 => max_spatial_id += 1,
+            _ => break,
+        }
+    }
+
+    // template_dtis()
+    for _ in 0..(template_count * decode_target_count) {
+        let _dti = reader.read_bits(2)?;
+    }
+
+    // template_fdiffs()
+    for _ in 0..template_count {
+        while reader.read_bit()? {
+            let _fdiff_minus_one = reader.read_bits(4)?;
+        }
+    }
+
+    // template_chains()
+    let chain_count = reader.read_non_symmetric(decode_target_count + 1)?;
+    if chain_count > 0 {
+        for _ in 0..decode_target_count {
+            let _decode_target_protected_by = reader.read_non_symmetric(chain_count)?;
+        }
+        for _ in 0..(template_count * chain_count) {
+            let _template_chain_fdiff = reader.read_bits(4)?;
+        }
+    }
+
+    // decode_target_layers() doesn't read anything.
+
+    let resolutions_present = reader.read_bit()?;
+    if !resolutions_present {
+        return Ok(None);
+    }
+    let mut resolution = PixelSize::default();
+    for _ in 0..=max_spatial_id {
+        resolution = PixelSize {
+            width: (reader.read_bits(16)? as u16).saturating_add(1),
+            height: (reader.read_bits(16)? as u16).saturating_add(1),
+        };
+    }
+    Ok(Some(resolution))
+}
+
+// This assumes the descriptor has at least the mandatory fields,
+// which is true if it was read successfully.
+pub fn modify_frame_number(descriptor: &mut [u8], frame_number: TruncatedFrameNumber) {
+    descriptor[FRAME_NUMBER_RANGE].copy_from_slice(&frame_number.to_be_bytes());
+}
+
+pub fn expand_frame_number(
+    truncated: TruncatedFrameNumber,
+    max: &mut FullFrameNumber,
+) -> FullFrameNumber {
+    expand_truncated_counter(truncated, max, 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct BitWriter {
+        bits: Vec<bool>,
+    }
+
+    impl BitWriter {
+        fn write_bits(&mut self, value: u32, n: usize) -> &mut Self {
+            for i in (0..n).rev() {
+                self.bits.push((value >> i) & 1 == 1);
+            }
+            self
+        }
+
+        fn into_bytes(self) -> Vec<u8> {
+            self.bits
+                .chunks(8)
+                .map(|chunk| {
+                    chunk
+                        .iter()
+                        .enumerate()
+                        .fold(0u8, |byte, (i, bit)| byte | ((*bit as u8) << (7 - i)))
+                })
+                .collect()
+        }
+    }
+
+    fn write_mandatory_fields(
+        writer: &mut BitWriter,
+        start_of_frame: bool,
+        end_of_frame: bool,
+        frame_number: u16,
+    ) {
+        writer
+            .write_bits(start_of_frame as u32, 1)
+            .write_bits(end_of_frame as u32, 1)
+            .write_bits(0, 6)
+            .write_bits(frame_number as u32, 16);
+    }
+
+    #[test]
+    fn mandatory_fields_only() {
+        let mut writer = BitWriter::default();
+        write_mandatory_fields(&mut writer, true, false, 0x1234);
+        assert_eq!(
+            Ok(ParsedDescriptor {
+                start_of_frame: true,
+                end_of_frame: false,
+                frame_number: 0x1234,
+                is_key_frame: false,
+                resolution: None,
+            }),
+            ParsedDescriptor::read(&writer.into_bytes())
+        );
+    }
+
+    #[test]
+    fn too_short() {
+        assert_eq!(
+            Err(DependencyDescriptorError::TooShort),
+            ParsedDescriptor::read(&[])
+        );
+        assert_eq!(
+            Err(DependencyDescriptorError::TooShort),
+            ParsedDescriptor::read(&[0b1100_0000, 0x12])
+        );
+
+        // Claims a structure but doesn't have one.
+        let mut writer = BitWriter::default();
+        write_mandatory_fields(&mut writer, true, true, 1);
+        writer.write_bits(0b10000, 5);
+        assert_eq!(
+            Err(DependencyDescriptorError::TooShort),
+            ParsedDescriptor::read(&writer.into_bytes())
+        );
+    }
+
+    #[test]
+    fn extended_fields_without_structure() {
+        let mut writer = BitWriter::default();
+        write_mandatory_fields(&mut writer, true, true, 7);
+        // No structure, but active decode targets and custom values that we skip.
+        writer.write_bits(0b01110, 5).write_bits(0xFFFF, 16);
+        assert_eq!(
+            Ok(ParsedDescriptor {
+                start_of_frame: true,
+                end_of_frame: true,
+                frame_number: 7,
+                is_key_frame: false,
+                resolution: None,
+            }),
+            ParsedDescriptor::read(&writer.into_bytes())
+        );
+    }
+
+    #[test]
+    fn single_layer_structure() {
+        let mut writer = BitWriter::default();
+        write_mandatory_fields(&mut writer, true, false, 100);
+        writer
+            .write_bits(0b10000, 5)
+            // template_id_offset and dt_cnt_minus_one
+            .write_bits(0, 6)
+            .write_bits(0, 5)
+            // One template
+            .write_bits(3, 2)
+            // Its DTI
+            .write_bits(2, 2)
+            // No fdiffs
+            .write_bits(0, 1)
+            // chain_cnt = ns(2) = 0
+            .write_bits(0, 1)
+            // Resolution
+            .write_bits(1, 1)
+            .write_bits(639, 16)
+            .write_bits(479, 16);
+        assert_eq!(
+            Ok(ParsedDescriptor {
+                start_of_frame: true,
+                end_of_frame: false,
+                frame_number: 100,
+                is_key_frame: true,
+                resolution: Some(PixelSize {
+                    width: 640,
+                    height: 480
+                }),
+            }),
+            ParsedDescriptor::read(&writer.into_bytes())
+        );
+    }
+
+    #[test]
+    fn structure_in_a_later_packet_of_the_frame_is_not_a_key_frame() {
+        let mut writer = BitWriter::default();
+        write_mandatory_fields(&mut writer, false, true, 100);
+        writer
+            .write_bits(0b10000, 5)
+            .write_bits(0, 6)
+            .write_bits(0, 5)
+            .write_bits(3, 2)
+            .write_bits(2, 2)
+            .write_bits(0, 1)
+            .write_bits(0, 1)
+            // No resolution
+            .write_bits(0, 1);
+        let parsed = ParsedDescriptor::read(&writer.into_bytes()).unwrap();
+        assert!(!parsed.is_key_frame);
+        assert_eq!(None, parsed.resolution);
+    }
+
+    #[test]
+    fn multiple_layer_structure() {
+        let mut writer = BitWriter::default();
+        write_mandatory_fields(&mut writer, true, true, 0xFFFF);
+        writer
+            .write_bits(0b10000, 5)
+            .write_bits(5, 6)
+            // Two decode targets
+            .write_bits(1, 5)
+            // Templates: (S0, T0), (S0, T1), (S1, T0)
+            .write_bits(1, 2)
+            .write_bits(2, 2)
+            .write_bits(3, 2)
+            // DTIs for 3 templates x 2 decode targets
+            .write_bits(0b10_10_00_01_10_11, 12)
+            // fdiffs: none, [1], [1, 2]
+            .write_bits(0, 1)
+            .write_bits(1, 1)
+            .write_bits(0, 4)
+            .write_bits(0, 1)
+            .write_bits(1, 1)
+            .write_bits(0, 4)
+            .write_bits(1, 1)
+            .write_bits(1, 4)
+            .write_bits(0, 1)
+            // chain_cnt = ns(3) = 2, which is written as 0b11
+            .write_bits(0b11, 2)
+            // decode_target_protected_by = ns(2) = 0, 1
+            .write_bits(0, 1)
+            .write_bits(1, 1)
+            // Chain fdiffs for 3 templates x 2 chains
+            .write_bits(0, 24)
+            // Resolutions for S0 and S1
+            .write_bits(1, 1)
+            .write_bits(319, 16)
+            .write_bits(179, 16)
+            .write_bits(639, 16)
+            .write_bits(359, 16);
+        assert_eq!(
+            Ok(ParsedDescriptor {
+                start_of_frame: true,
+                end_of_frame: true,
+                frame_number: 0xFFFF,
+                is_key_frame: true,
+                resolution: Some(PixelSize {
+                    width: 640,
+                    height: 360
+                }),
+            }),
+            ParsedDescriptor::read(&writer.into_bytes())
+        );
+    }
+
+    #[test]
+    fn non_symmetric() {
+        fn read(bits: u32, n: usize, range: u32) -> u32 {
+            let mut writer = BitWriter::default();
+            writer.write_bits(bits, n);
+            BitReader::new(&writer.into_bytes())
+                .read_non_symmetric(range)
+                .unwrap()
+        }
+        // With only one possible value, nothing is read.
+        assert_eq!(0, read(0b1, 1, 1));
+        assert_eq!(0, read(0b0, 1, 2));
+        assert_eq!(1, read(0b1, 1, 2));
+        // With 3 possible values, 0 takes 1 bit and the others take 2.
+        assert_eq!(0, read(0b0, 1, 3));
+        assert_eq!(1, read(0b10, 2, 3));
+        assert_eq!(2, read(0b11, 2, 3));
+        // With 5 possible values, 0..3 take 2 bits and the others take 3.
+        assert_eq!(2, read(0b10, 2, 5));
+        assert_eq!(3, read(0b110, 3, 5));
+        assert_eq!(4, read(0b111, 3, 5));
+    }
+
+    #[test]
+    fn modify() {
+        let mut writer = BitWriter::default();
+        write_mandatory_fields(&mut writer, true, true, 0x1234);
+        let mut descriptor = writer.into_bytes();
+        modify_frame_number(&mut descriptor, 0xABCD);
+        assert_eq!(vec![0b1100_0000, 0xAB, 0xCD], descriptor);
+        assert_eq!(
+            0xABCD,
+            ParsedDescriptor::read(&descriptor).unwrap().frame_number
+        );
+    }
+
+    #[test]
+    fn expand() {
+        let mut max = 0;
+        assert_eq!(0xFFFF, expand_frame_number(0xFFFF, &mut max));
+        assert_eq!(0x1_0001, expand_frame_number(1, &mut max));
+        assert_eq!(0xFFFE, expand_frame_number(0xFFFE, &mut max));
+    }
+}
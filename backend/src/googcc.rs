@@ -17,7 +17,7 @@ use futures::{
     FutureExt,
 };
 
-use crate::transportcc::Ack;
+use crate::transportcc::{Ack, Loss};
 
 mod ack_rates;
 use ack_rates::*;
@@ -28,6 +28,12 @@ use delay_directions::*;
 mod feedback_rtts;
 use feedback_rtts::*;
 
+mod loss_estimates;
+use loss_estimates::*;
+
+mod probe_results;
+use probe_results::*;
+
 mod stream;
 use stream::StreamExt as OurStreamExt;
 
@@ -84,6 +90,9 @@ pub struct CongestionController {
     acked_rates: Pin<Box<dyn Stream<Item = DataRate> + Send>>,
     delay_directions: Pin<Box<dyn Stream<Item = (Instant, DelayDirection)> + Send>>,
     calculator: TargetCalculator,
+    loss_estimator: LossBasedEstimator,
+    probe_results: ProbeResultCalculator,
+    last_probed: Option<Instant>,
 }
 
 impl CongestionController {
@@ -112,6 +121,14 @@ impl CongestionController {
         //                             |          |        |                                   |
         //                        +----v----------v--------v----+                              |
         //                        | calculate_target_send_rates <------------------------------+
+        //                        +--------------+--------------+
+        //                                       |
+        //                        +--------------v--------------+
+        //                        | raise by probe_results      <---- Acks from probe clusters
+        //                        +--------------+--------------+
+        //                                       |
+        //                        +--------------v--------------+
+        //                        |   limit by loss_estimator   <---- Acks and Losses
         //                        +-----------------------------+
         let (acks_sender1, ack_reports1) = unbounded_channel_that_must_not_fail();
         let (acks_sender2, ack_reports2) = unbounded_channel_that_must_not_fail();
@@ -132,6 +149,9 @@ impl CongestionController {
             acked_rates: Box::pin(acked_rates),
             delay_directions: Box::pin(delay_directions),
             calculator,
+            loss_estimator: LossBasedEstimator::new(),
+            probe_results: ProbeResultCalculator::new(),
+            last_probed: None,
         }
     }
 
@@ -139,7 +159,79 @@ impl CongestionController {
         self.current_request = Some(request);
     }
 
-    pub fn recalculate_target_send_rate(&mut self, mut acks: Vec<Ack>) -> Option<DataRate> {
+    /// `late_ack_count` is the number of the acks for packets that earlier feedback reported
+    /// as lost, which the loss-based estimator stops counting as lost.
+    /// `congestion_experienced_count` is the number of the acks that were ECN-CE marked,
+    /// which are treated like losses by the loss-based estimator.
+    pub fn recalculate_target_send_rate(
+        &mut self,
+        acks: Vec<Ack>,
+        losses: &[Loss],
+        late_ack_count: usize,
+        congestion_experienced_count: usize,
+        now: Instant,
+    ) -> Option<DataRate> {
+        if acks.is_empty() && losses.is_empty() {
+            return None;
+        }
+
+        let acks_len = acks.len();
+        let congestion_experienced_count = min(congestion_experienced_count, acks_len);
+        let received_count = acks_len - congestion_experienced_count;
+        let probe_result = self.probe_results.update(&acks, now);
+        let delay_based_target_send_rate = self.recalculate_delay_based_target_send_rate(acks);
+        let probed_target_send_rate = probe_result
+            .and_then(|probe_result| self.calculator.apply_probe_result(probe_result, now));
+        self.loss_estimator.update(
+            received_count,
+            losses.len() + congestion_experienced_count,
+            min(late_ack_count, acks_len),
+            self.calculator.target_send_rate,
+            self.calculator.rtt,
+            now,
+        );
+        self.calculator
+            .apply_limit(self.loss_estimator.limit(), now)
+            .or(probed_target_send_rate)
+            .or(delay_based_target_send_rate)
+    }
+
+    /// Returns the rates to send probe clusters at (see Pacer::start_probe_cluster),
+    /// if it's a good time to probe for more bandwidth.
+    /// We probe when the ideal send rate is above the target send rate,
+    /// but not when there's loss or if we probed recently.
+    pub fn probe_send_rates(&mut self, now: Instant) -> Vec<DataRate> {
+        const PROBE_MULTIPLIERS: [f64; 2] = [2.0, 3.0];
+        const MIN_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+        let ideal_send_rate = self
+            .current_request
+            .as_ref()
+            .unwrap_or(&self.calculator.requested)
+            .ideal
+            .min(self.calculator.config.max_target_send_rate);
+        let target_send_rate = self.calculator.target_send_rate;
+        if ideal_send_rate <= target_send_rate || self.loss_estimator.limit().is_some() {
+            return vec![];
+        }
+        if let Some(last_probed) = self.last_probed {
+            if now < last_probed + MIN_PROBE_INTERVAL {
+                return vec![];
+            }
+        }
+        self.last_probed = Some(now);
+
+        let mut probe_send_rates: Vec<DataRate> = vec![];
+        for multiplier in PROBE_MULTIPLIERS {
+            let probe_send_rate = min(target_send_rate * multiplier, ideal_send_rate);
+            if probe_send_rates.last() != Some(&probe_send_rate) {
+                probe_send_rates.push(probe_send_rate);
+            }
+        }
+        probe_send_rates
+    }
+
+    fn recalculate_delay_based_target_send_rate(&mut self, mut acks: Vec<Ack>) -> Option<DataRate> {
         if acks.is_empty() {
             return None;
         }
@@ -174,6 +266,14 @@ impl CongestionController {
     pub fn rtt(&self) -> Duration {
         self.calculator.rtt
     }
+
+    /// RTTs from RTCP reports are much less frequent than those from transport-cc feedback,
+    /// so they are only used until we have one from feedback.
+    pub fn remember_reported_rtt(&mut self, rtt: Duration) {
+        if !self.calculator.rtt_from_feedback {
+            self.calculator.rtt = rtt;
+        }
+    }
 }
 
 struct TargetCalculator {
@@ -183,6 +283,7 @@ struct TargetCalculator {
     previous_direction: Option<DelayDirection>,
     requested: Request,
     rtt: Duration,
+    rtt_from_feedback: bool,
     target_send_rate: DataRate,
     target_send_rate_updated: Instant,
 }
@@ -200,6 +301,7 @@ impl TargetCalculator {
                 ideal: config.max_target_send_rate,
             },
             rtt: Duration::from_millis(100),
+            rtt_from_feedback: false,
             target_send_rate: config.initial_target_send_rate,
             target_send_rate_updated: start_time,
             config,
@@ -224,6 +326,7 @@ impl TargetCalculator {
 
         if let Some(rtt) = rtt {
             self.rtt = rtt;
+            self.rtt_from_feedback = true;
         }
 
         if let Some(acked_rate) = acked_rate {
@@ -368,6 +471,30 @@ impl TargetCalculator {
         }
         None
     }
+
+    // Returns the new target send rate if the probe result was higher than it.
+    fn apply_probe_result(&mut self, probe_result: DataRate, now: Instant) -> Option<DataRate> {
+        let probe_result = self.config.clamp_target_send_rate(probe_result);
+        if probe_result > self.target_send_rate {
+            self.target_send_rate = probe_result;
+            self.target_send_rate_updated = now;
+            Some(self.target_send_rate)
+        } else {
+            None
+        }
+    }
+
+    // Returns the new target send rate if it had to be lowered to fit under the limit.
+    fn apply_limit(&mut self, limit: Option<DataRate>, now: Instant) -> Option<DataRate> {
+        let limit = self.config.clamp_target_send_rate(limit?);
+        if self.target_send_rate > limit {
+            self.target_send_rate = limit;
+            self.target_send_rate_updated = now;
+            Some(self.target_send_rate)
+        } else {
+            None
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1362,6 +1489,185 @@ mod rate_averager_tests {
     }
 }
 
+#[cfg(test)]
+mod probe_tests {
+    use super::*;
+    use crate::transportcc::RemoteInstant;
+
+    fn request_ideal(controller: &mut CongestionController, ideal_kbps: u64) {
+        controller.request(Request {
+            base: DataRate::ZERO,
+            ideal: DataRate::from_kbps(ideal_kbps),
+        });
+    }
+
+    fn probe_send_rates_kbps(controller: &mut CongestionController, now: Instant) -> Vec<u64> {
+        controller
+            .probe_send_rates(now)
+            .iter()
+            .map(|rate| rate.as_kbps())
+            .collect()
+    }
+
+    #[test]
+    fn probe_send_rates() {
+        let now = Instant::now();
+        let at = |secs| now + Duration::from_secs(secs);
+        let mut controller = CongestionController::new(Config::default(), now);
+
+        // Don't probe if we're already sending as much as we want to.
+        request_ideal(&mut controller, 500);
+        assert_eq!(
+            Vec::<u64>::new(),
+            probe_send_rates_kbps(&mut controller, at(0))
+        );
+
+        // Probe at 2x and 3x the target send rate.
+        request_ideal(&mut controller, 5000);
+        assert_eq!(
+            vec![1600, 2400],
+            probe_send_rates_kbps(&mut controller, at(0))
+        );
+        // But not again for a while.
+        assert_eq!(
+            Vec::<u64>::new(),
+            probe_send_rates_kbps(&mut controller, at(4))
+        );
+
+        // Don't probe beyond the ideal send rate.
+        request_ideal(&mut controller, 2000);
+        assert_eq!(
+            vec![1600, 2000],
+            probe_send_rates_kbps(&mut controller, at(5))
+        );
+        request_ideal(&mut controller, 1000);
+        assert_eq!(vec![1000], probe_send_rates_kbps(&mut controller, at(10)));
+    }
+
+    #[test]
+    fn probe_result_raises_target_send_rate() {
+        let now = Instant::now();
+        let mut controller = CongestionController::new(Config::default(), now);
+        request_ideal(&mut controller, 5000);
+
+        // 1200 bytes every 4ms is 2.4mbps
+        let acks = (0..10)
+            .map(|i| Ack {
+                size: DataSize::from_bytes(1200),
+                departure: now + Duration::from_millis(4 * i),
+                arrival: RemoteInstant::from_millis(100 + 4 * i),
+                feedback_arrival: now + Duration::from_millis(200),
+                probe_cluster_id: Some(1),
+            })
+            .collect();
+        assert_eq!(
+            Some(DataRate::from_kbps(2400)),
+            controller.recalculate_target_send_rate(
+                acks,
+                &[],
+                0,
+                0,
+                now + Duration::from_millis(200)
+            )
+        );
+        assert_eq!(
+            vec![4800, 5000],
+            probe_send_rates_kbps(&mut controller, now + Duration::from_secs(1))
+        );
+    }
+}
+
+#[cfg(test)]
+mod loss_tests {
+    use super::*;
+    use crate::transportcc::RemoteInstant;
+
+    // Packets of 1200 bytes sent every 10ms which arrive 50ms later.
+    fn acks(seqnums: std::ops::Range<u64>, feedback_arrival: Instant, start: Instant) -> Vec<Ack> {
+        seqnums
+            .map(|i| Ack {
+                size: DataSize::from_bytes(1200),
+                departure: start + Duration::from_millis(10 * i),
+                arrival: RemoteInstant::from_millis(50 + 10 * i),
+                feedback_arrival,
+                probe_cluster_id: None,
+            })
+            .collect()
+    }
+
+    fn losses(
+        seqnums: std::ops::Range<u64>,
+        feedback_arrival: Instant,
+        start: Instant,
+    ) -> Vec<Loss> {
+        seqnums
+            .map(|i| Loss {
+                size: DataSize::from_bytes(1200),
+                departure: start + Duration::from_millis(10 * i),
+                feedback_arrival,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn losses_limit_target_send_rate() {
+        let now = Instant::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        let mut controller = CongestionController::new(Config::default(), now);
+
+        // 20% loss cuts the target send rate by 10%.
+        assert_eq!(
+            Some(720),
+            controller
+                .recalculate_target_send_rate(
+                    acks(0..16, at(200), now),
+                    &losses(16..20, at(200), now),
+                    0,
+                    0,
+                    at(200)
+                )
+                .map(|rate| rate.as_kbps())
+        );
+        assert_eq!(
+            Some(720),
+            controller.loss_estimator.limit().map(|rate| rate.as_kbps())
+        );
+
+        // ECN-CE marks count as losses too.
+        let mut controller = CongestionController::new(Config::default(), now);
+        assert_eq!(
+            Some(720),
+            controller
+                .recalculate_target_send_rate(acks(0..20, at(250), now), &[], 0, 4, at(250))
+                .map(|rate| rate.as_kbps())
+        );
+    }
+
+    #[test]
+    fn late_acks_are_not_losses() {
+        let now = Instant::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        let mut controller = CongestionController::new(Config::default(), now);
+
+        // Too few packets to decide anything yet.
+        controller.recalculate_target_send_rate(
+            acks(0..10, at(150), now),
+            &losses(10..15, at(150), now),
+            0,
+            0,
+            at(150),
+        );
+        assert_eq!(None, controller.loss_estimator.limit());
+
+        // The "lost" packets arrived after all, so there's no loss.
+        let mut late_and_new_acks = acks(10..15, at(250), now);
+        late_and_new_acks.extend(acks(15..20, at(250), now));
+        controller.recalculate_target_send_rate(late_and_new_acks, &[], 5, 0, at(250));
+        assert_eq!(None, controller.loss_estimator.limit());
+        assert!(controller.calculator.target_send_rate >= DataRate::from_kbps(800));
+    }
+}
+
 fn unbounded_channel_that_must_not_fail<T>() -> (Sender<T>, Receiver<T>) {
     let (sender, receiver) = futures::channel::mpsc::unbounded();
     (Sender(sender), Receiver(receiver))
@@ -79,6 +79,7 @@ mod accumulate_acked_sizes_tests {
             departure: start_time,
             arrival: RemoteInstant::from_millis(duration),
             feedback_arrival: start_time,
+            probe_cluster_id: None,
         }))
     }
 
@@ -117,6 +117,7 @@ mod accumulate_ack_groups_tests {
             departure,
             arrival,
             feedback_arrival: departure,
+            probe_cluster_id: None,
         }))
     }
 
@@ -506,6 +507,7 @@ fn acks_at_regular_intervals(
             arrival,
             feedback_arrival: departure + RTT_FOR_ACKS_AT_REGULAR_INTERVALS,
             size: DataSize::from_bytes(1200),
+            probe_cluster_id: None,
         };
         departure += departure_interval;
         arrival += arrival_interval;
@@ -56,6 +56,7 @@ mod tests {
                 departure: start_time,
                 arrival: RemoteInstant::from_millis(0),
                 feedback_arrival: start_time + Duration::from_millis(*rtt),
+                probe_cluster_id: None,
             })
             .collect()
     }
@@ -128,6 +129,7 @@ mod tests {
             departure: start_time,
             arrival: RemoteInstant::from_millis(0),
             feedback_arrival: start_time - Duration::from_millis(1),
+            probe_cluster_id: None,
         }];
 
         let stream = estimate_feedback_rtts(stream! {
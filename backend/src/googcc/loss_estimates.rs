@@ -0,0 +1,288 @@
+//
+// Copyright 2023 Signal Messenger, LLC
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Loss-based bandwidth estimation, similar to libwebrtc's SendSideBandwidthEstimation.
+//!
+//! Some links (such as policed ones) drop packets without the delay growing first,
+//! so the delay-based estimate never decreases.  This caps the target send rate while
+//! there is sustained loss and then slowly lifts the cap once the loss goes away.
+
+use std::{
+    cmp::{max, min},
+    collections::VecDeque,
+};
+
+use calling_common::{DataRate, Duration, Instant};
+
+// TODO: Consider making these configurable
+/// How far back to look when calculating the loss ratio.
+const LOSS_WINDOW: Duration = Duration::from_secs(1);
+/// Don't make a decision based on fewer packets than this.
+const MIN_PACKETS_FOR_LOSS_RATIO: usize = 20;
+/// Below this, the limit is increased.
+const LOW_LOSS_RATIO: f64 = 0.02;
+/// Above this, the limit is decreased.  In between, it's held.
+const HIGH_LOSS_RATIO: f64 = 0.10;
+/// The limit is decreased at most once per this (plus the RTT).
+const MIN_DECREASE_INTERVAL: Duration = Duration::from_millis(300);
+const MULTIPLICATIVE_INCREASE_PER_SECOND: f64 = 0.08;
+const MIN_MULTIPLICATIVE_INCREASE: DataRate = DataRate::from_kbps(1);
+
+pub struct LossBasedEstimator {
+    // (feedback arrival, received count, lost count)
+    reports: VecDeque<(Instant, usize, usize)>,
+    // None means there isn't any loss to limit the target send rate.
+    limit: Option<DataRate>,
+    limit_increased: Option<Instant>,
+    limit_decreased: Option<Instant>,
+}
+
+impl LossBasedEstimator {
+    pub fn new() -> Self {
+        Self {
+            reports: VecDeque::new(),
+            limit: None,
+            limit_increased: None,
+            limit_decreased: None,
+        }
+    }
+
+    pub fn limit(&self) -> Option<DataRate> {
+        self.limit
+    }
+
+    /// Remembers the counts of packets reported as received and lost by one batch of
+    /// feedback and updates the limit.
+    /// `late_received_count` is how many of the received packets were reported lost by
+    /// earlier feedback; they're taken back out of the earlier lost counts.
+    /// Returns the new limit if it changed (including to None).
+    pub fn update(
+        &mut self,
+        received_count: usize,
+        lost_count: usize,
+        late_received_count: usize,
+        target_send_rate: DataRate,
+        rtt: Duration,
+        now: Instant,
+    ) -> Option<Option<DataRate>> {
+        let mut not_lost_count = late_received_count;
+        for (_, _, earlier_lost_count) in self.reports.iter_mut().rev() {
+            let count = min(*earlier_lost_count, not_lost_count);
+            *earlier_lost_count -= count;
+            not_lost_count -= count;
+        }
+        self.reports.push_back((now, received_count, lost_count));
+        while let Some((feedback_arrival, _, _)) = self.reports.front() {
+            if now.saturating_duration_since(*feedback_arrival) > LOSS_WINDOW {
+                self.reports.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let (received_count, lost_count) =
+            self.reports
+                .iter()
+                .fold((0, 0), |(received_sum, lost_sum), (_, received, lost)| {
+                    (received_sum + received, lost_sum + lost)
+                });
+        let total_count = received_count + lost_count;
+        if total_count < MIN_PACKETS_FOR_LOSS_RATIO {
+            return None;
+        }
+        let loss_ratio = lost_count as f64 / total_count as f64;
+
+        if loss_ratio > HIGH_LOSS_RATIO {
+            if let Some(limit_decreased) = self.limit_decreased {
+                if now < limit_decreased + MIN_DECREASE_INTERVAL + rtt {
+                    // Wait for the previous decrease to take effect.
+                    return None;
+                }
+            }
+            let current_rate = match self.limit {
+                Some(limit) => limit.min(target_send_rate),
+                None => target_send_rate,
+            };
+            let decreased_limit = current_rate * (1.0 - (0.5 * loss_ratio));
+            self.limit = Some(decreased_limit);
+            self.limit_increased = Some(now);
+            self.limit_decreased = Some(now);
+            // Don't count the same losses twice.
+            self.reports.clear();
+            Some(self.limit)
+        } else if loss_ratio < LOW_LOSS_RATIO {
+            let limit = self.limit?;
+            let increase_duration = self
+                .limit_increased
+                .map_or(Duration::ZERO, |limit_increased| {
+                    now.saturating_duration_since(limit_increased)
+                });
+            let multiplier = (1.0 + MULTIPLICATIVE_INCREASE_PER_SECOND)
+                .powf(increase_duration.as_secs_f64().min(1.0))
+                - 1.0;
+            let increased_limit = limit + max(MIN_MULTIPLICATIVE_INCREASE, limit * multiplier);
+            self.limit_increased = Some(now);
+            if increased_limit > target_send_rate {
+                // The delay-based estimate is more limiting now, so there's no need for the limit.
+                self.limit = None;
+            } else {
+                self.limit = Some(increased_limit);
+            }
+            Some(self.limit)
+        } else {
+            // Hold the limit where it is.
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Scripts a sequence of feedback, one every 100ms, and returns the limit after each.
+    fn limits_after_feedback(
+        target_send_rate: DataRate,
+        feedback: &[(usize, usize)],
+    ) -> Vec<Option<u64>> {
+        let start_time = Instant::now();
+        let rtt = Duration::from_millis(100);
+        let mut estimator = LossBasedEstimator::new();
+        feedback
+            .iter()
+            .enumerate()
+            .map(|(i, (received_count, lost_count))| {
+                let now = start_time + Duration::from_millis(100 * i as u64);
+                estimator.update(*received_count, *lost_count, 0, target_send_rate, rtt, now);
+                estimator.limit().map(|limit| limit.as_kbps())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn no_loss_has_no_limit() {
+        assert_eq!(
+            vec![None; 10],
+            limits_after_feedback(DataRate::from_kbps(1000), &[(10, 0); 10])
+        );
+    }
+
+    #[test]
+    fn too_few_packets_has_no_limit() {
+        // 50% loss, but only 2 packets per 100ms is too few.
+        assert_eq!(
+            vec![None; 5],
+            limits_after_feedback(DataRate::from_kbps(1000), &[(1, 1); 5])
+        );
+    }
+
+    #[test]
+    fn moderate_loss_has_no_limit() {
+        // 5% loss is between the low and high thresholds.
+        assert_eq!(
+            vec![None; 10],
+            limits_after_feedback(DataRate::from_kbps(1000), &[(19, 1); 10])
+        );
+    }
+
+    #[test]
+    fn sustained_loss_decreases_limit_at_most_once_per_interval() {
+        // 20% loss decreases by 10% each time, but waits 400ms (300ms + RTT) between decreases
+        // and for enough new packets after each decrease.
+        assert_eq!(
+            vec![
+                None,
+                Some(900),
+                Some(900),
+                Some(900),
+                Some(900),
+                Some(810),
+                Some(810),
+                Some(810),
+                Some(810),
+                Some(729),
+            ],
+            limits_after_feedback(DataRate::from_kbps(1000), &[(8, 2); 10])
+        );
+    }
+
+    #[test]
+    fn late_received_packets_are_not_lost() {
+        let start_time = Instant::now();
+        let at = |millis| start_time + Duration::from_millis(millis);
+        let rtt = Duration::from_millis(100);
+        let target_send_rate = DataRate::from_kbps(1000);
+        let mut estimator = LossBasedEstimator::new();
+
+        assert_eq!(
+            None,
+            estimator.update(10, 5, 0, target_send_rate, rtt, at(0))
+        );
+        // Without the late packets, this would be 20% loss.
+        assert_eq!(
+            None,
+            estimator.update(10, 0, 5, target_send_rate, rtt, at(100))
+        );
+        assert_eq!(None, estimator.limit());
+    }
+
+    #[test]
+    fn loss_going_away_increases_limit_until_not_needed() {
+        let start_time = Instant::now();
+        let at = |millis| start_time + Duration::from_millis(millis);
+        let rtt = Duration::from_millis(100);
+        let target_send_rate = DataRate::from_kbps(1000);
+        let mut estimator = LossBasedEstimator::new();
+
+        // 50% loss cuts the limit by 25%.
+        assert_eq!(
+            Some(Some(DataRate::from_kbps(750))),
+            estimator.update(20, 20, 0, target_send_rate, rtt, at(0))
+        );
+
+        // Then no loss increases by 8% per second.
+        assert_eq!(
+            None,
+            estimator.update(10, 0, 0, target_send_rate, rtt, at(500))
+        );
+        assert_eq!(
+            Some(Some(DataRate::from_bps(810_000))),
+            estimator.update(10, 0, 0, target_send_rate, rtt, at(1000))
+        );
+        assert_eq!(
+            Some(Some(DataRate::from_bps(874_800))),
+            estimator.update(20, 0, 0, target_send_rate, rtt, at(2000))
+        );
+        // Increasing for more than a second at a time doesn't increase more than 8%.
+        assert_eq!(
+            Some(Some(DataRate::from_bps(944_784))),
+            estimator.update(20, 0, 0, target_send_rate, rtt, at(5000))
+        );
+        // Once the limit goes above the target send rate, it's removed.
+        assert_eq!(
+            Some(None),
+            estimator.update(20, 0, 0, target_send_rate, rtt, at(6000))
+        );
+        assert_eq!(None, estimator.limit());
+    }
+
+    #[test]
+    fn decrease_starts_from_lower_of_limit_and_target() {
+        let start_time = Instant::now();
+        let at = |millis| start_time + Duration::from_millis(millis);
+        let rtt = Duration::from_millis(100);
+        let mut estimator = LossBasedEstimator::new();
+
+        assert_eq!(
+            Some(Some(DataRate::from_kbps(900))),
+            estimator.update(16, 4, 0, DataRate::from_kbps(1000), rtt, at(0))
+        );
+        // The delay-based target went lower than the limit.
+        assert_eq!(
+            Some(Some(DataRate::from_kbps(450))),
+            estimator.update(16, 4, 0, DataRate::from_kbps(500), rtt, at(1000))
+        );
+    }
+}
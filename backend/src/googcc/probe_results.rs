@@ -0,0 +1,238 @@
+//
+// Copyright 2023 Signal Messenger, LLC
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Calculation of probe results, similar to libwebrtc's ProbeBitrateEstimator.
+//!
+//! The Pacer tags the packets of each probe cluster, so once enough of them have been
+//! acked, we can compare the rate they were sent at to the rate they were received at.
+//! If the receive rate kept up with the send rate, the link can handle at least that much.
+
+use std::collections::HashMap;
+
+use calling_common::{DataRate, DataSize, Duration, Instant};
+
+use crate::{
+    pacer::ProbeClusterId,
+    transportcc::{Ack, RemoteInstant},
+};
+
+// TODO: Consider making these configurable
+/// Don't calculate a result from fewer acks than this.
+/// This is a bit less than the Pacer's minimum so that a lost packet doesn't ruin the probe.
+const MIN_ACKS_FOR_RESULT: usize = 4;
+/// Probes sent or received over a longer time than this are too spread out to be meaningful.
+const MAX_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+/// Forget about a cluster if we haven't had an ack for it in this long.
+const MAX_CLUSTER_AGE: Duration = Duration::from_secs(1);
+/// If the receive rate is below this ratio of the send rate, the link is saturated,
+/// so the receive rate is the best estimate of the capacity.
+const MIN_RECEIVE_RATIO: f64 = 0.90;
+/// And in that case, back off a little from the receive rate.
+const SATURATED_RECEIVE_RATE_MULTIPLIER: f64 = 0.95;
+
+struct ProbeClusterAcks {
+    // (size, departure, arrival)
+    acks: Vec<(DataSize, Instant, RemoteInstant)>,
+    last_feedback_arrival: Instant,
+}
+
+impl ProbeClusterAcks {
+    fn calculate_result(&self) -> Option<DataRate> {
+        if self.acks.len() < MIN_ACKS_FOR_RESULT {
+            return None;
+        }
+
+        let total_size = self
+            .acks
+            .iter()
+            .fold(DataSize::ZERO, |sum, (size, _, _)| sum + *size);
+
+        // The last packet sent doesn't count toward the send rate because the send interval ends
+        // when it's sent.  Similarly, the first packet received doesn't count toward the receive
+        // rate because the receive interval starts when it's received.
+        let (first_departure, _) = self
+            .acks
+            .iter()
+            .map(|(size, departure, _)| (*departure, *size))
+            .min()?;
+        let (last_departure, last_sent_size) = self
+            .acks
+            .iter()
+            .map(|(size, departure, _)| (*departure, *size))
+            .max()?;
+        let (first_arrival, first_received_size) = self
+            .acks
+            .iter()
+            .map(|(size, _, arrival)| (*arrival, *size))
+            .min()?;
+        let (last_arrival, _) = self
+            .acks
+            .iter()
+            .map(|(size, _, arrival)| (*arrival, *size))
+            .max()?;
+
+        let send_interval = last_departure.saturating_duration_since(first_departure);
+        let receive_interval = last_arrival.saturating_duration_since(first_arrival);
+        if send_interval == Duration::ZERO
+            || receive_interval == Duration::ZERO
+            || send_interval > MAX_PROBE_INTERVAL
+            || receive_interval > MAX_PROBE_INTERVAL
+        {
+            return None;
+        }
+
+        let send_rate = total_size.saturating_sub(last_sent_size) / send_interval;
+        let receive_rate = total_size.saturating_sub(first_received_size) / receive_interval;
+        if receive_rate < send_rate * MIN_RECEIVE_RATIO {
+            Some(receive_rate * SATURATED_RECEIVE_RATE_MULTIPLIER)
+        } else {
+            Some(std::cmp::min(send_rate, receive_rate))
+        }
+    }
+}
+
+pub struct ProbeResultCalculator {
+    clusters: HashMap<ProbeClusterId, ProbeClusterAcks>,
+}
+
+impl ProbeResultCalculator {
+    pub fn new() -> Self {
+        Self {
+            clusters: HashMap::new(),
+        }
+    }
+
+    /// Remembers the acks that were part of probe clusters.
+    /// Returns the highest probe result that could be calculated from the updated clusters.
+    pub fn update(&mut self, acks: &[Ack], now: Instant) -> Option<DataRate> {
+        self.clusters.retain(|_, cluster| {
+            now.saturating_duration_since(cluster.last_feedback_arrival) <= MAX_CLUSTER_AGE
+        });
+
+        let mut updated_cluster_ids = Vec::new();
+        for ack in acks {
+            if let Some(probe_cluster_id) = ack.probe_cluster_id {
+                let cluster =
+                    self.clusters
+                        .entry(probe_cluster_id)
+                        .or_insert_with(|| ProbeClusterAcks {
+                            acks: Vec::new(),
+                            last_feedback_arrival: now,
+                        });
+                cluster.acks.push((ack.size, ack.departure, ack.arrival));
+                cluster.last_feedback_arrival = now;
+                if !updated_cluster_ids.contains(&probe_cluster_id) {
+                    updated_cluster_ids.push(probe_cluster_id);
+                }
+            }
+        }
+
+        updated_cluster_ids
+            .iter()
+            .filter_map(|probe_cluster_id| self.clusters.get(probe_cluster_id)?.calculate_result())
+            .max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each probe packet is 1250 bytes, which is 10mbps if sent every 1ms.
+    fn probe_acks(
+        probe_cluster_id: ProbeClusterId,
+        count: u64,
+        send_interval_micros: u64,
+        receive_interval_micros: u64,
+        start_time: Instant,
+    ) -> Vec<Ack> {
+        (0..count)
+            .map(|i| Ack {
+                size: DataSize::from_bytes(1250),
+                departure: start_time + Duration::from_micros(i * send_interval_micros),
+                arrival: RemoteInstant::from_micros(50_000 + i * receive_interval_micros),
+                feedback_arrival: start_time + Duration::from_millis(100),
+                probe_cluster_id: Some(probe_cluster_id),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn too_few_acks_has_no_result() {
+        let now = Instant::now();
+        let mut calculator = ProbeResultCalculator::new();
+        let acks = probe_acks(1, 5, 1000, 1000, now);
+        assert_eq!(None, calculator.update(&acks[..3], now));
+        // But once more come in, there's a result.
+        assert_eq!(
+            Some(DataRate::from_kbps(10_000)),
+            calculator.update(&acks[3..], now)
+        );
+    }
+
+    #[test]
+    fn non_probe_acks_are_ignored() {
+        let now = Instant::now();
+        let mut calculator = ProbeResultCalculator::new();
+        let mut acks = probe_acks(1, 10, 1000, 1000, now);
+        for ack in &mut acks {
+            ack.probe_cluster_id = None;
+        }
+        assert_eq!(None, calculator.update(&acks, now));
+    }
+
+    #[test]
+    fn result_is_the_send_rate_if_received_as_fast() {
+        let now = Instant::now();
+        let mut calculator = ProbeResultCalculator::new();
+        // Received faster than sent (it was queued somewhere before the probe).
+        assert_eq!(
+            Some(DataRate::from_kbps(10_000)),
+            calculator.update(&probe_acks(1, 15, 1000, 500, now), now)
+        );
+        // Received slightly slower than sent.
+        assert_eq!(
+            Some(DataRate::from_kbps(19_047)),
+            calculator
+                .update(&probe_acks(2, 30, 500, 525, now), now)
+                .map(|rate| DataRate::from_kbps(rate.as_kbps()))
+        );
+    }
+
+    #[test]
+    fn result_is_less_than_the_receive_rate_if_saturated() {
+        let now = Instant::now();
+        let mut calculator = ProbeResultCalculator::new();
+        // Sent at 20mbps but received at 10mbps
+        assert_eq!(
+            Some(DataRate::from_kbps(9_500)),
+            calculator.update(&probe_acks(1, 30, 500, 1000, now), now)
+        );
+    }
+
+    #[test]
+    fn highest_result_of_multiple_clusters() {
+        let now = Instant::now();
+        let mut calculator = ProbeResultCalculator::new();
+        let mut acks = probe_acks(1, 15, 1000, 1000, now);
+        acks.extend(probe_acks(2, 30, 500, 500, now));
+        assert_eq!(
+            Some(DataRate::from_kbps(20_000)),
+            calculator.update(&acks, now)
+        );
+    }
+
+    #[test]
+    fn old_clusters_are_forgotten() {
+        let now = Instant::now();
+        let mut calculator = ProbeResultCalculator::new();
+        let acks = probe_acks(1, 10, 1000, 1000, now);
+        assert_eq!(None, calculator.update(&acks[..3], now));
+        assert_eq!(
+            None,
+            calculator.update(&acks[3..5], now + Duration::from_secs(2))
+        );
+    }
+}
@@ -8,8 +8,16 @@
 //!   GET /metrics
 //!   GET /v2/conference/participants
 //!   PUT /v2/conference/participants
-
-use std::{convert::TryInto, net::SocketAddr, str, sync::Arc, time::UNIX_EPOCH};
+//!   POST /v2/conference/participants/ice-restart
+//!   POST /v2/conference/participants/rekey
+
+use std::{
+    convert::TryInto,
+    net::SocketAddr,
+    str::{self, FromStr},
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
 
 use anyhow::{anyhow, Result};
 use axum::{
@@ -17,12 +25,11 @@ use axum::{
     http::StatusCode,
     middleware,
     response::IntoResponse,
-    routing::get,
+    routing::{get, post},
     Extension, Json, Router, TypedHeader,
 };
 use hex::{FromHex, ToHex};
 use log::*;
-use parking_lot::Mutex;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
@@ -30,9 +37,10 @@ use tokio::sync::oneshot::Receiver;
 use tower::ServiceBuilder;
 
 use crate::{
-    config, ice,
+    call, config, ice,
     middleware::log_response,
     region::Region,
+    rtp,
     sfu::{self, Sfu},
 };
 
@@ -58,6 +66,15 @@ pub struct JoinRequest {
     pub ice_ufrag: String,
     pub dhe_public_key: String,
     pub hkdf_extra_info: Option<String>,
+    /// "transport-cc" (the default) or "rfc8888"
+    pub congestion_control_feedback: Option<String>,
+    /// "AEAD_AES_128_GCM" (the default) or "AEAD_AES_256_GCM"
+    pub srtp_protection_profile: Option<String>,
+    /// "vp8" (the default) or "dependency-descriptor" (for end-to-end encrypted video)
+    pub video_metadata_source: Option<String>,
+    /// Receive the loudest speakers mixed into one audio stream rather than each speaker's audio,
+    /// if the server mixes audio. Defaults to false.
+    pub receive_mixed_audio: Option<bool>,
 }
 
 #[derive(Serialize, Debug)]
@@ -66,6 +83,8 @@ pub struct JoinResponse {
     pub demux_id: u32,
     pub port: u16,
     pub port_tcp: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port_tls: Option<u16>,
     pub ip: String,
     pub ips: Vec<String>,
     pub ice_ufrag: String,
@@ -73,6 +92,34 @@ pub struct JoinResponse {
     pub dhe_public_key: String,
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IceRestartRequest {
+    pub demux_id: u32,
+    pub ice_ufrag: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IceRestartResponse {
+    pub ice_ufrag: String,
+    pub ice_pwd: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RekeyRequest {
+    pub demux_id: u32,
+    pub dhe_public_key: String,
+    pub hkdf_extra_info: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RekeyResponse {
+    pub dhe_public_key: String,
+}
+
 mod metrics {
     use serde::Serialize;
 
@@ -168,20 +215,18 @@ fn parse_and_authenticate(
     authenticate(config, password)
 }
 
-async fn get_metrics(
-    Extension(sfu): Extension<Arc<Mutex<Sfu>>>,
-) -> Result<impl IntoResponse, StatusCode> {
+async fn get_metrics(Extension(sfu): Extension<Arc<Sfu>>) -> Result<impl IntoResponse, StatusCode> {
     trace!("get_metrics():");
 
-    let calls = sfu.lock().get_calls_snapshot(); // SFU lock released here.
+    let calls = sfu.get_calls_snapshot(); // Shard locks released here.
 
     let calls = calls
         .iter()
         .map(|call| {
-            // We can take this call lock after closing the SFU lock because we are only reading it
-            // and do not care if it is removed from the list of active calls around the same time.
-            // This is in contrast to if we were updating it with a mut reference and we might revive
-            // the call.
+            // We can take this call lock after releasing the shard locks because we are only
+            // reading it and do not care if it is removed from the list of active calls around the
+            // same time. This is in contrast to if we were updating it with a mut reference and we
+            // might revive the call.
             let call = call.lock();
             let clients = call
                 .get_stats()
@@ -244,7 +289,7 @@ async fn get_metrics(
 
 async fn get_participants(
     Extension(config): Extension<&'static config::Config>,
-    Extension(sfu): Extension<Arc<Mutex<Sfu>>>,
+    Extension(sfu): Extension<Arc<Sfu>>,
     TypedHeader(authorization_header): TypedHeader<headers::Authorization<Basic>>,
 ) -> Result<impl IntoResponse, StatusCode> {
     trace!("get_participants():");
@@ -257,14 +302,8 @@ async fn get_participants(
         }
     };
 
-    let sfu = sfu.lock();
-
     if let Some(signaling) = sfu.get_call_signaling_info(call_id) {
         let max_devices = sfu.config.max_clients_per_call;
-        drop(sfu);
-        // Release the SFU lock as early as possible. Before call lock is fine for a imut ref to a
-        // call, as nothing we will do later with the reference can affect SFU decisions around call
-        // dropping.
 
         let participants = signaling
             .client_ids
@@ -303,7 +342,7 @@ async fn get_participants(
 
 async fn join_conference(
     Extension(config): Extension<&'static config::Config>,
-    Extension(sfu): Extension<Arc<Mutex<Sfu>>>,
+    Extension(sfu): Extension<Arc<Sfu>>,
     TypedHeader(authorization_header): TypedHeader<headers::Authorization<Basic>>,
     Json(join_request): Json<JoinRequest>,
 ) -> Result<impl IntoResponse, StatusCode> {
@@ -350,6 +389,56 @@ async fn join_conference(
         },
     };
 
+    let congestion_control_feedback = match join_request.congestion_control_feedback {
+        None => rtp::CongestionControlFeedback::default(),
+        Some(congestion_control_feedback) => {
+            match rtp::CongestionControlFeedback::from_str(&congestion_control_feedback) {
+                Ok(congestion_control_feedback) => congestion_control_feedback,
+                Err(_) => {
+                    return Ok((
+                        StatusCode::NOT_ACCEPTABLE,
+                        "Invalid congestion_control_feedback in the request.".to_string(),
+                    )
+                        .into_response());
+                }
+            }
+        }
+    };
+
+    let srtp_protection_profile = match join_request.srtp_protection_profile {
+        None => rtp::SrtpProtectionProfile::default(),
+        Some(srtp_protection_profile) => {
+            match rtp::SrtpProtectionProfile::from_str(&srtp_protection_profile) {
+                Ok(srtp_protection_profile) => srtp_protection_profile,
+                Err(_) => {
+                    return Ok((
+                        StatusCode::NOT_ACCEPTABLE,
+                        "Invalid srtp_protection_profile in the request.".to_string(),
+                    )
+                        .into_response());
+                }
+            }
+        }
+    };
+
+    let video_metadata_source = match join_request.video_metadata_source {
+        None => call::VideoMetadataSource::default(),
+        Some(video_metadata_source) => {
+            match call::VideoMetadataSource::from_str(&video_metadata_source) {
+                Ok(video_metadata_source) => video_metadata_source,
+                Err(_) => {
+                    return Ok((
+                        StatusCode::NOT_ACCEPTABLE,
+                        "Invalid video_metadata_source in the request.".to_string(),
+                    )
+                        .into_response());
+                }
+            }
+        }
+    };
+
+    let receive_mixed_audio = join_request.receive_mixed_audio.unwrap_or(false);
+
     // Generate ids for the client.
     let resolution_request_id = rand::thread_rng().gen::<u64>();
     // The endpoint_id is the term currently used on the client side, it is
@@ -360,10 +449,10 @@ async fn join_conference(
     let server_ice_ufrag = ice::random_ufrag();
     let server_ice_pwd = ice::random_pwd();
 
-    let mut sfu = sfu.lock();
     match sfu.get_or_create_call_and_add_client(
         call_id,
         &user_id,
+        false,
         resolution_request_id,
         endpoint_id,
         demux_id,
@@ -373,6 +462,10 @@ async fn join_conference(
         client_dhe_public_key,
         client_hkdf_extra_info,
         Region::Unset,
+        congestion_control_feedback,
+        srtp_protection_profile,
+        video_metadata_source,
+        receive_mixed_audio,
     ) {
         Ok(server_dhe_public_key) => {
             let media_server = config::ServerMediaAddress::from(config);
@@ -381,6 +474,7 @@ async fn join_conference(
                 demux_id: demux_id.into(),
                 port: media_server.ports.udp,
                 port_tcp: media_server.ports.tcp,
+                port_tls: media_server.ports.tls,
                 ip: media_server.ip().to_string(),
                 ips: media_server
                     .addresses
@@ -401,7 +495,138 @@ async fn join_conference(
     }
 }
 
-fn app(sfu: Arc<Mutex<Sfu>>, config: &'static config::Config) -> Router {
+async fn restart_ice(
+    Extension(config): Extension<&'static config::Config>,
+    Extension(sfu): Extension<Arc<Sfu>>,
+    TypedHeader(authorization_header): TypedHeader<headers::Authorization<Basic>>,
+    Json(ice_restart_request): Json<IceRestartRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    trace!("restart_ice():");
+
+    let (user_id, call_id) = match parse_and_authenticate(config, &authorization_header) {
+        Ok((user_id, call_id)) => (user_id, call_id),
+        Err(err) => {
+            warn!("restart_ice(): unauthorized {}", err);
+            return Ok((StatusCode::UNAUTHORIZED, err.to_string()).into_response());
+        }
+    };
+
+    // Only the client itself may change its ICE credentials.
+    let demux_id = find_own_demux_id(&sfu, &call_id, &user_id, ice_restart_request.demux_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let server_ice_ufrag = ice::random_ufrag();
+    let server_ice_pwd = ice::random_pwd();
+
+    match sfu.restart_ice(
+        call_id,
+        demux_id,
+        server_ice_ufrag.clone(),
+        server_ice_pwd.clone(),
+        ice_restart_request.ice_ufrag,
+    ) {
+        Ok(()) => Ok(Json(IceRestartResponse {
+            ice_ufrag: server_ice_ufrag,
+            ice_pwd: server_ice_pwd,
+        })
+        .into_response()),
+        Err(err @ sfu::SfuError::MissingConnection(..)) => {
+            Ok((StatusCode::NOT_FOUND, err.to_string()).into_response())
+        }
+        Err(err) => {
+            error!("client failed to restart ICE {}", err);
+            Ok((StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response())
+        }
+    }
+}
+
+async fn rekey(
+    Extension(config): Extension<&'static config::Config>,
+    Extension(sfu): Extension<Arc<Sfu>>,
+    TypedHeader(authorization_header): TypedHeader<headers::Authorization<Basic>>,
+    Json(rekey_request): Json<RekeyRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    trace!("rekey():");
+
+    let (user_id, call_id) = match parse_and_authenticate(config, &authorization_header) {
+        Ok((user_id, call_id)) => (user_id, call_id),
+        Err(err) => {
+            warn!("rekey(): unauthorized {}", err);
+            return Ok((StatusCode::UNAUTHORIZED, err.to_string()).into_response());
+        }
+    };
+
+    // Only the client itself may change its SRTP keys.
+    let demux_id = find_own_demux_id(&sfu, &call_id, &user_id, rekey_request.demux_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let client_dhe_public_key = match <[u8; 32]>::from_hex(rekey_request.dhe_public_key) {
+        Ok(client_dhe_public_key) => client_dhe_public_key,
+        Err(_) => {
+            return Ok((
+                StatusCode::NOT_ACCEPTABLE,
+                "Invalid dhe_public_key in the request.".to_string(),
+            )
+                .into_response());
+        }
+    };
+
+    let client_hkdf_extra_info = match rekey_request.hkdf_extra_info {
+        None => vec![],
+        Some(client_hkdf_extra_info) => match Vec::<u8>::from_hex(client_hkdf_extra_info) {
+            Ok(client_hkdf_extra_info) => client_hkdf_extra_info,
+            Err(_) => {
+                return Ok((
+                    StatusCode::NOT_ACCEPTABLE,
+                    "Invalid hkdf_extra_info in the request.".to_string(),
+                )
+                    .into_response());
+            }
+        },
+    };
+
+    match sfu.rekey(
+        call_id,
+        demux_id,
+        client_dhe_public_key,
+        client_hkdf_extra_info,
+    ) {
+        Ok(server_dhe_public_key) => Ok(Json(RekeyResponse {
+            dhe_public_key: server_dhe_public_key.encode_hex(),
+        })
+        .into_response()),
+        Err(err @ sfu::SfuError::MissingConnection(..)) => {
+            Ok((StatusCode::NOT_FOUND, err.to_string()).into_response())
+        }
+        Err(err) => {
+            error!("client failed to rekey {}", err);
+            Ok((StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response())
+        }
+    }
+}
+
+/// Finds the client in the call with the given demux ID, if it belongs to the given user.
+fn find_own_demux_id(
+    sfu: &Sfu,
+    call_id: &sfu::CallId,
+    user_id: &sfu::UserId,
+    demux_id: u32,
+) -> Option<sfu::DemuxId> {
+    let user_id_prefix = format!("{}-", user_id.as_slice().encode_hex::<String>());
+    sfu.get_call_signaling_info(call_id.clone())
+        .and_then(|signaling| {
+            signaling
+                .client_ids
+                .into_iter()
+                .find(|(client_demux_id, active_speaker_id)| {
+                    u32::from(*client_demux_id) == demux_id
+                        && active_speaker_id.starts_with(&user_id_prefix)
+                })
+        })
+        .map(|(demux_id, _)| demux_id)
+}
+
+fn app(sfu: Arc<Sfu>, config: &'static config::Config) -> Router {
     let metrics_route = Router::new()
         .route("/metrics", get(get_metrics))
         .layer(Extension(sfu.clone()));
@@ -411,6 +636,8 @@ fn app(sfu: Arc<Mutex<Sfu>>, config: &'static config::Config) -> Router {
             "/v2/conference/participants",
             get(get_participants).put(join_conference),
         )
+        .route("/v2/conference/participants/ice-restart", post(restart_ice))
+        .route("/v2/conference/participants/rekey", post(rekey))
         .layer(
             ServiceBuilder::new()
                 .layer(Extension(config))
@@ -422,7 +649,7 @@ fn app(sfu: Arc<Mutex<Sfu>>, config: &'static config::Config) -> Router {
 
 pub async fn start(
     config: &'static config::Config,
-    sfu: Arc<Mutex<Sfu>>,
+    sfu: Arc<Sfu>,
     http_ender_rx: Receiver<()>,
 ) -> Result<()> {
     let addr = SocketAddr::new(config.binding_ip, config.signaling_port);
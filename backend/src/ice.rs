@@ -26,6 +26,7 @@ const FINGERPRINT_LEN: usize = 4;
 const ATTR_HEADER_LEN: usize = 4;
 const BINDING_REQUEST_ID: [u8; 2] = [0x00, 0x01];
 const BINDING_RESPONSE_ID: [u8; 2] = [0x01, 0x01];
+const BINDING_INDICATION_ID: [u8; 2] = [0x00, 0x11];
 const MAGIC_COOKIE: [u8; 4] = [0x21, 0x12, 0xA4, 0x42];
 
 const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
@@ -52,6 +53,13 @@ pub fn join_username(sender_ufrag: &[u8], receiver_ufrag: &[u8]) -> Vec<u8> {
     [receiver_ufrag, sender_ufrag].join(b":".as_ref())
 }
 
+/// Binding indications are sent by some clients as keepalives (RFC 5389 section 10.1).
+pub fn looks_like_binding_indication(packet: &[u8]) -> bool {
+    packet.len() >= HEADER_LEN
+        && packet[0..2] == BINDING_INDICATION_ID
+        && packet[4..8] == MAGIC_COOKIE
+}
+
 pub struct BindingRequest<'a> {
     packet: &'a [u8],
     is_nominated: bool,
@@ -435,7 +443,30 @@ mod tests {
     mod header_identification_tests {
         use hex_literal::hex;
 
-        use super::BindingRequest;
+        use super::{looks_like_binding_indication, BindingRequest};
+
+        #[test]
+        fn binding_indication() {
+            assert!(looks_like_binding_indication(&hex!(
+                "0011 0000 2112A442 010203040506070809101112"
+            )));
+            assert!(
+                !looks_like_binding_indication(&hex!("0011 0000 2112A442")),
+                "Too short"
+            );
+            assert!(
+                !looks_like_binding_indication(&hex!(
+                    "0001 0000 2112A442 010203040506070809101112"
+                )),
+                "Binding request"
+            );
+            assert!(
+                !BindingRequest::looks_like_header(&hex!(
+                    "0011 0000 2112A442 010203040506070809101112"
+                )),
+                "Not a binding request"
+            );
+        }
 
         #[test]
         fn looks_like_binding_request_header() {
@@ -8,13 +8,16 @@ pub mod metrics;
 
 pub mod audio;
 pub mod call;
+pub mod ccfb;
 pub mod config;
 pub mod connection;
+pub mod dependency_descriptor;
 pub mod googcc;
 pub mod http_server;
 pub mod ice;
 pub mod metrics_server;
 pub mod middleware;
+pub mod mixer;
 pub mod pacer;
 pub mod packet_server;
 pub mod protos;
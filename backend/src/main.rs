@@ -16,7 +16,6 @@ use calling_common::{DataRate, Duration, Instant};
 use clap::Parser;
 use env_logger::Env;
 use once_cell::sync::Lazy;
-use parking_lot::Mutex;
 use tokio::{
     runtime,
     signal::unix::{signal, SignalKind},
@@ -33,6 +32,9 @@ fn print_config(config: &'static config::Config) {
     info!("  {:38}{:?}", "ice_candidate_ip:", config.ice_candidate_ip);
     info!("  {:38}{}", "ice_candidate_port:", config.ice_candidate_port);
     info!("  {:38}{}", "ice_candidate_port_tcp:", config.ice_candidate_port_tcp);
+    info!("  {:38}{:?}", "ice_candidate_port_tls:", config.ice_candidate_port_tls);
+    info!("  {:38}{:?}", "tls_certificate_path:", config.tls_certificate_path);
+    info!("  {:38}{}", "tls_forward_http_to_signaling:", config.tls_forward_http_to_signaling);
     info!("  {:38}{:?}", "signaling_ip:", config.signaling_ip);
     info!("  {:38}{}", "signaling_port:", config.signaling_port);
     info!("  {:38}{}", "max_clients_per_call:", config.max_clients_per_call);
@@ -43,6 +45,12 @@ fn print_config(config: &'static config::Config) {
     info!("  {:38}{}", "active_speaker_message_interval_ms:", config.active_speaker_message_interval_ms);
     info!("  {:38}{}", "inactivity_check_interval_secs:", config.inactivity_check_interval_secs);
     info!("  {:38}{}", "inactivity_timeout_secs:", config.inactivity_timeout_secs);
+    info!("  {:38}{}", "ice_binding_requests_per_second_per_address:", config.ice_binding_requests_per_second_per_address);
+    info!("  {:38}{:?}", "ingress_max_rate_multiplier:", config.ingress_max_rate_multiplier);
+    info!("  {:38}{}", "ingress_drop_excess:", config.ingress_drop_excess);
+    info!("  {:38}{}", "ingress_disconnect_abusive_clients:", config.ingress_disconnect_abusive_clients);
+    info!("  {:38}{:?}", "audio_mixing_max_speakers:", config.audio_mixing_max_speakers);
+    info!("  {:38}{:?}", "reliable_data_history_size:", config.reliable_data_history_size);
     info!("  {:38}{}", "datadog metrics:",
           match &config.metrics.datadog {
               Some(host) => host,
@@ -111,7 +119,7 @@ fn main() -> Result<()> {
     print_config(config);
 
     // Create the shared SFU context.
-    let sfu: Arc<Mutex<Sfu>> = Arc::new(Mutex::new(Sfu::new(Instant::now(), config)?));
+    let sfu: Arc<Sfu> = Arc::new(Sfu::new(Instant::now(), config)?);
 
     // Create a threaded tokio runtime. By default, starts a worker thread
     // for each core on the system.
@@ -34,7 +34,7 @@ static CURRENT_PROCESS: Lazy<Mutex<Process>> =
 
 pub async fn start(
     config: &'static config::Config,
-    sfu: Arc<Mutex<Sfu>>,
+    sfu: Arc<Sfu>,
     shutdown_signal_rx: Receiver<()>,
 ) -> Result<()> {
     match Datadog::new(config) {
@@ -65,7 +65,7 @@ pub async fn start(
                         time_scope_us!("calling.sfu.get_stats");
                         // Note that we are including the time waiting for the lock in this stat.
 
-                        let stats = sfu.lock().get_stats();
+                        let stats = sfu.get_stats();
                         for (name, histogram) in stats.histograms {
                             datadog.send_count_histogram(name, &histogram, &None);
                         }
@@ -0,0 +1,530 @@
+//
+// Copyright 2023 Signal Messenger, LLC
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Server-side audio mixing for very large calls.
+//!
+//! Rather than forwarding every speaker's audio to every client, the mixer decodes the
+//! loudest incoming Opus streams, mixes them, and re-encodes one stream for each group of
+//! listeners that should hear the same thing. Mixed speakers hear everyone but themselves
+//! ("mix-minus"), so there is one encoder for listeners who aren't in the mix plus one for
+//! each mixed speaker who is also listening.
+//!
+//! Mixing requires decoding the audio, so it only works for clients that don't end-to-end
+//! encrypt their audio.
+
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
+
+use audiopus::{
+    coder::{Decoder, Encoder},
+    packet::Packet,
+    Application, Bitrate, Channels, MutSignals, SampleRate,
+};
+use calling_common::{Duration, Instant};
+use log::*;
+
+use crate::{audio, call::DemuxId, rtp};
+
+/// The SSRC that mixed audio is sent to clients with.
+/// Like the other SSRCs the server sends with, it's below the first DemuxId, and it's
+/// neither CLIENT_SERVER_DATA_SSRC nor its RTX SSRC. Its own RTX SSRC would be unused too.
+pub const MIXED_AUDIO_SSRC: rtp::Ssrc = 4;
+
+const SAMPLE_RATE: SampleRate = SampleRate::Hz48000;
+const CHANNELS: Channels = Channels::Mono;
+// 20ms of 48kHz mono audio, which is what clients send.
+const SAMPLES_PER_FRAME: usize = 960;
+pub const FRAME_DURATION: Duration = Duration::from_millis(20);
+// The most samples a single (120ms) Opus packet can decode to.
+const MAX_DECODED_SAMPLES: usize = 5760;
+// The largest Opus packet allowed by RFC 6716.
+const MAX_ENCODED_SIZE: usize = 1275;
+const MIXED_AUDIO_BITRATE_BPS: i32 = 32_000;
+// How much decoded audio to keep for each speaker to absorb jitter.
+const MAX_QUEUED_FRAMES: usize = 5;
+// Speakers we haven't heard from in this long aren't mixed.
+const SPEAKER_TIMEOUT: Duration = Duration::from_millis(500);
+// mix() is called every FRAME_DURATION, so this only covers a late timer. If mixing falls
+// further behind than this, skip ahead rather than sending a burst of audio.
+const MAX_FRAMES_PER_MIX: usize = 2;
+
+struct Speaker {
+    // Created once the speaker is loud enough to be mixed.
+    decoder: Option<Decoder>,
+    // Smoothed; higher is louder
+    level: audio::Level,
+    last_received: Instant,
+    frames: VecDeque<Vec<i16>>,
+}
+
+impl Speaker {
+    fn new(now: Instant) -> Self {
+        Self {
+            decoder: None,
+            level: 0,
+            last_received: now,
+            frames: VecDeque::new(),
+        }
+    }
+
+    fn update_level(&mut self, level: audio::Level) {
+        self.level = ((self.level as u16 * 3 + level as u16) / 4) as audio::Level;
+    }
+
+    fn decode(&mut self, payload: &[u8]) -> audiopus::Result<()> {
+        if self.decoder.is_none() {
+            self.decoder = Some(Decoder::new(SAMPLE_RATE, CHANNELS)?);
+        }
+        let decoder = self.decoder.as_mut().expect("decoder was just created");
+
+        let mut samples = [0i16; MAX_DECODED_SAMPLES];
+        let sample_count = decoder.decode(
+            Some(Packet::try_from(payload)?),
+            MutSignals::try_from(&mut samples[..])?,
+            false,
+        )?;
+        for chunk in samples[..sample_count].chunks(SAMPLES_PER_FRAME) {
+            let mut frame = chunk.to_vec();
+            frame.resize(SAMPLES_PER_FRAME, 0);
+            if self.frames.len() >= MAX_QUEUED_FRAMES {
+                event!("calling.mixer.frame_dropped");
+                self.frames.pop_front();
+            }
+            self.frames.push_back(frame);
+        }
+        Ok(())
+    }
+}
+
+struct Listener {
+    next_seqnum: rtp::FullSequenceNumber,
+}
+
+/// Mixes the loudest audio of a call for the clients that asked for it.
+/// Incoming audio is passed to receive_audio() and mixed audio comes out of mix(),
+/// which should be called every FRAME_DURATION.
+///
+/// Decoding and encoding are slow, so the mixer has its own lock rather than being
+/// part of the Call, and it never takes the Call's lock.
+pub struct AudioMixer {
+    max_mixed_speakers: usize,
+    speakers: HashMap<DemuxId, Speaker>,
+    listeners: HashMap<DemuxId, Listener>,
+    // Keyed by the speaker left out of the mix, if any.
+    encoders: HashMap<Option<DemuxId>, Encoder>,
+    mixed_speakers: Vec<DemuxId>,
+    next_mix_time: Option<Instant>,
+    next_timestamp: rtp::TruncatedTimestamp,
+}
+
+impl AudioMixer {
+    pub fn new(max_mixed_speakers: usize) -> Self {
+        Self {
+            max_mixed_speakers,
+            speakers: HashMap::new(),
+            listeners: HashMap::new(),
+            encoders: HashMap::new(),
+            mixed_speakers: Vec::new(),
+            next_mix_time: None,
+            next_timestamp: 0,
+        }
+    }
+
+    pub fn add_listener(&mut self, demux_id: DemuxId) {
+        self.listeners
+            .entry(demux_id)
+            .or_insert(Listener { next_seqnum: 1 });
+    }
+
+    pub fn remove_client(&mut self, demux_id: DemuxId) {
+        self.speakers.remove(&demux_id);
+        self.listeners.remove(&demux_id);
+        self.encoders.remove(&Some(demux_id));
+        self.mixed_speakers.retain(|mixed| *mixed != demux_id);
+    }
+
+    /// Decodes the audio if the sender is loud enough to be mixed.
+    pub fn receive_audio(
+        &mut self,
+        sender_demux_id: DemuxId,
+        audio_level: Option<audio::Level>,
+        payload: &[u8],
+        now: Instant,
+    ) {
+        if self.listeners.is_empty() {
+            return;
+        }
+
+        let speaker = self
+            .speakers
+            .entry(sender_demux_id)
+            .or_insert_with(|| Speaker::new(now));
+        speaker.last_received = now;
+        if let Some(audio_level) = audio_level {
+            speaker.update_level(audio_level);
+        }
+        if audio_level == Some(0) {
+            // Silence doesn't change the mix.
+            return;
+        }
+
+        let level = speaker.level;
+        let loud_enough = self.mixed_speakers.contains(&sender_demux_id)
+            || self.mixed_speakers.len() < self.max_mixed_speakers
+            || self.mixed_speakers.iter().any(|mixed| {
+                self.speakers
+                    .get(mixed)
+                    .map_or(true, |mixed| mixed.level < level)
+            });
+        if !loud_enough {
+            return;
+        }
+
+        let speaker = self
+            .speakers
+            .get_mut(&sender_demux_id)
+            .expect("speaker was just added");
+        if let Err(err) = speaker.decode(payload) {
+            event!("calling.mixer.decode_error");
+            debug!(
+                "mixer: failed to decode audio from {}: {}",
+                sender_demux_id.as_u32(),
+                err
+            );
+        }
+    }
+
+    /// Returns the mixed audio that's due to be sent to each listener.
+    pub fn mix(&mut self, now: Instant) -> Vec<(DemuxId, rtp::Packet<Vec<u8>>)> {
+        let mut rtp_to_send = vec![];
+        if self.listeners.is_empty() {
+            self.next_mix_time = None;
+            return rtp_to_send;
+        }
+
+        let mut next_mix_time = self.next_mix_time.unwrap_or(now);
+        let mut frames_mixed = 0;
+        while next_mix_time <= now {
+            if frames_mixed == MAX_FRAMES_PER_MIX {
+                event!("calling.mixer.fell_behind");
+                next_mix_time = now + FRAME_DURATION;
+                break;
+            }
+            self.mix_frame(now, &mut rtp_to_send);
+            frames_mixed += 1;
+            next_mix_time += FRAME_DURATION;
+        }
+        self.next_mix_time = Some(next_mix_time);
+        rtp_to_send
+    }
+
+    fn select_mixed_speakers(&mut self, now: Instant) {
+        let mut candidates: Vec<(DemuxId, audio::Level)> = self
+            .speakers
+            .iter()
+            .filter(|(_, speaker)| {
+                speaker.level > 0
+                    && now.saturating_duration_since(speaker.last_received) < SPEAKER_TIMEOUT
+            })
+            .map(|(demux_id, speaker)| (*demux_id, speaker.level))
+            .collect();
+        // Louder first, breaking ties in favor of whoever is already mixed so the mix doesn't flap.
+        candidates.sort_by_key(|(demux_id, level)| {
+            (
+                std::cmp::Reverse(*level),
+                !self.mixed_speakers.contains(demux_id),
+                *demux_id,
+            )
+        });
+        self.mixed_speakers = candidates
+            .into_iter()
+            .take(self.max_mixed_speakers)
+            .map(|(demux_id, _)| demux_id)
+            .collect();
+    }
+
+    fn mix_frame(&mut self, now: Instant, rtp_to_send: &mut Vec<(DemuxId, rtp::Packet<Vec<u8>>)>) {
+        self.select_mixed_speakers(now);
+
+        // Take one frame from each mixed speaker and drop everyone else's
+        // so they don't play late if they're mixed later.
+        let mut frames: Vec<(DemuxId, Vec<i16>)> = vec![];
+        for (demux_id, speaker) in &mut self.speakers {
+            if self.mixed_speakers.contains(demux_id) {
+                if let Some(frame) = speaker.frames.pop_front() {
+                    frames.push((*demux_id, frame));
+                }
+            } else {
+                speaker.frames.clear();
+            }
+        }
+        // Drop the encoders of speakers that are no longer mixed.
+        self.encoders.retain(|excluded, _| {
+            excluded.map_or(true, |excluded| self.mixed_speakers.contains(&excluded))
+        });
+
+        let timestamp = self.next_timestamp;
+        self.next_timestamp = self
+            .next_timestamp
+            .wrapping_add(SAMPLES_PER_FRAME as rtp::TruncatedTimestamp);
+        if frames.is_empty() {
+            // Sending nothing is like the speakers using DTX.
+            return;
+        }
+
+        let mut total = [0i32; SAMPLES_PER_FRAME];
+        for (_, frame) in &frames {
+            for (sum, sample) in total.iter_mut().zip(frame) {
+                *sum += *sample as i32;
+            }
+        }
+
+        let mut listeners_by_excluded_speaker: HashMap<Option<DemuxId>, Vec<DemuxId>> =
+            HashMap::new();
+        for listener_demux_id in self.listeners.keys() {
+            let excluded = frames
+                .iter()
+                .map(|(demux_id, _)| *demux_id)
+                .find(|demux_id| demux_id == listener_demux_id);
+            listeners_by_excluded_speaker
+                .entry(excluded)
+                .or_default()
+                .push(*listener_demux_id);
+        }
+
+        for (excluded, listener_demux_ids) in listeners_by_excluded_speaker {
+            let excluded_frame = frames
+                .iter()
+                .find(|(demux_id, _)| Some(*demux_id) == excluded)
+                .map(|(_, frame)| frame);
+            if excluded_frame.is_some() && frames.len() == 1 {
+                // The only speaker doesn't need to hear themselves.
+                continue;
+            }
+            let mixed: Vec<i16> = total
+                .iter()
+                .enumerate()
+                .map(|(i, sum)| {
+                    let sum = sum - excluded_frame.map_or(0, |frame| frame[i] as i32);
+                    sum.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+                })
+                .collect();
+
+            let encoder = match self.encoders.entry(excluded) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => match create_encoder() {
+                    Ok(encoder) => entry.insert(encoder),
+                    Err(err) => {
+                        event!("calling.mixer.encode_error");
+                        warn!("mixer: failed to create encoder: {}", err);
+                        continue;
+                    }
+                },
+            };
+            let mut encoded = [0u8; MAX_ENCODED_SIZE];
+            let encoded_size = match encoder.encode(&mixed, &mut encoded) {
+                Ok(encoded_size) => encoded_size,
+                Err(err) => {
+                    event!("calling.mixer.encode_error");
+                    debug!("mixer: failed to encode audio: {}", err);
+                    continue;
+                }
+            };
+
+            for listener_demux_id in listener_demux_ids {
+                let listener = self
+                    .listeners
+                    .get_mut(&listener_demux_id)
+                    .expect("listener exists");
+                let seqnum = listener.next_seqnum;
+                listener.next_seqnum += 1;
+                rtp_to_send.push((
+                    listener_demux_id,
+                    rtp::Packet::with_empty_tag(
+                        rtp::OPUS_PAYLOAD_TYPE,
+                        seqnum,
+                        timestamp,
+                        MIXED_AUDIO_SSRC,
+                        None,
+                        &encoded[..encoded_size],
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+fn create_encoder() -> audiopus::Result<Encoder> {
+    let mut encoder = Encoder::new(SAMPLE_RATE, CHANNELS, Application::Voip)?;
+    encoder.set_bitrate(Bitrate::BitsPerSecond(MIXED_AUDIO_BITRATE_BPS))?;
+    Ok(encoder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded_tone(encoder: &Encoder, amplitude: i16) -> Vec<u8> {
+        let samples: Vec<i16> = (0..SAMPLES_PER_FRAME)
+            .map(|i| {
+                let phase = (i as f64) * 2.0 * std::f64::consts::PI * 440.0 / 48000.0;
+                (phase.sin() * amplitude as f64) as i16
+            })
+            .collect();
+        let mut encoded = [0u8; MAX_ENCODED_SIZE];
+        let encoded_size = encoder.encode(&samples, &mut encoded).unwrap();
+        encoded[..encoded_size].to_vec()
+    }
+
+    fn demux_id(n: u32) -> DemuxId {
+        DemuxId::try_from(n << 4).unwrap()
+    }
+
+    #[test]
+    fn mixes_for_listeners_only() {
+        let encoder = create_encoder().unwrap();
+        let mut mixer = AudioMixer::new(3);
+        let now = Instant::now();
+
+        // Nobody is listening, so there's nothing to mix.
+        mixer.receive_audio(demux_id(1), Some(50), &encoded_tone(&encoder, 5000), now);
+        assert!(mixer.mix(now).is_empty());
+
+        mixer.add_listener(demux_id(2));
+        mixer.add_listener(demux_id(3));
+        mixer.receive_audio(demux_id(1), Some(50), &encoded_tone(&encoder, 5000), now);
+        let rtp_to_send = mixer.mix(now);
+        let mut receivers: Vec<u32> = rtp_to_send
+            .iter()
+            .map(|(demux_id, _)| demux_id.as_u32())
+            .collect();
+        receivers.sort_unstable();
+        assert_eq!(vec![demux_id(2).as_u32(), demux_id(3).as_u32()], receivers);
+        for (_, rtp) in &rtp_to_send {
+            assert_eq!(MIXED_AUDIO_SSRC, rtp.ssrc());
+            assert_eq!(rtp::OPUS_PAYLOAD_TYPE, rtp.payload_type());
+            assert_eq!(1, rtp.seqnum());
+            assert_eq!(0, rtp.timestamp);
+        }
+
+        // The next frame isn't due yet.
+        assert!(mixer.mix(now + Duration::from_millis(10)).is_empty());
+
+        mixer.receive_audio(
+            demux_id(1),
+            Some(50),
+            &encoded_tone(&encoder, 5000),
+            now + Duration::from_millis(20),
+        );
+        let rtp_to_send = mixer.mix(now + Duration::from_millis(20));
+        assert_eq!(2, rtp_to_send.len());
+        for (_, rtp) in &rtp_to_send {
+            assert_eq!(2, rtp.seqnum());
+            assert_eq!(960, rtp.timestamp);
+        }
+    }
+
+    #[test]
+    fn speakers_dont_hear_themselves() {
+        let encoder = create_encoder().unwrap();
+        let mut mixer = AudioMixer::new(3);
+        let now = Instant::now();
+
+        mixer.add_listener(demux_id(1));
+        mixer.add_listener(demux_id(2));
+        mixer.add_listener(demux_id(3));
+
+        // A lone speaker only has the others to talk to.
+        mixer.receive_audio(demux_id(1), Some(50), &encoded_tone(&encoder, 5000), now);
+        let mut receivers: Vec<u32> = mixer
+            .mix(now)
+            .iter()
+            .map(|(demux_id, _)| demux_id.as_u32())
+            .collect();
+        receivers.sort_unstable();
+        assert_eq!(vec![demux_id(2).as_u32(), demux_id(3).as_u32()], receivers);
+
+        // With two speakers, everyone gets something.
+        let now = now + FRAME_DURATION;
+        mixer.receive_audio(demux_id(1), Some(50), &encoded_tone(&encoder, 5000), now);
+        mixer.receive_audio(demux_id(2), Some(50), &encoded_tone(&encoder, 5000), now);
+        assert_eq!(3, mixer.mix(now).len());
+        // One mix for each speaker and one for the listener that isn't speaking.
+        assert_eq!(3, mixer.encoders.len());
+    }
+
+    #[test]
+    fn only_the_loudest_are_mixed() {
+        let encoder = create_encoder().unwrap();
+        let mut mixer = AudioMixer::new(2);
+        let now = Instant::now();
+
+        mixer.add_listener(demux_id(10));
+        for (n, level) in [(1, 10), (2, 100), (3, 50)] {
+            let speaker = mixer
+                .speakers
+                .entry(demux_id(n))
+                .or_insert_with(|| Speaker::new(now));
+            for _ in 0..10 {
+                speaker.update_level(level);
+            }
+        }
+        for n in [1, 2, 3] {
+            mixer.receive_audio(demux_id(n), None, &encoded_tone(&encoder, 5000), now);
+        }
+        assert_eq!(1, mixer.mix(now).len());
+        assert_eq!(vec![demux_id(2), demux_id(3)], mixer.mixed_speakers);
+        // Quieter speakers' audio isn't kept around for later.
+        assert!(mixer.speakers[&demux_id(1)].frames.is_empty());
+
+        // Speakers that stop sending aren't mixed.
+        let later = now + SPEAKER_TIMEOUT;
+        mixer.receive_audio(demux_id(1), None, &encoded_tone(&encoder, 5000), later);
+        mixer.mix(later);
+        assert_eq!(vec![demux_id(1)], mixer.mixed_speakers);
+    }
+
+    #[test]
+    fn skips_ahead_when_behind() {
+        let encoder = create_encoder().unwrap();
+        let mut mixer = AudioMixer::new(3);
+        let now = Instant::now();
+
+        mixer.add_listener(demux_id(2));
+        for _ in 0..MAX_QUEUED_FRAMES {
+            mixer.receive_audio(demux_id(1), Some(50), &encoded_tone(&encoder, 5000), now);
+        }
+        assert_eq!(1, mixer.mix(now).len());
+
+        let later = now + Duration::from_secs(1);
+        for _ in 0..MAX_QUEUED_FRAMES {
+            mixer.receive_audio(demux_id(1), Some(50), &encoded_tone(&encoder, 5000), later);
+        }
+        assert_eq!(MAX_FRAMES_PER_MIX, mixer.mix(later).len());
+        assert_eq!(Some(later + FRAME_DURATION), mixer.next_mix_time);
+    }
+
+    #[test]
+    fn remove_client() {
+        let encoder = create_encoder().unwrap();
+        let mut mixer = AudioMixer::new(3);
+        let now = Instant::now();
+
+        mixer.add_listener(demux_id(1));
+        mixer.add_listener(demux_id(2));
+        mixer.receive_audio(demux_id(1), Some(50), &encoded_tone(&encoder, 5000), now);
+        assert_eq!(1, mixer.mix(now).len());
+
+        mixer.remove_client(demux_id(2));
+        let now = now + FRAME_DURATION;
+        mixer.receive_audio(demux_id(1), Some(50), &encoded_tone(&encoder, 5000), now);
+        assert!(mixer.mix(now).is_empty());
+        assert!(mixer.speakers.contains_key(&demux_id(1)));
+
+        mixer.remove_client(demux_id(1));
+        assert!(mixer.speakers.is_empty());
+        assert!(mixer.listeners.is_empty());
+        assert!(mixer.mixed_speakers.is_empty());
+    }
+}
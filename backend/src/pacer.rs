@@ -5,7 +5,7 @@
 
 use std::collections::VecDeque;
 
-use calling_common::{DataRate, DataSize, Instant};
+use calling_common::{DataRate, DataSize, Duration, Instant};
 
 use crate::rtp;
 
@@ -17,24 +17,137 @@ pub struct Config {
     pub padding_send_rate: DataRate,
     /// The SSRC to use when sending padding.  If None, we can't send padding.
     pub padding_ssrc: Option<rtp::Ssrc>,
+    /// Video that has been queued for longer than this is dropped (see Pacer::drop_stale_video).
+    /// If None, it's never dropped.
+    pub max_video_queue_delay: Option<Duration>,
 }
 
 pub type Scheduler = dyn Fn(Instant) + Send;
 
+/// Identifies the packets sent as part of a probe cluster so that the
+/// transport-cc feedback for them can be used to calculate the probe result.
+pub type ProbeClusterId = u32;
+
+// A probe cluster lasts at least this long and has at least this many packets.
+// These are the same as WebRTC's.
+const MIN_PROBE_DURATION: Duration = Duration::from_millis(15);
+const MIN_PROBE_PACKETS: usize = 5;
+
+struct ProbeCluster {
+    id: ProbeClusterId,
+    send_rate: DataRate,
+    sent_size: DataSize,
+    sent_packets: usize,
+}
+
+impl ProbeCluster {
+    fn is_complete(&self) -> bool {
+        self.sent_packets >= MIN_PROBE_PACKETS
+            && self.sent_size >= self.send_rate * MIN_PROBE_DURATION
+    }
+}
+
+/// Packets are queued by class and dequeued in order of priority:
+/// audio, then retransmissions, then video (and then padding if nothing is queued).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum QueueClass {
+    Audio,
+    Rtx,
+    Video,
+}
+
+impl QueueClass {
+    fn of(packet: &rtp::Packet<Vec<u8>>) -> Self {
+        if packet.is_rtx() {
+            Self::Rtx
+        } else if packet.is_audio() {
+            Self::Audio
+        } else {
+            Self::Video
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    pub queued_size: DataSize,
+    pub queued_packets: usize,
+    /// Total number of packets dropped from the queue (only video is dropped).
+    pub dropped_packets: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub audio: QueueStats,
+    pub rtx: QueueStats,
+    pub video: QueueStats,
+}
+
+#[derive(Default)]
+struct Queue {
+    // (packet, when it was enqueued)
+    packets: VecDeque<(rtp::Packet<Vec<u8>>, Instant)>,
+    stats: QueueStats,
+}
+
+impl Queue {
+    fn push_back(&mut self, packet: rtp::Packet<Vec<u8>>, now: Instant) {
+        self.stats.queued_size += packet.size();
+        self.stats.queued_packets += 1;
+        self.packets.push_back((packet, now));
+    }
+
+    fn pop_front(&mut self) -> Option<rtp::Packet<Vec<u8>>> {
+        let (packet, _) = self.packets.pop_front()?;
+        self.stats.queued_size = self.stats.queued_size.saturating_sub(packet.size());
+        self.stats.queued_packets -= 1;
+        Some(packet)
+    }
+
+    // Returns what was dropped.
+    fn drop_queued_longer_than(
+        &mut self,
+        max_delay: Duration,
+        now: Instant,
+    ) -> Vec<rtp::Packet<Vec<u8>>> {
+        let mut dropped = vec![];
+        while let Some((_, enqueued)) = self.packets.front() {
+            if now.saturating_duration_since(*enqueued) <= max_delay {
+                break;
+            }
+            if let Some(packet) = self.pop_front() {
+                self.stats.dropped_packets += 1;
+                dropped.push(packet);
+            }
+        }
+        dropped
+    }
+}
+
 /// A Pacer smooths out the sending of packets such that we send packets at a regular interval
 /// instead of in bursts.  It does so by queuing packets and then leaking them out.  If there
 /// is nothing to leak out, it generates padding.  The padding send rate can be lower than the
 /// media send rate to allow for cases where we want only want to pad up to some rate
 /// (such as the ideal send rate) but can use the full target send rate when draining the queue.
+///
+/// The queue is split by class (see QueueClass) so that audio doesn't wait behind video.
+///
+/// It can also send probe clusters, which are short bursts sent at a higher rate (using media
+/// if queued or padding otherwise) to find out if more bandwidth is available.
 pub struct Pacer {
     // If not set, then you must call dequeue_outgoing_rtp regularly.
     pub dequeue_scheduler: Option<Box<Scheduler>>,
 
     config: Config,
 
-    queue: VecDeque<rtp::Packet<Vec<u8>>>,
+    audio_queue: Queue,
+    rtx_queue: Queue,
+    video_queue: Queue,
     queued_size: DataSize,
     last_sent: Option<(DataSize, Instant)>,
+
+    probe_clusters: VecDeque<ProbeCluster>,
+    next_probe_cluster_id: ProbeClusterId,
 }
 
 impl Pacer {
@@ -44,10 +157,46 @@ impl Pacer {
             dequeue_scheduler: None,
 
             config,
-            queue: Default::default(),
+            audio_queue: Default::default(),
+            rtx_queue: Default::default(),
+            video_queue: Default::default(),
             queued_size: Default::default(),
             last_sent: None,
+
+            probe_clusters: Default::default(),
+            next_probe_cluster_id: 1,
+        }
+    }
+
+    /// Queues a probe cluster to be sent at the given rate once any previous ones are done.
+    pub fn start_probe_cluster(&mut self, send_rate: DataRate, now: Instant) -> ProbeClusterId {
+        let id = self.next_probe_cluster_id;
+        self.next_probe_cluster_id = self.next_probe_cluster_id.wrapping_add(1);
+        self.probe_clusters.push_back(ProbeCluster {
+            id,
+            send_rate,
+            sent_size: DataSize::ZERO,
+            sent_packets: 0,
+        });
+        self.reschedule_dequeue(now);
+        id
+    }
+
+    pub fn is_probing(&self) -> bool {
+        !self.probe_clusters.is_empty()
+    }
+
+    // Returns the ID of the probe cluster that the sent packet was a part of.
+    fn remember_sent(&mut self, size: DataSize, now: Instant) -> Option<ProbeClusterId> {
+        self.last_sent = Some((size, now));
+        let probe_cluster = self.probe_clusters.front_mut()?;
+        let id = probe_cluster.id;
+        probe_cluster.sent_size += size;
+        probe_cluster.sent_packets += 1;
+        if probe_cluster.is_complete() {
+            self.probe_clusters.pop_front();
         }
+        Some(id)
     }
 
     pub fn set_config(&mut self, config: Config, now: Instant) {
@@ -64,7 +213,9 @@ impl Pacer {
     }
 
     fn calculate_next_send_time(&self, now: Instant) -> Option<Instant> {
-        if self.queue.is_empty() {
+        if let Some(probe_cluster) = self.probe_clusters.front() {
+            self.calculate_next_send_time_by_rate(probe_cluster.send_rate, now)
+        } else if self.queue_is_empty() {
             self.calculate_next_padding_send_time(now)
         } else {
             self.calculate_next_media_send_time(now)
@@ -72,7 +223,16 @@ impl Pacer {
     }
 
     fn calculate_next_media_send_time(&self, now: Instant) -> Option<Instant> {
-        self.calculate_next_send_time_by_rate(self.config.media_send_rate, now)
+        if let Some(probe_cluster) = self.probe_clusters.front() {
+            self.calculate_next_send_time_by_rate(probe_cluster.send_rate, now)
+        } else {
+            self.calculate_next_send_time_by_rate(self.config.media_send_rate, now)
+        }
+    }
+
+    fn calculate_next_probe_padding_send_time(&self, now: Instant) -> Option<Instant> {
+        let probe_cluster = self.probe_clusters.front()?;
+        self.calculate_next_send_time_by_rate(probe_cluster.send_rate, now)
     }
 
     fn calculate_next_padding_send_time(&self, now: Instant) -> Option<Instant> {
@@ -112,19 +272,23 @@ impl Pacer {
         now: Instant,
     ) -> Option<rtp::Packet<Vec<u8>>> {
         let next_media_send_time = self.calculate_next_media_send_time(now);
-        if self.queue.is_empty() && self.past_send_time(next_media_send_time, now) {
+        if self.queue_is_empty()
+            && !self.is_probing()
+            && self.past_send_time(next_media_send_time, now)
+        {
             // Skip the queue
             self.last_sent = Some((media.size(), now));
             self.reschedule_dequeue(now);
             Some(media)
         } else {
-            let was_empty = self.queue.is_empty();
+            let was_empty = self.queue_is_empty();
             self.queued_size += media.size();
-            self.queue.push_back(media);
+            self.queue_mut(QueueClass::of(&media)).push_back(media, now);
             if was_empty {
                 self.reschedule_dequeue(now);
             } else {
-                // The next dequeue time shouldn't change because this isn't at the front of the queue.
+                // The next dequeue time shouldn't change because something was already queued
+                // and all classes are sent at the same rate.
                 // So don't waste cycles scheduling it.
             }
             None
@@ -135,22 +299,73 @@ impl Pacer {
         self.queued_size
     }
 
+    pub fn stats(&self) -> Stats {
+        Stats {
+            audio: self.audio_queue.stats,
+            rtx: self.rtx_queue.stats,
+            video: self.video_queue.stats,
+        }
+    }
+
+    fn queue_is_empty(&self) -> bool {
+        self.queued_size == DataSize::ZERO
+    }
+
+    fn queue_mut(&mut self, class: QueueClass) -> &mut Queue {
+        match class {
+            QueueClass::Audio => &mut self.audio_queue,
+            QueueClass::Rtx => &mut self.rtx_queue,
+            QueueClass::Video => &mut self.video_queue,
+        }
+    }
+
+    // Pops from the highest priority queue that isn't empty.
+    fn pop_front(&mut self) -> Option<rtp::Packet<Vec<u8>>> {
+        let packet = self
+            .audio_queue
+            .pop_front()
+            .or_else(|| self.rtx_queue.pop_front())
+            .or_else(|| self.video_queue.pop_front())?;
+        self.queued_size = self.queued_size.saturating_sub(packet.size());
+        Some(packet)
+    }
+
+    /// Drops video that has been queued for longer than Config::max_video_queue_delay.
+    /// This should be called before dequeue().  The dropped video is returned so that
+    /// the caller can make sure it isn't resent.
+    pub fn drop_stale_video(&mut self, now: Instant) -> Vec<rtp::Packet<Vec<u8>>> {
+        let max_video_queue_delay = match self.config.max_video_queue_delay {
+            Some(max_video_queue_delay) => max_video_queue_delay,
+            None => return vec![],
+        };
+        let dropped = self
+            .video_queue
+            .drop_queued_longer_than(max_video_queue_delay, now);
+        if !dropped.is_empty() {
+            for packet in &dropped {
+                self.queued_size = self.queued_size.saturating_sub(packet.size());
+            }
+            event!("calling.pacer.stale_video_dropped", dropped.len());
+        }
+        dropped
+    }
+
+    /// Also returns the ID of the probe cluster the packet is a part of, if any.
     pub fn dequeue(
         &mut self,
         generate_padding: impl FnOnce(rtp::Ssrc) -> Option<rtp::Packet<Vec<u8>>>,
         now: Instant,
-    ) -> Option<rtp::Packet<Vec<u8>>> {
-        if !self.queue.is_empty() {
+    ) -> Option<(rtp::Packet<Vec<u8>>, Option<ProbeClusterId>)> {
+        if !self.queue_is_empty() {
             // Maybe send media
             let next_media_send_time = self.calculate_next_media_send_time(now);
             if self.past_send_time(next_media_send_time, now) {
-                // Shouldn't fail because we checked !is_empty() above.
-                let media = self.queue.pop_front()?;
+                // Shouldn't fail because we checked !queue_is_empty() above.
+                let media = self.pop_front()?;
                 let media_size = media.size();
-                self.queued_size = self.queued_size.saturating_sub(media_size);
-                self.last_sent = Some((media_size, now));
+                let probe_cluster_id = self.remember_sent(media_size, now);
                 self.reschedule_dequeue(now);
-                Some(media)
+                Some((media, probe_cluster_id))
             } else {
                 // Wait to send the front of the queue.
                 // This doesn't require a reschedule because this can only happen if
@@ -162,12 +377,16 @@ impl Pacer {
         } else {
             // Maybe send padding
             if let Some(padding_ssrc) = self.config.padding_ssrc {
-                let next_padding_send_time = self.calculate_next_padding_send_time(now);
+                let next_padding_send_time = if self.is_probing() {
+                    self.calculate_next_probe_padding_send_time(now)
+                } else {
+                    self.calculate_next_padding_send_time(now)
+                };
                 if self.past_send_time(next_padding_send_time, now) {
                     if let Some(padding) = generate_padding(padding_ssrc) {
-                        self.last_sent = Some((padding.size(), now));
+                        let probe_cluster_id = self.remember_sent(padding.size(), now);
                         self.reschedule_dequeue(now);
-                        Some(padding)
+                        Some((padding, probe_cluster_id))
                     } else {
                         // For some reason padding generation failed.
                         None
@@ -182,6 +401,8 @@ impl Pacer {
                 }
             } else {
                 // Can't send padding because it's not configured.
+                // Without padding or media, we can't probe either.
+                self.probe_clusters.clear();
                 None
             }
         }
@@ -265,6 +486,7 @@ mod tests {
                     media_send_rate: DataRate::from_kbps(media_send_rate_kbps),
                     padding_send_rate: DataRate::from_kbps(padding_send_rate_kbps),
                     padding_ssrc,
+                    max_video_queue_delay: None,
                 },
                 self.time_from_ms(now_ms),
             );
@@ -286,7 +508,9 @@ mod tests {
             while let Some(dequeue_time) = self.pop_scheduled_dequeue_time_if_before(deadline) {
                 let mut pacer = self.pacer.lock().unwrap();
                 let generate_padding = Box::new(|_| Some(padding.clone()));
-                if let Some(sent) = pacer.dequeue(generate_padding, dequeue_time) {
+                if let Some((sent, _probe_cluster_id)) =
+                    pacer.dequeue(generate_padding, dequeue_time)
+                {
                     let dequeue_time_ms = self.time_as_ms(dequeue_time);
                     if Some(sent.ssrc()) == pacer.config.padding_ssrc {
                         padding_sent_times_ms.push(dequeue_time_ms);
@@ -457,4 +681,216 @@ mod tests {
         assert_eq!(0, media_sent_times_ms.len());
         assert_eq!(0, padding_sent_times_ms.len());
     }
+
+    #[test]
+    fn test_pacer_probe_clusters() {
+        let media = {
+            let pt = 108;
+            let seqnum = 1;
+            let timestamp = 1000;
+            let ssrc = 10_000;
+            let tcc_seqnum = 1;
+            let payload = &[0u8; 1214];
+            rtp::Packet::with_empty_tag(pt, seqnum, timestamp, ssrc, Some(tcc_seqnum), payload)
+        };
+        let padding_ssrc = 10_001;
+        let padding = {
+            let pt = 109;
+            let seqnum = 2;
+            let timestamp = 1001;
+            let ssrc = padding_ssrc;
+            let tcc_seqnum = 2;
+            let payload = &[0u8; 1214];
+            rtp::Packet::with_empty_tag(pt, seqnum, timestamp, ssrc, Some(tcc_seqnum), payload)
+        };
+        // Each packet is 10kbit, so each packet per 1ms makes 10mbps.
+        assert_eq!(padding.size().as_bytes(), 1250);
+
+        let epoch = Instant::now();
+        let at_micros = |micros| epoch + Duration::from_micros(micros);
+        let mut pacer = Pacer::new(Config {
+            media_send_rate: DataRate::from_kbps(1_000),
+            padding_send_rate: DataRate::ZERO,
+            padding_ssrc: Some(padding_ssrc),
+            max_video_queue_delay: None,
+        });
+        // (ssrc, probe_cluster_id, time in micros)
+        let dequeue_until = |pacer: &mut Pacer, start_micros, end_micros, step_micros| {
+            let mut sent = Vec::new();
+            for micros in (start_micros..end_micros).step_by(step_micros) {
+                if let Some((packet, probe_cluster_id)) =
+                    pacer.dequeue(|_| Some(padding.clone()), at_micros(micros))
+                {
+                    sent.push((packet.ssrc(), probe_cluster_id, micros));
+                }
+            }
+            sent
+        };
+
+        // Other than the first packet, nothing is sent without probing because the padding rate is zero.
+        assert!(!pacer.is_probing());
+        assert_eq!(
+            vec![(padding_ssrc, None, 0)],
+            dequeue_until(&mut pacer, 0, 10_000, 1_000)
+        );
+
+        // A 10mbps probe sends padding every 1ms until it has sent for 15ms.
+        let probe1 = pacer.start_probe_cluster(DataRate::from_kbps(10_000), at_micros(10_000));
+        assert!(pacer.is_probing());
+        let sent = dequeue_until(&mut pacer, 10_000, 40_000, 1_000);
+        assert_eq!(
+            (10_000..25_000)
+                .step_by(1_000)
+                .map(|micros| (padding_ssrc, Some(probe1), micros))
+                .collect::<Vec<_>>(),
+            sent
+        );
+        assert!(!pacer.is_probing());
+
+        // Media is queued while probing, and is sent first as part of the probe.
+        // The next probe cluster is sent after the first is done.
+        let probe2 = pacer.start_probe_cluster(DataRate::from_kbps(20_000), at_micros(100_000));
+        let probe3 = pacer.start_probe_cluster(DataRate::from_kbps(30_000), at_micros(100_000));
+        assert_ne!(probe2, probe3);
+        assert!(pacer.enqueue(media.clone(), at_micros(100_000)).is_none());
+        assert!(pacer.enqueue(media.clone(), at_micros(100_000)).is_none());
+        let sent = dequeue_until(&mut pacer, 100_000, 200_000, 100);
+        let probe2_sent = sent
+            .iter()
+            .filter(|(_, probe_cluster_id, _)| *probe_cluster_id == Some(probe2))
+            .collect::<Vec<_>>();
+        let probe3_sent = sent
+            .iter()
+            .filter(|(_, probe_cluster_id, _)| *probe_cluster_id == Some(probe3))
+            .collect::<Vec<_>>();
+        assert_eq!(sent.len(), probe2_sent.len() + probe3_sent.len());
+        assert_eq!(
+            &[
+                &(10_000, Some(probe2), 100_000),
+                &(10_000, Some(probe2), 100_500),
+                &(padding_ssrc, Some(probe2), 101_000),
+            ],
+            &probe2_sent[..3]
+        );
+        // 20mbps for 15ms is 30 packets
+        assert_eq!(30, probe2_sent.len());
+        // 30mbps for 15ms is 45 packets
+        assert_eq!(45, probe3_sent.len());
+        assert!(!pacer.is_probing());
+        assert_eq!(DataSize::ZERO, pacer.queued_size());
+
+        // Probing is abandoned if there's no way to send padding.
+        pacer.set_config(
+            Config {
+                media_send_rate: DataRate::from_kbps(1_000),
+                padding_send_rate: DataRate::ZERO,
+                padding_ssrc: None,
+                max_video_queue_delay: None,
+            },
+            at_micros(300_000),
+        );
+        pacer.start_probe_cluster(DataRate::from_kbps(10_000), at_micros(300_000));
+        assert_eq!(0, dequeue_until(&mut pacer, 300_000, 400_000, 1_000).len());
+        assert!(!pacer.is_probing());
+    }
+
+    #[test]
+    fn test_pacer_priority_and_stale_video() {
+        let new_packet = |pt, seqnum| {
+            let timestamp = 1000;
+            let ssrc = 10_000;
+            let tcc_seqnum = seqnum;
+            let payload = &[0u8; 1214];
+            rtp::Packet::with_empty_tag(pt, seqnum, timestamp, ssrc, Some(tcc_seqnum), payload)
+        };
+        let audio = |seqnum| new_packet(102, seqnum);
+        let video = |seqnum| new_packet(108, seqnum);
+        let rtx = |seqnum| video(seqnum).to_rtx(seqnum + 1000);
+
+        let epoch = Instant::now();
+        let at = |millis| epoch + Duration::from_millis(millis);
+        // 1 packet per 1ms and video is dropped after 5ms
+        let mut pacer = Pacer::new(Config {
+            media_send_rate: DataRate::from_kbps(10_000),
+            padding_send_rate: DataRate::ZERO,
+            padding_ssrc: None,
+            max_video_queue_delay: Some(Duration::from_millis(5)),
+        });
+        // (seqnum, is_rtx, time in millis) of what was sent, and the seqnums of what was dropped
+        let dequeue_until = |pacer: &mut Pacer, start_millis, end_millis| {
+            let mut sent = Vec::new();
+            let mut dropped = Vec::new();
+            for millis in start_millis..end_millis {
+                dropped.extend(
+                    pacer
+                        .drop_stale_video(at(millis))
+                        .iter()
+                        .map(|packet| packet.seqnum()),
+                );
+                if let Some((packet, _)) = pacer.dequeue(|_| None, at(millis)) {
+                    sent.push((packet.seqnum(), packet.is_rtx(), millis));
+                }
+            }
+            (sent, dropped)
+        };
+
+        // Audio goes first, then RTX, and then video, regardless of the order they're queued.
+        assert!(pacer.enqueue(video(1), at(0)).is_some());
+        assert!(pacer.enqueue(video(2), at(0)).is_none());
+        assert!(pacer.enqueue(video(3), at(0)).is_none());
+        assert!(pacer.enqueue(rtx(1), at(0)).is_none());
+        assert!(pacer.enqueue(audio(4), at(0)).is_none());
+        assert_eq!(
+            Stats {
+                audio: QueueStats {
+                    queued_size: audio(4).size(),
+                    queued_packets: 1,
+                    dropped_packets: 0,
+                },
+                rtx: QueueStats {
+                    queued_size: rtx(1).size(),
+                    queued_packets: 1,
+                    dropped_packets: 0,
+                },
+                video: QueueStats {
+                    queued_size: video(2).size() * 2.0,
+                    queued_packets: 2,
+                    dropped_packets: 0,
+                },
+            },
+            pacer.stats()
+        );
+        assert_eq!(
+            // The RTX packet is slightly bigger, so it takes slightly longer than 1ms to send.
+            (
+                vec![(4, false, 1), (1, true, 2), (2, false, 4), (3, false, 5)],
+                vec![]
+            ),
+            dequeue_until(&mut pacer, 1, 10)
+        );
+        assert_eq!(Stats::default(), pacer.stats());
+
+        // Video queued for too long is dropped, but audio isn't.
+        assert!(pacer.enqueue(video(5), at(10)).is_some());
+        for seqnum in 6..15 {
+            assert!(pacer.enqueue(video(seqnum), at(10)).is_none());
+        }
+        assert!(pacer.enqueue(audio(15), at(10)).is_none());
+        assert_eq!(
+            (
+                vec![
+                    (15, false, 11),
+                    (6, false, 12),
+                    (7, false, 13),
+                    (8, false, 14),
+                    (9, false, 15),
+                ],
+                vec![10, 11, 12, 13, 14]
+            ),
+            dequeue_until(&mut pacer, 11, 20)
+        );
+        assert_eq!(DataSize::ZERO, pacer.queued_size());
+        assert_eq!(5, pacer.stats().video.dropped_packets);
+        assert_eq!(0, pacer.stats().video.queued_packets);
+    }
 }
@@ -16,22 +16,34 @@ use std::{
 
 use anyhow::Result;
 use log::*;
-use parking_lot::Mutex;
 use tokio::sync::oneshot::Receiver;
 
-#[cfg(all(feature = "epoll", target_os = "linux"))]
+// io_uring takes precedence over epoll, which is on by default.
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+mod io_uring;
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+pub use self::io_uring::PacketServerState;
+// The epoll server is also built for the io_uring tests to compare against.
+#[cfg(all(
+    any(
+        all(feature = "epoll", not(feature = "io_uring")),
+        all(test, feature = "io_uring")
+    ),
+    target_os = "linux"
+))]
+#[cfg_attr(all(feature = "io_uring", target_os = "linux"), allow(dead_code))]
 mod epoll;
-#[cfg(all(feature = "epoll", target_os = "linux"))]
+#[cfg(all(feature = "epoll", not(feature = "io_uring"), target_os = "linux"))]
 pub use epoll::PacketServerState;
-#[cfg(not(all(feature = "epoll", target_os = "linux")))]
+#[cfg(not(all(any(feature = "epoll", feature = "io_uring"), target_os = "linux")))]
 mod generic;
-#[cfg(not(all(feature = "epoll", target_os = "linux")))]
+#[cfg(not(all(any(feature = "epoll", feature = "io_uring"), target_os = "linux")))]
 pub use generic::PacketServerState;
 
 use calling_common::{Duration, Instant, ThreadPool};
 
 use crate::{
-    config,
+    config, connection, mixer,
     sfu::{Sfu, SfuError},
 };
 
@@ -52,7 +64,7 @@ impl fmt::Display for SocketLocator {
 
 pub async fn start(
     config: &'static config::Config,
-    sfu: Arc<Mutex<Sfu>>,
+    sfu: Arc<Sfu>,
     packet_ender_rx: Receiver<()>,
     is_healthy: Arc<AtomicBool>,
 ) -> Result<()> {
@@ -63,86 +75,111 @@ pub async fn start(
     let local_addr_udp = SocketAddr::new(config.binding_ip, config.ice_candidate_port);
     let local_addr_tcp = SocketAddr::new(config.binding_ip, config.ice_candidate_port_tcp);
 
-    let packet_handler_state =
-        PacketServerState::new(local_addr_udp, local_addr_tcp, num_threads, tick_interval)?;
+    let packet_handler_state = PacketServerState::new(
+        local_addr_udp,
+        local_addr_tcp,
+        num_threads,
+        tick_interval,
+        config.send_ecn_capable,
+    )?;
     let packet_handler_state_for_tick = packet_handler_state.clone();
     let packet_handler_state_for_dequeue = packet_handler_state.clone();
     let packet_handler_state_for_stats = packet_handler_state.clone();
+    let packet_handler_state_for_mix = packet_handler_state.clone();
+
+    // Bind the TLS port before going any further, so that a bad TLS configuration stops the
+    // server from starting rather than leaving it advertising a port that isn't listening.
+    let tls_handle = start_tls_listener(config, packet_handler_state.clone()).await?;
 
     let sfu_for_tick = sfu.clone();
     let sfu_for_cleanup = sfu.clone();
+    let sfu_for_mix = sfu.clone();
 
     info!(
         "packet_server ready: udp {:?}, tcp {:?}; starting {} threads",
         local_addr_udp, local_addr_tcp, num_threads
     );
 
-    sfu.lock()
-        .set_packet_server(Some(packet_handler_state_for_stats));
+    sfu.set_packet_server(Some(packet_handler_state_for_stats));
 
     let thread_pool = ThreadPool::new(num_threads);
 
-    sfu.lock()
-        .set_new_connection_handler(Box::new(move |connection| {
-            let thread_pool_for_dequeue = thread_pool.clone();
-            let connection_for_dequeue = connection.clone();
-            let packet_handler_state_for_dequeue = packet_handler_state_for_dequeue.clone();
-            connection
-                .lock()
-                // Note: this creates a reference cycle, but that cycle is broken
-                // by the SFU when it removes the connection from its tables
-                // by calling .set_dequeue_scheduler(None).
-                .set_dequeue_scheduler(Some(Box::new(move |time_to_dequeue| {
-                    let connection_for_dequeue = connection_for_dequeue.clone();
-                    let packet_handler_state_for_dequeue = packet_handler_state_for_dequeue.clone();
-                    thread_pool_for_dequeue.spawn_blocking_at(
-                        time_to_dequeue,
-                        Box::new(move || {
-                            if let Some((buf, addr)) = connection_for_dequeue
-                                .lock()
-                                .dequeue_outgoing_rtp(Instant::now())
-                            {
-                                packet_handler_state_for_dequeue.send_packet(&buf, addr);
-                            }
-                        }),
-                    );
-                })));
-        }));
+    sfu.set_new_connection_handler(Box::new(move |connection| {
+        let thread_pool_for_dequeue = thread_pool.clone();
+        let connection_for_dequeue = connection.clone();
+        let packet_handler_state_for_dequeue = packet_handler_state_for_dequeue.clone();
+        connection
+            .lock()
+            // Note: this creates a reference cycle, but that cycle is broken
+            // by the SFU when it removes the connection from its tables
+            // by calling .set_dequeue_scheduler(None).
+            .set_dequeue_scheduler(Some(Box::new(move |time_to_dequeue| {
+                let connection_for_dequeue = connection_for_dequeue.clone();
+                let packet_handler_state_for_dequeue = packet_handler_state_for_dequeue.clone();
+                thread_pool_for_dequeue.spawn_blocking_at(
+                    time_to_dequeue,
+                    Box::new(move || {
+                        if let Some((buf, addr)) = connection_for_dequeue
+                            .lock()
+                            .dequeue_outgoing_rtp(Instant::now())
+                        {
+                            packet_handler_state_for_dequeue.send_packet(&buf, addr);
+                        }
+                    }),
+                );
+            })));
+    }));
 
     // Spawn (blocking) threads for the packet server.
-    let packet_packet_handles = packet_handler_state.start_threads(move |sender_addr, data| {
-        time_scope_us!("calling.udp_server.handle_packet"); // metric names use udp_server for historic continuity
+    let packet_packet_handles =
+        packet_handler_state.start_threads(move |sender_addr, ecn, data| {
+            time_scope_us!("calling.udp_server.handle_packet"); // metric names use udp_server for historic continuity
 
-        trace!(
-            "received packet of {} bytes from {}",
-            data.len(),
-            sender_addr
-        );
+            trace!(
+                "received packet of {} bytes from {}",
+                data.len(),
+                sender_addr
+            );
 
-        sampling_histogram!("calling.udp_server.incoming_packet.size_bytes", || data
-            .len());
-
-        Sfu::handle_packet(&sfu, sender_addr, data).unwrap_or_else(|err| {
-            // Check for certain errors that can arise in normal conditions
-            // (say, because UDP packets arrive out of order).
-            // Note that we still use ".sfu" prefixes for these error events.
-            match &err {
-                SfuError::UnknownPacketType(_) => {
-                    event!("calling.sfu.error.expected.unhandled");
-                    trace!("handle_packet() failed: {}", err);
-                }
-                SfuError::IceBindingRequestUnknownUsername(_) => {
-                    event!("calling.sfu.error.expected.ice_binding_request_unknown_username");
-                    trace!("handle_packet() failed: {}", err);
-                }
-                _ => {
-                    event!("calling.sfu.error.unexpected");
-                    debug!("handle_packet() failed: {}", err);
-                }
-            }
-            Vec::new()
-        })
-    });
+            sampling_histogram!("calling.udp_server.incoming_packet.size_bytes", || data
+                .len());
+
+            sfu.handle_packet(sender_addr, ecn, data)
+                .unwrap_or_else(|err| {
+                    // Check for certain errors that can arise in normal conditions
+                    // (say, because UDP packets arrive out of order).
+                    // Note that we still use ".sfu" prefixes for these error events.
+                    match &err {
+                        SfuError::UnknownPacketType(_) => {
+                            event!("calling.sfu.error.expected.unhandled");
+                            trace!("handle_packet() failed: {}", err);
+                        }
+                        SfuError::IceBindingRequestUnknownUsername(_) => {
+                            event!(
+                                "calling.sfu.error.expected.ice_binding_request_unknown_username"
+                            );
+                            trace!("handle_packet() failed: {}", err);
+                        }
+                        SfuError::IceBindingRequestRateLimited(_) => {
+                            event!("calling.sfu.error.expected.ice_binding_request_rate_limited");
+                            trace!("handle_packet() failed: {}", err);
+                        }
+                        SfuError::ConnectionError(
+                            connection::Error::ReceivedIceWithInvalidHmac(_),
+                        ) => {
+                            event!("calling.sfu.error.expected.ice_binding_request_invalid_hmac");
+                            trace!("handle_packet() failed: {}", err);
+                        }
+                        _ => {
+                            event!("calling.sfu.error.unexpected");
+                            debug!("handle_packet() failed: {}", err);
+                        }
+                    }
+                    Vec::new()
+                })
+        });
+
+    let is_healthy_for_packet_threads = is_healthy.clone();
 
     // Spawn a normal (cooperative) task to run some regular maintenance on an interval.
     let tick_handle = tokio::spawn(async move {
@@ -153,9 +190,8 @@ pub async fn start(
             tokio::time::sleep(tick_interval.into()).await;
             time_scope_us!("calling.udp_server.tick.processing");
 
-            let tick_output = { sfu_for_tick.lock().tick(Instant::now()) };
+            let tick_output = sfu_for_tick.tick(Instant::now());
 
-            // Process outside the scope of the lock on the sfu.
             match packet_handler_state_for_tick.tick(tick_output) {
                 Ok(()) => {}
                 Err(err) => {
@@ -166,14 +202,143 @@ pub async fn start(
         }
     });
 
+    // Mixed audio has to go out every frame, which is more often than the tick, so it gets its own
+    // task. Unlike the tick, this uses interval() to keep the frames evenly spaced.
+    let mix_handle = config.audio_mixing_max_speakers.map(|_| {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(mixer::FRAME_DURATION.into());
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                time_scope_us!("calling.udp_server.mix_audio");
+                for (buf, addr) in sfu_for_mix.mix_audio(Instant::now()) {
+                    packet_handler_state_for_mix.send_packet(&buf, addr);
+                }
+            }
+        })
+    });
+
     // Wait for any task to complete and cancel the rest.
     tokio::select!(
-        _ = packet_packet_handles => {},
+        _ = packet_packet_handles => {
+            // The threads only exit if they can't go on.
+            error!("a packet server thread exited");
+            is_healthy_for_packet_threads.store(false, Ordering::Relaxed);
+        },
         _ = tick_handle => {},
         _ = packet_ender_rx => {},
     );
 
-    sfu_for_cleanup.lock().set_packet_server(None);
+    if let Some(tls_handle) = tls_handle {
+        tls_handle.abort();
+    }
+    if let Some(mix_handle) = mix_handle {
+        mix_handle.abort();
+    }
+
+    sfu_for_cleanup.set_packet_server(None);
     info!("packet_server shutdown");
     Ok(())
 }
+
+/// Asks the kernel for the ECN codepoint of the packets received on `socket` and, if
+/// `ecn_capable`, marks the packets sent on it as ECN-capable (ECT(0)).
+///
+/// IPv6 sockets get the IPv4 options too, for IPv4-mapped addresses.
+#[cfg(all(any(feature = "epoll", feature = "io_uring"), target_os = "linux"))]
+pub(crate) fn set_up_ecn(
+    socket: &impl std::os::unix::io::AsRawFd,
+    is_ipv6: bool,
+    ecn_capable: bool,
+) {
+    use nix::libc;
+
+    let ect0 = crate::ccfb::Ecn::Ect0 as libc::c_int;
+    let mut options = vec![(libc::IPPROTO_IP, libc::IP_RECVTOS, 1)];
+    if ecn_capable {
+        options.push((libc::IPPROTO_IP, libc::IP_TOS, ect0));
+    }
+    if is_ipv6 {
+        options.push((libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS, 1));
+        if ecn_capable {
+            options.push((libc::IPPROTO_IPV6, libc::IPV6_TCLASS, ect0));
+        }
+    }
+    for (level, name, value) in options {
+        // Safety: the value is an int, as all of these options expect.
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            warn!(
+                "failed to set socket option {}/{} for ECN: {}",
+                level,
+                name,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// Binds the TLS port, if configured, and spawns a task to accept ICE-TCP connections wrapped in
+/// TLS on it and hand them to the packet server.
+///
+/// Errors accepting connections are logged by the task rather than stopping the packet server.
+#[cfg(all(feature = "epoll", not(feature = "io_uring"), target_os = "linux"))]
+async fn start_tls_listener(
+    config: &'static config::Config,
+    packet_server: Arc<PacketServerState>,
+) -> Result<Option<tokio::task::JoinHandle<()>>> {
+    let Some(port) = config.ice_candidate_port_tls else {
+        return Ok(None);
+    };
+    let (Some(certificate_path), Some(private_key_path)) =
+        (&config.tls_certificate_path, &config.tls_private_key_path)
+    else {
+        anyhow::bail!(
+            "ice_candidate_port_tls requires tls_certificate_path and tls_private_key_path"
+        );
+    };
+    let tls_config = epoll::tls::load_server_config(
+        certificate_path,
+        private_key_path,
+        config.tls_forward_http_to_signaling,
+    )?;
+    let signaling_addr = config.tls_forward_http_to_signaling.then(|| {
+        SocketAddr::new(
+            config.signaling_ip.unwrap_or(config.binding_ip),
+            config.signaling_port,
+        )
+    });
+
+    let local_addr_tls = SocketAddr::new(config.binding_ip, port);
+    let listener = tokio::net::TcpListener::bind(local_addr_tls).await?;
+    info!("packet_server ready: tls {:?}", local_addr_tls);
+
+    Ok(Some(tokio::spawn(async move {
+        if let Err(err) =
+            epoll::tls::accept_connections(listener, tls_config, signaling_addr, packet_server)
+                .await
+        {
+            event!("calling.udp.epoll.tls_listener_error");
+            error!("TLS listener failed: {}", err);
+        }
+    })))
+}
+
+#[cfg(not(all(feature = "epoll", not(feature = "io_uring"), target_os = "linux")))]
+async fn start_tls_listener(
+    config: &'static config::Config,
+    _packet_server: Arc<PacketServerState>,
+) -> Result<Option<tokio::task::JoinHandle<()>>> {
+    if config.ice_candidate_port_tls.is_some() {
+        anyhow::bail!("ice_candidate_port_tls is only supported by the epoll packet server");
+    }
+    Ok(None)
+}
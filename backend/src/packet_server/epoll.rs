@@ -6,6 +6,7 @@
 use std::{
     cmp::Ordering,
     collections::{hash_map, HashMap, VecDeque},
+    fs::File,
     future::Future,
     io::{self, IoSlice, Read, Write},
     net::{
@@ -23,18 +24,28 @@ use anyhow::Result;
 use byteorder::{BigEndian, ByteOrder};
 use calling_common::{try_scoped, Duration, Instant};
 use log::*;
-use nix::{errno::Errno, sys::epoll::*};
+use nix::{
+    errno::Errno,
+    sys::{
+        epoll::*,
+        eventfd::{eventfd, EfdFlags},
+    },
+};
 use parking_lot::{Mutex, RwLock};
 use scopeguard::ScopeGuard;
 use unique_id::sequence::SequenceGenerator;
 use unique_id::Generator;
 
 use crate::{
+    ccfb::Ecn,
     metrics::TimingOptions,
-    packet_server::SocketLocator,
+    packet_server::{self, SocketLocator},
     sfu::{self, SfuStats},
 };
 
+pub mod tls;
+mod udp_batch;
+
 /// Controls number of sockets a particular thread will handle without going back to epoll.
 ///
 /// A higher number saves calls into the kernel, but claims more events for a single thread to
@@ -60,6 +71,12 @@ const MAX_RTP_LENGTH: usize = 1500;
 /// Tcp socket sendbuffer size, enough for 10 Mbps with a 1 second round trip time.
 const TCP_SEND_BUFFER_BYTES: usize = 10_000_000 / 8;
 
+/// Controls how many packets will be read from a TLS socket before going back to epoll.
+///
+/// Unlike plain TCP, we can't stop after one packet, because the rest of a TLS record may already
+/// have been read from the socket. But once that's drained, we can stop.
+const MAX_TLS_PACKETS_PER_EVENT: usize = 16;
+
 /// The shared state for an epoll-based packet server.
 ///
 /// This server is implemented with a "new client" socket that receives new connections, plus a map
@@ -82,6 +99,14 @@ pub struct PacketServerState {
     tick_interval: Duration,
     tick_number: AtomicU64, // u64 will never rollover
     tcp_id_generator: SequenceGenerator,
+    udp_batching: udp_batch::Support,
+    /// Whether UDP packets are sent marked as ECN-capable.
+    send_ecn_capable: bool,
+    /// An eventfd that wakes a thread to read the sockets in `tls_sockets_to_read`.
+    tls_wakeup: File,
+    /// TLS connections that were added with data already read from the socket and decrypted,
+    /// which epoll won't report because it only knows about the socket.
+    tls_sockets_to_read: Mutex<Vec<RawFd>>,
 }
 
 impl PacketServerState {
@@ -93,6 +118,7 @@ impl PacketServerState {
         local_addr_tcp: SocketAddr,
         num_threads: usize,
         tick_interval: Duration,
+        send_ecn_capable: bool,
     ) -> Result<Arc<Self>> {
         let new_client_socket = Socket::Udp(Self::open_socket_with_reusable_port(&local_addr_udp)?);
         let new_tcp_socket = Self::open_listen_socket(&local_addr_tcp)?;
@@ -100,6 +126,10 @@ impl PacketServerState {
             .map(|_| epoll_create1(EpollCreateFlags::empty()))
             .collect::<nix::Result<_>>()?;
         let tcp_id_generator = SequenceGenerator::default();
+        // Safety: we have just created this FD, so we know it's valid.
+        let tls_wakeup = unsafe {
+            File::from_raw_fd(eventfd(0, EfdFlags::EFD_NONBLOCK | EfdFlags::EFD_CLOEXEC)?)
+        };
 
         let result = Self {
             local_addr_udp,
@@ -110,9 +140,20 @@ impl PacketServerState {
             tick_interval,
             tick_number: 0.into(),
             tcp_id_generator,
+            udp_batching: udp_batch::Support::new(),
+            send_ecn_capable,
+            tls_wakeup,
+            tls_sockets_to_read: Mutex::new(vec![]),
         };
+        result.udp_batching.enable_gro(&result.new_client_socket);
+        packet_server::set_up_ecn(
+            &result.new_client_socket,
+            local_addr_udp.is_ipv6(),
+            send_ecn_capable,
+        );
         result.add_socket_to_poll_for_reads(&result.new_client_socket)?;
         result.add_socket_to_poll_for_reads(&result.new_tcp_socket)?;
+        result.add_socket_to_poll_for_reads(&result.tls_wakeup)?;
         Ok(Arc::new(result))
     }
 
@@ -218,16 +259,67 @@ impl PacketServerState {
         Ok(())
     }
 
+    /// Adds a newly accepted TCP connection to the connection map, optionally with a TLS session
+    /// that has already completed its handshake (see [tls::accept_connections]).
+    fn add_accepted_tcp_socket(
+        &self,
+        client_socket: TcpStream,
+        addr: SocketAddr,
+        tls_connection: Option<rustls::ServerConnection>,
+    ) {
+        // TODO: explore TCP_CORK instead of/in addition to TCP_NODELAY
+        let _ = client_socket.set_nodelay(true); // fail quietly
+        client_socket
+            .set_nonblocking(true)
+            .expect("Cannot set non-blocking");
+
+        let is_ipv6 = match addr.ip() {
+            V4(_) => false,
+            V6(addr) => addr.to_ipv4_mapped().is_none(),
+        };
+        let id = self.tcp_id_generator.next_id();
+        let mut has_buffered_plaintext = false;
+        let client_socket = match tls_connection {
+            None => Socket::new_tcp(client_socket, id, is_ipv6),
+            Some(tls_connection) => {
+                // The client may have sent packets right after the handshake, which were read
+                // from the socket along with it.
+                let mut tls_stream = tls::TlsStream::new(client_socket, tls_connection);
+                has_buffered_plaintext = tls_stream.has_buffered_plaintext();
+                Socket::new_tls(tls_stream, id, is_ipv6)
+            }
+        };
+        let client_socket_fd = client_socket.as_raw_fd();
+
+        let mut write_lock = self.all_connections.write();
+
+        if self.add_socket_to_poll_for_reads(&client_socket).is_ok() {
+            write_lock.get_or_insert_connected(
+                client_socket,
+                SocketLocator::Tcp { id, is_ipv6 },
+                Some(self.tick_number.load(AtomicOrdering::Relaxed)),
+            );
+            drop(write_lock);
+
+            if has_buffered_plaintext {
+                self.tls_sockets_to_read.lock().push(client_socket_fd);
+                if let Err(err) = (&self.tls_wakeup).write_all(&1u64.to_ne_bytes()) {
+                    warn!("failed to wake up a thread to read from TLS: {}", err);
+                }
+            }
+        }
+    }
+
     /// Launches the configured number of threads for the server using Tokio's blocking thread pool
     /// ([`tokio::task::spawn_blocking`]).
     ///
-    /// `handle_packet` should take a single incoming packet's source address and data and produce a
-    /// (possibly empty) set of outgoing packets.
+    /// `handle_packet` should take a single incoming packet's source address, ECN codepoint, and data
+    /// and produce a (possibly empty) set of outgoing packets.
     ///
     /// This should only be called once.
     pub fn start_threads(
         self: Arc<Self>,
-        handle_packet: impl FnMut(SocketLocator, &mut [u8]) -> Vec<(Vec<u8>, SocketLocator)>
+        handle_packet: impl FnMut(SocketLocator, Ecn, &mut [u8]) -> Vec<(Vec<u8>, SocketLocator)>
             + Clone
             + Send
             + 'static,
@@ -248,11 +340,14 @@ impl PacketServerState {
     fn run(
         self: Arc<Self>,
         epoll_fd: RawFd,
-        mut handle_packet: impl FnMut(SocketLocator, &mut [u8]) -> Vec<(Vec<u8>, SocketLocator)>,
+        mut handle_packet: impl FnMut(SocketLocator, Ecn, &mut [u8]) -> Vec<(Vec<u8>, SocketLocator)>,
     ) {
         let new_client_socket_fd = self.new_client_socket.as_raw_fd();
         let new_tcp_socket_fd = self.new_tcp_socket.as_raw_fd();
+        let tls_wakeup_fd = self.tls_wakeup.as_raw_fd();
         let mut buf = [0u8; MAX_RTP_LENGTH];
+        let mut recv_batch = udp_batch::RecvBatch::new(&self.udp_batching);
+        let mut ready_sockets = Vec::with_capacity(MAX_EPOLL_EVENTS);
 
         loop {
             let mut current_events = [EpollEvent::empty(); MAX_EPOLL_EVENTS];
@@ -260,8 +355,23 @@ impl PacketServerState {
                 warn!("epoll_wait() failed: {}", err);
                 0
             });
+            ready_sockets.clear();
             for event in &current_events[..num_events] {
                 let socket_fd = event.data() as i32;
+                if socket_fd == tls_wakeup_fd {
+                    // Reset the eventfd. If another thread got here first, this does nothing.
+                    let _ = (&self.tls_wakeup).read(&mut [0u8; 8]);
+                    ready_sockets.extend(
+                        self.tls_sockets_to_read
+                            .lock()
+                            .drain(..)
+                            .map(|socket_fd| (socket_fd, EpollFlags::EPOLLIN)),
+                    );
+                } else {
+                    ready_sockets.push((socket_fd, event.events()));
+                }
+            }
+            for &(socket_fd, events) in &ready_sockets {
                 let connections_lock = self.all_connections.read();
                 let socket = if socket_fd == new_client_socket_fd {
                     &self.new_client_socket
@@ -270,28 +380,7 @@ impl PacketServerState {
 
                     match self.new_tcp_socket.accept() {
                         Ok((client_socket, addr)) => {
-                            // TODO: explore TCP_CORK instead of/in addition to TCP_NODELAY
-                            let _ = client_socket.set_nodelay(true); // fail quietly
-                            client_socket
-                                .set_nonblocking(true)
-                                .expect("Cannot set non-blocking");
-
-                            let is_ipv6 = match addr.ip() {
-                                V4(_) => false,
-                                V6(addr) => addr.to_ipv4_mapped().is_none(),
-                            };
-                            let id = self.tcp_id_generator.next_id();
-                            let client_socket = Socket::new_tcp(client_socket, id, is_ipv6);
-
-                            let mut write_lock = self.all_connections.write();
-
-                            if self.add_socket_to_poll_for_reads(&client_socket).is_ok() {
-                                write_lock.get_or_insert_connected(
-                                    client_socket,
-                                    SocketLocator::Tcp { id, is_ipv6 },
-                                    Some(self.tick_number.load(AtomicOrdering::Relaxed)),
-                                );
-                            }
+                            self.add_accepted_tcp_socket(client_socket, addr, None);
                         }
                         Err(err) => match err.kind() {
                             io::ErrorKind::WouldBlock => {}
@@ -312,7 +401,7 @@ impl PacketServerState {
                     }
                 };
 
-                if event.events().contains(EpollFlags::EPOLLERR) {
+                if events.contains(EpollFlags::EPOLLERR) {
                     match socket.take_error() {
                         Err(err) => {
                             warn!("take_error() failed: {}", err);
@@ -371,18 +460,18 @@ impl PacketServerState {
 
                 // We ignore all other events but EPOLLIN; hangups will be handled by tick()
                 // expiring the connection.
-                if !event.events().contains(EpollFlags::EPOLLIN) {
+                if !events.contains(EpollFlags::EPOLLIN) {
                     continue;
                 }
 
-                // We only read one packet for each socket that's ready. This isn't as efficient
-                // as it could be; if one socket has many packets ready, we have to go back into
+                // We only read one small batch of packets for each socket that's ready (one
+                // packet for TCP, as many as were already decrypted for TLS). If one socket has many packets ready, we have to go back into
                 // the epoll loop to find that out. On the other hand, this does ensure that we
                 // don't get stuck reading from one socket and ignore all others.
                 //
                 // Note that this relies on using epoll in level-triggered mode rather than
                 // edge-triggered.
-                let (size, sender_addr) = match socket.recv_from(&mut buf) {
+                let received = match socket.recv(&mut buf, &mut recv_batch, &self.udp_batching) {
                     Err(err) => {
                         match err.kind() {
                             io::ErrorKind::TimedOut
@@ -413,13 +502,34 @@ impl PacketServerState {
                         continue;
                     }
 
-                    Ok((size, sender_addr)) => (size, sender_addr),
+                    Ok(received) => received,
                 };
                 drop(connections_lock);
 
-                let packets_to_send = handle_packet(sender_addr, &mut buf[..size]);
-                for (buf, addr) in packets_to_send {
-                    self.send_packet(&buf, addr)
+                let mut packets_to_send = vec![];
+                match received {
+                    Received::UdpBatch => recv_batch.for_each_packet(|sender_addr, ecn, data| {
+                        packets_to_send.extend(handle_packet(
+                            SocketLocator::Udp(sender_addr),
+                            ecn,
+                            data,
+                        ))
+                    }),
+                    Received::Tcp(size, sender_addr) => {
+                        packets_to_send.extend(handle_packet(
+                            sender_addr,
+                            Ecn::NotEct,
+                            &mut buf[..size],
+                        ));
+                    }
+                    Received::Tls(mut packets, sender_addr) => {
+                        for packet in &mut packets {
+                            packets_to_send.extend(handle_packet(sender_addr, Ecn::NotEct, packet));
+                        }
+                    }
+                }
+                for (addr, bufs) in Self::group_by_addr(&packets_to_send) {
+                    self.send_packets(&bufs, addr)
                 }
             }
         }
@@ -452,10 +562,20 @@ impl PacketServerState {
         }
     }
 
-    /// Sends socket and returns true if socket is still good, or false if
+    /// Groups packets by where they're going, so that those going to the same place can be sent
+    /// together.
+    fn group_by_addr(packets: &[(Vec<u8>, SocketLocator)]) -> HashMap<SocketLocator, Vec<&[u8]>> {
+        let mut bufs_by_addr: HashMap<SocketLocator, Vec<&[u8]>> = HashMap::new();
+        for (buf, addr) in packets {
+            bufs_by_addr.entry(*addr).or_default().push(buf);
+        }
+        bufs_by_addr
+    }
+
+    /// Sends on socket and returns true if socket is still good, or false if
     /// it should be closed.
-    fn send_and_keep(socket: &Socket, buf: &[u8]) -> bool {
-        if let Err(err) = socket.send(buf) {
+    fn send_and_keep(&self, socket: &Socket, bufs: &[&[u8]]) -> bool {
+        if let Err(err) = socket.send(bufs, &self.udp_batching) {
             match err.kind() {
                 io::ErrorKind::ConnectionRefused | io::ErrorKind::BrokenPipe => {
                     // This can happen when someone leaves a call
@@ -475,17 +595,24 @@ impl PacketServerState {
     }
 
     pub fn send_packet(&self, buf: &[u8], addr: SocketLocator) {
-        trace!("sending packet of {} bytes to {}", buf.len(), addr);
+        self.send_packets(&[buf], addr)
+    }
+
+    /// Sends packets to the same address, batching them if possible.
+    fn send_packets(&self, bufs: &[&[u8]], addr: SocketLocator) {
+        trace!("sending {} packets to {}", bufs.len(), addr);
         time_scope!(
             "calling.udp.epoll.send_packet",
             TimingOptions::nanosecond_1000_per_minute()
         );
-        sampling_histogram!("calling.epoll.send_packet.size_bytes", || buf.len());
+        for buf in bufs {
+            sampling_histogram!("calling.epoll.send_packet.size_bytes", || buf.len());
+        }
 
         let connections_lock = self.all_connections.read();
         match connections_lock.get_by_addr(&addr) {
             ConnectionState::Connected(socket) => {
-                if !Self::send_and_keep(socket, buf) {
+                if !self.send_and_keep(socket, bufs) {
                     // Drop the read lock...
                     drop(connections_lock);
                     // ...and connect with a write lock...
@@ -504,7 +631,7 @@ impl PacketServerState {
                 }
             }
             ConnectionState::New(socket) => {
-                if Self::send_and_keep(socket, buf) {
+                if self.send_and_keep(socket, bufs) {
                     drop(connections_lock);
                     let mut write_lock = self.all_connections.write();
                     write_lock.mark_as_active(&addr);
@@ -527,14 +654,14 @@ impl PacketServerState {
                 match write_lock.get_by_addr(&addr) {
                     ConnectionState::New(socket) => {
                         warn!("shouldn't find new TCP socket in send_packet after NotYetConnected");
-                        if Self::send_and_keep(socket, buf) {
+                        if self.send_and_keep(socket, bufs) {
                             write_lock.mark_as_active(&addr);
                         } else {
                             write_lock.mark_closed(&addr, Instant::now());
                         }
                     }
                     ConnectionState::Connected(socket) => {
-                        if !Self::send_and_keep(socket, buf) {
+                        if !self.send_and_keep(socket, bufs) {
                             write_lock.mark_closed(&addr, Instant::now());
                         }
                     }
@@ -548,11 +675,17 @@ impl PacketServerState {
                                 let client_socket =
                                     Self::open_socket_with_reusable_port(&self.local_addr_udp)?;
                                 client_socket.connect(udp_addr)?;
+                                self.udp_batching.enable_gro(&client_socket);
+                                packet_server::set_up_ecn(
+                                    &client_socket,
+                                    self.local_addr_udp.is_ipv6(),
+                                    self.send_ecn_capable,
+                                );
                                 self.add_socket_to_poll_for_reads(&client_socket)?;
                                 let client_socket = Socket::Udp(client_socket);
                                 let client_socket =
                                     write_lock.get_or_insert_connected(client_socket, addr, None);
-                                if !Self::send_and_keep(client_socket, buf) {
+                                if !self.send_and_keep(client_socket, bufs) {
                                     write_lock.mark_closed(&addr, Instant::now());
                                 }
                                 Ok(())
@@ -582,13 +715,13 @@ impl PacketServerState {
 
         {
             time_scope_us!("calling.packet_server.tick.sending");
-            for (buf, addr) in tick_update.packets_to_send {
-                trace!("sending tick packet of {} bytes to {}", buf.len(), addr);
+            for (addr, bufs) in Self::group_by_addr(&tick_update.packets_to_send) {
+                trace!("sending {} tick packets to {}", bufs.len(), addr);
 
                 let connections_lock = self.all_connections.read();
                 match connections_lock.get_by_addr(&addr) {
                     ConnectionState::New(socket) => {
-                        if !Self::send_and_keep(socket, &buf) {
+                        if !self.send_and_keep(socket, &bufs) {
                             warn!("shouldn't find new TCP socket in tick, closing");
                             // This will call mark_closed below
                             tick_update.expired_client_addrs.push(addr)
@@ -597,7 +730,7 @@ impl PacketServerState {
                         }
                     }
                     ConnectionState::Connected(socket) => {
-                        if !Self::send_and_keep(socket, &buf) {
+                        if !self.send_and_keep(socket, &bufs) {
                             // This will call mark_closed below
                             tick_update.expired_client_addrs.push(addr)
                         }
@@ -696,8 +829,8 @@ impl PacketServerState {
     }
 }
 
-struct TcpState {
-    socket: TcpStream,
+struct TcpState<S = TcpStream> {
+    socket: S,
     size: usize,
     pos: usize,
     buf: [u8; MAX_RTP_LENGTH],
@@ -706,7 +839,26 @@ struct TcpState {
     is_ipv6: bool,
 }
 
-impl TcpState {
+impl<S: Read + Write> TcpState<S> {
+    fn new(socket: S, id: i64, is_ipv6: bool) -> Self {
+        Self {
+            socket,
+            size: 0,
+            pos: 0,
+            buf: [0u8; MAX_RTP_LENGTH],
+            outq: VecDeque::new(),
+            id,
+            is_ipv6,
+        }
+    }
+
+    fn locator(&self) -> SocketLocator {
+        SocketLocator::Tcp {
+            id: self.id,
+            is_ipv6: self.is_ipv6,
+        }
+    }
+
     fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketLocator)> {
         if self.size > MAX_RTP_LENGTH {
             // this has already been logged
@@ -756,13 +908,7 @@ impl TcpState {
                         buf[0..self.pos].copy_from_slice(&self.buf[0..self.pos]);
                         self.size = 0;
                         self.pos = 0;
-                        return Ok((
-                            size,
-                            SocketLocator::Tcp {
-                                id: self.id,
-                                is_ipv6: self.is_ipv6,
-                            },
-                        ));
+                        return Ok((size, self.locator()));
                     }
                 }
                 Err(err) => return Err(err),
@@ -855,11 +1001,62 @@ impl TcpState {
 
         Err(io::Error::from(io::ErrorKind::WouldBlock))
     }
+
+    fn send_all(&mut self, bufs: &[&[u8]]) -> io::Result<()> {
+        let mut result = Ok(());
+        for buf in bufs {
+            match self.send(buf) {
+                Ok(()) => {}
+                // The packet was queued or dropped; later ones might still fit.
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => result = Err(err),
+                Err(err) => return Err(err),
+            }
+        }
+        result
+    }
+}
+
+impl TcpState<tls::TlsStream> {
+    /// Receives all the packets that can be decrypted without going back to epoll, up to a limit.
+    fn recv_all(&mut self, buf: &mut [u8]) -> io::Result<Vec<Vec<u8>>> {
+        let mut packets = vec![];
+        loop {
+            match self.recv_from(buf) {
+                Ok((size, _)) => {
+                    packets.push(buf[..size].to_vec());
+                    if packets.len() >= MAX_TLS_PACKETS_PER_EVENT
+                        && !self.socket.has_buffered_plaintext()
+                    {
+                        break;
+                    }
+                }
+                // recv_from stops after reading the length, even if the rest is already here.
+                Err(err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        && self.socket.has_buffered_plaintext() => {}
+                // Any error other than WouldBlock will happen again on the next read.
+                Err(err) if packets.is_empty() => return Err(err),
+                Err(_) => break,
+            }
+        }
+        Ok(packets)
+    }
+}
+
+/// The result of [`Socket::recv`].
+enum Received {
+    /// A batch of UDP packets, left in the [udp_batch::RecvBatch].
+    UdpBatch,
+    /// A single TCP packet of the given size, in the provided buffer.
+    Tcp(usize, SocketLocator),
+    /// Packets from a TLS connection.
+    Tls(Vec<Vec<u8>>, SocketLocator),
 }
 
 enum Socket {
     Udp(UdpSocket),
     Tcp(Box<Mutex<TcpState>>),
+    Tls(Box<Mutex<TcpState<tls::TlsStream>>>),
 }
 
 impl AsRawFd for Socket {
@@ -867,49 +1064,57 @@ impl AsRawFd for Socket {
         match self {
             Socket::Udp(s) => s.as_raw_fd(),
             Socket::Tcp(m) => m.lock().socket.as_raw_fd(),
+            Socket::Tls(m) => m.lock().socket.as_raw_fd(),
         }
     }
 }
 
 impl Socket {
     fn new_tcp(s: TcpStream, id: i64, is_ipv6: bool) -> Self {
-        Socket::Tcp(Box::new(Mutex::new(TcpState {
-            socket: s,
-            size: 0,
-            pos: 0,
-            buf: [0u8; MAX_RTP_LENGTH],
-            outq: VecDeque::new(),
-            id,
-            is_ipv6,
-        })))
+        Socket::Tcp(Box::new(Mutex::new(TcpState::new(s, id, is_ipv6))))
+    }
+
+    fn new_tls(s: tls::TlsStream, id: i64, is_ipv6: bool) -> Self {
+        Socket::Tls(Box::new(Mutex::new(TcpState::new(s, id, is_ipv6))))
     }
 
-    fn send(&self, buf: &[u8]) -> io::Result<()> {
+    fn send(&self, bufs: &[&[u8]], udp_batching: &udp_batch::Support) -> io::Result<()> {
         match self {
-            Socket::Udp(s) => s.send(buf).map(|_| ()),
-            Socket::Tcp(m) => m.lock().send(buf),
+            Socket::Udp(s) => udp_batch::send(udp_batching, s.as_raw_fd(), bufs),
+            Socket::Tcp(m) => m.lock().send_all(bufs),
+            Socket::Tls(m) => m.lock().send_all(bufs),
         }
     }
 
-    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketLocator)> {
+    /// Receives a single TCP packet into `buf`, packets from a TLS connection, or a batch of UDP
+    /// packets into `recv_batch`.
+    fn recv(
+        &self,
+        buf: &mut [u8],
+        recv_batch: &mut udp_batch::RecvBatch,
+        udp_batching: &udp_batch::Support,
+    ) -> io::Result<Received> {
         match self {
-            Socket::Udp(s) => s
+            Socket::Udp(s) => recv_batch
+                .recv(udp_batching, s.as_raw_fd())
+                .map(|()| Received::UdpBatch),
+            Socket::Tcp(m) => m
+                .lock()
                 .recv_from(buf)
-                .map(|(size, addr)| (size, SocketLocator::Udp(addr))),
-            Socket::Tcp(m) => m.lock().recv_from(buf),
+                .map(|(size, sender_addr)| Received::Tcp(size, sender_addr)),
+            Socket::Tls(m) => {
+                let mut state = m.lock();
+                let packets = state.recv_all(buf)?;
+                Ok(Received::Tls(packets, state.locator()))
+            }
         }
     }
 
     fn peer_addr(&self) -> io::Result<SocketLocator> {
         match self {
             Socket::Udp(s) => s.peer_addr().map(SocketLocator::Udp),
-            Socket::Tcp(m) => {
-                let state = m.lock();
-                Ok(SocketLocator::Tcp {
-                    id: state.id,
-                    is_ipv6: state.is_ipv6,
-                })
-            }
+            Socket::Tcp(m) => Ok(m.lock().locator()),
+            Socket::Tls(m) => Ok(m.lock().locator()),
         }
     }
 
@@ -917,6 +1122,7 @@ impl Socket {
         match self {
             Socket::Udp(s) => s.take_error(),
             Socket::Tcp(m) => m.lock().socket.take_error(),
+            Socket::Tls(m) => m.lock().socket.take_error(),
         }
     }
 }
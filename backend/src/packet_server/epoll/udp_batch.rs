@@ -0,0 +1,551 @@
+//
+// Copyright 2023 Signal Messenger, LLC
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Batched UDP sends and receives for the epoll server.
+//!
+//! Packets sent to the same (connected) socket together are combined into one `sendmmsg`, and runs
+//! of same-sized packets within that are combined further using UDP generic segmentation offload
+//! (GSO). Receives use `recvmmsg` with UDP generic receive offload (GRO) enabled, so one buffer can
+//! hold several packets. If the kernel doesn't support one of these, we do without it from then on.
+//! A GSO send that fails for another reason is retried without GSO.
+//!
+//! Receives also report the ECN codepoint each packet arrived with (see [`set_up_ecn`]).
+
+use std::{
+    io, mem,
+    net::{SocketAddr, SocketAddrV4, SocketAddrV6},
+    os::unix::io::{AsRawFd, RawFd},
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use log::*;
+use nix::{
+    errno::Errno,
+    libc,
+    sys::socket::{setsockopt, sockopt, SockaddrLike, SockaddrStorage},
+};
+
+use crate::ccfb::Ecn;
+#[cfg(test)]
+use crate::packet_server::set_up_ecn;
+
+/// The most segments the kernel allows in one GSO send (UDP_MAX_SEGMENTS).
+const MAX_GSO_SEGMENTS: usize = 64;
+
+/// The most bytes we put in one GSO send, which must fit in a single IP packet before
+/// segmentation.
+const MAX_GSO_SIZE: usize = 65_000;
+
+/// The buffer size needed to receive packets coalesced by GRO.
+const GRO_BUFFER_SIZE: usize = 65_535;
+
+/// Maximum RTP packet size (WebRTC has kVideoMtu = 1200).
+const MAX_RTP_LENGTH: usize = 1500;
+
+/// The most messages read with one `recvmmsg`.
+///
+/// This is kept small so that one busy socket doesn't keep a thread from the others.
+const RECV_BATCH_SIZE: usize = 8;
+
+/// Room for two control messages holding an int (UDP_SEGMENT or UDP_GRO, plus the TOS or traffic
+/// class), suitably aligned.
+type ControlBuffer = [u64; 8];
+
+/// Which of the batching features the kernel supports.
+///
+/// Everything is assumed to be supported until a send or receive shows otherwise.
+pub struct Support {
+    gso: AtomicBool,
+    gro: AtomicBool,
+    sendmmsg: AtomicBool,
+    recvmmsg: AtomicBool,
+}
+
+impl Support {
+    pub fn new() -> Self {
+        Self {
+            gso: AtomicBool::new(true),
+            gro: AtomicBool::new(true),
+            sendmmsg: AtomicBool::new(true),
+            recvmmsg: AtomicBool::new(true),
+        }
+    }
+
+    /// Turns on GRO for `socket`, if supported.
+    pub fn enable_gro(&self, socket: &impl AsRawFd) {
+        if !self.gro.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Err(err) = setsockopt(socket.as_raw_fd(), sockopt::UdpGroSegment, &true) {
+            info!("UDP GRO not supported: {}", err);
+            self.gro.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Disables `feature` if `err` shows that the kernel doesn't support it.
+    /// Returns true if it did.
+    ///
+    /// Errors such as EINVAL and EIO can be caused by a single send (like its segment size or
+    /// destination), so they don't disable anything.
+    fn disable_if_unsupported(feature: &AtomicBool, name: &str, err: &io::Error) -> bool {
+        let unsupported = matches!(
+            err.raw_os_error().map(Errno::from_i32),
+            Some(Errno::ENOSYS | Errno::EOPNOTSUPP | Errno::ENOPROTOOPT)
+        );
+        if unsupported && feature.swap(false, Ordering::Relaxed) {
+            info!("{} not supported: {}", name, err);
+            event!("calling.udp.epoll.batching_unsupported");
+        }
+        unsupported
+    }
+}
+
+/// Returns the end of the run of packets starting at `start` that can be sent with a single GSO
+/// send: all the same size, except that the last can be smaller.
+fn gso_run_end(bufs: &[&[u8]], start: usize) -> usize {
+    let segment_size = bufs[start].len();
+    let mut end = start + 1;
+    while end < bufs.len()
+        && end - start < MAX_GSO_SEGMENTS
+        && (end - start + 1) * segment_size <= MAX_GSO_SIZE
+    {
+        let len = bufs[end].len();
+        if len == 0 || len > segment_size {
+            break;
+        }
+        end += 1;
+        if len < segment_size {
+            break;
+        }
+    }
+    end
+}
+
+/// Sends `bufs` in order on connected socket `fd`, using as few system calls as it can.
+///
+/// Returns the error from the first send that failed, if any; packets after that aren't sent.
+pub fn send(support: &Support, fd: RawFd, bufs: &[&[u8]]) -> io::Result<()> {
+    send_with_or_without_gso(support, fd, bufs, support.gso.load(Ordering::Relaxed))
+}
+
+fn send_with_or_without_gso(
+    support: &Support,
+    fd: RawFd,
+    bufs: &[&[u8]],
+    use_gso: bool,
+) -> io::Result<()> {
+    if bufs.is_empty() {
+        return Ok(());
+    }
+
+    // Each message is one or more packets (GSO segments).
+    let mut messages: Vec<&[&[u8]]> = Vec::with_capacity(bufs.len());
+    let mut start = 0;
+    while start < bufs.len() {
+        let end = if use_gso {
+            gso_run_end(bufs, start)
+        } else {
+            start + 1
+        };
+        messages.push(&bufs[start..end]);
+        start = end;
+    }
+    sampling_histogram!("calling.epoll.send_batch.packets", || bufs.len());
+    sampling_histogram!("calling.epoll.send_batch.messages", || messages.len());
+
+    let mut iovecs: Vec<libc::iovec> = bufs
+        .iter()
+        .map(|buf| libc::iovec {
+            iov_base: buf.as_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        })
+        .collect();
+    let mut controls: Vec<ControlBuffer> = vec![[0; 8]; messages.len()];
+    let mut headers: Vec<libc::mmsghdr> = Vec::with_capacity(messages.len());
+    let mut first_iovec = 0;
+    for (message, control) in messages.iter().zip(controls.iter_mut()) {
+        // Safety: these are plain C structs, for which all zeros is valid.
+        let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+        header.msg_hdr.msg_iov = iovecs[first_iovec..].as_mut_ptr();
+        header.msg_hdr.msg_iovlen = message.len() as _;
+        if message.len() > 1 {
+            let segment_size = message[0].len() as u16;
+            header.msg_hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            // Safety: the control buffer is big enough for one u16 control message
+            // and the header points to it.
+            unsafe {
+                header.msg_hdr.msg_controllen = libc::CMSG_SPACE(mem::size_of::<u16>() as u32) as _;
+                let cmsg = libc::CMSG_FIRSTHDR(&header.msg_hdr);
+                (*cmsg).cmsg_level = libc::SOL_UDP;
+                (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as u32) as _;
+                ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment_size);
+            }
+        }
+        headers.push(header);
+        first_iovec += message.len();
+    }
+
+    let mut sent = 0;
+    while sent < headers.len() {
+        let result = if support.sendmmsg.load(Ordering::Relaxed) {
+            // Safety: the headers point to iovecs and control buffers that outlive the call.
+            unsafe {
+                libc::sendmmsg(
+                    fd,
+                    headers[sent..].as_mut_ptr(),
+                    (headers.len() - sent) as _,
+                    0,
+                )
+            }
+        } else {
+            // Safety: same as above.
+            match unsafe { libc::sendmsg(fd, &headers[sent].msg_hdr, 0) } {
+                err if err < 0 => -1,
+                _ => 1,
+            }
+        };
+        if result < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(Errno::ENOSYS as i32)
+                && Support::disable_if_unsupported(&support.sendmmsg, "sendmmsg", &err)
+            {
+                continue;
+            }
+            if messages[sent].len() > 1 {
+                let retry_without_gso =
+                    if Support::disable_if_unsupported(&support.gso, "UDP GSO", &err) {
+                        true
+                    } else if matches!(
+                        err.raw_os_error().map(Errno::from_i32),
+                        Some(Errno::EINVAL | Errno::EIO)
+                    ) {
+                        // Possibly just this send's fault, so keep using GSO for others.
+                        debug!("UDP GSO send failed, retrying without it: {}", err);
+                        event!("calling.udp.epoll.gso_send_retried");
+                        true
+                    } else {
+                        false
+                    };
+                if retry_without_gso {
+                    // Send what's left without GSO.
+                    let unsent_bufs = &bufs[messages[..sent].iter().map(|m| m.len()).sum()..];
+                    return send_with_or_without_gso(support, fd, unsent_bufs, false);
+                }
+            }
+            return Err(err);
+        }
+        sent += result as usize;
+    }
+    Ok(())
+}
+
+/// Buffers for receiving a batch of packets with `recvmmsg`.
+pub struct RecvBatch {
+    buffers: Vec<Vec<u8>>,
+    addrs: Vec<libc::sockaddr_storage>,
+    iovecs: Vec<libc::iovec>,
+    controls: Vec<ControlBuffer>,
+    headers: Vec<libc::mmsghdr>,
+    count: usize,
+}
+
+impl RecvBatch {
+    pub fn new(support: &Support) -> Self {
+        let buffer_size = if support.gro.load(Ordering::Relaxed) {
+            GRO_BUFFER_SIZE
+        } else {
+            MAX_RTP_LENGTH
+        };
+        Self {
+            buffers: vec![vec![0u8; buffer_size]; RECV_BATCH_SIZE],
+            // Safety: these are plain C structs, for which all zeros is valid.
+            addrs: vec![unsafe { mem::zeroed() }; RECV_BATCH_SIZE],
+            iovecs: vec![unsafe { mem::zeroed() }; RECV_BATCH_SIZE],
+            controls: vec![[0; 8]; RECV_BATCH_SIZE],
+            headers: vec![unsafe { mem::zeroed() }; RECV_BATCH_SIZE],
+            count: 0,
+        }
+    }
+
+    /// Receives the packets that are ready on `fd`, up to the batch size, without blocking.
+    ///
+    /// Returns a WouldBlock error if there aren't any.
+    pub fn recv(&mut self, support: &Support, fd: RawFd) -> io::Result<()> {
+        self.count = 0;
+        for i in 0..RECV_BATCH_SIZE {
+            self.iovecs[i].iov_base = self.buffers[i].as_mut_ptr() as *mut libc::c_void;
+            self.iovecs[i].iov_len = self.buffers[i].len();
+            let header = &mut self.headers[i];
+            header.msg_len = 0;
+            header.msg_hdr.msg_name = &mut self.addrs[i] as *mut _ as *mut libc::c_void;
+            header.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+            header.msg_hdr.msg_iov = &mut self.iovecs[i];
+            header.msg_hdr.msg_iovlen = 1;
+            header.msg_hdr.msg_control = self.controls[i].as_mut_ptr() as *mut libc::c_void;
+            header.msg_hdr.msg_controllen = mem::size_of::<ControlBuffer>() as _;
+            header.msg_hdr.msg_flags = 0;
+        }
+
+        if support.recvmmsg.load(Ordering::Relaxed) {
+            // Safety: the headers point to buffers that outlive the call.
+            let result = unsafe {
+                libc::recvmmsg(
+                    fd,
+                    self.headers.as_mut_ptr(),
+                    RECV_BATCH_SIZE as _,
+                    libc::MSG_DONTWAIT,
+                    ptr::null_mut(),
+                )
+            };
+            if result >= 0 {
+                self.count = result as usize;
+                sampling_histogram!("calling.epoll.recv_batch.messages", || self.count);
+                return Ok(());
+            }
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(Errno::ENOSYS as i32)
+                || !Support::disable_if_unsupported(&support.recvmmsg, "recvmmsg", &err)
+            {
+                return Err(err);
+            }
+        }
+
+        // Safety: same as above.
+        let result = unsafe { libc::recvmsg(fd, &mut self.headers[0].msg_hdr, libc::MSG_DONTWAIT) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.headers[0].msg_len = result as _;
+        self.count = 1;
+        Ok(())
+    }
+
+    /// Calls `f` with the sender, ECN codepoint, and contents of each packet received by the last
+    /// call to [`RecvBatch::recv`], splitting up packets coalesced by GRO (which only coalesces
+    /// packets with the same codepoint).
+    pub fn for_each_packet(&mut self, mut f: impl FnMut(SocketAddr, Ecn, &mut [u8])) {
+        for i in 0..self.count {
+            let header = &self.headers[i];
+            if header.msg_hdr.msg_flags & libc::MSG_TRUNC != 0 {
+                event!("calling.udp.epoll.recv_truncated");
+                continue;
+            }
+            let len = header.msg_len as usize;
+            let Some(sender_addr) = Self::sender_addr(&self.addrs[i], header.msg_hdr.msg_namelen)
+            else {
+                continue;
+            };
+            let (gro_segment_size, ecn) = Self::read_control_messages(&header.msg_hdr);
+            let segment_size = gro_segment_size.unwrap_or(len);
+            if segment_size == 0 {
+                continue;
+            }
+            for packet in self.buffers[i][..len].chunks_mut(segment_size) {
+                f(sender_addr, ecn, packet);
+            }
+        }
+        self.count = 0;
+    }
+
+    fn sender_addr(addr: &libc::sockaddr_storage, len: libc::socklen_t) -> Option<SocketAddr> {
+        let addr = addr as *const _ as *const libc::sockaddr;
+        // Safety: the kernel filled in the address and its length.
+        let addr = unsafe { SockaddrStorage::from_raw(addr, Some(len)) }?;
+        if let Some(addr) = addr.as_sockaddr_in() {
+            Some(SocketAddrV4::from(*addr).into())
+        } else {
+            addr.as_sockaddr_in6()
+                .map(|addr| SocketAddrV6::from(*addr).into())
+        }
+    }
+
+    /// Returns the GRO segment size, if the packets were coalesced, and their ECN codepoint
+    /// (Not-ECT if the kernel didn't say).
+    fn read_control_messages(header: &libc::msghdr) -> (Option<usize>, Ecn) {
+        let mut gro_segment_size = None;
+        let mut ecn = Ecn::NotEct;
+        // Safety: the kernel filled in the control messages and their length.
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(header);
+            while !cmsg.is_null() {
+                let data = libc::CMSG_DATA(cmsg);
+                match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                    (libc::SOL_UDP, libc::UDP_GRO) => {
+                        let segment_size = ptr::read_unaligned(data as *const libc::c_int);
+                        gro_segment_size = Some(segment_size as usize);
+                    }
+                    (libc::IPPROTO_IP, libc::IP_TOS) => {
+                        ecn = Ecn::from_tos(ptr::read_unaligned(data));
+                    }
+                    (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                        let traffic_class = ptr::read_unaligned(data as *const libc::c_int);
+                        ecn = Ecn::from_tos(traffic_class as u8);
+                    }
+                    _ => {}
+                }
+                cmsg = libc::CMSG_NXTHDR(header, cmsg);
+            }
+        }
+        (gro_segment_size, ecn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, UdpSocket};
+
+    use super::*;
+
+    fn connected_pair(support: &Support, ecn_capable: bool) -> (UdpSocket, UdpSocket) {
+        let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let receiver = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        support.enable_gro(&receiver);
+        set_up_ecn(&sender, false, ecn_capable);
+        set_up_ecn(&receiver, false, false);
+        sender.connect(receiver.local_addr().unwrap()).unwrap();
+        receiver.connect(sender.local_addr().unwrap()).unwrap();
+        (sender, receiver)
+    }
+
+    fn recv_all(support: &Support, receiver: &UdpSocket, count: usize) -> Vec<(Ecn, Vec<u8>)> {
+        let mut batch = RecvBatch::new(support);
+        let mut received = vec![];
+        let sender_addr = receiver.peer_addr().unwrap();
+        for _ in 0..100 {
+            match batch.recv(support, receiver.as_raw_fd()) {
+                Ok(()) => batch.for_each_packet(|addr, ecn, packet| {
+                    assert_eq!(sender_addr, addr);
+                    received.push((ecn, packet.to_vec()));
+                }),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                }
+                Err(err) => panic!("recv failed: {}", err),
+            }
+            if received.len() >= count {
+                break;
+            }
+        }
+        received
+    }
+
+    fn test_packets() -> Vec<Vec<u8>> {
+        // A run of same-sized packets ending with a smaller one, then different sizes.
+        let mut packets: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; 1200]).collect();
+        packets.push(vec![10; 500]);
+        packets.push(vec![11; 1000]);
+        packets.push(vec![12; 1100]);
+        packets
+    }
+
+    #[test]
+    fn gso_runs() {
+        let a = [1u8; 100];
+        let b = [2u8; 50];
+        let c = [3u8; 200];
+        let bufs: Vec<&[u8]> = vec![&a, &a, &a, &b, &b, &c, &a, &b, &b];
+        assert_eq!(4, gso_run_end(&bufs, 0));
+        assert_eq!(5, gso_run_end(&bufs, 3));
+        // A smaller packet can end a run.
+        assert_eq!(7, gso_run_end(&bufs, 5));
+        assert_eq!(8, gso_run_end(&bufs, 6));
+        assert_eq!(9, gso_run_end(&bufs, 7));
+
+        let many = vec![&a[..]; 100];
+        assert_eq!(MAX_GSO_SEGMENTS, gso_run_end(&many, 0));
+        let big = [0u8; 1500];
+        let many_big = vec![&big[..]; 100];
+        assert_eq!(MAX_GSO_SIZE / 1500, gso_run_end(&many_big, 0));
+    }
+
+    fn with_ecn(ecn: Ecn, packets: &[Vec<u8>]) -> Vec<(Ecn, Vec<u8>)> {
+        packets.iter().map(|packet| (ecn, packet.clone())).collect()
+    }
+
+    #[test]
+    fn send_and_recv_batches() {
+        let support = Support::new();
+        let (sender, receiver) = connected_pair(&support, false);
+
+        let packets = test_packets();
+        let bufs: Vec<&[u8]> = packets.iter().map(|packet| &packet[..]).collect();
+        send(&support, sender.as_raw_fd(), &bufs).unwrap();
+        assert_eq!(
+            with_ecn(Ecn::NotEct, &packets),
+            recv_all(&support, &receiver, packets.len())
+        );
+    }
+
+    #[test]
+    fn send_and_recv_ecn_capable() {
+        let support = Support::new();
+        let (sender, receiver) = connected_pair(&support, true);
+
+        let packets = test_packets();
+        let bufs: Vec<&[u8]> = packets.iter().map(|packet| &packet[..]).collect();
+        send(&support, sender.as_raw_fd(), &bufs).unwrap();
+        assert_eq!(
+            with_ecn(Ecn::Ect0, &packets),
+            recv_all(&support, &receiver, packets.len())
+        );
+    }
+
+    #[test]
+    fn send_and_recv_without_batching() {
+        let support = Support::new();
+        for feature in [
+            &support.gso,
+            &support.gro,
+            &support.sendmmsg,
+            &support.recvmmsg,
+        ] {
+            feature.store(false, Ordering::Relaxed);
+        }
+        let (sender, receiver) = connected_pair(&support, false);
+
+        let packets = test_packets();
+        let bufs: Vec<&[u8]> = packets.iter().map(|packet| &packet[..]).collect();
+        send(&support, sender.as_raw_fd(), &bufs).unwrap();
+        assert_eq!(
+            with_ecn(Ecn::NotEct, &packets),
+            recv_all(&support, &receiver, packets.len())
+        );
+    }
+
+    #[test]
+    fn only_unsupported_errors_disable_batching() {
+        let support = Support::new();
+        for errno in [Errno::EINVAL, Errno::EIO, Errno::EMSGSIZE] {
+            assert!(!Support::disable_if_unsupported(
+                &support.gso,
+                "UDP GSO",
+                &io::Error::from_raw_os_error(errno as i32)
+            ));
+            assert!(support.gso.load(Ordering::Relaxed));
+        }
+        assert!(Support::disable_if_unsupported(
+            &support.gso,
+            "UDP GSO",
+            &io::Error::from_raw_os_error(Errno::EOPNOTSUPP as i32)
+        ));
+        assert!(!support.gso.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn recv_would_block() {
+        let support = Support::new();
+        let (_sender, receiver) = connected_pair(&support, false);
+        let mut batch = RecvBatch::new(&support);
+        assert_eq!(
+            io::ErrorKind::WouldBlock,
+            batch
+                .recv(&support, receiver.as_raw_fd())
+                .unwrap_err()
+                .kind()
+        );
+    }
+}
@@ -15,6 +15,7 @@ use calling_common::Duration;
 use log::*;
 
 use crate::{
+    ccfb::Ecn,
     metrics::TimingOptions,
     packet_server::SocketLocator,
     sfu::{self, SfuStats},
@@ -37,7 +38,11 @@ impl PacketServerState {
         _local_addr_tcp: SocketAddr,
         num_threads: usize,
         _tick_interval: Duration,
+        send_ecn_capable: bool,
     ) -> Result<Arc<Self>> {
+        if send_ecn_capable {
+            anyhow::bail!("the generic packet server doesn't support ECN; unset send_ecn_capable");
+        }
         Ok(Arc::new(Self {
             socket: UdpSocket::bind(local_addr_udp)?,
             num_threads,
@@ -47,13 +52,13 @@ impl PacketServerState {
     /// Launches the configured number of threads for the server using Tokio's blocking thread pool
     /// ([`tokio::task::spawn_blocking`]).
     ///
-    /// `handle_packet` should take a single incoming packet's source address and data and produce a
-    /// (possibly empty) set of outgoing packets.
+    /// `handle_packet` should take a single incoming packet's source address, ECN codepoint, and data
+    /// and produce a (possibly empty) set of outgoing packets.
     ///
     /// This should only be called once.
     pub fn start_threads(
         self: Arc<Self>,
-        handle_packet: impl FnMut(SocketLocator, &mut [u8]) -> Vec<(Vec<u8>, SocketLocator)>
+        handle_packet: impl FnMut(SocketLocator, Ecn, &mut [u8]) -> Vec<(Vec<u8>, SocketLocator)>
             + Clone
             + Send
             + 'static,
@@ -71,7 +76,7 @@ impl PacketServerState {
     /// See [`PacketServerState::start_threads`].
     fn run(
         self: Arc<Self>,
-        mut handle_packet: impl FnMut(SocketLocator, &mut [u8]) -> Vec<(Vec<u8>, SocketLocator)>,
+        mut handle_packet: impl FnMut(SocketLocator, Ecn, &mut [u8]) -> Vec<(Vec<u8>, SocketLocator)>,
     ) {
         let mut buf = [0u8; 1500];
 
@@ -85,8 +90,11 @@ impl PacketServerState {
             };
 
             if let Some((size, sender_addr)) = received_packet {
-                let packets_to_send =
-                    handle_packet(SocketLocator::Udp(sender_addr), &mut buf[..size]);
+                let packets_to_send = handle_packet(
+                    SocketLocator::Udp(sender_addr),
+                    Ecn::NotEct,
+                    &mut buf[..size],
+                );
                 for (buf, addr) in packets_to_send {
                     time_scope!(
                         "calling.udp.generic.send_packet",
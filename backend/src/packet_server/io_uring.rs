@@ -0,0 +1,1021 @@
+//
+// Copyright 2023 Signal Messenger, LLC
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    net::{SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket},
+    os::unix::io::{AsRawFd, FromRawFd},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Result;
+use calling_common::{Duration, Instant};
+use io_uring::{opcode, squeue, types, IoUring};
+use log::*;
+use nix::{
+    errno::Errno,
+    libc,
+    sys::socket::{SockaddrLike, SockaddrStorage},
+};
+use parking_lot::{Mutex, RwLock};
+use scopeguard::ScopeGuard;
+
+use crate::{
+    ccfb::Ecn,
+    metrics::TimingOptions,
+    packet_server::{self, SocketLocator},
+    sfu::{self, SfuStats},
+};
+
+/// Maximum RTP packet size (WebRTC has kVideoMtu = 1200).
+const MAX_RTP_LENGTH: usize = 1500;
+
+/// The number of submission queue entries for each thread's ring.
+/// The completion queue is twice as large.
+const RING_ENTRIES: u32 = 256;
+
+/// The number of clients each thread can have a connected socket for.
+///
+/// Each connected socket always has a read in flight into its own registered buffer. Clients
+/// beyond this keep using the thread's listening socket.
+const MAX_CONNECTIONS_PER_THREAD: usize = 256;
+
+/// The number of receives each thread keeps submitted on its listening socket, which only gets
+/// packets from clients that don't have a connected socket (yet).
+const NUM_LISTEN_RECVS_IN_FLIGHT: usize = 16;
+
+/// The maximum number of sends each thread has submitted but not yet seen complete, which is also
+/// the number of registered send buffers.
+///
+/// Beyond this, packets are sent synchronously rather than through the ring.
+const MAX_SENDS_IN_FLIGHT: usize = 128;
+
+/// The index of each thread's registered buffer (see `register_buffers`), which holds a read
+/// buffer for each connection followed by a send buffer for each send in flight.
+const FIXED_BUFFER_INDEX: u16 = 0;
+
+/// The fixed file index of each thread's listening socket (see `register_files`).
+const LISTEN_SOCKET_FIXED_FILE: types::Fixed = types::Fixed(0);
+
+/// How long before an address can get a new connected socket, if it was previously used by a
+/// now-closed one.
+///
+/// This keeps delayed sends from "reviving" a closed connection.
+const CLOSED_SOCKET_EXPIRATION_IN_TICKS: u32 = 10;
+
+/// How many times in a row `submit_and_wait` can fail before the thread gives up.
+const MAX_CONSECUTIVE_SUBMIT_ERRORS: u32 = 10;
+
+/// How long to wait after `submit_and_wait` fails the first time. This doubles for each failure
+/// in a row.
+const INITIAL_SUBMIT_ERROR_BACKOFF: Duration = Duration::from_millis(1);
+
+// The high bits of user_data say what kind of operation completed.
+// The low bits are the index of the receive, read, or send.
+const USER_DATA_KIND_MASK: u64 = 0xFF << 56;
+const USER_DATA_LISTEN_RECV: u64 = 1 << 56;
+const USER_DATA_READ: u64 = 2 << 56;
+const USER_DATA_SEND: u64 = 3 << 56;
+
+/// The shared state for an io_uring-based packet server, only UDP is supported.
+///
+/// Each thread has a listening socket bound to the same address with `SO_REUSEPORT`, so the kernel
+/// spreads new clients across threads, and its own [io_uring]. Like the epoll server, the first
+/// packet sent to a client creates a socket connected to that client, which then gets all of that
+/// client's packets. Reads and writes on connected sockets don't need an address, so they use
+/// buffers registered with the ring up front (`ReadFixed` and `WriteFixed`) rather than having
+/// the kernel map each buffer for each operation. The sends produced by handling a batch of
+/// received packets are submitted together with the next reads in a single system call.
+///
+/// Packets sent from outside of the packet server threads (such as by [`Self::tick`]) don't go
+/// through a ring; they're sent synchronously.
+///
+/// [io_uring]: https://man7.org/linux/man-pages/man7/io_uring.7.html
+pub struct PacketServerState {
+    local_addr_udp: SocketAddr,
+    send_ecn_capable: bool,
+    /// Used for sends to clients without a connected socket from outside of the packet server
+    /// threads. This is the same socket as the first thread's listening socket.
+    send_socket: UdpSocket,
+    /// Taken by [`Self::start_threads`].
+    unstarted_threads: Mutex<Vec<UnstartedThread>>,
+    connections: RwLock<HashMap<SocketAddr, Connection>>,
+    tick_interval: Duration,
+    /// The number of connected sockets being read, including closed ones whose reads haven't
+    /// completed yet.
+    connected_sockets: AtomicUsize,
+    sends_in_flight: AtomicUsize,
+    synchronous_sends: AtomicUsize,
+}
+
+/// What each thread needs to start running, set up ahead of time so that failures stop the server
+/// from starting.
+struct UnstartedThread {
+    listen_socket: UdpSocket,
+    ring: IoUring,
+    /// Already registered with `ring`.
+    fixed_buffer: Box<[u8]>,
+}
+
+/// The state of a client's connected socket in [`PacketServerState::connections`].
+enum Connection {
+    Connected(Arc<ConnectedSocket>),
+    /// The client has left, so packets to it are dropped until the entry expires.
+    Closed(Instant),
+}
+
+/// A socket connected to a single client, read by the ring of the thread that created it.
+struct ConnectedSocket {
+    socket: UdpSocket,
+    peer_addr: SocketAddr,
+    /// Set before the socket is shut down, so that the thread reading it knows to drop it.
+    closed: AtomicBool,
+}
+
+impl ConnectedSocket {
+    /// Shuts down reads on the socket, which completes the read in flight for it.
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        if let Err(err) =
+            nix::sys::socket::shutdown(self.socket.as_raw_fd(), nix::sys::socket::Shutdown::Read)
+        {
+            warn!(
+                "shutting down socket for {} failed: {}",
+                self.peer_addr, err
+            );
+        }
+    }
+}
+
+impl PacketServerState {
+    /// Sets up the server state by binding a listening socket to `local_addr` for each thread we
+    /// plan to use, and creating a ring for each of them.
+    pub fn new(
+        local_addr_udp: SocketAddr,
+        local_addr_tcp: SocketAddr,
+        num_threads: usize,
+        tick_interval: Duration,
+        send_ecn_capable: bool,
+    ) -> Result<Arc<Self>> {
+        if local_addr_tcp.port() != 0 {
+            anyhow::bail!(
+                "the io_uring packet server doesn't support TCP; set ice_candidate_port_tcp to 0"
+            );
+        }
+        let unstarted_threads = (0..num_threads)
+            .map(|_| {
+                let listen_socket = Self::open_socket_with_reusable_port(&local_addr_udp)?;
+                packet_server::set_up_ecn(
+                    &listen_socket,
+                    local_addr_udp.is_ipv6(),
+                    send_ecn_capable,
+                );
+                let ring = IoUring::new(RING_ENTRIES)?;
+                ring.submitter()
+                    .register_files(&[listen_socket.as_raw_fd()])?;
+                let mut fixed_buffer =
+                    vec![0u8; (MAX_CONNECTIONS_PER_THREAD + MAX_SENDS_IN_FLIGHT) * MAX_RTP_LENGTH]
+                        .into_boxed_slice();
+                ring.submitter()
+                    .register_buffers(&[libc::iovec {
+                        iov_base: fixed_buffer.as_mut_ptr() as *mut libc::c_void,
+                        iov_len: fixed_buffer.len(),
+                    }])
+                    .map_err(|err| {
+                        anyhow::anyhow!(
+                            "registering io_uring buffers failed (RLIMIT_MEMLOCK may be too low): {}",
+                            err
+                        )
+                    })?;
+                Ok(UnstartedThread {
+                    listen_socket,
+                    ring,
+                    fixed_buffer,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // Share the first thread's socket rather than binding another one, which would be in the
+        // same SO_REUSEPORT group and so be given incoming packets that nothing reads.
+        let send_socket = unstarted_threads
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("at least one thread is needed"))?
+            .listen_socket
+            .try_clone()?;
+        Ok(Arc::new(Self {
+            local_addr_udp,
+            send_ecn_capable,
+            send_socket,
+            unstarted_threads: Mutex::new(unstarted_threads),
+            connections: RwLock::new(HashMap::new()),
+            tick_interval,
+            connected_sockets: 0.into(),
+            sends_in_flight: 0.into(),
+            synchronous_sends: 0.into(),
+        }))
+    }
+
+    /// Opens a socket and binds it to `local_addr` after setting the `SO_REUSEPORT` sockopt.
+    ///
+    /// This allows multiple sockets to bind to the same address.
+    fn open_socket_with_reusable_port(local_addr: &SocketAddr) -> Result<UdpSocket> {
+        use nix::sys::socket::*;
+
+        let socket_fd = socket(
+            if local_addr.is_ipv4() {
+                AddressFamily::Inet
+            } else {
+                AddressFamily::Inet6
+            },
+            SockType::Datagram,
+            SockFlag::empty(),
+            SockProtocol::Udp,
+        )?;
+        let socket_fd = scopeguard::guard(socket_fd, |fd| match nix::unistd::close(fd) {
+            Ok(()) => {}
+            Err(e) => warn!("error closing failed socket: {}", e),
+        });
+        setsockopt(*socket_fd, sockopt::ReusePort, &true)?;
+        bind(*socket_fd, &SockaddrStorage::from(*local_addr))?;
+        // Safety: we have just created this socket FD, so we know it's valid.
+        Ok(unsafe { UdpSocket::from_raw_fd(ScopeGuard::into_inner(socket_fd)) })
+    }
+
+    /// Launches the configured number of threads for the server using Tokio's blocking thread pool
+    /// ([`tokio::task::spawn_blocking`]).
+    ///
+    /// `handle_packet` should take a single incoming packet's source address, ECN codepoint, and data
+    /// and produce a (possibly empty) set of outgoing packets.
+    ///
+    /// The returned future completes if any of the threads gives up.
+    ///
+    /// This should only be called once.
+    pub fn start_threads(
+        self: Arc<Self>,
+        handle_packet: impl FnMut(SocketLocator, Ecn, &mut [u8]) -> Vec<(Vec<u8>, SocketLocator)>
+            + Clone
+            + Send
+            + 'static,
+    ) -> impl Future {
+        let unstarted_threads = std::mem::take(&mut *self.unstarted_threads.lock());
+        let all_handles = unstarted_threads.into_iter().map(|thread| {
+            let self_for_thread = self.clone();
+            let handle_packet_for_thread = handle_packet.clone();
+            tokio::task::spawn_blocking(move || {
+                self_for_thread.run(thread, handle_packet_for_thread)
+            })
+        });
+        futures::future::select_all(all_handles)
+    }
+
+    /// Runs a single listener on the current thread, using the thread's ring to receive from its
+    /// listening socket and from the connected sockets it creates, and to send on the connected
+    /// sockets.
+    ///
+    /// Only returns if the ring keeps failing.
+    ///
+    /// See [`PacketServerState::start_threads`].
+    fn run(
+        self: Arc<Self>,
+        thread: UnstartedThread,
+        mut handle_packet: impl FnMut(SocketLocator, Ecn, &mut [u8]) -> Vec<(Vec<u8>, SocketLocator)>,
+    ) {
+        let mut ring = Ring::new(thread);
+        for index in 0..NUM_LISTEN_RECVS_IN_FLIGHT {
+            ring.submit_listen_recv(index);
+        }
+
+        let mut consecutive_submit_errors = 0;
+        let mut completions = Vec::with_capacity(RING_ENTRIES as usize * 2);
+        loop {
+            match ring.ring.submit_and_wait(1) {
+                Ok(_) => consecutive_submit_errors = 0,
+                Err(err) if err.raw_os_error() == Some(Errno::EINTR as i32) => continue,
+                // The completion queue is full; drain it before submitting more.
+                Err(err) if err.raw_os_error() == Some(Errno::EBUSY as i32) => {}
+                Err(err) => {
+                    event!("calling.udp.io_uring.submit_error");
+                    consecutive_submit_errors += 1;
+                    if consecutive_submit_errors >= MAX_CONSECUTIVE_SUBMIT_ERRORS {
+                        error!(
+                            "submit_and_wait() failed {} times in a row, stopping: {}",
+                            consecutive_submit_errors, err
+                        );
+                        return;
+                    }
+                    warn!("submit_and_wait() failed: {}", err);
+                    std::thread::sleep(
+                        (INITIAL_SUBMIT_ERROR_BACKOFF * 2u32.pow(consecutive_submit_errors - 1))
+                            .into(),
+                    );
+                    continue;
+                }
+            }
+
+            completions.extend(
+                ring.ring
+                    .completion()
+                    .map(|cqe| (cqe.user_data(), cqe.result())),
+            );
+            for (user_data, result) in completions.drain(..) {
+                let index = (user_data & !USER_DATA_KIND_MASK) as usize;
+                match user_data & USER_DATA_KIND_MASK {
+                    USER_DATA_LISTEN_RECV => {
+                        if result < 0 {
+                            match Errno::from_i32(-result) {
+                                Errno::EINTR | Errno::EAGAIN => {}
+                                errno => Self::socket_error(&io::Error::from(errno)),
+                            }
+                        } else if let Some(sender_addr) = ring.listen_recv_sender_addr(index) {
+                            let packets_to_send = handle_packet(
+                                SocketLocator::Udp(sender_addr),
+                                Ecn::NotEct,
+                                ring.listen_recv_buffer(index, result as usize),
+                            );
+                            for (buf, addr) in packets_to_send {
+                                self.send_packet_on_ring(&mut ring, buf, addr);
+                            }
+                        }
+                        ring.submit_listen_recv(index);
+                    }
+                    USER_DATA_READ => {
+                        let Some(connected_socket) = ring.reads[index].clone() else {
+                            warn!("completed read for unused slot {}", index);
+                            continue;
+                        };
+                        if result < 0 {
+                            match Errno::from_i32(-result) {
+                                Errno::EINTR | Errno::EAGAIN => {}
+                                errno => Self::socket_error(&io::Error::from(errno)),
+                            }
+                        } else if !connected_socket.closed.load(Ordering::SeqCst) {
+                            let packets_to_send = handle_packet(
+                                SocketLocator::Udp(connected_socket.peer_addr),
+                                Ecn::NotEct,
+                                ring.read_buffer(index, result as usize),
+                            );
+                            for (buf, addr) in packets_to_send {
+                                self.send_packet_on_ring(&mut ring, buf, addr);
+                            }
+                        }
+                        // Check again after handling the packet, so that a socket closed in the
+                        // meantime isn't read again. If it's closed after this, the read
+                        // completes right away.
+                        if connected_socket.closed.load(Ordering::SeqCst) {
+                            ring.finish_reading(index);
+                            self.connected_sockets.fetch_sub(1, Ordering::Relaxed);
+                        } else {
+                            ring.submit_read(index);
+                        }
+                    }
+                    USER_DATA_SEND => {
+                        ring.finish_send(index);
+                        self.sends_in_flight.fetch_sub(1, Ordering::Relaxed);
+                        if result < 0 {
+                            Self::socket_error(&io::Error::from(Errno::from_i32(-result)));
+                        }
+                    }
+                    _ => {
+                        warn!("unexpected io_uring completion: {:x}", user_data);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Counts socket errors; unexpected errors are logged.
+    #[track_caller]
+    fn socket_error(err: &io::Error) {
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => {
+                event!("calling.udp.io_uring.socket_error.permission_denied");
+            }
+            io::ErrorKind::ConnectionRefused => {
+                // This can happen when someone leaves a call
+                // because e.g. their router stops forwarding packets.
+                event!("calling.udp.io_uring.socket_error.connection_refused");
+            }
+            _ => {
+                let errno = err.raw_os_error();
+                if errno == Some(Errno::EHOSTUNREACH as i32) {
+                    event!("calling.udp.io_uring.socket_error.host_unreachable");
+                } else if errno == Some(Errno::EMSGSIZE as i32) {
+                    event!("calling.udp.io_uring.socket_error.packet_too_big");
+                } else {
+                    event!("calling.udp.io_uring.socket_error");
+                    warn!("socket_error: {}", err);
+                }
+            }
+        }
+    }
+
+    /// Queues a send on the ring of the current thread, connecting a socket to `addr` first if
+    /// there isn't one yet.
+    ///
+    /// If there are already too many sends queued, or this thread can't connect any more sockets,
+    /// the packet is sent synchronously.
+    fn send_packet_on_ring(&self, ring: &mut Ring, buf: Vec<u8>, addr: SocketLocator) {
+        let SocketLocator::Udp(udp_addr) = addr else {
+            warn!("unable to send packet to {}", addr);
+            return;
+        };
+        trace!("queueing packet of {} bytes to {}", buf.len(), udp_addr);
+        sampling_histogram!("calling.io_uring.send_packet.size_bytes", || buf.len());
+
+        let connected_socket = match self.connections.read().get(&udp_addr) {
+            Some(Connection::Connected(connected_socket)) => Some(connected_socket.clone()),
+            Some(Connection::Closed(_)) => {
+                trace!("dropping packet (connection already closed)");
+                return;
+            }
+            None => None,
+        };
+        let Some(connected_socket) = connected_socket.or_else(|| self.connect(ring, udp_addr))
+        else {
+            self.synchronous_sends.fetch_add(1, Ordering::Relaxed);
+            self.send_packet(&buf, addr);
+            return;
+        };
+        if buf.len() <= MAX_RTP_LENGTH && ring.sends_in_flight() < MAX_SENDS_IN_FLIGHT {
+            ring.submit_send(&buf, connected_socket);
+            self.sends_in_flight.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.synchronous_sends.fetch_add(1, Ordering::Relaxed);
+            if let Err(err) = connected_socket.socket.send(&buf) {
+                Self::socket_error(&err);
+            }
+        }
+    }
+
+    /// Connects a new socket to `peer_addr` and starts reading it on `ring`, unless another thread
+    /// got there first.
+    ///
+    /// Returns `None` if the connection has been closed, the ring has no room for another
+    /// connection, or connecting fails.
+    fn connect(&self, ring: &mut Ring, peer_addr: SocketAddr) -> Option<Arc<ConnectedSocket>> {
+        let mut connections = self.connections.write();
+        match connections.get(&peer_addr) {
+            Some(Connection::Connected(connected_socket)) => {
+                return Some(connected_socket.clone());
+            }
+            Some(Connection::Closed(_)) => return None,
+            None => {}
+        }
+        if !ring.has_room_to_read() {
+            event!("calling.udp.io_uring.too_many_connections");
+            return None;
+        }
+
+        trace!("connecting to {:?}", peer_addr);
+        let socket =
+            match Self::open_socket_with_reusable_port(&self.local_addr_udp).and_then(|socket| {
+                socket.connect(peer_addr)?;
+                Ok(socket)
+            }) {
+                Ok(socket) => socket,
+                Err(err) => {
+                    error!("failed to connect to peer: {}", err);
+                    return None;
+                }
+            };
+        packet_server::set_up_ecn(
+            &socket,
+            self.local_addr_udp.is_ipv6(),
+            self.send_ecn_capable,
+        );
+        let connected_socket = Arc::new(ConnectedSocket {
+            socket,
+            peer_addr,
+            closed: false.into(),
+        });
+        connections.insert(peer_addr, Connection::Connected(connected_socket.clone()));
+        ring.start_reading(connected_socket.clone());
+        self.connected_sockets.fetch_add(1, Ordering::Relaxed);
+        Some(connected_socket)
+    }
+
+    pub fn send_packet(&self, buf: &[u8], addr: SocketLocator) {
+        time_scope!(
+            "calling.udp.io_uring.send_packet",
+            TimingOptions::nanosecond_1000_per_minute()
+        );
+        match addr {
+            SocketLocator::Udp(addr) => {
+                trace!("sending packet of {} bytes to {}", buf.len(), addr);
+                let result = match self.connections.read().get(&addr) {
+                    Some(Connection::Connected(connected_socket)) => {
+                        connected_socket.socket.send(buf)
+                    }
+                    Some(Connection::Closed(_)) => {
+                        trace!("dropping packet (connection already closed)");
+                        return;
+                    }
+                    None => self.send_socket.send_to(buf, addr),
+                };
+                if let Err(err) = result {
+                    Self::socket_error(&err);
+                }
+            }
+            _ => warn!("unable to send packet to {}", addr),
+        }
+    }
+
+    /// Process the results of [`sfu::Sfu::tick`].
+    ///
+    /// This includes closing the sockets of clients that have left, and forgetting them once
+    /// they've been closed for a while.
+    pub fn tick(&self, tick_update: sfu::TickOutput) -> Result<()> {
+        time_scope_us!("calling.packet_server.tick");
+
+        for (buf, addr) in tick_update.packets_to_send {
+            self.send_packet(&buf, addr);
+        }
+
+        let now = Instant::now();
+        let expiration = now - (CLOSED_SOCKET_EXPIRATION_IN_TICKS * self.tick_interval);
+        let is_expired = |connection: &Connection| matches!(connection, Connection::Closed(closed_at) if *closed_at <= expiration);
+        // Scan ahead of time to avoid taking the write lock if there's nothing to do.
+        if tick_update.expired_client_addrs.is_empty()
+            && !self.connections.read().values().any(is_expired)
+        {
+            return Ok(());
+        }
+
+        let mut connections = self.connections.write();
+        connections.retain(|_, connection| !is_expired(connection));
+        for addr in tick_update.expired_client_addrs {
+            if let SocketLocator::Udp(addr) = addr {
+                if let Some(connection) = connections.get_mut(&addr) {
+                    if let Connection::Connected(connected_socket) = connection {
+                        connected_socket.close();
+                    }
+                    *connection = Connection::Closed(now);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_stats(&self) -> SfuStats {
+        let histograms = HashMap::new();
+        let mut values = HashMap::new();
+        values.insert(
+            "calling.packet_server.io_uring.connections.count",
+            self.connections.read().len() as f32,
+        );
+        values.insert(
+            "calling.packet_server.io_uring.connected_sockets.count",
+            self.connected_sockets.load(Ordering::Relaxed) as f32,
+        );
+        values.insert(
+            "calling.packet_server.io_uring.sends_in_flight.count",
+            self.sends_in_flight.load(Ordering::Relaxed) as f32,
+        );
+        values.insert(
+            "calling.packet_server.io_uring.synchronous_sends.count",
+            self.synchronous_sends.swap(0, Ordering::Relaxed) as f32,
+        );
+        SfuStats { histograms, values }
+    }
+}
+
+/// The parameters of a recvmsg on the listening socket that the kernel writes to.
+/// These must not move while the receive is in flight, so they are kept in a boxed slice.
+struct PendingRecv {
+    sender_addr: libc::sockaddr_storage,
+    buf: [u8; MAX_RTP_LENGTH],
+    iov: libc::iovec,
+    msg: libc::msghdr,
+}
+
+/// One thread's ring, along with everything that the operations on it point to.
+///
+/// Dropping this while operations are in flight would leave the kernel pointing at freed memory,
+/// so the ring is declared (and thus dropped) before anything else.
+struct Ring {
+    ring: IoUring,
+    _listen_socket: UdpSocket,
+    listen_recvs: Box<[PendingRecv]>,
+    /// Registered with the ring as a single buffer: a read buffer for each connection, followed by
+    /// a send buffer for each send in flight.
+    fixed_buffer: Box<[u8]>,
+    /// The connected socket read into each read buffer.
+    reads: Vec<Option<Arc<ConnectedSocket>>>,
+    unused_read_indices: Vec<usize>,
+    /// The socket each send buffer is being sent on, which must stay open until the send completes.
+    sends: Vec<Option<Arc<ConnectedSocket>>>,
+    unused_send_indices: Vec<usize>,
+}
+
+impl Ring {
+    fn new(thread: UnstartedThread) -> Self {
+        let mut listen_recvs: Box<[PendingRecv]> = (0..NUM_LISTEN_RECVS_IN_FLIGHT)
+            // Safety: these are plain C structs and bytes, for which all zeros is valid.
+            .map(|_| unsafe { std::mem::zeroed() })
+            .collect();
+        for recv in listen_recvs.iter_mut() {
+            recv.iov.iov_base = recv.buf.as_mut_ptr() as *mut libc::c_void;
+            recv.iov.iov_len = MAX_RTP_LENGTH;
+            recv.msg.msg_iov = &mut recv.iov;
+            recv.msg.msg_iovlen = 1;
+            recv.msg.msg_name = &mut recv.sender_addr as *mut _ as *mut libc::c_void;
+        }
+        Self {
+            ring: thread.ring,
+            _listen_socket: thread.listen_socket,
+            listen_recvs,
+            fixed_buffer: thread.fixed_buffer,
+            reads: vec![None; MAX_CONNECTIONS_PER_THREAD],
+            unused_read_indices: (0..MAX_CONNECTIONS_PER_THREAD).rev().collect(),
+            sends: vec![None; MAX_SENDS_IN_FLIGHT],
+            unused_send_indices: (0..MAX_SENDS_IN_FLIGHT).rev().collect(),
+        }
+    }
+
+    /// Pushes `entry` onto the submission queue, submitting what's already queued if it's full.
+    fn push(&mut self, entry: squeue::Entry) {
+        loop {
+            // Safety: all of our entries point to buffers that live as long as the Ring
+            // and aren't reused until the entry completes.
+            if unsafe { self.ring.submission().push(&entry) }.is_ok() {
+                return;
+            }
+            if let Err(err) = self.ring.submit() {
+                warn!("submit() failed: {}", err);
+            }
+        }
+    }
+
+    fn submit_listen_recv(&mut self, index: usize) {
+        let recv = &mut self.listen_recvs[index];
+        recv.msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        let entry = opcode::RecvMsg::new(LISTEN_SOCKET_FIXED_FILE, &mut recv.msg)
+            .build()
+            .user_data(USER_DATA_LISTEN_RECV | index as u64);
+        self.push(entry);
+    }
+
+    fn listen_recv_sender_addr(&self, index: usize) -> Option<SocketAddr> {
+        let recv = &self.listen_recvs[index];
+        let addr = &recv.sender_addr as *const _ as *const libc::sockaddr;
+        // Safety: the kernel filled in the address and its length.
+        let addr = unsafe { SockaddrStorage::from_raw(addr, Some(recv.msg.msg_namelen)) }?;
+        if let Some(addr) = addr.as_sockaddr_in() {
+            Some(SocketAddrV4::from(*addr).into())
+        } else {
+            addr.as_sockaddr_in6()
+                .map(|addr| SocketAddrV6::from(*addr).into())
+        }
+    }
+
+    fn listen_recv_buffer(&mut self, index: usize, len: usize) -> &mut [u8] {
+        &mut self.listen_recvs[index].buf[..len]
+    }
+
+    fn has_room_to_read(&self) -> bool {
+        !self.unused_read_indices.is_empty()
+    }
+
+    /// Keeps a read in flight for `connected_socket` until it's closed.
+    ///
+    /// The caller must check [`Self::has_room_to_read`] first.
+    fn start_reading(&mut self, connected_socket: Arc<ConnectedSocket>) {
+        let index = self
+            .unused_read_indices
+            .pop()
+            .expect("checked by has_room_to_read");
+        self.reads[index] = Some(connected_socket);
+        self.submit_read(index);
+    }
+
+    fn submit_read(&mut self, index: usize) {
+        let Some(connected_socket) = &self.reads[index] else {
+            return;
+        };
+        let fd = types::Fd(connected_socket.socket.as_raw_fd());
+        let buf = self.fixed_buffer[index * MAX_RTP_LENGTH..].as_mut_ptr();
+        let entry = opcode::ReadFixed::new(fd, buf, MAX_RTP_LENGTH as u32, FIXED_BUFFER_INDEX)
+            .build()
+            .user_data(USER_DATA_READ | index as u64);
+        self.push(entry);
+    }
+
+    fn read_buffer(&mut self, index: usize, len: usize) -> &mut [u8] {
+        let start = index * MAX_RTP_LENGTH;
+        &mut self.fixed_buffer[start..start + len]
+    }
+
+    fn finish_reading(&mut self, index: usize) {
+        if self.reads[index].take().is_some() {
+            self.unused_read_indices.push(index);
+        }
+    }
+
+    fn sends_in_flight(&self) -> usize {
+        MAX_SENDS_IN_FLIGHT - self.unused_send_indices.len()
+    }
+
+    /// Copies `buf` into a free send buffer and queues it to be sent on `connected_socket`.
+    ///
+    /// The caller must check that `buf` fits and that [`Self::sends_in_flight`] is below the limit.
+    fn submit_send(&mut self, buf: &[u8], connected_socket: Arc<ConnectedSocket>) {
+        let index = self
+            .unused_send_indices
+            .pop()
+            .expect("checked by sends_in_flight");
+        let start = (MAX_CONNECTIONS_PER_THREAD + index) * MAX_RTP_LENGTH;
+        let send_buf = &mut self.fixed_buffer[start..start + buf.len()];
+        send_buf.copy_from_slice(buf);
+        let entry = opcode::WriteFixed::new(
+            types::Fd(connected_socket.socket.as_raw_fd()),
+            send_buf.as_ptr(),
+            buf.len() as u32,
+            FIXED_BUFFER_INDEX,
+        )
+        .build()
+        .user_data(USER_DATA_SEND | index as u64);
+        self.sends[index] = Some(connected_socket);
+        self.push(entry);
+    }
+
+    fn finish_send(&mut self, index: usize) {
+        if self.sends[index].take().is_some() {
+            self.unused_send_indices.push(index);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, UdpSocket};
+
+    use super::*;
+    use crate::packet_server::epoll;
+
+    /// How many packets the "SFU" sends for each one it receives.
+    const FAN_OUT: usize = 8;
+    /// How many packets the client sends before waiting for the responses.
+    const BATCH_SIZE: usize = 16;
+    const BATCH_COUNT: usize = 500;
+
+    fn unused_local_addr() -> SocketAddr {
+        // There's a small chance that something else takes this port before we use it,
+        // but the servers need a fixed port to share it between their threads.
+        UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    /// The TCP address to pass to the servers, which the io_uring server requires to be port 0.
+    fn no_tcp() -> SocketAddr {
+        (Ipv4Addr::LOCALHOST, 0).into()
+    }
+
+    fn fan_out(
+        sender_addr: SocketLocator,
+        _ecn: Ecn,
+        data: &mut [u8],
+    ) -> Vec<(Vec<u8>, SocketLocator)> {
+        (0..FAN_OUT).map(|_| (data.to_vec(), sender_addr)).collect()
+    }
+
+    fn new_client() -> UdpSocket {
+        let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        client
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        client
+    }
+
+    /// Sends batches of packets to `server_addr` and waits for the fanned-out responses to each
+    /// batch before sending the next, returning the number of packets received back.
+    fn send_batches_and_count_fanned_out(server_addr: SocketAddr) -> usize {
+        let client = new_client();
+        let mut buf = [0u8; MAX_RTP_LENGTH];
+
+        let mut received_count = 0;
+        for batch in 0..BATCH_COUNT {
+            for i in 0..BATCH_SIZE {
+                let packet = [(batch % 256) as u8, i as u8, 0, 0, 0, 0, 0, 0];
+                client.send_to(&packet, server_addr).unwrap();
+            }
+            for _ in 0..(BATCH_SIZE * FAN_OUT) {
+                let (size, from) = client.recv_from(&mut buf).expect("every packet fanned out");
+                assert_eq!(server_addr, from);
+                assert_eq!(8, size);
+                assert_eq!((batch % 256) as u8, buf[0]);
+                received_count += 1;
+            }
+        }
+        received_count
+    }
+
+    /// Returns how many packets per second came back from `server_addr`.
+    fn measure_fan_out_throughput(server_addr: SocketAddr) -> f64 {
+        let start = std::time::Instant::now();
+        let received_count = send_batches_and_count_fanned_out(server_addr);
+        assert_eq!(BATCH_COUNT * BATCH_SIZE * FAN_OUT, received_count);
+        received_count as f64 / start.elapsed().as_secs_f64()
+    }
+
+    #[test]
+    fn test_io_uring_fan_out() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+
+        let server_addr = unused_local_addr();
+        let server =
+            PacketServerState::new(server_addr, no_tcp(), 2, Duration::from_millis(100), false)
+                .unwrap();
+        drop(server.start_threads(fan_out));
+
+        // Each client gets its own connected socket.
+        for _ in 0..3 {
+            assert_eq!(
+                BATCH_COUNT * BATCH_SIZE * FAN_OUT,
+                send_batches_and_count_fanned_out(server_addr)
+            );
+        }
+
+        // The server threads never exit.
+        runtime.shutdown_background();
+    }
+
+    #[test]
+    fn test_io_uring_throughput_compared_to_epoll() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+
+        let io_uring_addr = unused_local_addr();
+        let io_uring_server = PacketServerState::new(
+            io_uring_addr,
+            no_tcp(),
+            2,
+            Duration::from_millis(100),
+            false,
+        )
+        .unwrap();
+        drop(io_uring_server.start_threads(fan_out));
+
+        let epoll_addr = unused_local_addr();
+        let epoll_server = epoll::PacketServerState::new(
+            epoll_addr,
+            no_tcp(),
+            2,
+            Duration::from_millis(100),
+            false,
+        )
+        .unwrap();
+        drop(epoll_server.start_threads(fan_out));
+
+        // Warm up both, so that neither pays for connecting the client's socket in the
+        // measurement.
+        measure_fan_out_throughput(io_uring_addr);
+        measure_fan_out_throughput(epoll_addr);
+
+        let io_uring_throughput = measure_fan_out_throughput(io_uring_addr);
+        let epoll_throughput = measure_fan_out_throughput(epoll_addr);
+        println!(
+            "fan-out throughput: io_uring {:.0} packets/s, epoll {:.0} packets/s",
+            io_uring_throughput, epoll_throughput
+        );
+        // epoll sends each fan-out with a single GSO send, and timing on shared test machines is
+        // noisy, so this only catches io_uring falling far behind.
+        assert!(
+            io_uring_throughput > epoll_throughput / 4.0,
+            "io_uring {:.0} packets/s, epoll {:.0} packets/s",
+            io_uring_throughput,
+            epoll_throughput
+        );
+
+        runtime.shutdown_background();
+    }
+
+    #[test]
+    fn test_io_uring_send_packet_and_tick() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+
+        let server_addr = unused_local_addr();
+        let server =
+            PacketServerState::new(server_addr, no_tcp(), 1, Duration::from_millis(100), false)
+                .unwrap();
+        drop(server.clone().start_threads(fan_out));
+
+        let client = new_client();
+        let client_addr = SocketLocator::Udp(client.local_addr().unwrap());
+        let mut buf = [0u8; MAX_RTP_LENGTH];
+
+        server.send_packet(&[1, 2, 3], client_addr);
+        assert_eq!(
+            (3, server_addr),
+            client.recv_from(&mut buf).expect("sent directly")
+        );
+        assert_eq!([1, 2, 3], buf[..3]);
+
+        server
+            .tick(sfu::TickOutput {
+                packets_to_send: vec![(vec![4, 5], client_addr)],
+                expired_client_addrs: vec![],
+            })
+            .unwrap();
+        assert_eq!(
+            (2, server_addr),
+            client.recv_from(&mut buf).expect("sent by tick")
+        );
+        assert_eq!([4, 5], buf[..2]);
+
+        // Sends through the ring come from the same address.
+        client.send_to(&[6], server_addr).unwrap();
+        for _ in 0..FAN_OUT {
+            assert_eq!(
+                (1, server_addr),
+                client.recv_from(&mut buf).expect("fanned out")
+            );
+            assert_eq!(6, buf[0]);
+        }
+
+        // And so do sends on the connected socket from outside the ring.
+        server.send_packet(&[7], client_addr);
+        assert_eq!(
+            (1, server_addr),
+            client
+                .recv_from(&mut buf)
+                .expect("sent on connected socket")
+        );
+        assert_eq!(7, buf[0]);
+
+        runtime.shutdown_background();
+    }
+
+    #[test]
+    fn test_io_uring_expired_clients_free_their_sockets() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+
+        let server_addr = unused_local_addr();
+        // With no tick interval, closed connections are forgotten on the next tick.
+        let server =
+            PacketServerState::new(server_addr, no_tcp(), 1, Duration::ZERO, false).unwrap();
+        drop(server.clone().start_threads(fan_out));
+
+        let client = new_client();
+        let client_addr = SocketLocator::Udp(client.local_addr().unwrap());
+        let mut buf = [0u8; MAX_RTP_LENGTH];
+        let stat = |name| server.get_stats().values[name];
+
+        // More times than the thread has read buffers, so this fails if they aren't reused.
+        for i in 0..(MAX_CONNECTIONS_PER_THREAD + 10) {
+            client.send_to(&[i as u8], server_addr).unwrap();
+            for _ in 0..FAN_OUT {
+                assert_eq!(
+                    (1, server_addr),
+                    client.recv_from(&mut buf).expect("fanned out")
+                );
+                assert_eq!(i as u8, buf[0]);
+            }
+            assert_eq!(
+                0.0,
+                stat("calling.packet_server.io_uring.synchronous_sends.count")
+            );
+            assert_eq!(
+                1.0,
+                stat("calling.packet_server.io_uring.connections.count")
+            );
+
+            server
+                .tick(sfu::TickOutput {
+                    packets_to_send: vec![],
+                    expired_client_addrs: vec![client_addr],
+                })
+                .unwrap();
+            // Packets to a closed connection are dropped.
+            server.send_packet(&[0xFF], client_addr);
+            server
+                .tick(sfu::TickOutput {
+                    packets_to_send: vec![],
+                    expired_client_addrs: vec![],
+                })
+                .unwrap();
+            assert_eq!(
+                0.0,
+                stat("calling.packet_server.io_uring.connections.count")
+            );
+
+            // The same address can't be connected again until the thread lets go of the socket.
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+            while stat("calling.packet_server.io_uring.connected_sockets.count") != 0.0 {
+                assert!(std::time::Instant::now() < deadline, "socket never let go");
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+        }
+
+        runtime.shutdown_background();
+    }
+}
@@ -15,18 +15,19 @@ use std::{
 
 use aes::{
     cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit},
-    Aes128,
+    Aes128, Aes256,
 };
-use aes_gcm::{AeadInPlace, Aes128Gcm};
+use aes_gcm::{AeadInPlace, Aes128Gcm, Aes256Gcm};
 use byteorder::{ReadBytesExt, BE};
 use calling_common::{
     expand_truncated_counter, parse_u16, parse_u32, read_u16, round_up_to_multiple_of, Bits,
     CheckedSplitAt, DataSize, Duration, Instant, KeySortedCache, TwoGenerationCache, Writer, U24,
 };
 use log::*;
+use strum_macros::EnumString;
 use zeroize::Zeroizing;
 
-use crate::{audio, transportcc as tcc};
+use crate::{audio, ccfb, pacer::ProbeClusterId, transportcc as tcc};
 
 const VERSION: u8 = 2;
 const RTP_MIN_HEADER_LEN: usize = 12;
@@ -36,15 +37,20 @@ const RTP_TIMESTAMP_RANGE: Range<usize> = 4..8;
 const RTP_SSRC_RANGE: Range<usize> = 8..12;
 const RTP_EXTENSIONS_HEADER_LEN: usize = 4;
 const RTP_ONE_BYTE_EXTENSIONS_PROFILE: u16 = 0xBEDE;
+// The lower 4 bits are "appbits", which we ignore.
+const RTP_TWO_BYTE_EXTENSIONS_PROFILE: u16 = 0x1000;
+const RTP_TWO_BYTE_EXTENSIONS_PROFILE_MASK: u16 = 0xFFF0;
 const RTP_EXT_ID_TCC_SEQNUM: u8 = 1; // Really u4
 const RTP_EXT_ID_VIDEO_ORIENTATION: u8 = 4; // Really u4
 const RTP_EXT_ID_AUDIO_LEVEL: u8 = 5; // Really u4
+const RTP_EXT_ID_DEPENDENCY_DESCRIPTOR: u8 = 6; // Really u4
 const RTCP_PAYLOAD_TYPES: RangeInclusive<u8> = 64..=95;
 const RTCP_HEADER_LEN: usize = 8;
 const RTCP_PAYLOAD_TYPE_OFFSET: usize = 1;
 const RTCP_PAYLOAD_LEN_RANGE: Range<usize> = 2..4;
 const RTCP_SENDER_SSRC_RANGE: Range<usize> = 4..8;
-pub const SRTP_KEY_LEN: usize = 16;
+pub const SRTP_AES_128_KEY_LEN: usize = 16;
+pub const SRTP_AES_256_KEY_LEN: usize = 32;
 pub const SRTP_SALT_LEN: usize = 12;
 const SRTP_IV_LEN: usize = 12;
 const SRTP_AUTH_TAG_LEN: usize = 16;
@@ -52,26 +58,141 @@ const SRTCP_FOOTER_LEN: usize = 4;
 const RTCP_TYPE_SENDER_REPORT: u8 = 200;
 const RTCP_TYPE_RECEIVER_REPORT: u8 = 201;
 const RTCP_TYPE_EXTENDED_REPORT: u8 = 207;
+const RTCP_SENDER_INFO_LEN: usize = 20;
+const RTCP_XR_BLOCK_TYPE_RECEIVER_REFERENCE_TIME: u8 = 4;
+const RTCP_XR_BLOCK_TYPE_DLRR: u8 = 5;
 const RTCP_TYPE_SDES: u8 = 202;
 const RTCP_TYPE_BYE: u8 = 203;
 pub const RTCP_TYPE_GENERIC_FEEDBACK: u8 = 205;
 pub const RTCP_FORMAT_NACK: u8 = 1;
 pub const RTCP_FORMAT_TRANSPORT_CC: u8 = 15;
+pub const RTCP_FORMAT_CONGESTION_CONTROL_FEEDBACK: u8 = 11;
 pub const RTCP_TYPE_SPECIFIC_FEEDBACK: u8 = 206;
 pub const RTCP_FORMAT_PLI: u8 = 1;
 const RTCP_FORMAT_LOSS_NOTIFICATION: u8 = 15;
-const OPUS_PAYLOAD_TYPE: PayloadType = 102;
+pub const OPUS_PAYLOAD_TYPE: PayloadType = 102;
 pub const VP8_PAYLOAD_TYPE: PayloadType = 108;
 const RTX_PAYLOAD_TYPE_OFFSET: PayloadType = 10;
 const RTX_SSRC_OFFSET: Ssrc = 1;
+// How long to keep accepting packets encrypted with the previous keys
+// after the remote side starts using new ones.
+const PREVIOUS_DECRYPT_KEYS_RETENTION: Duration = Duration::from_secs(10);
+// How long to wait for the remote side to start using new keys before
+// dropping them and going back to the previous ones.
+const PENDING_KEYS_TIMEOUT: Duration = Duration::from_secs(30);
 
-pub type Key = Zeroizing<[u8; SRTP_KEY_LEN]>;
 pub type Salt = [u8; SRTP_SALT_LEN];
 pub type Iv = [u8; SRTP_IV_LEN];
-// In the order [client_key, client_salt, server_key, server_salt]
+// In the order [client_key, client_salt, server_key, server_salt].
+// This is long enough for the largest key; profiles with shorter keys only use a prefix of it.
 pub const MASTER_KEY_MATERIAL_LEN: usize =
-    SRTP_KEY_LEN + SRTP_SALT_LEN + SRTP_KEY_LEN + SRTP_SALT_LEN;
-pub type MasterKeyMaterial = Zeroizing<[u8; MASTER_KEY_MATERIAL_LEN]>;
+    SRTP_AES_256_KEY_LEN + SRTP_SALT_LEN + SRTP_AES_256_KEY_LEN + SRTP_SALT_LEN;
+// A Vec rather than an array because the zeroize version we're pinned to (by x25519-dalek)
+// only supports arrays of up to 64 bytes.
+pub type MasterKeyMaterial = Zeroizing<Vec<u8>>;
+
+/// Which SRTP AEAD cipher is used with a client, selected when it joins.
+/// See https://datatracker.ietf.org/doc/html/rfc7714#section-14.2
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, EnumString)]
+pub enum SrtpProtectionProfile {
+    #[default]
+    #[strum(serialize = "AEAD_AES_128_GCM")]
+    Aes128Gcm,
+    #[strum(serialize = "AEAD_AES_256_GCM")]
+    Aes256Gcm,
+}
+
+impl SrtpProtectionProfile {
+    pub fn key_len(self) -> usize {
+        match self {
+            Self::Aes128Gcm => SRTP_AES_128_KEY_LEN,
+            Self::Aes256Gcm => SRTP_AES_256_KEY_LEN,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Key {
+    Aes128Gcm(Zeroizing<[u8; SRTP_AES_128_KEY_LEN]>),
+    Aes256Gcm(Zeroizing<[u8; SRTP_AES_256_KEY_LEN]>),
+}
+
+impl From<[u8; SRTP_AES_128_KEY_LEN]> for Key {
+    fn from(key: [u8; SRTP_AES_128_KEY_LEN]) -> Self {
+        Self::Aes128Gcm(Zeroizing::new(key))
+    }
+}
+
+impl From<[u8; SRTP_AES_256_KEY_LEN]> for Key {
+    fn from(key: [u8; SRTP_AES_256_KEY_LEN]) -> Self {
+        Self::Aes256Gcm(Zeroizing::new(key))
+    }
+}
+
+impl Key {
+    // Panics if the slice isn't the right length for the profile.
+    fn from_slice(profile: SrtpProtectionProfile, key: &[u8]) -> Self {
+        match profile {
+            SrtpProtectionProfile::Aes128Gcm => {
+                Self::Aes128Gcm(Zeroizing::new(key.try_into().unwrap()))
+            }
+            SrtpProtectionProfile::Aes256Gcm => {
+                Self::Aes256Gcm(Zeroizing::new(key.try_into().unwrap()))
+            }
+        }
+    }
+
+    pub fn profile(&self) -> SrtpProtectionProfile {
+        match self {
+            Self::Aes128Gcm(_) => SrtpProtectionProfile::Aes128Gcm,
+            Self::Aes256Gcm(_) => SrtpProtectionProfile::Aes256Gcm,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Aes128Gcm(key) => &key[..],
+            Self::Aes256Gcm(key) => &key[..],
+        }
+    }
+
+    fn encrypt_in_place_detached(
+        &self,
+        iv: &Iv,
+        aad: &[u8],
+        plaintext: &mut [u8],
+        tag: &mut [u8],
+    ) -> Option<()> {
+        let nonce = GenericArray::from_slice(iv);
+        let computed_tag = match self {
+            Self::Aes128Gcm(key) => Aes128Gcm::new(GenericArray::from_slice(&key[..]))
+                .encrypt_in_place_detached(nonce, aad, plaintext),
+            Self::Aes256Gcm(key) => Aes256Gcm::new(GenericArray::from_slice(&key[..]))
+                .encrypt_in_place_detached(nonce, aad, plaintext),
+        }
+        .ok()?;
+        tag.copy_from_slice(&computed_tag);
+        Some(())
+    }
+
+    fn decrypt_in_place_detached(
+        &self,
+        iv: &Iv,
+        aad: &[u8],
+        ciphertext: &mut [u8],
+        tag: &[u8],
+    ) -> Option<()> {
+        let nonce = GenericArray::from_slice(iv);
+        let tag = GenericArray::from_slice(tag);
+        match self {
+            Self::Aes128Gcm(key) => Aes128Gcm::new(GenericArray::from_slice(&key[..]))
+                .decrypt_in_place_detached(nonce, aad, ciphertext, tag),
+            Self::Aes256Gcm(key) => Aes256Gcm::new(GenericArray::from_slice(&key[..]))
+                .decrypt_in_place_detached(nonce, aad, ciphertext, tag),
+        }
+        .ok()
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct KeyAndSalt {
@@ -89,28 +210,20 @@ impl KeysAndSalts {
     // Returns (client, server)
     pub fn derive_client_and_server_from_master_key_material(
         master_key_material: &MasterKeyMaterial,
+        profile: SrtpProtectionProfile,
     ) -> (KeysAndSalts, KeysAndSalts) {
-        let client_key: Key =
-            Zeroizing::new(master_key_material[..SRTP_KEY_LEN].try_into().unwrap());
-        let client_salt: Salt = master_key_material[SRTP_KEY_LEN..][..SRTP_SALT_LEN]
-            .try_into()
-            .unwrap();
-        let server_key: Key = Zeroizing::new(
-            master_key_material[SRTP_KEY_LEN..][SRTP_SALT_LEN..][..SRTP_KEY_LEN]
-                .try_into()
-                .unwrap(),
-        );
-        let server_salt: Salt = master_key_material[SRTP_KEY_LEN..][SRTP_SALT_LEN..]
-            [SRTP_KEY_LEN..][..SRTP_SALT_LEN]
-            .try_into()
-            .unwrap();
+        let key_len = profile.key_len();
+        let (client_key, rest) = master_key_material.split_at(key_len);
+        let (client_salt, rest) = rest.split_at(SRTP_SALT_LEN);
+        let (server_key, rest) = rest.split_at(key_len);
+        let server_salt = &rest[..SRTP_SALT_LEN];
         let client = Self::derive_from_master(&KeyAndSalt {
-            key: client_key,
-            salt: client_salt,
+            key: Key::from_slice(profile, client_key),
+            salt: client_salt.try_into().unwrap(),
         });
         let server = Self::derive_from_master(&KeyAndSalt {
-            key: server_key,
-            salt: server_salt,
+            key: Key::from_slice(profile, server_key),
+            salt: server_salt.try_into().unwrap(),
         });
         (client, server)
     }
@@ -131,17 +244,36 @@ impl KeysAndSalts {
         }
     }
 
+    // The key is the AES-CM keystream (see RFC 3711 section 4.1.1) starting at the IV made from
+    // the salt and label, so a 256-bit key takes two blocks (see RFC 6188 section 3).
     fn derive_key_from_master(master: &KeyAndSalt, label: u8) -> Key {
-        let cipher = Aes128::new(GenericArray::from_slice(&master.key[..]));
-        let mut derived = Zeroizing::new([0; SRTP_KEY_LEN]);
-        derived[..SRTP_SALT_LEN].copy_from_slice(&master.salt);
-        derived[7] ^= label;
-        cipher.encrypt_block(GenericArray::from_mut_slice(&mut derived[..]));
-        derived
+        let mut iv = Zeroizing::new([0u8; 16]);
+        iv[..SRTP_SALT_LEN].copy_from_slice(&master.salt);
+        iv[7] ^= label;
+        match &master.key {
+            Key::Aes128Gcm(master_key) => {
+                let cipher = Aes128::new(GenericArray::from_slice(&master_key[..]));
+                let mut derived = Zeroizing::new(*iv);
+                cipher.encrypt_block(GenericArray::from_mut_slice(&mut derived[..]));
+                Key::Aes128Gcm(derived)
+            }
+            Key::Aes256Gcm(master_key) => {
+                let cipher = Aes256::new(GenericArray::from_slice(&master_key[..]));
+                let mut derived = Zeroizing::new([0u8; SRTP_AES_256_KEY_LEN]);
+                let (first_block, second_block) = derived.split_at_mut(16);
+                first_block.copy_from_slice(&iv[..]);
+                second_block.copy_from_slice(&iv[..]);
+                // The block counter is in the last 16 bits.
+                second_block[15] ^= 1;
+                cipher.encrypt_block(GenericArray::from_mut_slice(first_block));
+                cipher.encrypt_block(GenericArray::from_mut_slice(second_block));
+                Key::Aes256Gcm(derived)
+            }
+        }
     }
 
     fn derive_salt_from_master(master: &KeyAndSalt, label: u8) -> Salt {
-        Self::derive_key_from_master(master, label)[..SRTP_SALT_LEN]
+        Self::derive_key_from_master(master, label).as_bytes()[..SRTP_SALT_LEN]
             .try_into()
             .unwrap()
     }
@@ -208,6 +340,9 @@ pub struct Header {
     tcc_seqnum: Option<TruncatedSequenceNumber>,
     // We parse the range as well in order to replace it easily.
     tcc_seqnum_range: Option<Range<usize>>,
+    // Only the Call reads this, and only for senders that use it instead of the payload
+    // for video metadata. But it gets rewritten like the tcc seqnum.
+    dependency_descriptor_range: Option<Range<usize>>,
     // The payload start is the same as the header len.
     // The payload end isn't technically part of the "Header",
     // but it's convenient to parse at the same time.
@@ -234,6 +369,7 @@ impl Header {
 
         let mut tcc_seqnum = None;
         let mut tcc_seqnum_range = None;
+        let mut dependency_descriptor_range = None;
         let mut video_rotation = None;
         let mut audio_level = None;
 
@@ -245,11 +381,11 @@ impl Header {
             let extensions_profile = parse_u16(&extensions_header[0..2]);
             let extensions_len = (parse_u16(&extensions_header[2..4]) as usize) * 4;
 
-            if extensions_profile != RTP_ONE_BYTE_EXTENSIONS_PROFILE {
-                // 2-byte header extension is only needed for extensions of size = 0
-                // size > 16, and we don't use any such extensions.
+            let two_byte_extensions = (extensions_profile & RTP_TWO_BYTE_EXTENSIONS_PROFILE_MASK)
+                == RTP_TWO_BYTE_EXTENSIONS_PROFILE;
+            if extensions_profile != RTP_ONE_BYTE_EXTENSIONS_PROFILE && !two_byte_extensions {
                 warn!(
-                    "Invalid RTP: not using 1-byte extensions; profile = 0x{:x}",
+                    "Invalid RTP: not using 1-byte or 2-byte extensions; profile = 0x{:x}",
                     extensions_profile
                 );
                 debug!("{}", hex::encode(&packet[..packet.len().min(100)]));
@@ -262,14 +398,30 @@ impl Header {
             // extension_start is relative to extensions (relative to extensions_start + RTP_EXTENSIONS_HEADER_LEN)
             let mut extension_start = 0;
             while extensions.len() > extension_start {
-                let (extension_header, extension_val) =
-                    extensions[extension_start..].checked_split_at(1)?;
-                let extension_id = extension_header[0] >> 4;
-                if extension_id == 0 {
-                    // Tail padding
-                    break;
-                }
-                let extension_len = ((extension_header[0] & 0x0F) as usize) + 1;
+                // 2-byte headers are only needed for extensions of size = 0 or size > 16,
+                // such as a dependency descriptor with a template dependency structure.
+                let (extension_id, extension_len, extension_header_len) = if two_byte_extensions {
+                    let (extension_header, _extension_val) =
+                        extensions[extension_start..].checked_split_at(1)?;
+                    if extension_header[0] == 0 {
+                        // Padding, which is allowed between extensions
+                        extension_start += 1;
+                        continue;
+                    }
+                    let (extension_header, _extension_val) =
+                        extensions[extension_start..].checked_split_at(2)?;
+                    (extension_header[0], extension_header[1] as usize, 2)
+                } else {
+                    let (extension_header, _extension_val) =
+                        extensions[extension_start..].checked_split_at(1)?;
+                    let extension_id = extension_header[0] >> 4;
+                    if extension_id == 0 {
+                        // Tail padding
+                        break;
+                    }
+                    (extension_id, ((extension_header[0] & 0x0F) as usize) + 1, 1)
+                };
+                let extension_val = &extensions[(extension_start + extension_header_len)..];
                 if extension_val.len() < extension_len {
                     warn!(
                         "Invalid RTP: extension too short: {} < {}.  ID = {}",
@@ -281,9 +433,10 @@ impl Header {
                     return None;
                 }
                 let extension_val = &extension_val[..extension_len];
-                // TODO: Dedup this with the above code.
-                let extension_val_start =
-                    extensions_start + RTP_EXTENSIONS_HEADER_LEN + extension_start + 1;
+                let extension_val_start = extensions_start
+                    + RTP_EXTENSIONS_HEADER_LEN
+                    + extension_start
+                    + extension_header_len;
                 let extension_val_end = extension_val_start + extension_len;
                 let extension_val_range = extension_val_start..extension_val_end;
 
@@ -301,9 +454,12 @@ impl Header {
                             // by a factor of 10, so this ends up being 120 as the lowest value (muted).
                             Some(120u8.saturating_sub(negative_audio_level_with_voice_activity & 0b0111_1111));
                     }
+                    (RTP_EXT_ID_DEPENDENCY_DESCRIPTOR, _) => {
+                        dependency_descriptor_range = Some(extension_val_range);
+                    }
                     _ => {}
                 }
-                extension_start += 1 + extension_len;
+                extension_start += extension_header_len + extension_len;
             }
             payload_start = extensions_start + RTP_EXTENSIONS_HEADER_LEN + extensions_len;
         };
@@ -330,6 +486,7 @@ impl Header {
             audio_level,
             tcc_seqnum,
             tcc_seqnum_range,
+            dependency_descriptor_range,
             payload_range,
         })
     }
@@ -403,6 +560,7 @@ pub struct Packet<T> {
 
     // These are relative to self.serialized.
     tcc_seqnum_range: Option<Range<usize>>,
+    dependency_descriptor_range: Option<Range<usize>>,
     payload_range_in_header: Range<usize>,
 
     // If encrypted, that means the payload is ciphertext
@@ -417,10 +575,14 @@ pub struct Packet<T> {
 }
 
 impl<T> Packet<T> {
-    fn is_rtx(&self) -> bool {
+    pub fn is_rtx(&self) -> bool {
         self.seqnum_in_payload.is_some()
     }
 
+    pub fn is_audio(&self) -> bool {
+        self.payload_type() == OPUS_PAYLOAD_TYPE
+    }
+
     pub fn payload_type(&self) -> PayloadType {
         if self.is_rtx() {
             from_rtx_payload_type(self.payload_type_in_header)
@@ -471,6 +633,10 @@ impl<T: Borrow<[u8]>> Packet<T> {
         &self.serialized()[self.payload_range()]
     }
 
+    pub fn dependency_descriptor(&self) -> Option<&[u8]> {
+        Some(&self.serialized()[self.dependency_descriptor_range.clone()?])
+    }
+
     pub fn size(&self) -> DataSize {
         DataSize::from_bytes(self.serialized().len() as u64)
     }
@@ -487,6 +653,7 @@ impl<T: Borrow<[u8]>> Packet<T> {
             audio_level: self.audio_level,
             tcc_seqnum: self.tcc_seqnum,
             tcc_seqnum_range: self.tcc_seqnum_range.clone(),
+            dependency_descriptor_range: self.dependency_descriptor_range.clone(),
             payload_range_in_header: self.payload_range_in_header.clone(),
             encrypted: self.encrypted,
 
@@ -506,6 +673,7 @@ impl<T: Borrow<[u8]>> Packet<T> {
             audio_level: self.audio_level,
             tcc_seqnum: self.tcc_seqnum,
             tcc_seqnum_range: self.tcc_seqnum_range.clone(),
+            dependency_descriptor_range: self.dependency_descriptor_range.clone(),
             payload_range_in_header: self.payload_range_in_header.clone(),
             encrypted: self.encrypted,
 
@@ -568,16 +736,17 @@ impl<T: BorrowMut<[u8]>> Packet<T> {
         &mut self.serialized_mut()[payload_range]
     }
 
+    pub fn dependency_descriptor_mut(&mut self) -> Option<&mut [u8]> {
+        let dependency_descriptor_range = self.dependency_descriptor_range.clone()?;
+        Some(&mut self.header_mut()[dependency_descriptor_range])
+    }
+
     // TODO: Return a Result instead
     // pub for tests
     pub fn decrypt_in_place(&mut self, key: &Key, salt: &Salt) -> Option<()> {
         assert!(self.encrypted, "Can't decrypt an unencrypted packet");
-        let (cipher, nonce, aad, ciphertext, tag) = self.prepare_for_crypto(key, salt);
-        let nonce = GenericArray::from_slice(&nonce);
-        let tag = GenericArray::from_slice(tag);
-        cipher
-            .decrypt_in_place_detached(nonce, aad, ciphertext, tag)
-            .ok()?;
+        let (iv, aad, ciphertext, tag) = self.prepare_for_crypto(salt);
+        key.decrypt_in_place_detached(&iv, aad, ciphertext, tag)?;
         self.encrypted = false;
         Some(())
     }
@@ -586,21 +755,13 @@ impl<T: BorrowMut<[u8]>> Packet<T> {
     // public for tests
     pub fn encrypt_in_place(&mut self, key: &Key, salt: &Salt) -> Option<()> {
         assert!(!self.encrypted, "Can't encrypt an already encrypted packet");
-        let (cipher, nonce, aad, plaintext, tag) = self.prepare_for_crypto(key, salt);
-        let nonce = GenericArray::from_slice(&nonce);
-        let computed_tag = cipher
-            .encrypt_in_place_detached(nonce, aad, plaintext)
-            .ok()?;
-        tag.copy_from_slice(&computed_tag);
+        let (iv, aad, plaintext, tag) = self.prepare_for_crypto(salt);
+        key.encrypt_in_place_detached(&iv, aad, plaintext, tag)?;
         self.encrypted = true;
         Some(())
     }
 
-    fn prepare_for_crypto(
-        &mut self,
-        key: &Key,
-        salt: &Salt,
-    ) -> (Aes128Gcm, [u8; SRTP_IV_LEN], &[u8], &mut [u8], &mut [u8]) {
+    fn prepare_for_crypto(&mut self, salt: &Salt) -> (Iv, &[u8], &mut [u8], &mut [u8]) {
         let ssrc = self.ssrc_in_header;
         let seqnum = self.seqnum_in_header;
         let header_len = self.payload_range_in_header.start;
@@ -609,8 +770,7 @@ impl<T: BorrowMut<[u8]>> Packet<T> {
         let (header, payload_plus_tag) = self.serialized.borrow_mut().split_at_mut(header_len);
         let (payload, tag) = payload_plus_tag.split_at_mut(payload_len);
         let iv = rtp_iv(ssrc, seqnum, salt);
-        let cipher = Aes128Gcm::new(GenericArray::from_slice(&key[..]));
-        (cipher, iv, header, payload, tag)
+        (iv, header, payload, tag)
     }
 
     fn set_payload_type_in_header(&mut self, pt: PayloadType) {
@@ -678,6 +838,7 @@ impl<T: BorrowMut<[u8]>> Packet<T> {
             audio_level: self.audio_level,
             tcc_seqnum: self.tcc_seqnum,
             tcc_seqnum_range: self.tcc_seqnum_range.clone(),
+            dependency_descriptor_range: self.dependency_descriptor_range.clone(),
             payload_range_in_header: self.payload_range_in_header.clone(),
             encrypted: self.encrypted,
 
@@ -699,6 +860,13 @@ fn write_extension(id: u8, value: impl Writer) -> impl Writer {
     ([header], value)
 }
 
+/// Encodes a two-byte RTP extension.
+fn write_two_byte_extension(id: u8, value: impl Writer) -> impl Writer {
+    assert!(id != 0, "id 0 is padding");
+    let length = u8::try_from(value.written_len()).expect("length must fit in 8 bits");
+    ([id, length], value)
+}
+
 impl Packet<Vec<u8>> {
     /// Writes a valid RTP packet with the given parameters.
     ///
@@ -714,6 +882,30 @@ impl Packet<Vec<u8>> {
         ssrc: Ssrc,
         extensions: impl Writer,
         payload: &[u8],
+    ) -> (Vec<u8>, Range<usize>) {
+        Self::write_serialized_with_extensions_profile(
+            marker,
+            pt,
+            seqnum,
+            timestamp,
+            ssrc,
+            RTP_ONE_BYTE_EXTENSIONS_PROFILE,
+            extensions,
+            payload,
+        )
+    }
+
+    /// Like [`Self::write_serialized`], but the extensions can use the two-byte header format.
+    #[allow(clippy::too_many_arguments)]
+    fn write_serialized_with_extensions_profile(
+        marker: bool,
+        pt: PayloadType,
+        seqnum: FullSequenceNumber,
+        timestamp: TruncatedTimestamp,
+        ssrc: Ssrc,
+        extensions_profile: u16,
+        extensions: impl Writer,
+        payload: &[u8],
     ) -> (Vec<u8>, Range<usize>) {
         let has_padding = 0u8;
         let extensions_len = extensions.written_len();
@@ -734,7 +926,7 @@ impl Packet<Vec<u8>> {
             let padding_len = padded_len - extensions_len;
             let extension_padding = &[0u8, 0, 0][..padding_len];
             Some((
-                RTP_ONE_BYTE_EXTENSIONS_PROFILE,
+                extensions_profile,
                 u16::try_from(padded_len / 4).expect("too many extensions"),
                 extensions,
                 extension_padding,
@@ -785,12 +977,54 @@ impl Packet<Vec<u8>> {
             } else {
                 None
             },
+            dependency_descriptor_range: None,
             payload_range_in_header: payload_range,
             encrypted: false,
             serialized,
         }
     }
 
+    // pub for tests
+    pub fn with_empty_tag_and_dependency_descriptor(
+        pt: PayloadType,
+        seqnum: FullSequenceNumber,
+        timestamp: TruncatedTimestamp,
+        ssrc: Ssrc,
+        dependency_descriptor: &[u8],
+        payload: &[u8],
+    ) -> Self {
+        let marker = false;
+        let extensions =
+            write_two_byte_extension(RTP_EXT_ID_DEPENDENCY_DESCRIPTOR, dependency_descriptor);
+        let (serialized, _payload_range) = Self::write_serialized_with_extensions_profile(
+            marker,
+            pt,
+            seqnum,
+            timestamp,
+            ssrc,
+            RTP_TWO_BYTE_EXTENSIONS_PROFILE,
+            extensions,
+            payload,
+        );
+        let header = Header::parse(&serialized).expect("valid header");
+        Self {
+            marker,
+            payload_type_in_header: pt,
+            ssrc_in_header: ssrc,
+            seqnum_in_header: seqnum,
+            seqnum_in_payload: None,
+            timestamp,
+            video_rotation: None,
+            audio_level: None,
+            tcc_seqnum: None,
+            tcc_seqnum_range: None,
+            dependency_descriptor_range: header.dependency_descriptor_range,
+            payload_range_in_header: header.payload_range,
+            encrypted: false,
+            serialized,
+        }
+    }
+
     // pub for tests
     pub fn to_rtx(&self, rtx_seqnum: FullSequenceNumber) -> Self {
         if self.is_rtx() {
@@ -811,6 +1045,7 @@ impl Packet<Vec<u8>> {
                 seqnum_in_header: rtx_seqnum,
                 seqnum_in_payload: Some(self.seqnum_in_header),
                 tcc_seqnum_range: self.tcc_seqnum_range.clone(),
+                dependency_descriptor_range: self.dependency_descriptor_range.clone(),
                 payload_range_in_header: self.payload_range_in_header.start
                     ..(self.payload_range_in_header.end + 2),
                 serialized,
@@ -829,7 +1064,7 @@ impl Packet<Vec<u8>> {
 
 #[cfg(fuzzing)]
 fn fuzzing_key() -> Key {
-    [0u8; SRTP_KEY_LEN].into()
+    [0u8; SRTP_AES_128_KEY_LEN].into()
 }
 
 #[cfg(fuzzing)]
@@ -847,6 +1082,7 @@ pub fn parse_and_forward_rtp_for_fuzzing(data: Vec<u8>) -> Option<Vec<u8>> {
         audio_level: header.audio_level,
         tcc_seqnum: Default::default(),
         tcc_seqnum_range: header.tcc_seqnum_range,
+        dependency_descriptor_range: header.dependency_descriptor_range,
         payload_range_in_header: header.payload_range,
         encrypted: true,
         serialized: data,
@@ -918,7 +1154,13 @@ pub struct ControlPacket<'packet> {
     pub key_frame_requests: Vec<KeyFrameRequest>,
     // pub for tests
     pub tcc_feedbacks: Vec<&'packet [u8]>,
+    // RFC 8888 feedback
+    pub ccfb_feedbacks: Vec<&'packet [u8]>,
     pub nacks: Vec<Nack>,
+    // From both receiver reports and sender reports.
+    pub reception_reports: Vec<ReceptionReport>,
+    // From extended reports, in response to receiver reference times we sent.
+    pub delays_since_last_receiver_reference_time: Vec<DelaySinceLastReceiverReferenceTime>,
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -926,13 +1168,59 @@ pub struct KeyFrameRequest {
     pub ssrc: Ssrc,
 }
 
+/// A report block from an RTCP receiver report or sender report.
+/// See https://datatracker.ietf.org/doc/html/rfc3550#section-6.4.1
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct ReceptionReport {
+    /// The SSRC the report is about (one we sent).
+    pub ssrc: Ssrc,
+    /// Out of 256
+    pub fraction_lost: u8,
+    /// Really an i24
+    pub cumulative_loss: i32,
+    pub max_seqnum: u32,
+    /// In units of the RTP timestamp.
+    pub jitter: u32,
+    /// The middle 32 bits of the NTP timestamp of the last sender report.
+    /// Zero if no sender report has been received.
+    pub last_sender_report_timestamp: u32,
+    /// In units of 1/65536 seconds.
+    pub delay_since_last_sender_report: u32,
+}
+
+/// A DLRR sub-block from an RTCP extended report.
+/// See https://datatracker.ietf.org/doc/html/rfc3611#section-4.5
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct DelaySinceLastReceiverReferenceTime {
+    /// The SSRC that sent the receiver reference time (us).
+    pub ssrc: Ssrc,
+    /// The middle 32 bits of the NTP timestamp of the last receiver reference time.
+    pub last_receiver_reference_time: u32,
+    /// In units of 1/65536 seconds.
+    pub delay_since_last_receiver_reference_time: u32,
+}
+
 impl<'packet> ControlPacket<'packet> {
     // pub for tests
+    // The SRTCP indices of successfully decrypted packets are remembered by sender SSRC
+    // so that replayed packets are dropped.
     pub fn parse_and_decrypt_in_place(
         serialized: &'packet mut [u8],
         key: &Key,
         salt: &Salt,
+        srtcp_index_reuse_detector_by_ssrc: &mut HashMap<Ssrc, SequenceNumberReuseDetector>,
     ) -> Option<Self> {
+        Self::decrypt_in_place(serialized, key, salt, srtcp_index_reuse_detector_by_ssrc)?;
+        Self::parse(serialized)
+    }
+
+    // If decryption fails, the packet is left garbled.
+    fn decrypt_in_place(
+        serialized: &mut [u8],
+        key: &Key,
+        salt: &Salt,
+        srtcp_index_reuse_detector_by_ssrc: &mut HashMap<Ssrc, SequenceNumberReuseDetector>,
+    ) -> Option<()> {
         if serialized.len() < RTCP_HEADER_LEN + SRTP_AUTH_TAG_LEN + SRTCP_FOOTER_LEN {
             warn!("RTCP packet too small: {}", serialized.len());
             return None;
@@ -943,13 +1231,36 @@ impl<'packet> ControlPacket<'packet> {
         let srtcp_index = parse_u32(footer) & 0x7FFF_FFFF;
 
         if encrypted {
-            let (cipher, nonce, aad, ciphertext, tag) =
-                Self::prepare_for_crypto(serialized, sender_ssrc, srtcp_index, key, salt)?;
-            let nonce = GenericArray::from_slice(&nonce);
-            let tag = GenericArray::from_slice(tag);
-            cipher
-                .decrypt_in_place_detached(nonce, &aad, ciphertext, tag)
-                .ok()?;
+            let (iv, aad, ciphertext, tag) =
+                Self::prepare_for_crypto(serialized, sender_ssrc, srtcp_index, salt)?;
+            key.decrypt_in_place_detached(&iv, &aad, ciphertext, tag)?;
+
+            // We only remember the index after authenticating the packet
+            // so that forged packets can't push valid ones out of the window.
+            match srtcp_index_reuse_detector_by_ssrc
+                .entry(sender_ssrc)
+                .or_default()
+                .remember_used(srtcp_index as FullSequenceNumber)
+            {
+                SequenceNumberReuse::UsedBefore => {
+                    trace!("Dropping SRTCP packet because we've already seen this index ({}) from this ssrc ({})", srtcp_index, sender_ssrc);
+                    event!("calling.srtcp.index_drop.reused");
+                    return None;
+                }
+                SequenceNumberReuse::TooOldToKnow { delta } => {
+                    trace!(
+                        "Dropping SRTCP packet because it's such an old index ({}) from this ssrc ({}), delta: {}",
+                        srtcp_index,
+                        sender_ssrc,
+                        delta
+                    );
+                    event!("calling.srtcp.index_drop.old");
+                    return None;
+                }
+                SequenceNumberReuse::NotUsedBefore => {
+                    // Continue parsing
+                }
+            }
         } else {
             // Allow processing unencrypted packets when fuzzing;
             // otherwise we'd have to encrypt all fuzz inputs.
@@ -959,7 +1270,11 @@ impl<'packet> ControlPacket<'packet> {
                 return None;
             }
         }
+        Some(())
+    }
 
+    // Must be decrypted already.
+    fn parse(serialized: &'packet [u8]) -> Option<Self> {
         let mut incoming = Self::default();
 
         let len_without_tag_and_footer = serialized.len() - SRTP_AUTH_TAG_LEN - SRTCP_FOOTER_LEN;
@@ -982,9 +1297,30 @@ impl<'packet> ControlPacket<'packet> {
             let (payload, after_payload) = after_header.checked_split_at(payload_len)?;
             compound_packets = after_payload;
             match (pt, count_or_format) {
-                (RTCP_TYPE_SENDER_REPORT, _) => {}
-                (RTCP_TYPE_RECEIVER_REPORT, _) => {}
-                (RTCP_TYPE_EXTENDED_REPORT, _) => {}
+                (RTCP_TYPE_SENDER_REPORT, count) => {
+                    // We don't use the sender info (NTP timestamp, packet counts, etc) yet,
+                    // just the report blocks that follow it.
+                    match payload
+                        .get(RTCP_SENDER_INFO_LEN..)
+                        .ok_or_else(|| std::io::ErrorKind::UnexpectedEof.into())
+                        .and_then(|blocks| parse_reception_reports(blocks, count))
+                    {
+                        Ok(reports) => incoming.reception_reports.extend(reports),
+                        Err(err) => warn!("Failed to parse RTCP sender report: {}", err),
+                    }
+                }
+                (RTCP_TYPE_RECEIVER_REPORT, count) => {
+                    match parse_reception_reports(payload, count) {
+                        Ok(reports) => incoming.reception_reports.extend(reports),
+                        Err(err) => warn!("Failed to parse RTCP receiver report: {}", err),
+                    }
+                }
+                (RTCP_TYPE_EXTENDED_REPORT, _) => match parse_extended_report(payload) {
+                    Ok(delays) => incoming
+                        .delays_since_last_receiver_reference_time
+                        .extend(delays),
+                    Err(err) => warn!("Failed to parse RTCP extended report: {}", err),
+                },
                 (RTCP_TYPE_SDES, _) => {}
                 (RTCP_TYPE_BYE, _) => {}
                 (RTCP_TYPE_SPECIFIC_FEEDBACK, RTCP_FORMAT_LOSS_NOTIFICATION) => {}
@@ -997,6 +1333,10 @@ impl<'packet> ControlPacket<'packet> {
                     // (it requires expanding the seqnums), so we pass it into the tcc::Sender instead.
                     incoming.tcc_feedbacks.push(payload);
                 }
+                (RTCP_TYPE_GENERIC_FEEDBACK, RTCP_FORMAT_CONGESTION_CONTROL_FEEDBACK) => {
+                    // Same as above, but for the ccfb::Sender.
+                    incoming.ccfb_feedbacks.push(payload);
+                }
                 (RTCP_TYPE_SPECIFIC_FEEDBACK, RTCP_FORMAT_PLI) => {
                     // PLI See https://tools.ietf.org/html/rfc4585
                     if payload.len() < 4 {
@@ -1041,20 +1381,21 @@ impl<'packet> ControlPacket<'packet> {
         serialized[RTCP_HEADER_LEN + padded_payload_len + SRTP_AUTH_TAG_LEN..]
             .copy_from_slice(&(srtcp_index | 0x80000000/* "encrypted" */).to_be_bytes());
 
-        let (cipher, nonce, aad, plaintext, tag) =
-            Self::prepare_for_crypto(&mut serialized, sender_ssrc, srtcp_index, key, salt)?;
-        let nonce = GenericArray::from_slice(&nonce);
-        let computed_tag = cipher
-            .encrypt_in_place_detached(nonce, &aad, plaintext)
-            .ok()?;
-        tag.copy_from_slice(&computed_tag);
+        let (iv, aad, plaintext, tag) =
+            Self::prepare_for_crypto(&mut serialized, sender_ssrc, srtcp_index, salt)?;
+        key.encrypt_in_place_detached(&iv, &aad, plaintext, tag)?;
         Some(serialized)
     }
 }
 
 #[cfg(fuzzing)]
 pub fn parse_rtcp(buffer: &mut [u8]) {
-    ControlPacket::parse_and_decrypt_in_place(buffer, &fuzzing_key(), &Default::default());
+    ControlPacket::parse_and_decrypt_in_place(
+        buffer,
+        &fuzzing_key(),
+        &Default::default(),
+        &mut HashMap::new(),
+    );
 }
 
 impl ControlPacket<'_> {
@@ -1063,25 +1404,16 @@ impl ControlPacket<'_> {
         packet: &'packet mut [u8],
         sender_ssrc: Ssrc,
         srtcp_index: u32,
-        key: &Key,
         salt: &Salt,
-    ) -> Option<(
-        Aes128Gcm,
-        [u8; SRTP_IV_LEN],
-        Vec<u8>,
-        &'packet mut [u8],
-        &'packet mut [u8],
-    )> {
+    ) -> Option<(Iv, Vec<u8>, &'packet mut [u8], &'packet mut [u8])> {
         let (header, payload_plus_tag_plus_footer) = packet.split_at_mut(RTCP_HEADER_LEN);
         let (payload_plus_tag, footer) = payload_plus_tag_plus_footer
             .split_at_mut(payload_plus_tag_plus_footer.len() - SRTCP_FOOTER_LEN);
         let (payload, tag) =
             payload_plus_tag.split_at_mut(payload_plus_tag.len() - SRTP_AUTH_TAG_LEN);
         let iv = rtcp_iv(sender_ssrc, srtcp_index, salt)?;
-
-        let cipher = Aes128Gcm::new(GenericArray::from_slice(&key[..]));
         let aad = [header, footer].concat();
-        Some((cipher, iv, aad, payload, tag))
+        Some((iv, aad, payload, tag))
     }
 }
 
@@ -1128,6 +1460,75 @@ fn parse_nack(rtcp_payload: &[u8]) -> std::io::Result<Nack> {
     Ok(Nack { ssrc, seqnums })
 }
 
+fn parse_reception_reports(
+    rtcp_payload: &[u8],
+    count: u8,
+) -> std::io::Result<Vec<ReceptionReport>> {
+    let mut reader = rtcp_payload;
+    (0..count)
+        .map(|_| {
+            Ok(ReceptionReport {
+                ssrc: reader.read_u32::<BE>()?,
+                fraction_lost: reader.read_u8()?,
+                cumulative_loss: reader.read_i24::<BE>()?,
+                max_seqnum: reader.read_u32::<BE>()?,
+                jitter: reader.read_u32::<BE>()?,
+                last_sender_report_timestamp: reader.read_u32::<BE>()?,
+                delay_since_last_sender_report: reader.read_u32::<BE>()?,
+            })
+        })
+        .collect()
+}
+
+// Block types other than DLRR are ignored.
+fn parse_extended_report(
+    rtcp_payload: &[u8],
+) -> std::io::Result<Vec<DelaySinceLastReceiverReferenceTime>> {
+    let mut reader = rtcp_payload;
+    let mut delays = Vec::new();
+    while !reader.is_empty() {
+        let block_type = reader.read_u8()?;
+        let _type_specific = reader.read_u8()?;
+        let block_len = reader.read_u16::<BE>()? as usize * 4;
+        let (mut block, after_block) = reader
+            .checked_split_at(block_len)
+            .ok_or(std::io::ErrorKind::UnexpectedEof)?;
+        reader = after_block;
+        if block_type == RTCP_XR_BLOCK_TYPE_DLRR {
+            while !block.is_empty() {
+                delays.push(DelaySinceLastReceiverReferenceTime {
+                    ssrc: block.read_u32::<BE>()?,
+                    last_receiver_reference_time: block.read_u32::<BE>()?,
+                    delay_since_last_receiver_reference_time: block.read_u32::<BE>()?,
+                });
+            }
+        }
+    }
+    Ok(delays)
+}
+
+// The middle 32 bits of a 64-bit NTP timestamp, which is what RTCP uses
+// for round trip time calculations.
+fn compact_ntp_timestamp(ntp_timestamp: u64) -> u32 {
+    (ntp_timestamp >> 16) as u32
+}
+
+// See https://datatracker.ietf.org/doc/html/rfc3550#section-6.4.1
+// and https://datatracker.ietf.org/doc/html/rfc3611#section-4.5
+fn rtt_from_compact_ntp_timestamps(
+    now: u32,
+    last_report: u32,
+    delay_since_last_report: u32,
+) -> Option<Duration> {
+    if last_report == 0 {
+        // The remote side hasn't received a report from us yet.
+        return None;
+    }
+    let since_last_report = now.wrapping_sub(last_report);
+    let rtt = since_last_report.checked_sub(delay_since_last_report)?;
+    Some(Duration::from_micros((rtt as u64 * 1_000_000) >> 16))
+}
+
 // This will only work well if the iterator provides seqnums in order.
 // pub for tests
 pub fn write_nack(
@@ -1294,6 +1695,11 @@ impl RtxSender {
         );
     }
 
+    fn forget_sent(&mut self, ssrc: Ssrc, seqnum: FullSequenceNumber) {
+        self.previously_sent_by_seqnum
+            .remove(&(ssrc, seqnum as TruncatedSequenceNumber));
+    }
+
     fn resend_as_rtx(
         &mut self,
         ssrc: Ssrc,
@@ -1362,6 +1768,8 @@ struct ReceiverReportSender {
     last_receive_time: Instant,
     last_rtp_timestamp: u32,
     jitter_q4: u32,
+    // Out of 256, as of the latest report block.
+    last_fraction_lost: u8,
 }
 
 impl ReceiverReportSender {
@@ -1374,6 +1782,7 @@ impl ReceiverReportSender {
             last_receive_time: Instant::now(),
             last_rtp_timestamp: 0,
             jitter_q4: 0,
+            last_fraction_lost: 0,
         }
     }
 
@@ -1475,6 +1884,7 @@ impl ReceiverReportSender {
             } else {
                 (256 * lost_since_last / expected_since_last) as u8
             };
+            self.last_fraction_lost = fraction_lost_since_last;
 
             // Negative cumulative loss isn't supported because it can cause problems with WebRTC
             // https://source.chromium.org/chromium/chromium/src/+/main:third_party/webrtc/modules/rtp_rtcp/source/receive_statistics_impl.h;l=91-94;drc=18649971ab02d2f3fc8f360aee2e3c573652b7bd
@@ -1535,14 +1945,53 @@ pub struct Endpoint {
     // For seqnum expanasion of incoming packets
     // and for SRTP replay attack protection
     state_by_incoming_ssrc: HashMap<Ssrc, IncomingSsrcState>,
+    // For SRTCP replay attack protection
+    srtcp_index_reuse_detector_by_ssrc: HashMap<Ssrc, SequenceNumberReuseDetector>,
+
+    // For switching to new SRTP/SRTCP keys mid-call
+    rekey: Option<Rekey>,
 
     // For transport-cc
     tcc_receiver: tcc::Receiver,
     tcc_sender: tcc::Sender,
     max_received_tcc_seqnum: tcc::FullSequenceNumber,
 
+    // For RFC 8888 feedback, which is used instead of transport-cc feedback if selected.
+    // The transport-cc seqnums are still used to identify sent packets.
+    congestion_control_feedback: CongestionControlFeedback,
+    ccfb_receiver: ccfb::Receiver,
+    ccfb_sender: ccfb::Sender,
+
     // For RTX
     rtx_sender: RtxSender,
+
+    // For RTCP reports from the remote side.
+    // Our NTP timestamps are relative to this because only we interpret them.
+    ntp_epoch: Instant,
+    reception_stats_by_outgoing_ssrc: HashMap<Ssrc, ReceptionStats>,
+    reported_rtt: Option<Duration>,
+
+    // For estimating how much the remote side can send us.
+    // Out of 256, the worst of the SSRCs in the latest receiver report we sent.
+    // Cleared when taken so each report is only used once.
+    incoming_fraction_lost: Option<u8>,
+}
+
+/// The keys from before a rekey, kept while the remote side switches to the new ones.
+struct Rekey {
+    previous_decrypt: KeysAndSalts,
+    /// The remote side can't decrypt with the new keys until it gets our half of the DHE,
+    /// so we keep encrypting with the previous keys until it shows it has them by using them.
+    pending_encrypt: Option<KeysAndSalts>,
+    /// Until the remote side uses the new keys, when to drop them.
+    /// After it does, when to drop the previous ones.
+    expiration: Instant,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum KeyGeneration {
+    Current,
+    Previous,
 }
 
 struct IncomingSsrcState {
@@ -1558,7 +2007,14 @@ struct IncomingSsrcState {
 pub struct ProcessedControlPacket {
     pub key_frame_requests: Vec<KeyFrameRequest>,
     pub acks: Vec<tcc::Ack>,
+    pub losses: Vec<tcc::Loss>,
+    // The number of acked packets that earlier feedback reported as lost.
+    pub late_ack_count: usize,
+    // The number of acked packets marked as having experienced congestion (with ECN).
+    pub congestion_experienced_count: usize,
     pub nacks: Vec<Nack>,
+    // None if the packet didn't contain any receiver reports or sender reports.
+    pub reception_summary: Option<ReceptionSummary>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -1567,9 +2023,48 @@ pub struct Nack {
     pub seqnums: Vec<TruncatedSequenceNumber>,
 }
 
+/// Which kind of congestion control feedback is sent to and expected from the remote side.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, EnumString)]
+pub enum CongestionControlFeedback {
+    /// https://datatracker.ietf.org/doc/html/draft-holmer-rmcat-transport-wide-cc-extensions-01
+    #[default]
+    #[strum(serialize = "transport-cc")]
+    TransportCc,
+    /// https://datatracker.ietf.org/doc/html/rfc8888
+    #[strum(serialize = "rfc8888")]
+    Rfc8888,
+}
+
 pub struct EndpointStats {
     pub remembered_packet_count: usize,
     pub remembered_packet_bytes: usize,
+    pub reception_stats_by_ssrc: HashMap<Ssrc, ReceptionStats>,
+    pub reported_rtt: Option<Duration>,
+}
+
+/// How well the remote side is receiving one of our outgoing SSRCs,
+/// according to the latest RTCP receiver report or sender report.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReceptionStats {
+    /// Out of 256, since the previous report.
+    pub fraction_lost: u8,
+    pub cumulative_loss: i32,
+    pub max_seqnum: u32,
+    /// In units of the RTP timestamp.
+    pub jitter: u32,
+    /// Calculated from LSR/DLSR. None if the remote side hasn't received a sender report from us.
+    pub rtt: Option<Duration>,
+}
+
+/// Combines the reception reports of one RTCP packet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReceptionSummary {
+    /// Out of 256, the worst of all the reported SSRCs.
+    pub max_fraction_lost: u8,
+    /// Summed over all the reported SSRCs.
+    pub cumulative_loss: i64,
+    /// The latest RTT calculated from any report (LSR/DLSR or DLRR).
+    pub rtt: Option<Duration>,
 }
 
 impl Endpoint {
@@ -1588,23 +2083,54 @@ impl Endpoint {
             next_outgoing_srtcp_index: 1,
 
             state_by_incoming_ssrc: HashMap::new(),
+            srtcp_index_reuse_detector_by_ssrc: HashMap::new(),
+
+            rekey: None,
 
             tcc_sender: tcc::Sender::new(now),
             tcc_receiver: tcc::Receiver::new(ack_sender_ssrc, now),
             max_received_tcc_seqnum: 0,
 
+            congestion_control_feedback: CongestionControlFeedback::default(),
+            ccfb_receiver: ccfb::Receiver::new(),
+            ccfb_sender: ccfb::Sender::new(now),
+
             // 10 seconds of RTX history should be enough for anyone
             rtx_sender: RtxSender::new(Duration::from_secs(10)),
+
+            ntp_epoch: now,
+            reception_stats_by_outgoing_ssrc: HashMap::new(),
+            reported_rtt: None,
+
+            incoming_fraction_lost: None,
         }
     }
 
+    pub fn set_congestion_control_feedback(
+        &mut self,
+        congestion_control_feedback: CongestionControlFeedback,
+    ) {
+        self.congestion_control_feedback = congestion_control_feedback;
+    }
+
+    // Like receive_rtp_with_ecn, for a packet whose ECN codepoint isn't known.
+    pub fn receive_rtp<'packet>(
+        &mut self,
+        encrypted: &'packet mut [u8],
+        now: Instant,
+    ) -> Option<Packet<&'packet mut [u8]>> {
+        self.receive_rtp_with_ecn(encrypted, ccfb::Ecn::NotEct, now)
+    }
+
     // Returns a Packet and an optional transport-cc feedback RTCP packet that should be sent.
     // The packet's payload is also decrypted in place.
+    // The ECN codepoint it arrived with is reported in RFC 8888 feedback.
     // TODO: Use Result instead of Option.
     #[allow(clippy::type_complexity)]
-    pub fn receive_rtp<'packet>(
+    pub fn receive_rtp_with_ecn<'packet>(
         &mut self,
         encrypted: &'packet mut [u8],
+        ecn: ccfb::Ecn,
         now: Instant,
     ) -> Option<Packet<&'packet mut [u8]>> {
         // Header::parse will log a warning for every place where it fails to parse.
@@ -1650,15 +2176,25 @@ impl Endpoint {
             audio_level: header.audio_level,
             tcc_seqnum,
             tcc_seqnum_range: header.tcc_seqnum_range,
+            dependency_descriptor_range: header.dependency_descriptor_range,
             payload_range_in_header: header.payload_range,
             encrypted: true,
             serialized: encrypted,
         };
 
-        let decrypt_failed = incoming
-            .decrypt_in_place(&self.decrypt.rtp.key, &self.decrypt.rtp.salt)
-            .is_none();
-        if decrypt_failed {
+        // A failed attempt leaves the packet garbled, so we have to restore it before the next one.
+        let decryption_order = self.decryption_order(now);
+        let ciphertext = (decryption_order.len() > 1).then(|| incoming.serialized.to_vec());
+        let decrypted_with = decryption_order.iter().copied().find(|&generation| {
+            if let Some(ciphertext) = &ciphertext {
+                incoming.serialized.copy_from_slice(ciphertext);
+            }
+            let keys = &decryption_keys(&self.decrypt, &self.rekey, generation).rtp;
+            incoming.decrypt_in_place(&keys.key, &keys.salt).is_some()
+        });
+        if let Some(generation) = decrypted_with {
+            self.remember_decrypted_with(generation, now);
+        } else {
             debug!(
                 "Invalid RTP: decryption failed; ssrc: {}, seqnum: {}, pt: {}, payload_range: {:?}",
                 incoming.ssrc(),
@@ -1711,13 +2247,89 @@ impl Endpoint {
             );
         }
 
-        if let Some(tcc_seqnum) = incoming.tcc_seqnum {
-            self.tcc_receiver.remember_received(tcc_seqnum, now);
+        match self.congestion_control_feedback {
+            CongestionControlFeedback::TransportCc => {
+                if let Some(tcc_seqnum) = incoming.tcc_seqnum {
+                    self.tcc_receiver.remember_received(tcc_seqnum, now);
+                }
+            }
+            CongestionControlFeedback::Rfc8888 => {
+                self.ccfb_receiver
+                    .remember_received(header.ssrc, seqnum_in_header, ecn, now);
+            }
         }
 
         Some(incoming)
     }
 
+    pub fn srtp_protection_profile(&self) -> SrtpProtectionProfile {
+        self.decrypt.rtp.key.profile()
+    }
+
+    /// Switches to new keys mid-call. Seqnums, ROCs, and SRTCP indices carry over unchanged, so
+    /// replay protection stays in effect across the switch.
+    ///
+    /// Until the remote side uses the new keys, we keep encrypting with the current ones.
+    /// After it does, we keep accepting packets encrypted with the current ones for a short
+    /// while, in case they were reordered. If it never does, we drop the new keys after a while.
+    pub fn rekey(&mut self, decrypt: KeysAndSalts, encrypt: KeysAndSalts, now: Instant) {
+        event!("calling.srtp.rekey.start");
+        let previous_decrypt = match self.rekey.take() {
+            // The remote side never used the keys from the unfinished rekey,
+            // so it's still using the ones from before it.
+            Some(Rekey {
+                previous_decrypt,
+                pending_encrypt: Some(_),
+                ..
+            }) => previous_decrypt,
+            _ => self.decrypt.clone(),
+        };
+        self.decrypt = decrypt;
+        self.rekey = Some(Rekey {
+            previous_decrypt,
+            pending_encrypt: Some(encrypt),
+            expiration: now + PENDING_KEYS_TIMEOUT,
+        });
+    }
+
+    // The keys to try decrypting incoming packets with, most likely first.
+    fn decryption_order(&mut self, now: Instant) -> &'static [KeyGeneration] {
+        if let Some(rekey) = &self.rekey {
+            if now >= rekey.expiration {
+                if rekey.pending_encrypt.is_some() {
+                    event!("calling.srtp.rekey.abandoned");
+                    if let Some(rekey) = self.rekey.take() {
+                        self.decrypt = rekey.previous_decrypt;
+                    }
+                } else {
+                    event!("calling.srtp.rekey.previous_keys_retired");
+                    self.rekey = None;
+                }
+            }
+        }
+        match &self.rekey {
+            None => &[KeyGeneration::Current],
+            Some(Rekey {
+                pending_encrypt: Some(_),
+                ..
+            }) => &[KeyGeneration::Previous, KeyGeneration::Current],
+            Some(_) => &[KeyGeneration::Current, KeyGeneration::Previous],
+        }
+    }
+
+    fn remember_decrypted_with(&mut self, generation: KeyGeneration, now: Instant) {
+        if generation != KeyGeneration::Current {
+            return;
+        }
+        if let Some(rekey) = &mut self.rekey {
+            if let Some(encrypt) = rekey.pending_encrypt.take() {
+                event!("calling.srtp.rekey.complete");
+                self.encrypt = encrypt;
+                rekey.expiration = now + PREVIOUS_DECRYPT_KEYS_RETENTION;
+            }
+        }
+    }
+
     fn get_incoming_ssrc_state_mut(&mut self, ssrc: Ssrc) -> &mut IncomingSsrcState {
         self.state_by_incoming_ssrc
             .entry(ssrc)
@@ -1740,25 +2352,137 @@ impl Endpoint {
         encrypted: &mut [u8],
         now: Instant,
     ) -> Option<ProcessedControlPacket> {
-        let incoming = ControlPacket::parse_and_decrypt_in_place(
-            encrypted,
-            &self.decrypt.rtcp.key,
-            &self.decrypt.rtcp.salt,
-        )?;
+        // A failed attempt leaves the packet garbled, so we have to restore it before the next one.
+        let decryption_order = self.decryption_order(now);
+        let ciphertext = (decryption_order.len() > 1).then(|| encrypted.to_vec());
+        let decrypted_with = decryption_order.iter().copied().find(|&generation| {
+            if let Some(ciphertext) = &ciphertext {
+                encrypted.copy_from_slice(ciphertext);
+            }
+            let keys = &decryption_keys(&self.decrypt, &self.rekey, generation).rtcp;
+            ControlPacket::decrypt_in_place(
+                encrypted,
+                &keys.key,
+                &keys.salt,
+                &mut self.srtcp_index_reuse_detector_by_ssrc,
+            )
+            .is_some()
+        })?;
+        self.remember_decrypted_with(decrypted_with, now);
+        let incoming = ControlPacket::parse(encrypted)?;
 
         let mut acks = vec![];
+        let mut losses = vec![];
+        let mut late_ack_count = 0;
+        let mut congestion_experienced_count = 0;
         if !incoming.tcc_feedbacks.is_empty() {
-            acks = self
+            (acks, losses, late_ack_count) = self
                 .tcc_sender
                 .process_feedback_and_correlate_acks(incoming.tcc_feedbacks.into_iter(), now);
         }
+        if !incoming.ccfb_feedbacks.is_empty() {
+            let mut statuses = vec![];
+            for feedback in incoming.ccfb_feedbacks {
+                for status in self.ccfb_sender.process_feedback(feedback) {
+                    match status.arrival {
+                        Some((Some(arrival), ecn)) => {
+                            if ecn == ccfb::Ecn::CongestionExperienced {
+                                congestion_experienced_count += 1;
+                            }
+                            statuses.push((status.tcc_seqnum, Some(arrival)));
+                        }
+                        Some((None, _)) => {
+                            // Received, but we don't know when, so it's neither an ack nor a loss.
+                        }
+                        None => statuses.push((status.tcc_seqnum, None)),
+                    }
+                }
+            }
+            let (ccfb_acks, ccfb_losses, ccfb_late_ack_count) =
+                self.tcc_sender.correlate_statuses(statuses, now);
+            acks.extend(ccfb_acks);
+            losses.extend(ccfb_losses);
+            late_ack_count += ccfb_late_ack_count;
+        }
+        let reception_summary = self.process_reports(
+            &incoming.reception_reports,
+            &incoming.delays_since_last_receiver_reference_time,
+            now,
+        );
         Some(ProcessedControlPacket {
             key_frame_requests: incoming.key_frame_requests,
             acks,
+            losses,
+            late_ack_count,
+            congestion_experienced_count,
             nacks: incoming.nacks,
+            reception_summary,
         })
     }
 
+    fn process_reports(
+        &mut self,
+        reception_reports: &[ReceptionReport],
+        delays_since_last_receiver_reference_time: &[DelaySinceLastReceiverReferenceTime],
+        now: Instant,
+    ) -> Option<ReceptionSummary> {
+        let now_ntp = compact_ntp_timestamp(self.ntp_timestamp(now));
+
+        for delay in delays_since_last_receiver_reference_time {
+            if delay.ssrc != self.rtcp_sender_ssrc {
+                // It's about someone else's reference time, so we can't make sense of it.
+                continue;
+            }
+            if let Some(rtt) = rtt_from_compact_ntp_timestamps(
+                now_ntp,
+                delay.last_receiver_reference_time,
+                delay.delay_since_last_receiver_reference_time,
+            ) {
+                self.reported_rtt = Some(rtt);
+            }
+        }
+
+        if reception_reports.is_empty() {
+            return None;
+        }
+        let mut summary = ReceptionSummary::default();
+        for report in reception_reports {
+            let rtt = rtt_from_compact_ntp_timestamps(
+                now_ntp,
+                report.last_sender_report_timestamp,
+                report.delay_since_last_sender_report,
+            );
+            if rtt.is_some() {
+                self.reported_rtt = rtt;
+            }
+            let stats = self
+                .reception_stats_by_outgoing_ssrc
+                .entry(report.ssrc)
+                .or_default();
+            stats.fraction_lost = report.fraction_lost;
+            stats.cumulative_loss = report.cumulative_loss;
+            stats.max_seqnum = report.max_seqnum;
+            stats.jitter = report.jitter;
+            // Keep the previous RTT if this report doesn't have one.
+            stats.rtt = rtt.or(stats.rtt);
+
+            summary.max_fraction_lost =
+                std::cmp::max(summary.max_fraction_lost, report.fraction_lost);
+            summary.cumulative_loss += report.cumulative_loss as i64;
+        }
+        summary.rtt = self.reported_rtt;
+        Some(summary)
+    }
+
+    // A 64-bit NTP timestamp, but relative to ntp_epoch rather than 1900.
+    fn ntp_timestamp(&self, now: Instant) -> u64 {
+        let since_epoch = now.saturating_duration_since(self.ntp_epoch);
+        let secs = since_epoch.as_secs();
+        let subsec_nanos = (since_epoch.as_nanos() % 1_000_000_000) as u64;
+        let fraction = (subsec_nanos << 32) / 1_000_000_000;
+        (secs << 32) | fraction
+    }
+
     // Mutates the seqnum and transport-cc seqnum and encrypts the packet in place.
     // Also remembers the transport-cc seqnum for receiving and processing packets later.
     // TODO: Use Result instead of Option.
@@ -1783,6 +2507,12 @@ impl Endpoint {
         self.encrypt_and_send_rtp(rtx, now)
     }
 
+    /// Makes sure a packet that was never sent (such as one dropped from a queue)
+    /// isn't resent if it's NACKed.
+    pub fn forget_sent(&mut self, ssrc: Ssrc, seqnum: FullSequenceNumber) {
+        self.rtx_sender.forget_sent(ssrc, seqnum);
+    }
+
     pub fn send_padding(&mut self, rtx_ssrc: Ssrc, now: Instant) -> Option<Packet<Vec<u8>>> {
         let tcc_seqnum = self.tcc_sender.increment_seqnum();
         let padding = self.rtx_sender.send_padding(rtx_ssrc, tcc_seqnum);
@@ -1804,34 +2534,63 @@ impl Endpoint {
         Some(outgoing)
     }
 
-    pub fn remember_sent_for_tcc(&mut self, outgoing: &Packet<Vec<u8>>, now: Instant) {
+    pub fn remember_sent_for_tcc(
+        &mut self,
+        outgoing: &Packet<Vec<u8>>,
+        probe_cluster_id: Option<ProbeClusterId>,
+        now: Instant,
+    ) {
         if let Some(tcc_seqnum) = outgoing.tcc_seqnum {
             self.tcc_sender
-                .remember_sent(tcc_seqnum, outgoing.size(), now);
+                .remember_sent(tcc_seqnum, outgoing.size(), probe_cluster_id, now);
+            if self.congestion_control_feedback == CongestionControlFeedback::Rfc8888 {
+                self.ccfb_sender.remember_sent(
+                    outgoing.ssrc_in_header,
+                    outgoing.seqnum_in_header,
+                    tcc_seqnum,
+                    now,
+                );
+            }
         }
     }
 
     // Returns serialized RTCP packets containing ACKs, not just ACK payloads.
     // The SSRC can be any SSRC the sender uses to send TCC seqnums
+    // Only one of the transport-cc and RFC 8888 receivers remembers anything,
+    // so the ACKs are in the selected format.
     #[allow(clippy::needless_lifetimes)]
-    pub fn send_acks<'endpoint>(&'endpoint mut self) -> impl Iterator<Item = Vec<u8>> + 'endpoint {
+    pub fn send_acks<'endpoint>(
+        &'endpoint mut self,
+        now: Instant,
+    ) -> impl Iterator<Item = Vec<u8>> + 'endpoint {
         time_scope_us!("calling.rtp.send_acks");
 
+        let report_timestamp = compact_ntp_timestamp(self.ntp_timestamp(now));
+        let ccfb_payloads = self.ccfb_receiver.send_feedback(report_timestamp, now);
         let rtcp_sender_ssrc = self.rtcp_sender_ssrc;
         let next_outgoing_srtcp_index = &mut self.next_outgoing_srtcp_index;
         let key = &self.encrypt.rtcp.key;
         let salt = &self.encrypt.rtcp.salt;
-        self.tcc_receiver.send_acks().filter_map(move |payload| {
-            Self::send_rtcp_and_increment_index(
-                RTCP_TYPE_GENERIC_FEEDBACK,
-                RTCP_FORMAT_TRANSPORT_CC,
-                rtcp_sender_ssrc,
-                payload,
-                next_outgoing_srtcp_index,
-                key,
-                salt,
-            )
-        })
+        let tcc_acks = self
+            .tcc_receiver
+            .send_acks()
+            .map(|payload| (RTCP_FORMAT_TRANSPORT_CC, payload.to_vec()));
+        let ccfb_acks = ccfb_payloads
+            .into_iter()
+            .map(|payload| (RTCP_FORMAT_CONGESTION_CONTROL_FEEDBACK, payload));
+        tcc_acks
+            .chain(ccfb_acks)
+            .filter_map(move |(format, payload)| {
+                Self::send_rtcp_and_increment_index(
+                    RTCP_TYPE_GENERIC_FEEDBACK,
+                    format,
+                    rtcp_sender_ssrc,
+                    payload,
+                    next_outgoing_srtcp_index,
+                    key,
+                    salt,
+                )
+            })
     }
 
     // Returns full nack packets
@@ -1882,10 +2641,33 @@ impl Endpoint {
                     .write_receiver_report_block(*ssrc)
             })
             .collect();
+        if !blocks.is_empty() {
+            self.incoming_fraction_lost = self
+                .state_by_incoming_ssrc
+                .values()
+                .map(|state| state.receiver_report_sender.last_fraction_lost)
+                .max();
+        }
         let count = blocks.len() as u8;
         self.send_rtcp(RTCP_TYPE_RECEIVER_REPORT, count, blocks)
     }
 
+    // Returns a new, encrypted RTCP extended report with a receiver reference time.
+    // The remote side responds with a DLRR, which lets us calculate the RTT
+    // without sending sender reports.
+    // See https://datatracker.ietf.org/doc/html/rfc3611#section-4.4
+    pub fn send_receiver_reference_time(&mut self, now: Instant) -> Option<Vec<u8>> {
+        let ntp_timestamp = self.ntp_timestamp(now);
+        let block_len_in_words: u16 = 2;
+        let block = (
+            [RTCP_XR_BLOCK_TYPE_RECEIVER_REFERENCE_TIME, 0],
+            block_len_in_words,
+            (ntp_timestamp >> 32) as u32,
+            ntp_timestamp as u32,
+        );
+        self.send_rtcp(RTCP_TYPE_EXTENDED_REPORT, 0, block)
+    }
+
     // Returns a new, encrypted RTCP packet.
     // TODO: Use Result instead of Option.
     fn send_rtcp(&mut self, pt: u8, count_or_format: u8, payload: impl Writer) -> Option<Vec<u8>> {
@@ -1922,18 +2704,37 @@ impl Endpoint {
         Some(serialized)
     }
 
+    /// Returns the worst loss (out of 256) of what the remote side sent us, as of the
+    /// latest receiver report, if there has been a report since this was last called.
+    pub fn take_incoming_fraction_lost(&mut self) -> Option<u8> {
+        self.incoming_fraction_lost.take()
+    }
+
     pub fn stats(&self) -> EndpointStats {
         let (remembered_packet_count, remembered_packet_bytes) =
             self.rtx_sender.remembered_packet_stats();
         EndpointStats {
             remembered_packet_count,
             remembered_packet_bytes,
+            reception_stats_by_ssrc: self.reception_stats_by_outgoing_ssrc.clone(),
+            reported_rtt: self.reported_rtt,
         }
     }
 }
 
+fn decryption_keys<'a>(
+    current: &'a KeysAndSalts,
+    rekey: &'a Option<Rekey>,
+    generation: KeyGeneration,
+) -> &'a KeysAndSalts {
+    match (generation, rekey) {
+        (KeyGeneration::Previous, Some(rekey)) => &rekey.previous_decrypt,
+        _ => current,
+    }
+}
+
 #[derive(PartialEq, Eq, Debug)]
-enum SequenceNumberReuse {
+pub enum SequenceNumberReuse {
     UsedBefore,
     NotUsedBefore,
     TooOldToKnow { delta: u64 },
@@ -1945,7 +2746,7 @@ enum SequenceNumberReuse {
 // That's what libsrtp/WebRTC uses, which means it's probably
 // enough.
 #[derive(Default, Debug)]
-struct SequenceNumberReuseDetector {
+pub struct SequenceNumberReuseDetector {
     /// Everything before this seqnuence number is too old to
     /// know whether or not we have seen it.
     // We increase this as sequence numbers increase.
@@ -1964,7 +2765,7 @@ impl SequenceNumberReuseDetector {
 
     /// Update the history and return whether or not the sequence number is already
     /// used.  It's possible it's too old to know.
-    fn remember_used(&mut self, seqnum: FullSequenceNumber) -> SequenceNumberReuse {
+    pub fn remember_used(&mut self, seqnum: FullSequenceNumber) -> SequenceNumberReuse {
         if seqnum < self.first {
             // seqnum is before the first in the history, so we can't know if it's been used before.
             return SequenceNumberReuse::TooOldToKnow {
@@ -2080,6 +2881,7 @@ mod test {
                 audio_level: None,
                 tcc_seqnum: None,
                 tcc_seqnum_range: None,
+                dependency_descriptor_range: None,
                 payload_range: RTP_MIN_HEADER_LEN..RTP_MIN_HEADER_LEN,
             }),
             Header::parse(&packet)
@@ -2114,6 +2916,7 @@ mod test {
                 audio_level: None,
                 tcc_seqnum: Some(0x5678),
                 tcc_seqnum_range: Some(17..19),
+                dependency_descriptor_range: None,
                 payload_range: expected_payload_start..expected_payload_start,
             }),
             Header::parse(&packet)
@@ -2174,6 +2977,7 @@ mod test {
                 audio_level: Some(87),
                 tcc_seqnum: Some(0x5678),
                 tcc_seqnum_range: Some(17..19),
+                dependency_descriptor_range: None,
                 payload_range,
             }),
             Header::parse(&packet)
@@ -2195,6 +2999,7 @@ mod test {
                 audio_level: Some(87),
                 tcc_seqnum: Some(0x5678),
                 tcc_seqnum_range: Some(19..21),
+                dependency_descriptor_range: None,
                 payload_range,
             }),
             Header::parse(&packet)
@@ -2218,12 +3023,79 @@ mod test {
                 audio_level: Some(87),
                 tcc_seqnum: Some(0x5678),
                 tcc_seqnum_range: Some(19..21),
+                dependency_descriptor_range: None,
                 payload_range,
             }),
             Header::parse(&packet)
         );
     }
 
+    #[test]
+    fn test_parse_rtp_header_with_two_byte_extensions() {
+        let dependency_descriptor = [0xDDu8; 20];
+        let extensions = (
+            write_two_byte_extension(RTP_EXT_ID_TCC_SEQNUM, 0x5678u16),
+            // Padding is allowed between 2-byte extensions.
+            [0u8],
+            write_two_byte_extension(RTP_EXT_ID_DEPENDENCY_DESCRIPTOR, dependency_descriptor),
+            write_two_byte_extension(RTP_EXT_ID_AUDIO_LEVEL, [0x21u8]),
+        );
+        let (packet, payload_range) = Packet::write_serialized_with_extensions_profile(
+            false,
+            1,
+            2,
+            3,
+            4,
+            // Use some appbits, which are ignored.
+            RTP_TWO_BYTE_EXTENSIONS_PROFILE | 0x3,
+            extensions,
+            &[],
+        );
+        assert_eq!(
+            Some(Header {
+                marker: false,
+                payload_type: 1,
+                seqnum: 2,
+                timestamp: 3,
+                ssrc: 4,
+                video_rotation: None,
+                audio_level: Some(87),
+                tcc_seqnum: Some(0x5678),
+                tcc_seqnum_range: Some(18..20),
+                dependency_descriptor_range: Some(23..43),
+                payload_range,
+            }),
+            Header::parse(&packet)
+        );
+
+        let mut packet =
+            Packet::with_empty_tag_and_dependency_descriptor(1, 2, 3, 4, &[1, 2, 3], &[4, 5, 6]);
+        assert_eq!(Some(&[1u8, 2, 3][..]), packet.dependency_descriptor());
+        assert_eq!(&[4, 5, 6], packet.payload());
+        packet.dependency_descriptor_mut().unwrap()[1] = 7;
+        let rewritten = packet.borrow().rewrite(5, 6, 7);
+        assert_eq!(Some(&[1u8, 7, 3][..]), rewritten.dependency_descriptor());
+        assert_eq!(&[4, 5, 6], rewritten.payload());
+        assert_eq!(
+            None,
+            Packet::with_empty_tag(1, 2, 3, 4, None, &[]).dependency_descriptor()
+        );
+
+        // A 2-byte extension that claims to be longer than the extensions.
+        let extensions = ([RTP_EXT_ID_DEPENDENCY_DESCRIPTOR, 10], [0u8; 5]);
+        let (packet, _) = Packet::write_serialized_with_extensions_profile(
+            false,
+            1,
+            2,
+            3,
+            4,
+            RTP_TWO_BYTE_EXTENSIONS_PROFILE,
+            extensions,
+            &[],
+        );
+        assert_eq!(None, Header::parse(&packet));
+    }
+
     #[test]
     fn test_parse_rtp_header_with_orientation() {
         let extensions = write_extension(RTP_EXT_ID_VIDEO_ORIENTATION, [0x1u8]);
@@ -2239,6 +3111,7 @@ mod test {
                 audio_level: None,
                 tcc_seqnum: None,
                 tcc_seqnum_range: None,
+                dependency_descriptor_range: None,
                 payload_range,
             }),
             Header::parse(&packet)
@@ -2257,6 +3130,7 @@ mod test {
                 audio_level: None,
                 tcc_seqnum: None,
                 tcc_seqnum_range: None,
+                dependency_descriptor_range: None,
                 payload_range,
             }),
             Header::parse(&packet)
@@ -2279,6 +3153,7 @@ mod test {
                 audio_level: None,
                 tcc_seqnum: None,
                 tcc_seqnum_range: None,
+                dependency_descriptor_range: None,
                 payload_range,
             }),
             Header::parse(&packet)
@@ -2542,10 +3417,11 @@ mod test {
 
     #[test]
     fn test_endpoint_nack_rtx() {
-        let srtp_master_key_material = zeroize::Zeroizing::new([0u8; 56]);
+        let srtp_master_key_material = zeroize::Zeroizing::new(vec![0u8; MASTER_KEY_MATERIAL_LEN]);
         let (sender_key, receiver_key) =
             KeysAndSalts::derive_client_and_server_from_master_key_material(
                 &srtp_master_key_material,
+                SrtpProtectionProfile::Aes128Gcm,
             );
         let now = Instant::now();
         let at = |millis| now + Duration::from_millis(millis);
@@ -2598,10 +3474,14 @@ mod test {
             Some(ProcessedControlPacket {
                 key_frame_requests: vec![],
                 acks: vec![],
+                losses: vec![],
+                late_ack_count: 0,
+                congestion_experienced_count: 0,
                 nacks: vec![Nack {
                     ssrc: 3,
                     seqnums: vec![2],
                 }],
+                reception_summary: None,
             }),
             sender.receive_rtcp(&mut nacks[0], at(50))
         );
@@ -2740,10 +3620,11 @@ mod test {
 
     #[test]
     fn test_drop_incoming_rtp_when_seqnum_reused() {
-        let srtp_master_key_material = zeroize::Zeroizing::new([0u8; 56]);
+        let srtp_master_key_material = zeroize::Zeroizing::new(vec![0u8; MASTER_KEY_MATERIAL_LEN]);
         let (sender_key, receiver_key) =
             KeysAndSalts::derive_client_and_server_from_master_key_material(
                 &srtp_master_key_material,
+                SrtpProtectionProfile::Aes128Gcm,
             );
         let now = Instant::now();
         let at = |millis| now + Duration::from_millis(millis);
@@ -2793,6 +3674,293 @@ mod test {
         assert!(received2c.is_none());
     }
 
+    #[test]
+    fn test_resending_non_rtxable_packet_as_rtx_uses_new_index() {
+        const DATA_PAYLOAD_TYPE: PayloadType = 101;
+        let srtp_master_key_material = zeroize::Zeroizing::new(vec![0u8; MASTER_KEY_MATERIAL_LEN]);
+        let (sender_key, receiver_key) =
+            KeysAndSalts::derive_client_and_server_from_master_key_material(
+                &srtp_master_key_material,
+                SrtpProtectionProfile::Aes128Gcm,
+            );
+        let now = Instant::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        let mut sender = Endpoint::new(receiver_key.clone(), sender_key.clone(), now, 1, 2);
+        let mut receiver = Endpoint::new(sender_key, receiver_key, now, 1, 2);
+
+        let data = Packet::with_empty_tag(DATA_PAYLOAD_TYPE, 1, 2, 13, Some(0), &[4, 5, 6]);
+        let mut sent = sender.send_rtp(data.clone(), at(10)).unwrap();
+        let mut resent = sender.send_rtp(data.to_rtx(0), at(20)).unwrap();
+        assert_eq!((13, 1), (sent.ssrc_in_header, sent.seqnum_in_header));
+        assert_eq!((14, 1), (resent.ssrc_in_header, resent.seqnum_in_header));
+
+        let received = receiver
+            .receive_rtp(sent.serialized.borrow_mut(), at(10))
+            .unwrap();
+        assert_eq!(&[4, 5, 6], received.payload());
+        let received = receiver
+            .receive_rtp(resent.serialized.borrow_mut(), at(20))
+            .unwrap();
+        assert_eq!(&[0, 1, 4, 5, 6], received.payload());
+    }
+
+    #[test]
+    fn test_drop_incoming_rtcp_when_index_reused() {
+        let (client_keys, server_keys) =
+            KeysAndSalts::derive_client_and_server_from_master_key_material(
+                &Zeroizing::new(vec![0u8; MASTER_KEY_MATERIAL_LEN]),
+                SrtpProtectionProfile::Aes128Gcm,
+            );
+        let now = Instant::now();
+        let mut server = Endpoint::new(client_keys.clone(), server_keys, now, 1, 2);
+        let key = &client_keys.rtcp.key;
+        let salt = &client_keys.rtcp.salt;
+
+        let pli = |sender_ssrc: Ssrc, srtcp_index: u32| {
+            ControlPacket::serialize_and_encrypt(
+                RTCP_TYPE_SPECIFIC_FEEDBACK,
+                RTCP_FORMAT_PLI,
+                sender_ssrc,
+                3u32,
+                srtcp_index,
+                key,
+                salt,
+            )
+            .unwrap()
+        };
+
+        let mut sent1a = pli(5, 1);
+        let mut sent1b = sent1a.clone();
+        let mut sent2 = pli(5, 2);
+        let mut sent200a = pli(5, 200);
+        let mut sent200b = sent200a.clone();
+        // Indices are per sender SSRC.
+        let mut sent1_other_ssrc = pli(6, 1);
+
+        assert!(server.receive_rtcp(&mut sent1a, now).is_some());
+        assert!(server.receive_rtcp(&mut sent1b, now).is_none());
+        assert!(server.receive_rtcp(&mut sent200a, now).is_some());
+        assert!(server.receive_rtcp(&mut sent200b, now).is_none());
+        // Too old to know if it's been used, even though it hasn't.
+        assert!(server.receive_rtcp(&mut sent2, now).is_none());
+        assert!(server.receive_rtcp(&mut sent1_other_ssrc, now).is_some());
+
+        // A packet that fails to authenticate doesn't use up its index.
+        let mut forged = pli(5, 201);
+        let mut sent201 = forged.clone();
+        forged[RTCP_HEADER_LEN] ^= 1;
+        assert!(server.receive_rtcp(&mut forged, now).is_none());
+        assert!(server.receive_rtcp(&mut sent201, now).is_some());
+    }
+
+    #[test]
+    fn test_endpoint_rekey() {
+        let (client_keys1, server_keys1) =
+            KeysAndSalts::derive_client_and_server_from_master_key_material(
+                &Zeroizing::new(vec![1u8; MASTER_KEY_MATERIAL_LEN]),
+                SrtpProtectionProfile::Aes128Gcm,
+            );
+        let (client_keys2, server_keys2) =
+            KeysAndSalts::derive_client_and_server_from_master_key_material(
+                &Zeroizing::new(vec![2u8; MASTER_KEY_MATERIAL_LEN]),
+                SrtpProtectionProfile::Aes128Gcm,
+            );
+        let now = Instant::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        let mut server = Endpoint::new(client_keys1.clone(), server_keys1.clone(), now, 1, 2);
+
+        let receive_rtp = |server: &mut Endpoint,
+                           seqnum: FullSequenceNumber,
+                           keys: &KeysAndSalts,
+                           now: Instant| {
+            let mut packet =
+                Packet::with_empty_tag(VP8_PAYLOAD_TYPE, seqnum, 2, 3, None, &[4, 5, 6]);
+            packet
+                .encrypt_in_place(&keys.rtp.key, &keys.rtp.salt)
+                .unwrap();
+            server
+                .receive_rtp(packet.serialized.borrow_mut(), now)
+                .is_some()
+        };
+        // Returns whether the packet sent by the server could be decrypted with the keys.
+        let send_rtp = |server: &mut Endpoint, seqnum: FullSequenceNumber, keys: &KeysAndSalts| {
+            let mut sent = server
+                .send_rtp(
+                    Packet::with_empty_tag(VP8_PAYLOAD_TYPE, seqnum, 2, 3, None, &[4, 5, 6]),
+                    now,
+                )
+                .unwrap();
+            sent.decrypt_in_place(&keys.rtp.key, &keys.rtp.salt)
+                .is_some()
+        };
+        let pli = |srtcp_index: u32, keys: &KeysAndSalts| {
+            ControlPacket::serialize_and_encrypt(
+                RTCP_TYPE_SPECIFIC_FEEDBACK,
+                RTCP_FORMAT_PLI,
+                5,
+                3u32,
+                srtcp_index,
+                &keys.rtcp.key,
+                &keys.rtcp.salt,
+            )
+            .unwrap()
+        };
+
+        assert!(receive_rtp(&mut server, 1, &client_keys1, at(0)));
+        assert!(!receive_rtp(&mut server, 2, &client_keys2, at(0)));
+        server
+            .receive_rtcp(&mut pli(1, &client_keys1), at(0))
+            .unwrap();
+
+        server.rekey(client_keys2.clone(), server_keys2.clone(), at(50));
+
+        // Until the client uses the new keys, both are accepted and we keep sending with the old.
+        assert!(receive_rtp(&mut server, 3, &client_keys1, at(100)));
+        server
+            .receive_rtcp(&mut pli(2, &client_keys1), at(100))
+            .unwrap();
+        assert!(send_rtp(&mut server, 1, &server_keys1));
+
+        // Once the client uses the new keys, so do we.
+        assert!(receive_rtp(&mut server, 4, &client_keys2, at(200)));
+        assert!(send_rtp(&mut server, 2, &server_keys2));
+        // The indices carry over, so old packets can't be replayed with new keys.
+        assert!(!receive_rtp(&mut server, 4, &client_keys2, at(200)));
+        assert!(server
+            .receive_rtcp(&mut pli(2, &client_keys2), at(200))
+            .is_none());
+        server
+            .receive_rtcp(&mut pli(3, &client_keys2), at(200))
+            .unwrap();
+
+        // Reordered packets with the old keys are accepted for a while.
+        assert!(receive_rtp(&mut server, 5, &client_keys1, at(300)));
+        server
+            .receive_rtcp(&mut pli(4, &client_keys1), at(300))
+            .unwrap();
+
+        // But not forever.
+        assert!(!receive_rtp(&mut server, 6, &client_keys1, at(10_200)));
+        assert!(server
+            .receive_rtcp(&mut pli(5, &client_keys1), at(10_200))
+            .is_none());
+        assert!(receive_rtp(&mut server, 7, &client_keys2, at(10_200)));
+        server
+            .receive_rtcp(&mut pli(6, &client_keys2), at(10_200))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_endpoint_rekey_never_used() {
+        let (client_keys1, server_keys1) =
+            KeysAndSalts::derive_client_and_server_from_master_key_material(
+                &Zeroizing::new(vec![1u8; MASTER_KEY_MATERIAL_LEN]),
+                SrtpProtectionProfile::Aes128Gcm,
+            );
+        let (client_keys2, server_keys2) =
+            KeysAndSalts::derive_client_and_server_from_master_key_material(
+                &Zeroizing::new(vec![2u8; MASTER_KEY_MATERIAL_LEN]),
+                SrtpProtectionProfile::Aes128Gcm,
+            );
+        let now = Instant::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        let mut server = Endpoint::new(client_keys1.clone(), server_keys1.clone(), now, 1, 2);
+
+        let receive_rtp = |server: &mut Endpoint,
+                           seqnum: FullSequenceNumber,
+                           keys: &KeysAndSalts,
+                           now: Instant| {
+            let mut packet =
+                Packet::with_empty_tag(VP8_PAYLOAD_TYPE, seqnum, 2, 3, None, &[4, 5, 6]);
+            packet
+                .encrypt_in_place(&keys.rtp.key, &keys.rtp.salt)
+                .unwrap();
+            server
+                .receive_rtp(packet.serialized.borrow_mut(), now)
+                .is_some()
+        };
+
+        server.rekey(client_keys2.clone(), server_keys2, at(0));
+        assert!(receive_rtp(&mut server, 1, &client_keys1, at(100)));
+        assert!(receive_rtp(&mut server, 2, &client_keys1, at(29_000)));
+
+        // The client never switched, so the new keys are dropped.
+        assert!(receive_rtp(&mut server, 3, &client_keys1, at(30_000)));
+        assert!(!receive_rtp(&mut server, 4, &client_keys2, at(30_100)));
+        assert!(receive_rtp(&mut server, 5, &client_keys1, at(30_200)));
+        assert!(server.rekey.is_none());
+    }
+
+    #[test]
+    fn test_aes_256_gcm() {
+        let master_key_material =
+            Zeroizing::new((0..MASTER_KEY_MATERIAL_LEN as u8).collect::<Vec<u8>>());
+        let (client_keys, server_keys) =
+            KeysAndSalts::derive_client_and_server_from_master_key_material(
+                &master_key_material,
+                SrtpProtectionProfile::Aes256Gcm,
+            );
+        assert_eq!(
+            SrtpProtectionProfile::Aes256Gcm,
+            client_keys.rtp.key.profile()
+        );
+        assert_eq!(
+            SrtpProtectionProfile::Aes256Gcm,
+            server_keys.rtcp.key.profile()
+        );
+        assert_ne!(
+            client_keys.rtp.key.as_bytes(),
+            server_keys.rtp.key.as_bytes()
+        );
+        assert_ne!(
+            client_keys.rtp.key.as_bytes(),
+            client_keys.rtcp.key.as_bytes()
+        );
+        assert_ne!(
+            client_keys.rtp.key.as_bytes()[..16],
+            client_keys.rtp.key.as_bytes()[16..]
+        );
+
+        let now = Instant::now();
+        let mut client = Endpoint::new(server_keys.clone(), client_keys.clone(), now, 1, 2);
+        let mut server = Endpoint::new(client_keys.clone(), server_keys, now, 3, 4);
+
+        let mut sent = client
+            .send_rtp(
+                Packet::with_empty_tag(VP8_PAYLOAD_TYPE, 1, 2, 3, Some(0), &[4, 5, 6]),
+                now,
+            )
+            .unwrap();
+        let received = server
+            .receive_rtp(sent.serialized.borrow_mut(), now)
+            .unwrap();
+        assert_eq!(&[4, 5, 6], received.payload());
+
+        let mut pli = client.send_pli(3).unwrap();
+        let processed = server.receive_rtcp(&mut pli, now).unwrap();
+        assert_eq!(
+            vec![KeyFrameRequest { ssrc: 3 }],
+            processed.key_frame_requests
+        );
+
+        // The AES-128-GCM keys can't decrypt it.
+        let (aes_128_client_keys, aes_128_server_keys) =
+            KeysAndSalts::derive_client_and_server_from_master_key_material(
+                &master_key_material,
+                SrtpProtectionProfile::Aes128Gcm,
+            );
+        let mut aes_128_server = Endpoint::new(aes_128_client_keys, aes_128_server_keys, now, 3, 4);
+        let mut sent = client
+            .send_rtp(
+                Packet::with_empty_tag(VP8_PAYLOAD_TYPE, 2, 2, 3, Some(0), &[4, 5, 6]),
+                now,
+            )
+            .unwrap();
+        assert!(aes_128_server
+            .receive_rtp(sent.serialized.borrow_mut(), now)
+            .is_none());
+    }
+
     #[test]
     fn test_rtp_iv() {
         // This was the original implementation of rtp_iv, which very closely matches RFC 7714
@@ -2835,6 +4003,39 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_endpoint_incoming_fraction_lost() {
+        let srtp_master_key_material = zeroize::Zeroizing::new(vec![0u8; MASTER_KEY_MATERIAL_LEN]);
+        let (sender_key, receiver_key) =
+            KeysAndSalts::derive_client_and_server_from_master_key_material(
+                &srtp_master_key_material,
+                SrtpProtectionProfile::Aes128Gcm,
+            );
+        let now = Instant::now();
+        let mut sender = Endpoint::new(receiver_key.clone(), sender_key.clone(), now, 1, 2);
+        let mut receiver = Endpoint::new(sender_key, receiver_key, now, 1, 2);
+
+        // Nothing has been reported yet.
+        assert_eq!(None, receiver.take_incoming_fraction_lost());
+
+        for seqnum in [1, 2, 5] {
+            let mut sent = sender
+                .send_rtp(
+                    Packet::with_empty_tag(VP8_PAYLOAD_TYPE, seqnum, 2, 3, Some(0), &[4, 5, 6]),
+                    now,
+                )
+                .unwrap();
+            receiver
+                .receive_rtp(sent.serialized.borrow_mut(), now)
+                .unwrap();
+        }
+        assert!(receiver.send_receiver_report().is_some());
+        // 2 of 5 expected packets were lost.
+        assert_eq!(Some(102), receiver.take_incoming_fraction_lost());
+        // Each report is only taken once.
+        assert_eq!(None, receiver.take_incoming_fraction_lost());
+    }
+
     #[test]
     fn test_receiver_report_sender_packet_loss() {
         let mut receiver_report_sender = ReceiverReportSender::new();
@@ -3136,4 +4337,336 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn test_parse_reception_reports() {
+        let (keys, _) = KeysAndSalts::derive_client_and_server_from_master_key_material(
+            &Zeroizing::new(vec![0u8; MASTER_KEY_MATERIAL_LEN]),
+            SrtpProtectionProfile::Aes128Gcm,
+        );
+        let key = &keys.rtcp.key;
+        let salt = &keys.rtcp.salt;
+        let mut srtcp_index_reuse_detector_by_ssrc = HashMap::new();
+        let block1 = (1u32, [64u8], U24::truncate(10), 1000u32, 90u32, 0u32, 0u32);
+        // Negative cumulative loss
+        let block2 = (
+            2u32,
+            [0u8],
+            U24::truncate(0xFFFFFF),
+            2000u32,
+            48u32,
+            3u32,
+            4u32,
+        );
+        let report1 = ReceptionReport {
+            ssrc: 1,
+            fraction_lost: 64,
+            cumulative_loss: 10,
+            max_seqnum: 1000,
+            jitter: 90,
+            last_sender_report_timestamp: 0,
+            delay_since_last_sender_report: 0,
+        };
+        let report2 = ReceptionReport {
+            ssrc: 2,
+            fraction_lost: 0,
+            cumulative_loss: -1,
+            max_seqnum: 2000,
+            jitter: 48,
+            last_sender_report_timestamp: 3,
+            delay_since_last_sender_report: 4,
+        };
+
+        let mut receiver_report = ControlPacket::serialize_and_encrypt(
+            RTCP_TYPE_RECEIVER_REPORT,
+            2,
+            5,
+            (block1, block2),
+            1,
+            key,
+            salt,
+        )
+        .unwrap();
+        let parsed = ControlPacket::parse_and_decrypt_in_place(
+            &mut receiver_report,
+            key,
+            salt,
+            &mut srtcp_index_reuse_detector_by_ssrc,
+        )
+        .unwrap();
+        assert_eq!(vec![report1, report2], parsed.reception_reports);
+
+        let mut sender_report = ControlPacket::serialize_and_encrypt(
+            RTCP_TYPE_SENDER_REPORT,
+            1,
+            5,
+            ([0u8; RTCP_SENDER_INFO_LEN], block1),
+            2,
+            key,
+            salt,
+        )
+        .unwrap();
+        let parsed = ControlPacket::parse_and_decrypt_in_place(
+            &mut sender_report,
+            key,
+            salt,
+            &mut srtcp_index_reuse_detector_by_ssrc,
+        )
+        .unwrap();
+        assert_eq!(vec![report1], parsed.reception_reports);
+
+        // A receiver reference time block is ignored, but a DLRR block is not.
+        let rrtr_block = (
+            [RTCP_XR_BLOCK_TYPE_RECEIVER_REFERENCE_TIME, 0],
+            2u16,
+            1u32,
+            2u32,
+        );
+        let dlrr_block = ([RTCP_XR_BLOCK_TYPE_DLRR, 0], 3u16, (7u32, 8u32, 9u32));
+        let mut extended_report = ControlPacket::serialize_and_encrypt(
+            RTCP_TYPE_EXTENDED_REPORT,
+            0,
+            5,
+            (rrtr_block, dlrr_block),
+            3,
+            key,
+            salt,
+        )
+        .unwrap();
+        let parsed = ControlPacket::parse_and_decrypt_in_place(
+            &mut extended_report,
+            key,
+            salt,
+            &mut srtcp_index_reuse_detector_by_ssrc,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![DelaySinceLastReceiverReferenceTime {
+                ssrc: 7,
+                last_receiver_reference_time: 8,
+                delay_since_last_receiver_reference_time: 9,
+            }],
+            parsed.delays_since_last_receiver_reference_time
+        );
+        assert!(parsed.reception_reports.is_empty());
+
+        // A truncated report block is not parsed.
+        let mut truncated_report = ControlPacket::serialize_and_encrypt(
+            RTCP_TYPE_RECEIVER_REPORT,
+            2,
+            5,
+            block1,
+            4,
+            key,
+            salt,
+        )
+        .unwrap();
+        let parsed = ControlPacket::parse_and_decrypt_in_place(
+            &mut truncated_report,
+            key,
+            salt,
+            &mut srtcp_index_reuse_detector_by_ssrc,
+        )
+        .unwrap();
+        assert!(parsed.reception_reports.is_empty());
+    }
+
+    #[test]
+    fn test_endpoint_reception_stats() {
+        let (client_keys, server_keys) =
+            KeysAndSalts::derive_client_and_server_from_master_key_material(
+                &Zeroizing::new(vec![0u8; MASTER_KEY_MATERIAL_LEN]),
+                SrtpProtectionProfile::Aes128Gcm,
+            );
+        let now = Instant::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        let rtcp_sender_ssrc = 1;
+        let mut server = Endpoint::new(client_keys.clone(), server_keys, now, rtcp_sender_ssrc, 2);
+        let key = &client_keys.rtcp.key;
+        let salt = &client_keys.rtcp.salt;
+
+        // One second after the epoch is 1.0 in compact NTP.
+        assert!(server.send_receiver_reference_time(at(1000)).is_some());
+        let one_second_ntp = 1u32 << 16;
+        let half_second_ntp = 1u32 << 15;
+
+        // The client responds to the reference time after holding it for half a second.
+        let mut extended_report = ControlPacket::serialize_and_encrypt(
+            RTCP_TYPE_EXTENDED_REPORT,
+            0,
+            5,
+            (
+                [RTCP_XR_BLOCK_TYPE_DLRR, 0],
+                3u16,
+                (rtcp_sender_ssrc, one_second_ntp, half_second_ntp),
+            ),
+            1,
+            key,
+            salt,
+        )
+        .unwrap();
+        let processed = server.receive_rtcp(&mut extended_report, at(1600)).unwrap();
+        // Not a receiver report or sender report.
+        assert_eq!(None, processed.reception_summary);
+        // Roughly 100ms, after rounding to 1/65536 seconds.
+        let expected_rtt = Duration::from_micros(99_990);
+        assert_eq!(Some(expected_rtt), server.stats().reported_rtt);
+
+        // SSRC 3 has no LSR, but SSRC 4 does.
+        let mut receiver_report = ControlPacket::serialize_and_encrypt(
+            RTCP_TYPE_RECEIVER_REPORT,
+            2,
+            5,
+            (
+                (3u32, [25u8], U24::truncate(7), 100u32, 5u32, 0u32, 0u32),
+                (
+                    4u32,
+                    [50u8],
+                    U24::truncate(3),
+                    200u32,
+                    6u32,
+                    one_second_ntp,
+                    half_second_ntp,
+                ),
+            ),
+            2,
+            key,
+            salt,
+        )
+        .unwrap();
+        let processed = server.receive_rtcp(&mut receiver_report, at(1600)).unwrap();
+        assert_eq!(
+            Some(ReceptionSummary {
+                max_fraction_lost: 50,
+                cumulative_loss: 10,
+                rtt: Some(expected_rtt),
+            }),
+            processed.reception_summary
+        );
+        let stats = server.stats();
+        assert_eq!(
+            Some(&ReceptionStats {
+                fraction_lost: 25,
+                cumulative_loss: 7,
+                max_seqnum: 100,
+                jitter: 5,
+                rtt: None,
+            }),
+            stats.reception_stats_by_ssrc.get(&3)
+        );
+        assert_eq!(
+            Some(&ReceptionStats {
+                fraction_lost: 50,
+                cumulative_loss: 3,
+                max_seqnum: 200,
+                jitter: 6,
+                rtt: Some(expected_rtt),
+            }),
+            stats.reception_stats_by_ssrc.get(&4)
+        );
+
+        // A DLRR for some other SSRC's reference time is ignored.
+        let mut extended_report = ControlPacket::serialize_and_encrypt(
+            RTCP_TYPE_EXTENDED_REPORT,
+            0,
+            5,
+            ([RTCP_XR_BLOCK_TYPE_DLRR, 0], 3u16, (99u32, 1u32, 0u32)),
+            3,
+            key,
+            salt,
+        )
+        .unwrap();
+        server.receive_rtcp(&mut extended_report, at(5000)).unwrap();
+        assert_eq!(Some(expected_rtt), server.stats().reported_rtt);
+    }
+
+    #[test]
+    fn test_endpoint_congestion_control_feedback() {
+        let (client_keys, server_keys) =
+            KeysAndSalts::derive_client_and_server_from_master_key_material(
+                &Zeroizing::new(vec![0u8; MASTER_KEY_MATERIAL_LEN]),
+                SrtpProtectionProfile::Aes128Gcm,
+            );
+        let now = Instant::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        let mut sender = Endpoint::new(client_keys.clone(), server_keys.clone(), now, 1, 2);
+        let mut receiver = Endpoint::new(server_keys, client_keys, now, 1, 2);
+        sender.set_congestion_control_feedback(CongestionControlFeedback::Rfc8888);
+        receiver.set_congestion_control_feedback(CongestionControlFeedback::Rfc8888);
+
+        let mut sent = vec![];
+        for i in 1..=3u64 {
+            let packet = sender
+                .send_rtp(
+                    Packet::with_empty_tag(VP8_PAYLOAD_TYPE, i, 1, 3, Some(i), &[4, 5, 6]),
+                    at(10 * i),
+                )
+                .unwrap();
+            sender.remember_sent_for_tcc(&packet, None, at(10 * i));
+            sent.push(packet);
+        }
+        // The second packet is lost, and the third is marked as having experienced congestion.
+        for (i, ecn) in [(1, ccfb::Ecn::Ect0), (3, ccfb::Ecn::CongestionExperienced)] {
+            receiver
+                .receive_rtp_with_ecn(
+                    sent[i - 1].serialized.borrow_mut(),
+                    ecn,
+                    at(10 * i as u64 + 5),
+                )
+                .unwrap();
+        }
+
+        // Only RFC 8888 feedback is sent, not transport-cc feedback.
+        let mut feedback: Vec<Vec<u8>> = receiver.send_acks(at(40)).collect();
+        assert_eq!(1, feedback.len());
+        assert_eq!(
+            RTCP_FORMAT_CONGESTION_CONTROL_FEEDBACK,
+            feedback[0][0] & 0b11111
+        );
+        let empty: Vec<Vec<u8>> = vec![];
+        assert_eq!(empty, receiver.send_acks(at(50)).collect::<Vec<_>>());
+
+        let processed = sender.receive_rtcp(&mut feedback[0], at(60)).unwrap();
+        assert_eq!(
+            vec![at(10), at(30)],
+            processed
+                .acks
+                .iter()
+                .map(|ack| ack.departure)
+                .collect::<Vec<_>>()
+        );
+        // The arrival times are only as precise as 1/1024 seconds.
+        let arrival_delta = processed.acks[1]
+            .arrival
+            .saturating_duration_since(processed.acks[0].arrival);
+        assert!(arrival_delta.as_millis().abs_diff(20) <= 1);
+        assert_eq!(
+            vec![at(20)],
+            processed
+                .losses
+                .iter()
+                .map(|loss| loss.departure)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(0, processed.late_ack_count);
+        assert_eq!(1, processed.congestion_experienced_count);
+
+        // The second packet arrives late, after being reported lost.
+        receiver
+            .receive_rtp(sent[1].serialized.borrow_mut(), at(70))
+            .unwrap();
+        let mut feedback: Vec<Vec<u8>> = receiver.send_acks(at(80)).collect();
+        assert_eq!(1, feedback.len());
+        let processed = sender.receive_rtcp(&mut feedback[0], at(90)).unwrap();
+        assert_eq!(
+            vec![at(20)],
+            processed
+                .acks
+                .iter()
+                .map(|ack| ack.departure)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(0, processed.losses.len());
+        assert_eq!(1, processed.late_ack_count);
+    }
 }
@@ -7,30 +7,37 @@
 
 use core::ops::DerefMut;
 use std::{
-    cmp::min, collections::HashMap, convert::TryInto, fmt::Write, str::FromStr, sync::Arc,
+    cmp::min,
+    collections::{hash_map::DefaultHasher, HashMap},
+    convert::TryInto,
+    fmt::Write,
+    hash::{Hash, Hasher},
+    str::FromStr,
+    sync::Arc,
     time::SystemTime,
 };
 
 use anyhow::Result;
 use calling_common::{
-    DataRate, DataSize, Duration, Instant, TwoGenerationCacheWithManualRemoveOld,
+    DataRate, DataSize, Duration, Instant, TwoGenerationCache,
+    TwoGenerationCacheWithManualRemoveOld,
 };
 use hkdf::Hkdf;
 use log::*;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use rand::rngs::OsRng;
 use sha2::Sha256;
 use thiserror::Error;
 use x25519_dalek::{EphemeralSecret, PublicKey};
 
 use crate::{
-    call::{self, Call, LoggableCallId, DUMMY_DEMUX_ID},
-    config,
+    call::{self, Call, IngressPolicy, LoggableCallId, SharedAudioMixer, DUMMY_DEMUX_ID},
+    ccfb, config,
     connection::{self, AddressType, Connection, HandleRtcpResult, PacketToSend},
     googcc, ice,
     ice::BindingRequest,
     metrics::{Histogram, Timer},
-    pacer,
+    mixer, pacer,
     packet_server::{PacketServerState, SocketLocator},
     region::Region,
     rtp,
@@ -60,6 +67,8 @@ pub enum SfuError {
     ParseIceBindingRequest(ice::ParseError),
     #[error("ICE binding request with unknown username: {0:?}")]
     IceBindingRequestUnknownUsername(Vec<u8>),
+    #[error("ICE binding request from {0} is over the rate limit")]
+    IceBindingRequestRateLimited(SocketLocator),
     #[error("connection error: {0}")]
     ConnectionError(connection::Error),
     #[error("call error: {0}")]
@@ -73,6 +82,16 @@ impl std::fmt::Debug for SfuError {
     }
 }
 
+/// Each address may send a burst of this many seconds' worth of ICE binding requests
+/// before it's limited to Config::ice_binding_requests_per_second_per_address.
+const ICE_BINDING_REQUEST_BURST: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy)]
+struct IceBindingRequestBucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
 /// Uniquely identifies a Connection across calls using a combination
 /// of CallId and DemuxId.
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -119,26 +138,46 @@ pub struct Sfu {
     pub config: &'static config::Config,
 
     // If set, called each time a connection is added
-    new_connection_handler: Option<Box<ConnectionHandler>>,
+    new_connection_handler: Mutex<Option<Box<ConnectionHandler>>>,
+
+    /// Calls are partitioned across shards by their CallId. Each shard owns its calls and
+    /// the connections of their clients, so unrelated calls don't contend for the same lock.
+    shards: Vec<Mutex<SfuShard>>,
+    /// Packets are demuxed by either the incoming socket address or the ICE binding request username.
+    /// The CallId of the resulting ConnectionId determines the shard that owns the connection.
+    // These are only locked long enough to look up or update a route. If a shard and a route
+    // need to be locked at the same time, the shard must be locked first.
+    connection_id_by_ice_request_username: RwLock<HashMap<Vec<u8>, ConnectionId>>,
+    connection_id_by_address:
+        RwLock<TwoGenerationCacheWithManualRemoveOld<SocketLocator, ConnectionId>>,
+    /// ICE binding requests are rate limited per address before their HMAC is verified,
+    /// so a flood of them can't monopolize the packet threads.
+    /// Like the calls, these are partitioned into shards (by address) to avoid contention.
+    ice_binding_request_buckets_by_address_shards:
+        Vec<Mutex<TwoGenerationCache<SocketLocator, IceBindingRequestBucket>>>,
+
+    /// A reference to the packet server state.
+    packet_server: Mutex<Option<Arc<PacketServerState>>>,
+    /// The region where the sfu is running.
+    region: Region,
+}
 
+/// A partition of the calls tracked by the SFU, along with the connections of their clients.
+struct SfuShard {
     /// Mapping of Calls by their unique CallId. Set by configuration/signaling.
     call_by_call_id: HashMap<CallId, Arc<Mutex<Call>>>,
+    /// The audio mixers of the calls that mix audio, so they can be mixed without
+    /// locking the calls.
+    audio_mixer_by_call_id: HashMap<CallId, SharedAudioMixer>,
     /// Mapping of Connection by their unique ConnectionId, which is really (CallId, DemuxId)
     // The value needs to be an Arc so we can have a lock on the Connection while outside
-    // the lock of the SFU.
+    // the lock of the shard.
     connection_by_id: HashMap<ConnectionId, Arc<Mutex<Connection>>>,
-    /// Packets are demuxed by either the incoming socket address or the ICE binding request username.
-    connection_id_by_ice_request_username: HashMap<Vec<u8>, ConnectionId>,
-    connection_id_by_address: TwoGenerationCacheWithManualRemoveOld<SocketLocator, ConnectionId>,
 
     /// The last time activity was checked.
     activity_checked: Instant,
     /// The last time diagnostics were logged.
     diagnostics_logged: Instant,
-    /// A reference to the packet server state.
-    packet_server: Option<Arc<PacketServerState>>,
-    /// The region where the sfu is running.
-    region: Region,
 }
 
 /// The state that results from the SFU receiving a tick event, to be processed by the packet server.
@@ -156,36 +195,77 @@ pub struct SfuStats {
 
 impl Sfu {
     pub fn new(now: Instant, config: &'static config::Config) -> Result<Self> {
+        let shard_count = config.sfu_shard_count.unwrap_or_else(num_cpus::get).max(1);
         Ok(Self {
             config,
             // To enable, call set_new_connection_handler
-            new_connection_handler: None,
-            call_by_call_id: HashMap::new(),
-            connection_by_id: HashMap::new(),
-            connection_id_by_ice_request_username: HashMap::new(),
-            connection_id_by_address: TwoGenerationCacheWithManualRemoveOld::new(
+            new_connection_handler: Mutex::new(None),
+            shards: (0..shard_count)
+                .map(|_| {
+                    Mutex::new(SfuShard {
+                        call_by_call_id: HashMap::new(),
+                        audio_mixer_by_call_id: HashMap::new(),
+                        connection_by_id: HashMap::new(),
+                        activity_checked: now,
+                        diagnostics_logged: now,
+                    })
+                })
+                .collect(),
+            connection_id_by_ice_request_username: RwLock::new(HashMap::new()),
+            connection_id_by_address: RwLock::new(TwoGenerationCacheWithManualRemoveOld::new(
                 Duration::from_secs(30),
                 now,
-            ),
-            activity_checked: now,
-            diagnostics_logged: now,
-            packet_server: None,
+            )),
+            // A bucket that hasn't been used in a burst's time is full again,
+            // so it can be forgotten.
+            ice_binding_request_buckets_by_address_shards: (0..shard_count)
+                .map(|_| Mutex::new(TwoGenerationCache::new(ICE_BINDING_REQUEST_BURST, now)))
+                .collect(),
+            packet_server: Mutex::new(None),
             region: Region::from_str(&config.metrics.region).unwrap_or(Region::Unknown),
         })
     }
 
-    pub fn set_new_connection_handler(&mut self, new_connection_handler: Box<ConnectionHandler>) {
-        self.new_connection_handler = Some(new_connection_handler);
+    pub fn set_new_connection_handler(&self, new_connection_handler: Box<ConnectionHandler>) {
+        *self.new_connection_handler.lock() = Some(new_connection_handler);
+    }
+
+    /// Returns the shard that owns the call and all of its connections.
+    fn shard(&self, call_id: &CallId) -> &Mutex<SfuShard> {
+        let mut hasher = DefaultHasher::new();
+        call_id.hash(&mut hasher);
+        &self.shards[(hasher.finish() % self.shards.len() as u64) as usize]
+    }
+
+    /// Returns the shard of the ICE binding request rate limits that owns the address.
+    fn ice_binding_request_buckets_by_address(
+        &self,
+        sender_addr: &SocketLocator,
+    ) -> &Mutex<TwoGenerationCache<SocketLocator, IceBindingRequestBucket>> {
+        let mut hasher = DefaultHasher::new();
+        sender_addr.hash(&mut hasher);
+        let shards = &self.ice_binding_request_buckets_by_address_shards;
+        &shards[(hasher.finish() % shards.len() as u64) as usize]
     }
 
     /// Return a snapshot of all calls tracked by the Sfu.
     pub fn get_calls_snapshot(&self) -> Vec<Arc<Mutex<Call>>> {
-        self.call_by_call_id.values().map(Arc::clone).collect()
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .lock()
+                    .call_by_call_id
+                    .values()
+                    .map(Arc::clone)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
     }
 
     /// Get info about a call that is relevant to call signaling.
     pub fn get_call_signaling_info(&self, call_id: CallId) -> Option<CallSignalingInfo> {
-        let call = self.call_by_call_id.get(&Arc::new(call_id))?;
+        let call = self.get_call_from_id(&call_id).ok()?;
         let call = call.lock();
         Some(CallSignalingInfo {
             size: call.size(),
@@ -195,15 +275,15 @@ impl Sfu {
         })
     }
 
-    pub fn set_packet_server(&mut self, server: Option<Arc<PacketServerState>>) {
-        self.packet_server = server;
+    pub fn set_packet_server(&self, server: Option<Arc<PacketServerState>>) {
+        *self.packet_server.lock() = server;
     }
 
     /// Gives a snapshot of current metrics, such as call size.
     pub fn get_stats(&self) -> SfuStats {
         let (mut histograms, mut values);
 
-        if let Some(server) = &self.packet_server {
+        if let Some(server) = self.packet_server.lock().as_ref() {
             SfuStats { histograms, values } = server.get_stats();
         } else {
             histograms = HashMap::new();
@@ -219,25 +299,75 @@ impl Sfu {
         let mut call_size_above_one = Histogram::default();
         let mut call_size_squared_above_one = Histogram::default();
         let mut call_age_minutes_above_one = Histogram::default();
-        for call in self.call_by_call_id.values() {
-            let call = call.lock();
-            let clients = call.size();
-            let clients_squared = clients * clients;
-            let call_duration =
-                (call.created().elapsed().unwrap_or_default().as_secs() / 60) as usize;
-
-            all_clients += clients;
-            call_size.push(clients);
-            call_size_squared.push(clients_squared);
-            call_age_minutes.push(call_duration);
-            if clients > 1 {
-                call_size_above_one.push(clients);
-                call_size_squared_above_one.push(clients_squared);
-                call_age_minutes_above_one.push(call_duration);
-                calls_above_one += 1;
-                clients_in_calls_above_one += clients;
+        let mut call_count = 0;
+
+        let mut remembered_packet_count = Histogram::default();
+        let mut remembered_packet_bytes = Histogram::default();
+        let mut outgoing_queue_size = Histogram::default();
+        let mut outgoing_audio_queue_size = Histogram::default();
+        let mut outgoing_rtx_queue_size = Histogram::default();
+        let mut outgoing_video_queue_size = Histogram::default();
+        let mut udp_v4_connections = 0;
+        let mut udp_v6_connections = 0;
+        let mut tcp_v4_connections = 0;
+        let mut tcp_v6_connections = 0;
+        let mut consent_lost_connections = 0;
+        let mut validated_paths = Histogram::default();
+        let mut path_switches = Histogram::default();
+
+        for shard in &self.shards {
+            let shard = shard.lock();
+            call_count += shard.call_by_call_id.len();
+            for call in shard.call_by_call_id.values() {
+                let call = call.lock();
+                let clients = call.size();
+                let clients_squared = clients * clients;
+                let call_duration =
+                    (call.created().elapsed().unwrap_or_default().as_secs() / 60) as usize;
+
+                all_clients += clients;
+                call_size.push(clients);
+                call_size_squared.push(clients_squared);
+                call_age_minutes.push(call_duration);
+                if clients > 1 {
+                    call_size_above_one.push(clients);
+                    call_size_squared_above_one.push(clients_squared);
+                    call_age_minutes_above_one.push(call_duration);
+                    calls_above_one += 1;
+                    clients_in_calls_above_one += clients;
+                }
+            }
+
+            for connection in shard.connection_by_id.values() {
+                let connection = connection.lock();
+                let stats = connection.rtp_endpoint_stats();
+                remembered_packet_count.push(stats.remembered_packet_count);
+                remembered_packet_bytes.push(stats.remembered_packet_bytes);
+                outgoing_queue_size.push(connection.outgoing_queue_size().as_bytes() as usize);
+                let outgoing_queue_stats = connection.outgoing_queue_stats();
+                outgoing_audio_queue_size
+                    .push(outgoing_queue_stats.audio.queued_size.as_bytes() as usize);
+                outgoing_rtx_queue_size
+                    .push(outgoing_queue_stats.rtx.queued_size.as_bytes() as usize);
+                outgoing_video_queue_size
+                    .push(outgoing_queue_stats.video.queued_size.as_bytes() as usize);
+                let path_stats = connection.path_stats();
+                validated_paths.push(path_stats.validated_paths);
+                path_switches.push(path_stats.switches as usize);
+                if connection.consent_lost() {
+                    consent_lost_connections += 1;
+                }
+                if let Some(addr_type) = connection.outgoing_addr_type() {
+                    match addr_type {
+                        AddressType::UdpV4 => udp_v4_connections += 1,
+                        AddressType::UdpV6 => udp_v6_connections += 1,
+                        AddressType::TcpV4 => tcp_v4_connections += 1,
+                        AddressType::TcpV6 => tcp_v6_connections += 1,
+                    }
+                }
             }
         }
+
         histograms.insert("calling.sfu.call_size", call_size);
         histograms.insert("calling.sfu.call_size.squared", call_size_squared);
         histograms.insert("calling.sfu.call_age_minutes", call_age_minutes);
@@ -250,7 +380,7 @@ impl Sfu {
             "calling.sfu.call_age_minutes.above_one",
             call_age_minutes_above_one,
         );
-        values.insert("calling.sfu.calls.count", self.call_by_call_id.len() as f32);
+        values.insert("calling.sfu.calls.count", call_count as f32);
         values.insert("calling.sfu.calls.clients.count", all_clients as f32);
         values.insert("calling.sfu.calls.above_one.count", calls_above_one as f32);
         values.insert(
@@ -258,29 +388,6 @@ impl Sfu {
             clients_in_calls_above_one as f32,
         );
 
-        let mut remembered_packet_count = Histogram::default();
-        let mut remembered_packet_bytes = Histogram::default();
-        let mut outgoing_queue_size = Histogram::default();
-        let mut udp_v4_connections = 0;
-        let mut udp_v6_connections = 0;
-        let mut tcp_v4_connections = 0;
-        let mut tcp_v6_connections = 0;
-
-        for connection in self.connection_by_id.values() {
-            let connection = connection.lock();
-            let stats = connection.rtp_endpoint_stats();
-            remembered_packet_count.push(stats.remembered_packet_count);
-            remembered_packet_bytes.push(stats.remembered_packet_bytes);
-            outgoing_queue_size.push(connection.outgoing_queue_size().as_bytes() as usize);
-            if let Some(addr_type) = connection.outgoing_addr_type() {
-                match addr_type {
-                    AddressType::UdpV4 => udp_v4_connections += 1,
-                    AddressType::UdpV6 => udp_v6_connections += 1,
-                    AddressType::TcpV4 => tcp_v4_connections += 1,
-                    AddressType::TcpV6 => tcp_v6_connections += 1,
-                }
-            }
-        }
         histograms.insert(
             "calling.sfu.connections.remembered_packets.count",
             remembered_packet_count,
@@ -293,6 +400,24 @@ impl Sfu {
             "calling.sfu.connections.outgoing_queue_size_bytes",
             outgoing_queue_size,
         );
+        histograms.insert(
+            "calling.sfu.connections.outgoing_queue_size_bytes.audio",
+            outgoing_audio_queue_size,
+        );
+        histograms.insert(
+            "calling.sfu.connections.outgoing_queue_size_bytes.rtx",
+            outgoing_rtx_queue_size,
+        );
+        histograms.insert(
+            "calling.sfu.connections.outgoing_queue_size_bytes.video",
+            outgoing_video_queue_size,
+        );
+        histograms.insert("calling.sfu.connections.validated_paths", validated_paths);
+        histograms.insert("calling.sfu.connections.path_switches", path_switches);
+        values.insert(
+            "calling.sfu.connections.consent_lost_count",
+            consent_lost_connections as f32,
+        );
         values.insert(
             "calling.sfu.connections.udp_v4_count",
             udp_v4_connections as f32,
@@ -316,9 +441,10 @@ impl Sfu {
     /// Adds the given client, creating a call if it doesn't exist.
     #[allow(clippy::too_many_arguments)]
     pub fn get_or_create_call_and_add_client(
-        &mut self,
+        &self,
         call_id: CallId,
         user_id: &UserId,
+        is_admin: bool,
         resolution_request_id: u64,
         active_speaker_id: String,
         demux_id: DemuxId,
@@ -328,12 +454,17 @@ impl Sfu {
         client_dhe_public_key: DhePublicKey,
         client_hkdf_extra_info: Vec<u8>,
         region: Region,
+        congestion_control_feedback: rtp::CongestionControlFeedback,
+        srtp_protection_profile: rtp::SrtpProtectionProfile,
+        video_metadata_source: call::VideoMetadataSource,
+        receive_mixed_audio: bool,
     ) -> Result<DhePublicKey, SfuError> {
         let loggable_call_id = LoggableCallId::from(&call_id);
         trace!("get_or_create_call_and_add_client():");
 
         trace!("  {:25}{}", "call_id:", loggable_call_id);
         trace!("  {:25}{}", "user_id:", hex::encode(user_id.as_slice()));
+        trace!("  {:25}{}", "is_admin:", is_admin);
         trace!("  {:25}{}", "client_ice_ufrag:", client_ice_ufrag);
         trace!(
             "  {:25}{:?}",
@@ -348,6 +479,22 @@ impl Sfu {
         trace!("  {:25}{:?}", "demux_id:", demux_id);
         trace!("  {:25}{}", "resolution_request_id:", resolution_request_id);
         trace!("  {:25}{}", "active_speaker_id:", active_speaker_id);
+        trace!(
+            "  {:25}{:?}",
+            "congestion_control_feedback:",
+            congestion_control_feedback
+        );
+        trace!(
+            "  {:25}{:?}",
+            "srtp_protection_profile:",
+            srtp_protection_profile
+        );
+        trace!(
+            "  {:25}{:?}",
+            "video_metadata_source:",
+            video_metadata_source
+        );
+        trace!("  {:25}{}", "receive_mixed_audio:", receive_mixed_audio);
 
         let initial_target_send_rate =
             DataRate::from_kbps(self.config.initial_target_send_rate_kbps);
@@ -372,16 +519,34 @@ impl Sfu {
         let connection_id = ConnectionId::from_call_id_and_demux_id(call_id.clone(), demux_id);
 
         let active_speaker_message_interval_ms = self.config.active_speaker_message_interval_ms;
-        let call = self
+        let ingress_policy = IngressPolicy {
+            max_rate_multiplier: self.config.ingress_max_rate_multiplier,
+            drop_excess: self.config.ingress_drop_excess,
+            disconnect_abusive_clients: self.config.ingress_disconnect_abusive_clients,
+        };
+        let audio_mixing_max_speakers = self.config.audio_mixing_max_speakers;
+        let reliable_data_history_size = self.config.reliable_data_history_size;
+        let mut shard = self.shard(&call_id).lock();
+        let shard = shard.deref_mut();
+        let audio_mixer_by_call_id = &mut shard.audio_mixer_by_call_id;
+        let call = shard
             .call_by_call_id
             .entry(call_id.clone())
             .or_insert_with(|| {
+                let audio_mixer = audio_mixing_max_speakers.map(|max_speakers| {
+                    let audio_mixer = Arc::new(Mutex::new(mixer::AudioMixer::new(max_speakers)));
+                    audio_mixer_by_call_id.insert(call_id.clone(), audio_mixer.clone());
+                    audio_mixer
+                });
                 Arc::new(Mutex::new(Call::new(
                     LoggableCallId::from(&call_id),
                     user_id.clone(),
                     Duration::from_millis(active_speaker_message_interval_ms),
                     initial_target_send_rate,
                     default_requested_max_send_rate,
+                    ingress_policy,
+                    audio_mixer,
+                    reliable_data_history_size,
                     now,
                     created,
                 )))
@@ -414,8 +579,11 @@ impl Sfu {
             call.add_client(
                 demux_id,
                 user_id.clone(),
+                is_admin,
                 active_speaker_id,
                 resolution_request_id,
+                video_metadata_source,
+                receive_mixed_audio,
                 Instant::now(), // Now after taking the lock
             );
         }
@@ -424,20 +592,8 @@ impl Sfu {
         // video base layer, so use that.
         let ack_ssrc = call::LayerId::Video0.to_ssrc(demux_id);
 
-        let server_secret = EphemeralSecret::new(OsRngCompatibleWithDalek);
-        let server_dhe_public_key = PublicKey::from(&server_secret).to_bytes();
-        let shared_secret = server_secret.diffie_hellman(&PublicKey::from(client_dhe_public_key));
-        let mut srtp_master_key_material =
-            zeroize::Zeroizing::new([0u8; rtp::MASTER_KEY_MATERIAL_LEN]);
-        Hkdf::<Sha256>::new(None, shared_secret.as_bytes())
-            .expand_multi_info(
-                &[
-                    b"Signal_Group_Call_20211105_SignallingDH_SRTPKey_KDF",
-                    &client_hkdf_extra_info[..],
-                ],
-                srtp_master_key_material.deref_mut(),
-            )
-            .expect("Expand SRTP master key material");
+        let (server_dhe_public_key, srtp_master_key_material) =
+            derive_srtp_master_key_material(client_dhe_public_key, &client_hkdf_extra_info);
 
         let inactivity_timeout = Duration::from_secs(self.config.inactivity_timeout_secs);
 
@@ -446,7 +602,9 @@ impl Sfu {
             ice_response_username,
             ice_pwd,
             srtp_master_key_material,
+            srtp_protection_profile,
             ack_ssrc,
+            congestion_control_feedback,
             googcc::Config {
                 initial_target_send_rate,
                 min_target_send_rate,
@@ -455,12 +613,14 @@ impl Sfu {
             inactivity_timeout,
             now,
         )));
-        self.connection_by_id
+        shard
+            .connection_by_id
             .insert(connection_id.clone(), connection.clone());
-        if let Some(new_connection_handler) = self.new_connection_handler.as_ref() {
+        if let Some(new_connection_handler) = self.new_connection_handler.lock().as_ref() {
             new_connection_handler(connection);
         };
         self.connection_id_by_ice_request_username
+            .write()
             .insert(ice_request_username, connection_id);
         // Entries are inserted into self.connection_id_by_address as we received ICE binding
 
@@ -468,7 +628,7 @@ impl Sfu {
     }
 
     /// Remove a client from a call.
-    pub fn remove_client_from_call(&mut self, now: Instant, call_id: CallId, demux_id: DemuxId) {
+    pub fn remove_client_from_call(&self, now: Instant, call_id: CallId, demux_id: DemuxId) {
         let loggable_call_id = LoggableCallId::from(&call_id);
         let connection_id = ConnectionId::from_call_id_and_demux_id(call_id, demux_id);
 
@@ -476,7 +636,8 @@ impl Sfu {
         trace!("  call_id: {}", loggable_call_id);
         trace!("  demux_id: {:?}", demux_id);
 
-        if let Some(call) = self.call_by_call_id.get(&connection_id.call_id) {
+        let mut shard = self.shard(&connection_id.call_id).lock();
+        if let Some(call) = shard.call_by_call_id.get(&connection_id.call_id) {
             info!(
                 "call_id: {} removing demux_id: {}",
                 loggable_call_id,
@@ -487,32 +648,125 @@ impl Sfu {
             call.remove_client(demux_id, now);
         }
 
-        if let Some(connection) = self.connection_by_id.remove(&connection_id) {
+        if let Some(connection) = shard.connection_by_id.remove(&connection_id) {
             event!("calling.sfu.close_connection.remove_client_from_call");
 
             let mut connection = connection.lock();
             // This prevents an Arc cycle where connection -> scheduler -> connection.
             connection.set_dequeue_scheduler(None);
 
-            self.connection_id_by_ice_request_username
-                .remove(connection.ice_request_username());
+            remove_ice_request_usernames(&self.connection_id_by_ice_request_username, &connection);
 
             // Entries are removed from self.connection_id_by_address over time in tick().
         }
     }
 
+    /// Gives an existing client new ICE credentials, such as when it changes networks, without
+    /// changing its demux ID, SRTP keys, or congestion control state.
+    pub fn restart_ice(
+        &self,
+        call_id: CallId,
+        demux_id: DemuxId,
+        server_ice_ufrag: String,
+        server_ice_pwd: String,
+        client_ice_ufrag: String,
+    ) -> Result<(), SfuError> {
+        trace!("restart_ice():");
+        trace!("  {:25}{}", "call_id:", LoggableCallId::from(&call_id));
+        trace!("  {:25}{:?}", "demux_id:", demux_id);
+        trace!("  {:25}{}", "client_ice_ufrag:", client_ice_ufrag);
+        trace!("  {:25}{}", "server_ice_ufrag:", server_ice_ufrag);
+
+        let ice_request_username =
+            ice::join_username(client_ice_ufrag.as_bytes(), server_ice_ufrag.as_bytes());
+        let ice_response_username =
+            ice::join_username(server_ice_ufrag.as_bytes(), client_ice_ufrag.as_bytes());
+
+        let connection_id = ConnectionId::from_call_id_and_demux_id(call_id, demux_id);
+        let shard = self.shard(&connection_id.call_id).lock();
+        let connection = shard.connection_by_id.get(&connection_id).ok_or_else(|| {
+            SfuError::MissingConnection(connection_id.call_id.clone(), connection_id.demux_id)
+        })?;
+        let mut connection = connection.lock();
+        let expired_ice_request_username = connection.restart_ice(
+            ice_request_username.clone(),
+            ice_response_username,
+            server_ice_pwd.into_bytes(),
+        );
+
+        let mut connection_id_by_ice_request_username =
+            self.connection_id_by_ice_request_username.write();
+        if let Some(expired_ice_request_username) = expired_ice_request_username {
+            connection_id_by_ice_request_username.remove(&expired_ice_request_username);
+        }
+        connection_id_by_ice_request_username.insert(ice_request_username, connection_id);
+        // The new candidate pair is added to self.connection_id_by_address when the client
+        // sends ICE binding requests from it, and the old one ages out in tick().
+
+        Ok(())
+    }
+
+    /// Gives an existing client new SRTP keys from a new DHE exchange, such as periodically
+    /// during a long call, without changing anything else about its connection.
+    pub fn rekey(
+        &self,
+        call_id: CallId,
+        demux_id: DemuxId,
+        client_dhe_public_key: DhePublicKey,
+        client_hkdf_extra_info: Vec<u8>,
+    ) -> Result<DhePublicKey, SfuError> {
+        trace!("rekey():");
+        trace!("  {:25}{}", "call_id:", LoggableCallId::from(&call_id));
+        trace!("  {:25}{:?}", "demux_id:", demux_id);
+        trace!(
+            "  {:25}{:?}",
+            "client_dhe_public_key:",
+            client_dhe_public_key
+        );
+        trace!(
+            "  {:25}{:?}",
+            "client_hkdf_extra_info:",
+            client_hkdf_extra_info
+        );
+
+        let connection_id = ConnectionId::from_call_id_and_demux_id(call_id, demux_id);
+        let connection = self.get_connection_from_id(&connection_id).ok_or_else(|| {
+            SfuError::MissingConnection(connection_id.call_id.clone(), connection_id.demux_id)
+        })?;
+
+        let (server_dhe_public_key, srtp_master_key_material) =
+            derive_srtp_master_key_material(client_dhe_public_key, &client_hkdf_extra_info);
+        connection
+            .lock()
+            .rekey(srtp_master_key_material, Instant::now());
+
+        Ok(server_dhe_public_key)
+    }
+
+    /// Replaces the senders whose video every client in the call should see first,
+    /// such as a presenter chosen by the host.
+    pub fn set_spotlight(&self, call_id: CallId, demux_ids: &[DemuxId]) -> Result<(), SfuError> {
+        trace!("set_spotlight():");
+        trace!("  {:25}{}", "call_id:", LoggableCallId::from(&call_id));
+        trace!("  {:25}{:?}", "demux_ids:", demux_ids);
+
+        let call = self.get_call_from_id(&call_id)?;
+        call.lock().set_spotlight(demux_ids, Instant::now());
+        Ok(())
+    }
+
     // Remove connection from active connection HashMaps
-    fn remove_connection(&mut self, call_id: CallId, demux_id: DemuxId) {
+    fn remove_connection(&self, call_id: CallId, demux_id: DemuxId) {
         let connection_id = ConnectionId::from_call_id_and_demux_id(call_id, demux_id);
-        if let Some(connection) = self.connection_by_id.remove(&connection_id) {
+        let mut shard = self.shard(&connection_id.call_id).lock();
+        if let Some(connection) = shard.connection_by_id.remove(&connection_id) {
             event!("calling.sfu.close_connection.rtp");
 
             let mut connection = connection.lock();
             // This prevents an Arc cycle where connection -> scheduler -> connection.
             connection.set_dequeue_scheduler(None);
 
-            self.connection_id_by_ice_request_username
-                .remove(connection.ice_request_username());
+            remove_ice_request_usernames(&self.connection_id_by_ice_request_username, &connection);
 
             // Entries are removed from self.connection_id_by_address over time in tick().
         }
@@ -522,7 +776,8 @@ impl Sfu {
         &self,
         connection_id: &ConnectionId,
     ) -> Option<Arc<Mutex<Connection>>> {
-        let connection = self.connection_by_id.get(connection_id)?;
+        let shard = self.shard(&connection_id.call_id).lock();
+        let connection = shard.connection_by_id.get(connection_id)?;
         Some(Arc::clone(connection))
     }
 
@@ -532,41 +787,120 @@ impl Sfu {
     ) -> Result<(ConnectionId, Arc<Mutex<Connection>>), SfuError> {
         let connection_id = self
             .connection_id_by_address
+            .read()
             .get(address)
-            .ok_or(SfuError::UnknownAddress(*address))?;
-        let connection = self.connection_by_id.get(connection_id).ok_or_else(|| {
+            .ok_or(SfuError::UnknownAddress(*address))?
+            .clone();
+        let connection = self.get_connection_from_id(&connection_id).ok_or_else(|| {
             SfuError::MissingConnection(connection_id.call_id.clone(), connection_id.demux_id)
         })?;
-        Ok((connection_id.clone(), Arc::clone(connection)))
+        Ok((connection_id, connection))
     }
 
-    fn get_connection_from_ice_request_username(
+    fn get_connection_id_from_ice_request_username(
         &self,
         ice_request_username: &[u8],
-    ) -> Result<(ConnectionId, Arc<Mutex<Connection>>), SfuError> {
+    ) -> Result<ConnectionId, SfuError> {
         let connection_id = self
             .connection_id_by_ice_request_username
+            .read()
             .get(ice_request_username)
             .ok_or_else(|| {
                 SfuError::IceBindingRequestUnknownUsername(ice_request_username.to_vec())
-            })?;
-        let connection = self.connection_by_id.get(connection_id).ok_or_else(|| {
-            SfuError::MissingConnection(connection_id.call_id.clone(), connection_id.demux_id)
-        })?;
-        Ok((connection_id.clone(), Arc::clone(connection)))
+            })?
+            .clone();
+        Ok(connection_id)
+    }
+
+    /// Returns false if the address has sent more ICE binding requests than allowed.
+    fn admit_ice_binding_request(&self, sender_addr: SocketLocator, now: Instant) -> bool {
+        let rate = self.config.ice_binding_requests_per_second_per_address as f64;
+        let capacity = rate * ICE_BINDING_REQUEST_BURST.as_secs_f64();
+        let mut bucket_by_address = self
+            .ice_binding_request_buckets_by_address(&sender_addr)
+            .lock();
+        let mut bucket =
+            bucket_by_address
+                .get(&sender_addr)
+                .copied()
+                .unwrap_or(IceBindingRequestBucket {
+                    tokens: capacity,
+                    refilled: now,
+                });
+        let refill = rate * now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + refill).min(capacity);
+        bucket.refilled = now;
+        let admitted = bucket.tokens >= 1.0;
+        if admitted {
+            bucket.tokens -= 1.0;
+        }
+        bucket_by_address.insert(sender_addr, bucket, now);
+        admitted
     }
 
     fn get_call_from_id(&self, call_id: &CallId) -> Result<Arc<Mutex<Call>>, SfuError> {
-        let call = self
+        let shard = self.shard(call_id).lock();
+        let call = shard
             .call_by_call_id
             .get(call_id)
             .ok_or_else(|| SfuError::MissingCall(call_id.clone()))?;
         Ok(Arc::clone(call))
     }
 
+    /// Sends each RTP packet to the connection of the client in the call with its DemuxId.
+    fn send_or_enqueue_rtp(
+        &self,
+        call_connection_id: ConnectionId,
+        outgoing_rtp: Vec<(DemuxId, rtp::Packet<Vec<u8>>)>,
+        packets_to_send: &mut Vec<(PacketToSend, SocketLocator)>,
+    ) {
+        // We use one mutable outgoing ConnectionId to avoid cloning the CallId many times.
+        let mut outgoing_connection_id = call_connection_id;
+        for (demux_id, outgoing_rtp) in outgoing_rtp {
+            outgoing_connection_id.demux_id = demux_id;
+            if let Some(outgoing_connection) = self.get_connection_from_id(&outgoing_connection_id)
+            {
+                let mut outgoing_connection = outgoing_connection.lock();
+                time_scope_us!("calling.sfu.handle_packet.rtp.in_outgoing_connection_lock");
+                outgoing_connection.send_or_enqueue_rtp(
+                    outgoing_rtp,
+                    packets_to_send,
+                    Instant::now(),
+                );
+            }
+        }
+    }
+
+    /// Mixes the audio that's due for every call that mixes audio and sends it to the clients
+    /// that receive it. This should be called every mixer::FRAME_DURATION, which is more often
+    /// than tick().
+    pub fn mix_audio(&self, now: Instant) -> Vec<(PacketToSend, SocketLocator)> {
+        time_scope_us!("calling.sfu.mix_audio");
+        let mut packets_to_send = vec![];
+        for shard in &self.shards {
+            let audio_mixer_by_call_id = shard.lock().audio_mixer_by_call_id.clone();
+            for (call_id, audio_mixer) in audio_mixer_by_call_id {
+                let outgoing_rtp = audio_mixer.lock().mix(now);
+                if outgoing_rtp.is_empty() {
+                    continue;
+                }
+                self.send_or_enqueue_rtp(
+                    ConnectionId::from_call_id(call_id),
+                    outgoing_rtp,
+                    &mut packets_to_send,
+                );
+            }
+        }
+        packets_to_send
+    }
+
+    /// Handles a packet from a client. Only the shard that owns the client's connection is
+    /// locked, and only long enough to look up the connection and its call.
+    /// `ecn` is the ECN codepoint the packet arrived with (Not-ECT if that isn't known).
     pub fn handle_packet(
-        sfu: &Mutex<Self>,
+        &self,
         sender_addr: SocketLocator,
+        ecn: ccfb::Ecn,
         incoming_packet: &mut [u8],
     ) -> Result<Vec<(PacketToSend, SocketLocator)>, SfuError> {
         trace!("handle_packet():");
@@ -576,14 +910,16 @@ impl Sfu {
             trace!("looks like rtp");
             time_scope_us!("calling.sfu.handle_packet.rtp");
 
-            let (incoming_connection_id, incoming_rtp) = {
+            let (incoming_connection_id, mut incoming_rtp) = {
                 let (incoming_connection_id, incoming_connection) =
-                    sfu.lock().get_connection_from_address(&sender_addr)?;
+                    self.get_connection_from_address(&sender_addr)?;
                 let mut incoming_connection = incoming_connection.lock();
                 time_scope_us!("calling.sfu.handle_packet.rtp.in_incoming_connection_lock");
+                let now = Instant::now();
                 let incoming_rtp = incoming_connection
-                    .handle_rtp_packet(incoming_packet, Instant::now())
+                    .handle_rtp_packet(incoming_packet, ecn, now)
                     .map_err(SfuError::ConnectionError)?;
+                incoming_connection.handle_packet_received_on_path(sender_addr, now);
                 (incoming_connection_id, incoming_rtp)
             };
 
@@ -593,21 +929,19 @@ impl Sfu {
             trace!("  ssrc: {}", incoming_rtp.ssrc());
             trace!("  seqnum: {}", incoming_rtp.seqnum());
 
-            let outgoing_rtp = {
-                let call = sfu
-                    .lock()
-                    .get_call_from_id(&incoming_connection_id.call_id)?;
+            let (outgoing_rtp, audio_mixer) = {
+                let call = self.get_call_from_id(&incoming_connection_id.call_id)?;
                 let mut call = call.lock();
                 time_scope_us!("calling.sfu.handle_packet.rtp.in_call_lock");
-                match call.handle_rtp(
+                match call.handle_rtp_without_mixing(
                     incoming_connection_id.demux_id,
-                    incoming_rtp,
+                    incoming_rtp.borrow_mut(),
                     Instant::now(),
                 ) {
-                    Ok(outgoing_rtp) => outgoing_rtp,
-                    Err(call::Error::Leave) => {
+                    Ok(outgoing_rtp_and_audio_mixer) => outgoing_rtp_and_audio_mixer,
+                    Err(call::Error::Leave | call::Error::IngressAbuse) => {
                         drop(call);
-                        sfu.lock().remove_connection(
+                        self.remove_connection(
                             incoming_connection_id.call_id,
                             incoming_connection_id.demux_id,
                         );
@@ -617,22 +951,20 @@ impl Sfu {
                 }
             };
 
+            let sender_demux_id = incoming_connection_id.demux_id;
             let mut packets_to_send = vec![];
-            // We use one mutable outgoing ConnectionId to avoid cloning the CallId many times.
-            let mut outgoing_connection_id = incoming_connection_id;
-            for (demux_id, outgoing_rtp) in outgoing_rtp {
-                outgoing_connection_id.demux_id = demux_id;
-                if let Some(outgoing_connection) =
-                    sfu.lock().get_connection_from_id(&outgoing_connection_id)
-                {
-                    let mut outgoing_connection = outgoing_connection.lock();
-                    time_scope_us!("calling.sfu.handle_packet.rtp.in_outgoing_connection_lock");
-                    outgoing_connection.send_or_enqueue_rtp(
-                        outgoing_rtp,
-                        &mut packets_to_send,
-                        Instant::now(),
-                    );
-                }
+            self.send_or_enqueue_rtp(incoming_connection_id, outgoing_rtp, &mut packets_to_send);
+
+            // Decoding is slow, so it's done after forwarding and outside of the call's lock.
+            if let Some(audio_mixer) = audio_mixer {
+                let mut audio_mixer = audio_mixer.lock();
+                time_scope_us!("calling.sfu.handle_packet.rtp.in_audio_mixer_lock");
+                audio_mixer.receive_audio(
+                    sender_demux_id,
+                    incoming_rtp.audio_level,
+                    incoming_rtp.payload(),
+                    Instant::now(),
+                );
             }
 
             return Ok(packets_to_send);
@@ -648,23 +980,24 @@ impl Sfu {
                     incoming_key_frame_requests,
                     outgoing_rtx,
                     new_target_send_rate,
+                    reception_summary,
                 },
             ) = {
                 let (incoming_connection_id, incoming_connection) =
-                    sfu.lock().get_connection_from_address(&sender_addr)?;
+                    self.get_connection_from_address(&sender_addr)?;
                 let mut incoming_connection = incoming_connection.lock();
 
                 time_scope_us!("calling.sfu.handle_packet.rtcp.in_incomin_connection_lock");
+                let now = Instant::now();
                 let result = incoming_connection
-                    .handle_rtcp_packet(incoming_packet, Instant::now())
+                    .handle_rtcp_packet(incoming_packet, now)
                     .map_err(SfuError::ConnectionError)?;
+                incoming_connection.handle_packet_received_on_path(sender_addr, now);
                 (incoming_connection_id, result)
             };
 
             let outgoing_key_frame_requests = {
-                let call = sfu
-                    .lock()
-                    .get_call_from_id(&incoming_connection_id.call_id)?;
+                let call = self.get_call_from_id(&incoming_connection_id.call_id)?;
                 let mut call = call.lock();
                 time_scope_us!("calling.sfu.handle_packet.rtcp.in_call_lock");
 
@@ -675,6 +1008,13 @@ impl Sfu {
                         debug!("Failed to set target send rate: {:?}", err);
                     }
                 }
+                if let Some(reception_summary) = reception_summary {
+                    if let Err(err) = call
+                        .set_reception_summary(incoming_connection_id.demux_id, reception_summary)
+                    {
+                        debug!("Failed to set reception summary: {:?}", err);
+                    }
+                }
                 call.handle_key_frame_requests(
                     incoming_connection_id.demux_id,
                     &incoming_key_frame_requests,
@@ -689,7 +1029,7 @@ impl Sfu {
             for (demux_id, key_frame_request) in outgoing_key_frame_requests {
                 outgoing_connection_id.demux_id = demux_id;
                 if let Some(outgoing_connection) =
-                    sfu.lock().get_connection_from_id(&outgoing_connection_id)
+                    self.get_connection_from_id(&outgoing_connection_id)
                 {
                     let mut outgoing_connection = outgoing_connection.lock();
 
@@ -715,38 +1055,107 @@ impl Sfu {
             let ice_binding_request =
                 BindingRequest::parse(incoming_packet).map_err(SfuError::ParseIceBindingRequest)?;
 
-            let (incoming_connection_id, outgoing_response) = {
-                let (incoming_connection_id, incoming_connection) = sfu
-                    .lock()
-                    .get_connection_from_ice_request_username(ice_binding_request.username())?;
+            // Both of these are much cheaper than verifying the HMAC.
+            let incoming_connection_id =
+                self.get_connection_id_from_ice_request_username(ice_binding_request.username())?;
+            let now = Instant::now();
+            if !self.admit_ice_binding_request(sender_addr, now) {
+                return Err(SfuError::IceBindingRequestRateLimited(sender_addr));
+            }
+
+            let (outgoing_response, established) = {
+                let incoming_connection = self
+                    .get_connection_from_id(&incoming_connection_id)
+                    .ok_or_else(|| {
+                        SfuError::MissingConnection(
+                            incoming_connection_id.call_id.clone(),
+                            incoming_connection_id.demux_id,
+                        )
+                    })?;
                 let mut incoming_connection = incoming_connection.lock();
                 time_scope_us!("calling.sfu.handle_packet.ice.in_locks");
+                let had_outgoing_addr = incoming_connection.outgoing_addr().is_some();
                 let outgoing_response = incoming_connection
-                    .handle_ice_binding_request(sender_addr, ice_binding_request, Instant::now())
+                    .handle_ice_binding_request(sender_addr, ice_binding_request, now)
                     .map_err(SfuError::ConnectionError)?;
-                (incoming_connection_id, outgoing_response)
+                let established =
+                    !had_outgoing_addr && incoming_connection.outgoing_addr().is_some();
+                (outgoing_response, established)
             };
 
+            // Now that the client can receive packets, send it the reliable RtpData it missed.
+            let mut packets_to_send = vec![(outgoing_response, sender_addr)];
+            if established {
+                if let Ok(call) = self.get_call_from_id(&incoming_connection_id.call_id) {
+                    let catch_up_rtp = {
+                        let mut call = call.lock();
+                        time_scope_us!("calling.sfu.handle_packet.ice.in_call_lock");
+                        call.catch_up_on_reliable_data(incoming_connection_id.demux_id)
+                    };
+                    self.send_or_enqueue_rtp(
+                        incoming_connection_id.clone(),
+                        catch_up_rtp,
+                        &mut packets_to_send,
+                    );
+                }
+            }
+
             // Removal of old addresses is done in tick().
-            sfu.lock()
-                .connection_id_by_address
+            self.connection_id_by_address
+                .write()
                 .insert_without_removing_old(sender_addr, incoming_connection_id);
 
-            return Ok(vec![(outgoing_response, sender_addr)]);
+            return Ok(packets_to_send);
+        }
+
+        // Keepalives don't need a response, and they aren't authenticated, so they don't count
+        // as activity or consent either.
+        if ice::looks_like_binding_indication(incoming_packet) {
+            event!("calling.sfu.ice.binding_indication");
+            return Ok(vec![]);
         }
 
         Err(SfuError::UnknownPacketType(sender_addr))
     }
 
     /// Handle the periodic tick, which could be fired every 100ms in production.
-    /// For every tick, we need to iterate all calls, with the goal of iterating
-    /// only once. Since we need to sometimes remove clients or calls, we will
-    /// generally iterate with retain().
-    pub fn tick(&mut self, now: Instant) -> TickOutput {
+    /// Each shard is ticked in turn, so only one shard is locked at a time.
+    pub fn tick(&self, now: Instant) -> TickOutput {
         time_scope_us!("calling.sfu.tick");
-        let config = self.config;
         let mut packets_to_send: Vec<(PacketToSend, SocketLocator)> = vec![];
 
+        for shard in &self.shards {
+            shard.lock().tick(
+                self.config,
+                &self.connection_id_by_ice_request_username,
+                &mut packets_to_send,
+                now,
+            );
+        }
+
+        let expired_client_addrs = {
+            time_scope_us!("calling.sfu.tick.remove_inactive_client_addresses");
+            self.connection_id_by_address.write().remove_old(now)
+        };
+
+        TickOutput {
+            packets_to_send,
+            expired_client_addrs,
+        }
+    }
+}
+
+impl SfuShard {
+    /// For every tick, we need to iterate all calls in the shard, with the goal of
+    /// iterating only once. Since we need to sometimes remove clients or calls, we will
+    /// generally iterate with retain().
+    fn tick(
+        &mut self,
+        config: &'static config::Config,
+        connection_id_by_ice_request_username: &RwLock<HashMap<Vec<u8>, ConnectionId>>,
+        packets_to_send: &mut Vec<(PacketToSend, SocketLocator)>,
+        now: Instant,
+    ) {
         // Post diagnostics to the log if needed.
         if let Some(diagnostics_interval_secs) = config.diagnostics_interval_secs {
             if now >= self.diagnostics_logged + Duration::from_secs(diagnostics_interval_secs) {
@@ -766,14 +1175,15 @@ impl Sfu {
                         for client in stats.clients {
                             connection_id.demux_id = client.demux_id;
                             let rtt = if let Some(connection) =
-                                self.get_connection_from_id(&connection_id)
+                                self.connection_by_id.get(&connection_id)
                             {
                                 connection.lock().rtt().as_millis()
                             } else {
                                 0
                             };
 
-                            let _ = write!(diagnostic_string, " {{ demux_id: {}, incoming_heights: ({}, {}, {}), incoming_rates: ({}, {}, {}), target: {}, requested_base: {}, ideal: {}, allocated: {}, queue_drain: {}, max_requested_height: {}, rtt_ms: {} }}",
+                            let reception_summary = client.reception_summary.unwrap_or_default();
+                            let _ = write!(diagnostic_string, " {{ demux_id: {}, incoming_heights: ({}, {}, {}), incoming_rates: ({}, {}, {}), target: {}, requested_base: {}, ideal: {}, allocated: {}, queue_drain: {}, max_requested_height: {}, rtt_ms: {}, reported_loss: {}, reported_cumulative_loss: {}, reported_rtt_ms: {}, speaking_secs: {}, video_freezes: {} }}",
                                   client.demux_id.as_u32(),
                                   client.video0_incoming_height.unwrap_or_default().as_u16(),
                                   client.video1_incoming_height.unwrap_or_default().as_u16(),
@@ -788,6 +1198,11 @@ impl Sfu {
                                   client.outgoing_queue_drain_rate.as_kbps(),
                                   client.max_requested_height.unwrap_or_default().as_u16(),
                                   rtt,
+                                  reception_summary.max_fraction_lost,
+                                  reception_summary.cumulative_loss,
+                                  reception_summary.rtt.unwrap_or_default().as_millis(),
+                                  client.speaking_time.as_secs(),
+                                  client.video_freezes,
                             );
                         }
 
@@ -808,32 +1223,31 @@ impl Sfu {
             false
         };
 
-        // Borrow this explicitly so that we can modify it at the same time as self.call_by_call_id.
-        let connection_id_by_ice_request_username = &mut self.connection_id_by_ice_request_username;
-
         let remove_inactive_calls_timer = start_timer_us!("calling.sfu.tick.remove_inactive_calls");
 
         let mut expired_demux_ids_by_call_id: HashMap<CallId, Vec<DemuxId>> = HashMap::new();
         let mut outgoing_queue_sizes_by_call_id: HashMap<CallId, Vec<(DemuxId, DataSize)>> =
             HashMap::new();
+        let mut incoming_fraction_lost_by_call_id: HashMap<CallId, Vec<(DemuxId, u8)>> =
+            HashMap::new();
         self.connection_by_id.retain(|connection_id, connection| {
             let mut connection = connection.lock();
             if check_for_inactivity && connection.inactive(now) {
                 info!("dropping connection: {}", connection_id);
 
-                connection_id_by_ice_request_username
-                    .remove_entry(connection.ice_request_username());
+                remove_ice_request_usernames(connection_id_by_ice_request_username, &connection);
 
-                // Addresses in sfu.client_by_address will get aged out
-                // by self.client_by_address.remove_old() below.
-                // and don't need to be removed here.
+                // Addresses in sfu.connection_id_by_address will get aged out
+                // by Sfu::tick() and don't need to be removed here.
 
                 expired_demux_ids_by_call_id
                     .entry(connection_id.call_id.clone())
                     .or_default()
                     .push(connection_id.demux_id);
 
-                if connection.outgoing_addr().is_none() {
+                if connection.consent_lost() {
+                    event!("calling.sfu.close_connection.consent_lost");
+                } else if connection.outgoing_addr().is_none() {
                     event!("calling.sfu.close_connection.no_nominee");
                 } else {
                     event!("calling.sfu.close_connection.inactive");
@@ -844,19 +1258,24 @@ impl Sfu {
                 false
             } else {
                 // Don't remove the connection; it's still active!
-                connection.tick(&mut packets_to_send, now);
+                connection.tick(packets_to_send, now);
                 outgoing_queue_sizes_by_call_id
                     .entry(connection_id.call_id.clone())
                     .or_default()
                     .push((connection_id.demux_id, connection.outgoing_queue_size()));
+                if let Some(incoming_fraction_lost) = connection.take_incoming_fraction_lost() {
+                    incoming_fraction_lost_by_call_id
+                        .entry(connection_id.call_id.clone())
+                        .or_default()
+                        .push((connection_id.demux_id, incoming_fraction_lost));
+                }
                 true
             }
         });
 
         let mut call_tick_results = vec![];
         // Iterate all calls, maybe dropping some that are inactive.
-        let outgoing_queue_drain_duration =
-            Duration::from_millis(self.config.outgoing_queue_drain_ms);
+        let outgoing_queue_drain_duration = Duration::from_millis(config.outgoing_queue_drain_ms);
         self.call_by_call_id.retain(|call_id, call| {
             let mut call = call.lock();
 
@@ -963,6 +1382,22 @@ impl Sfu {
                         event!("calling.sfu.all_call_seconds.many", seconds);
                     }
 
+                    let mut speaking_seconds: Vec<u64> = call
+                        .speaking_time_by_demux_id()
+                        .values()
+                        .map(|speaking_time| speaking_time.as_secs())
+                        .collect();
+                    speaking_seconds.sort_unstable_by(|a, b| b.cmp(a));
+                    info!(
+                        "call_id: {} speaking seconds by participant: {:?}",
+                        call.loggable_call_id(),
+                        speaking_seconds
+                    );
+                    event!("calling.sfu.call_complete.speakers", speaking_seconds.len());
+                    if let Ok(seconds) = speaking_seconds.iter().sum::<u64>().try_into() {
+                        event!("calling.sfu.all_speaking_seconds", seconds);
+                    }
+
                     false
                 } else {
                     // Keep the call around for a while longer.
@@ -982,6 +1417,14 @@ impl Sfu {
                             .set_outgoing_queue_drain_rate(*demux_id, outgoing_queue_drain_rate);
                     }
                 }
+                if let Some(incoming_fraction_losts) =
+                    incoming_fraction_lost_by_call_id.get(call_id)
+                {
+                    for (demux_id, incoming_fraction_lost) in incoming_fraction_losts {
+                        // Ignore the error because it can only mean the client is gone, in which case it doesn't matter.
+                        let _ = call.set_incoming_fraction_lost(*demux_id, *incoming_fraction_lost);
+                    }
+                }
                 // Don't remove the call; there are still clients!
                 let (outgoing_rtp, outgoing_key_frame_requests) = call.tick(now);
                 let send_rate_allocation_infos =
@@ -996,6 +1439,9 @@ impl Sfu {
                 true
             }
         });
+        let call_by_call_id = &self.call_by_call_id;
+        self.audio_mixer_by_call_id
+            .retain(|call_id, _| call_by_call_id.contains_key(call_id));
         remove_inactive_calls_timer.stop();
 
         for (call_id, outgoing_rtp, outgoing_key_frame_requests, send_rate_allocation_infos) in
@@ -1025,6 +1471,7 @@ impl Sfu {
                                 send_rate_allocation_info.target_send_rate,
                             ),
                             padding_ssrc: send_rate_allocation_info.padding_ssrc,
+                            max_video_queue_delay: Some(outgoing_queue_drain_duration),
                         },
                         now,
                     );
@@ -1053,24 +1500,10 @@ impl Sfu {
                     self.connection_by_id.get_mut(&outgoing_connection_id)
                 {
                     let mut outgoing_connection = outgoing_connection.lock();
-                    outgoing_connection.send_or_enqueue_rtp(
-                        outgoing_rtp,
-                        &mut packets_to_send,
-                        now,
-                    );
+                    outgoing_connection.send_or_enqueue_rtp(outgoing_rtp, packets_to_send, now);
                 }
             }
         }
-
-        let expired_client_addrs = {
-            time_scope_us!("calling.sfu.tick.remove_inactive_client_addresses");
-            self.connection_id_by_address.remove_old(now)
-        };
-
-        TickOutput {
-            packets_to_send,
-            expired_client_addrs,
-        }
     }
 }
 
@@ -1105,6 +1538,41 @@ impl rand_core5::RngCore for OsRngCompatibleWithDalek {
 
 impl rand_core5::CryptoRng for OsRngCompatibleWithDalek {}
 
+/// Does the server half of the DHE with a client and derives SRTP master key material from it.
+/// Returns the server's public key, which the client needs to derive the same key material.
+fn derive_srtp_master_key_material(
+    client_dhe_public_key: DhePublicKey,
+    client_hkdf_extra_info: &[u8],
+) -> (DhePublicKey, rtp::MasterKeyMaterial) {
+    let server_secret = EphemeralSecret::new(OsRngCompatibleWithDalek);
+    let server_dhe_public_key = PublicKey::from(&server_secret).to_bytes();
+    let shared_secret = server_secret.diffie_hellman(&PublicKey::from(client_dhe_public_key));
+    let mut srtp_master_key_material =
+        zeroize::Zeroizing::new(vec![0u8; rtp::MASTER_KEY_MATERIAL_LEN]);
+    Hkdf::<Sha256>::new(None, shared_secret.as_bytes())
+        .expand_multi_info(
+            &[
+                b"Signal_Group_Call_20211105_SignallingDH_SRTPKey_KDF",
+                client_hkdf_extra_info,
+            ],
+            srtp_master_key_material.deref_mut(),
+        )
+        .expect("Expand SRTP master key material");
+    (server_dhe_public_key, srtp_master_key_material)
+}
+
+/// Removes the routes for all the ICE usernames a connection accepts.
+fn remove_ice_request_usernames(
+    connection_id_by_ice_request_username: &RwLock<HashMap<Vec<u8>, ConnectionId>>,
+    connection: &Connection,
+) {
+    let mut connection_id_by_ice_request_username = connection_id_by_ice_request_username.write();
+    connection_id_by_ice_request_username.remove(connection.ice_request_username());
+    if let Some(previous) = connection.previous_ice_request_username() {
+        connection_id_by_ice_request_username.remove(previous);
+    }
+}
+
 #[cfg(test)]
 mod sfu_tests {
     use std::{
@@ -1117,7 +1585,6 @@ mod sfu_tests {
 
     use hex::{FromHex, ToHex};
     use once_cell::sync::Lazy;
-    use parking_lot::Mutex;
     use rand::{thread_rng, Rng};
 
     use super::*;
@@ -1150,15 +1617,13 @@ mod sfu_tests {
 
     static DEFAULT_CONFIG: Lazy<config::Config> = Lazy::new(config::default_test_config);
 
-    fn new_sfu(now: Instant, config: &'static config::Config) -> Arc<Mutex<Sfu>> {
-        Arc::new(Mutex::new(
-            Sfu::new(now, config).expect("Sfu::new should be working"),
-        ))
+    fn new_sfu(now: Instant, config: &'static config::Config) -> Arc<Sfu> {
+        Arc::new(Sfu::new(now, config).expect("Sfu::new should be working"))
     }
 
     #[allow(clippy::ptr_arg)]
     fn add_test_client<'a>(
-        sfu: &'a mut Sfu,
+        sfu: &'a Sfu,
         call_id: &'a CallId,
         user_id: &'a UserId,
         demux_id: DemuxId,
@@ -1178,6 +1643,7 @@ mod sfu_tests {
         let _ = sfu.get_or_create_call_and_add_client(
             call_id.clone(),
             user_id,
+            false,
             resolution_request_id,
             active_speaker_id,
             demux_id,
@@ -1187,6 +1653,10 @@ mod sfu_tests {
             client_dhe_public_key,
             vec![],
             Region::Unset,
+            rtp::CongestionControlFeedback::default(),
+            rtp::SrtpProtectionProfile::default(),
+            call::VideoMetadataSource::default(),
+            false,
         )?;
         Ok(())
     }
@@ -1197,11 +1667,10 @@ mod sfu_tests {
         let sfu = new_sfu(initial_now, &DEFAULT_CONFIG);
 
         // Make sure elements exist correctly.
-        let sfu = sfu.lock();
         assert_eq!(Ipv4Addr::LOCALHOST, sfu.config.binding_ip);
         assert_eq!(8080, sfu.config.signaling_port);
         assert_eq!(8, sfu.config.max_clients_per_call);
-        assert_eq!(0, sfu.call_by_call_id.len());
+        assert_eq!(0, sfu.get_calls_snapshot().len());
     }
 
     #[tokio::test]
@@ -1214,17 +1683,9 @@ mod sfu_tests {
         let demux_id = 123392u32.try_into().unwrap();
 
         // We add a client but won't do anything with it in this test.
-        let mut sfu = sfu.lock();
-        let _ = add_test_client(
-            &mut sfu,
-            &call_id,
-            &user_id,
-            demux_id,
-            "1".to_string(),
-            [0; 32],
-        );
+        let _ = add_test_client(&sfu, &call_id, &user_id, demux_id, "1".to_string(), [0; 32]);
 
-        assert_eq!(1, sfu.call_by_call_id.len());
+        assert_eq!(1, sfu.get_calls_snapshot().len());
         assert_eq!(1, sfu.get_call_signaling_info(call_id).unwrap().size);
     }
 
@@ -1262,15 +1723,12 @@ mod sfu_tests {
         let count = 1000;
         let call_ids = random_call_ids(count);
 
-        // We aren't measuring lock time in this test.
-        let mut sfu = sfu.lock();
-
         let start = Instant::now();
         for (index, call_id) in call_ids.iter().enumerate() {
             let demux_id = ((index as u32) << 4).try_into().unwrap();
             // We add a client but won't do anything with it in this test.
             let _ = add_test_client(
-                &mut sfu,
+                &sfu,
                 call_id,
                 &user_id,
                 demux_id,
@@ -1281,7 +1739,7 @@ mod sfu_tests {
         let end = Instant::now();
 
         // Make sure there were no collisions to skew results.
-        assert_eq!(count, sfu.call_by_call_id.len());
+        assert_eq!(count, sfu.get_calls_snapshot().len());
         for call_id in call_ids {
             assert_eq!(1, sfu.get_call_signaling_info(call_id).unwrap().size);
         }
@@ -1302,15 +1760,7 @@ mod sfu_tests {
         let call_id = random_call_id();
         let demux_id = 123392.try_into().unwrap();
 
-        let mut sfu = sfu.lock();
-        match add_test_client(
-            &mut sfu,
-            &call_id,
-            &user_id,
-            demux_id,
-            "1".to_string(),
-            [0; 32],
-        ) {
+        match add_test_client(&sfu, &call_id, &user_id, demux_id, "1".to_string(), [0; 32]) {
             Ok(_) => {
                 // Expected results:
                 //  - A call should have been created
@@ -1321,7 +1771,7 @@ mod sfu_tests {
                 assert_eq!(user_id.as_slice(), call_info.creator_id.as_slice());
                 assert_eq!(1, call_info.size);
                 assert_eq!(demux_id, call_info.client_ids[0].0);
-                assert_eq!(1, sfu.call_by_call_id.len());
+                assert_eq!(1, sfu.get_calls_snapshot().len());
             }
             Err(err) => {
                 panic!("get_or_create_call_and_add_client() failed with: {}", err);
@@ -1341,21 +1791,11 @@ mod sfu_tests {
         let user_ids = random_user_ids(user_count);
         let call_ids = random_call_ids(call_count);
 
-        // We aren't measuring lock time in this test.
-        let mut sfu = sfu.lock();
-
         let start = Instant::now();
         for call_id in &call_ids {
             for (index, user_id) in user_ids.iter().enumerate() {
                 let demux_id = ((index as u32) << 4).try_into().unwrap();
-                match add_test_client(
-                    &mut sfu,
-                    call_id,
-                    user_id,
-                    demux_id,
-                    "1".to_string(),
-                    [0; 32],
-                ) {
+                match add_test_client(&sfu, call_id, user_id, demux_id, "1".to_string(), [0; 32]) {
                     Ok(_) => {
                         // Nothing to do here.
                     }
@@ -1368,7 +1808,7 @@ mod sfu_tests {
         let end = Instant::now();
 
         // Make sure there were no collisions to skew results.
-        assert_eq!(call_count, sfu.call_by_call_id.len());
+        assert_eq!(call_count, sfu.get_calls_snapshot().len());
         for call_id in call_ids {
             assert_eq!(
                 user_count,
@@ -1396,26 +1836,16 @@ mod sfu_tests {
     }
 
     fn setup_calls_and_clients(
-        sfu: Arc<Mutex<Sfu>>,
+        sfu: Arc<Sfu>,
         call_ids: &[CallId],
         user_ids: &[UserId],
     ) -> Vec<GroupRecord> {
-        // Get one write lock to fill the calls and clients.
-        let mut sfu = sfu.lock();
-
         let mut group_record: Vec<GroupRecord> = Vec::new();
 
         for call_id in call_ids {
             for (index, user_id) in user_ids.iter().enumerate() {
                 let demux_id = ((index as u32) << 4).try_into().unwrap();
-                match add_test_client(
-                    &mut sfu,
-                    call_id,
-                    user_id,
-                    demux_id,
-                    "1".to_string(),
-                    [0; 32],
-                ) {
+                match add_test_client(&sfu, call_id, user_id, demux_id, "1".to_string(), [0; 32]) {
                     Ok(_) => {
                         group_record.push(GroupRecord {
                             call_id: call_id.clone(),
@@ -1450,11 +1880,9 @@ mod sfu_tests {
         let group_record = setup_calls_and_clients(sfu.clone(), &call_ids, &user_ids);
 
         {
-            // Now get read access here and check the numbers.
-            let sfu = sfu.lock();
-
+            // Check the numbers.
             // Make sure there were no collisions to skew results.
-            assert_eq!(call_ids.len(), sfu.call_by_call_id.len());
+            assert_eq!(call_ids.len(), sfu.get_calls_snapshot().len());
             for call_id in call_ids {
                 assert_eq!(
                     user_ids.len(),
@@ -1464,15 +1892,13 @@ mod sfu_tests {
         }
 
         {
-            // Now get write access and remove all the users.
-            let mut sfu = sfu.lock();
-
+            // Now remove all the users.
             for record in group_record {
                 sfu.remove_client_from_call(initial_now, record.call_id, record.demux_id);
             }
 
             // There should still be calls but no more clients.
-            assert_eq!(1000, sfu.call_by_call_id.len());
+            assert_eq!(1000, sfu.get_calls_snapshot().len());
 
             // Run the tick for (inactivity_timeout_secs * 1000) / tick_interval_ms times.
             for i in 0..((INACTIVITY_TIMEOUT_SECS * 1000) / TICK_PERIOD_MS) {
@@ -1480,46 +1906,8 @@ mod sfu_tests {
             }
 
             // The calls should now be gone.
-            assert_eq!(0, sfu.call_by_call_id.len());
-        }
-    }
-
-    /// This version obtains a lock for every operation instead for all.
-    fn setup_calls_and_clients_parallel(
-        sfu: Arc<Mutex<Sfu>>,
-        call_ids: &[CallId],
-        user_ids: &[UserId],
-    ) -> Vec<GroupRecord> {
-        let mut group_record: Vec<GroupRecord> = Vec::new();
-
-        for call_id in call_ids {
-            for (index, user_id) in user_ids.iter().enumerate() {
-                let demux_id = ((index as u32) << 4).try_into().unwrap();
-                // Get the write lock to the sfu for this block.
-                let mut sfu = sfu.lock();
-
-                match add_test_client(
-                    &mut sfu,
-                    call_id,
-                    user_id,
-                    demux_id,
-                    "1".to_string(),
-                    [0; 32],
-                ) {
-                    Ok(_) => {
-                        group_record.push(GroupRecord {
-                            call_id: call_id.clone(),
-                            demux_id,
-                        });
-                    }
-                    Err(err) => {
-                        panic!("get_or_create_call_and_add_client() failed with: {}", err);
-                    }
-                }
-            }
+            assert_eq!(0, sfu.get_calls_snapshot().len());
         }
-
-        group_record
     }
 
     #[tokio::test(flavor = "multi_thread")]
@@ -1529,18 +1917,18 @@ mod sfu_tests {
 
         let count = 200;
 
-        let client_a = |sfu: Arc<Mutex<Sfu>>, count: usize| async move {
+        let client_a = |sfu: Arc<Sfu>, count: usize| async move {
             // N calls with 8 users each.
             let call_ids = random_call_ids(count);
             let user_ids = random_user_ids(8);
-            let _ = setup_calls_and_clients_parallel(sfu, &call_ids, &user_ids);
+            let _ = setup_calls_and_clients(sfu, &call_ids, &user_ids);
         };
 
-        let client_b = |sfu: Arc<Mutex<Sfu>>, count: usize| async move {
+        let client_b = |sfu: Arc<Sfu>, count: usize| async move {
             // N calls with 8 users each.
             let call_ids = random_call_ids(count);
             let user_ids = random_user_ids(8);
-            let _ = setup_calls_and_clients_parallel(sfu, &call_ids, &user_ids);
+            let _ = setup_calls_and_clients(sfu, &call_ids, &user_ids);
         };
 
         let _ = tokio::join!(
@@ -1548,9 +1936,7 @@ mod sfu_tests {
             tokio::spawn(client_b(sfu.clone(), count / 2)),
         );
 
-        let sfu = sfu.lock();
-
-        assert_eq!(count, sfu.call_by_call_id.len());
+        assert_eq!(count, sfu.get_calls_snapshot().len());
     }
 
     #[tokio::test(flavor = "multi_thread")]
@@ -1560,18 +1946,18 @@ mod sfu_tests {
 
         let count = 1000;
 
-        let client_a = |sfu: Arc<Mutex<Sfu>>, count: usize| async move {
+        let client_a = |sfu: Arc<Sfu>, count: usize| async move {
             // N calls with 8 users each.
             let call_ids = random_call_ids(count);
             let user_ids = random_user_ids(8);
-            let _ = setup_calls_and_clients_parallel(sfu, &call_ids, &user_ids);
+            let _ = setup_calls_and_clients(sfu, &call_ids, &user_ids);
         };
 
-        let client_b = |sfu: Arc<Mutex<Sfu>>, count: usize| async move {
+        let client_b = |sfu: Arc<Sfu>, count: usize| async move {
             // N calls with 8 users each.
             let call_ids = random_call_ids(count);
             let user_ids = random_user_ids(8);
-            let _ = setup_calls_and_clients_parallel(sfu, &call_ids, &user_ids);
+            let _ = setup_calls_and_clients(sfu, &call_ids, &user_ids);
         };
 
         let start = Instant::now();
@@ -1581,9 +1967,7 @@ mod sfu_tests {
         );
         let end = Instant::now();
 
-        let sfu = sfu.lock();
-
-        assert_eq!(count, sfu.call_by_call_id.len());
+        assert_eq!(count, sfu.get_calls_snapshot().len());
 
         println!(
             "test_parallel_operations() for {} calls took {}ns",
@@ -1602,10 +1986,268 @@ mod sfu_tests {
             20000,
         ));
 
-        let result = Sfu::handle_packet(&sfu, sender_addr, &mut buf);
+        let result = sfu.handle_packet(sender_addr, ccfb::Ecn::NotEct, &mut buf);
         assert_eq!(result, Err(SfuError::UnknownPacketType(sender_addr)));
     }
 
+    #[test]
+    fn test_calls_are_partitioned_across_shards() {
+        let sfu = new_sfu(Instant::now(), &DEFAULT_CONFIG);
+        assert_eq!(DEFAULT_CONFIG.sfu_shard_count, Some(sfu.shards.len()));
+
+        let user_id = random_user_id();
+        let call_ids = random_call_ids(32);
+        for call_id in &call_ids {
+            for demux_id in [16u32, 32] {
+                add_test_client(
+                    &sfu,
+                    call_id,
+                    &user_id,
+                    demux_id.try_into().unwrap(),
+                    "1".to_string(),
+                    [0; 32],
+                )
+                .unwrap();
+            }
+        }
+
+        // Every call lives in exactly one shard, along with all of its connections.
+        let mut shards_with_calls = 0;
+        for shard in &sfu.shards {
+            let shard = shard.lock();
+            if !shard.call_by_call_id.is_empty() {
+                shards_with_calls += 1;
+            }
+            assert_eq!(
+                shard.call_by_call_id.len() * 2,
+                shard.connection_by_id.len()
+            );
+            for connection_id in shard.connection_by_id.keys() {
+                assert!(shard.call_by_call_id.contains_key(&connection_id.call_id));
+            }
+        }
+        assert!(shards_with_calls > 1);
+        assert_eq!(call_ids.len(), sfu.get_calls_snapshot().len());
+    }
+
+    #[test]
+    fn test_handle_packet_routes_to_owning_shard() {
+        let sfu = new_sfu(Instant::now(), &DEFAULT_CONFIG);
+
+        let user_id = random_user_id();
+        let call_id = random_call_id();
+        let demux_id = 16.try_into().unwrap();
+        let server_ice_ufrag = ice::random_ufrag();
+        let server_ice_pwd = ice::random_pwd();
+        sfu.get_or_create_call_and_add_client(
+            call_id.clone(),
+            &user_id,
+            false,
+            1,
+            "1".to_string(),
+            demux_id,
+            server_ice_ufrag.clone(),
+            server_ice_pwd.clone(),
+            "client".to_string(),
+            [0; 32],
+            vec![],
+            Region::Unset,
+            rtp::CongestionControlFeedback::default(),
+            rtp::SrtpProtectionProfile::default(),
+            call::VideoMetadataSource::default(),
+            false,
+        )
+        .unwrap();
+
+        let sender_addr = SocketLocator::Udp(SocketAddr::new(
+            IpAddr::from_str("127.0.0.1").unwrap(),
+            20000,
+        ));
+        // The transaction ID here includes the STUN magic cookie.
+        let transaction_id = 0x2112A442_000000000000000000000001u128.to_be_bytes();
+        let mut binding_request = ice::create_binding_request_packet(
+            &transaction_id,
+            &ice::join_username(b"client", server_ice_ufrag.as_bytes()),
+            server_ice_pwd.as_bytes(),
+            true,
+        );
+        let outgoing = sfu
+            .handle_packet(sender_addr, ccfb::Ecn::NotEct, &mut binding_request)
+            .unwrap();
+        assert_eq!(1, outgoing.len());
+        assert_eq!(sender_addr, outgoing[0].1);
+
+        // The address now routes to the connection in the call's shard.
+        let (connection_id, connection) = sfu.get_connection_from_address(&sender_addr).unwrap();
+        assert_eq!(
+            ConnectionId::from_call_id_and_demux_id(call_id.clone(), demux_id),
+            connection_id
+        );
+        assert!(Arc::ptr_eq(
+            &connection,
+            sfu.shard(&call_id)
+                .lock()
+                .connection_by_id
+                .get(&connection_id)
+                .unwrap()
+        ));
+
+        // Once the client is removed, its ICE username no longer routes anywhere.
+        sfu.remove_client_from_call(Instant::now(), call_id, demux_id);
+        assert!(matches!(
+            sfu.handle_packet(sender_addr, ccfb::Ecn::NotEct, &mut binding_request),
+            Err(SfuError::IceBindingRequestUnknownUsername(_))
+        ));
+    }
+
+    #[test]
+    fn test_ice_binding_requests_are_rate_limited_per_address() {
+        let now = Instant::now();
+        let sfu = new_sfu(now, &DEFAULT_CONFIG);
+        let addr1 = SocketLocator::Udp(SocketAddr::new(
+            IpAddr::from_str("127.0.0.1").unwrap(),
+            20000,
+        ));
+        let addr2 = SocketLocator::Udp(SocketAddr::new(
+            IpAddr::from_str("127.0.0.1").unwrap(),
+            20001,
+        ));
+
+        let limit = DEFAULT_CONFIG.ice_binding_requests_per_second_per_address;
+        for _ in 0..limit {
+            assert!(sfu.admit_ice_binding_request(addr1, now));
+        }
+        assert!(!sfu.admit_ice_binding_request(addr1, now));
+        // Other addresses have their own limit.
+        assert!(sfu.admit_ice_binding_request(addr2, now));
+
+        // The limit refills over time.
+        let refill_interval = Duration::from_secs(1) / limit;
+        assert!(sfu.admit_ice_binding_request(addr1, now + refill_interval));
+        assert!(!sfu.admit_ice_binding_request(addr1, now + refill_interval));
+
+        // And it's full again after a while.
+        let later = now + Duration::from_secs(3);
+        for _ in 0..limit {
+            assert!(sfu.admit_ice_binding_request(addr1, later));
+        }
+        assert!(!sfu.admit_ice_binding_request(addr1, later));
+    }
+
+    #[test]
+    fn test_restart_ice_keeps_connection() {
+        let sfu = new_sfu(Instant::now(), &DEFAULT_CONFIG);
+
+        let user_id = random_user_id();
+        let call_id = random_call_id();
+        let demux_id = 16.try_into().unwrap();
+        let server_ice_ufrag = ice::random_ufrag();
+        let server_ice_pwd = ice::random_pwd();
+        sfu.get_or_create_call_and_add_client(
+            call_id.clone(),
+            &user_id,
+            false,
+            1,
+            "1".to_string(),
+            demux_id,
+            server_ice_ufrag.clone(),
+            server_ice_pwd.clone(),
+            "client".to_string(),
+            [0; 32],
+            vec![],
+            Region::Unset,
+            rtp::CongestionControlFeedback::default(),
+            rtp::SrtpProtectionProfile::default(),
+            call::VideoMetadataSource::default(),
+            false,
+        )
+        .unwrap();
+        let connection_id = ConnectionId::from_call_id_and_demux_id(call_id.clone(), demux_id);
+        let connection = sfu.get_connection_from_id(&connection_id).unwrap();
+
+        let binding_request = |client_ufrag: &str, server_ufrag: &str, server_pwd: &str| {
+            // The transaction ID here includes the STUN magic cookie.
+            ice::create_binding_request_packet(
+                &0x2112A442_000000000000000000000001u128.to_be_bytes(),
+                &ice::join_username(client_ufrag.as_bytes(), server_ufrag.as_bytes()),
+                server_pwd.as_bytes(),
+                true,
+            )
+        };
+        let wifi_addr = SocketLocator::Udp(SocketAddr::new(
+            IpAddr::from_str("127.0.0.1").unwrap(),
+            20000,
+        ));
+        let cellular_addr = SocketLocator::Udp(SocketAddr::new(
+            IpAddr::from_str("127.0.0.2").unwrap(),
+            30000,
+        ));
+        sfu.handle_packet(
+            wifi_addr,
+            ccfb::Ecn::NotEct,
+            &mut binding_request("client", &server_ice_ufrag, &server_ice_pwd),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            sfu.restart_ice(
+                call_id.clone(),
+                32.try_into().unwrap(),
+                ice::random_ufrag(),
+                ice::random_pwd(),
+                "client2".to_string(),
+            ),
+            Err(SfuError::MissingConnection(..))
+        ));
+
+        let new_server_ice_ufrag = ice::random_ufrag();
+        let new_server_ice_pwd = ice::random_pwd();
+        sfu.restart_ice(
+            call_id.clone(),
+            demux_id,
+            new_server_ice_ufrag.clone(),
+            new_server_ice_pwd.clone(),
+            "client2".to_string(),
+        )
+        .unwrap();
+        sfu.handle_packet(
+            cellular_addr,
+            ccfb::Ecn::NotEct,
+            &mut binding_request("client2", &new_server_ice_ufrag, &new_server_ice_pwd),
+        )
+        .unwrap();
+
+        // Both addresses route to the same connection, which now sends to the new one.
+        for addr in [wifi_addr, cellular_addr] {
+            let (id, routed_connection) = sfu.get_connection_from_address(&addr).unwrap();
+            assert_eq!(connection_id, id);
+            assert!(Arc::ptr_eq(&connection, &routed_connection));
+        }
+        assert_eq!(Some(cellular_addr), connection.lock().outgoing_addr());
+
+        // A second restart retires the original username.
+        sfu.restart_ice(
+            call_id.clone(),
+            demux_id,
+            ice::random_ufrag(),
+            ice::random_pwd(),
+            "client3".to_string(),
+        )
+        .unwrap();
+        assert!(matches!(
+            sfu.handle_packet(
+                wifi_addr,
+                ccfb::Ecn::NotEct,
+                &mut binding_request("client", &server_ice_ufrag, &server_ice_pwd)
+            ),
+            Err(SfuError::IceBindingRequestUnknownUsername(_))
+        ));
+
+        // Removing the client removes all of its usernames.
+        sfu.remove_client_from_call(Instant::now(), call_id, demux_id);
+        assert!(sfu.connection_id_by_ice_request_username.read().is_empty());
+    }
+
     #[test]
     fn test_connection_id_logging() {
         let id =
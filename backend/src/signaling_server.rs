@@ -9,6 +9,9 @@
 //!   GET /v1/info
 //!   GET /v1/call/$call_id/clients
 //!   POST /v1/call/$call_id/client/$demux_id (join)
+//!   POST /v1/call/$call_id/client/$demux_id/ice-restart
+//!   POST /v1/call/$call_id/client/$demux_id/rekey
+//!   PUT /v1/call/$call_id/spotlight
 
 use std::{
     convert::TryInto,
@@ -27,17 +30,16 @@ use axum::{
     http::StatusCode,
     middleware,
     response::IntoResponse,
-    routing::{get, post},
+    routing::{get, post, put},
     Extension, Json, Router,
 };
 use hex::{FromHex, ToHex};
 use log::*;
-use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use tokio::sync::oneshot::{self, Receiver};
 use tower::ServiceBuilder;
 
-use crate::{call, config, ice, middleware::log_response, region::Region, sfu, sfu::Sfu};
+use crate::{call, config, ice, middleware::log_response, region::Region, rtp, sfu, sfu::Sfu};
 
 const SYSTEM_MONITOR_INTERVAL: Duration = Duration::from_secs(10);
 
@@ -69,6 +71,18 @@ pub struct JoinRequest {
     pub client_dhe_public_key: String,
     pub hkdf_extra_info: Option<String>,
     pub region: Option<String>,
+    /// Whether the client's user is an admin of the call, as checked by the frontend.
+    /// Only admins may change call-wide state such as the spotlight. Defaults to false.
+    pub is_admin: Option<bool>,
+    /// "transport-cc" (the default) or "rfc8888"
+    pub congestion_control_feedback: Option<String>,
+    /// "AEAD_AES_128_GCM" (the default) or "AEAD_AES_256_GCM"
+    pub srtp_protection_profile: Option<String>,
+    /// "vp8" (the default) or "dependency-descriptor" (for end-to-end encrypted video)
+    pub video_metadata_source: Option<String>,
+    /// Receive the loudest speakers mixed into one audio stream rather than each speaker's audio,
+    /// if the server mixes audio. Defaults to false.
+    pub receive_mixed_audio: Option<bool>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -78,11 +92,46 @@ pub struct JoinResponse {
     pub server_ips: Vec<String>,
     pub server_port: u16,
     pub server_port_tcp: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_port_tls: Option<u16>,
     pub server_ice_ufrag: String,
     pub server_ice_pwd: String,
     pub server_dhe_public_key: String,
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IceRestartRequest {
+    pub client_ice_ufrag: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IceRestartResponse {
+    pub server_ice_ufrag: String,
+    pub server_ice_pwd: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RekeyRequest {
+    pub client_dhe_public_key: String,
+    pub hkdf_extra_info: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RekeyResponse {
+    pub server_dhe_public_key: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SpotlightRequest {
+    /// Most important first. An empty list clears the spotlight.
+    pub demux_ids: Vec<u32>,
+}
+
 /// Get a call_id (Vec<u8>) from a string hex value.
 fn call_id_from_hex(call_id: &str) -> Result<sfu::CallId> {
     if call_id.is_empty() {
@@ -122,20 +171,20 @@ pub fn parse_user_id_and_resolution_request_id_from_endpoint_id(
 
 /// Return a health response after accessing the SFU and obtaining basic information.
 async fn get_health(
-    State(sfu): State<Arc<Mutex<Sfu>>>,
+    State(sfu): State<Arc<Sfu>>,
     Extension(is_healthy): Extension<Arc<AtomicBool>>,
     Extension(cpu_idle_pct): Extension<Arc<AtomicU8>>,
 ) -> Result<impl IntoResponse, StatusCode> {
     trace!("get_health():");
 
     if is_healthy.load(Ordering::Relaxed) {
-        let calls = sfu.lock().get_calls_snapshot(); // SFU lock released here.
+        let calls = sfu.get_calls_snapshot(); // Shard locks released here.
 
         let client_count = calls
             .iter()
             .map(|call| {
-                // We can take this call lock after closing the SFU lock because we are treating
-                // it as read-only and can accommodate stale data.
+                // We can take this call lock after releasing the shard locks because we are
+                // treating it as read-only and can accommodate stale data.
                 let call = call.lock();
                 call.size()
             })
@@ -178,7 +227,7 @@ async fn get_info(
 /// the call does not exist or an empty list if there are no clients
 /// currently in the call.
 async fn get_clients(
-    State(sfu): State<Arc<Mutex<Sfu>>>,
+    State(sfu): State<Arc<Sfu>>,
     Path(call_id): Path<String>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     trace!("get_clients(): {}", call_id);
@@ -186,7 +235,6 @@ async fn get_clients(
     let call_id =
         call_id_from_hex(&call_id).map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
 
-    let sfu = sfu.lock();
     if let Some(signaling) = sfu.get_call_signaling_info(call_id) {
         let response = ClientsResponse {
             endpoint_ids: signaling
@@ -204,7 +252,7 @@ async fn get_clients(
 
 /// Handles a request for a client to join a call.
 async fn join(
-    State(sfu): State<Arc<Mutex<Sfu>>>,
+    State(sfu): State<Arc<Sfu>>,
     Path((call_id, demux_id)): Path<(String, u32)>,
     Extension(config): Extension<&'static config::Config>,
     Json(request): Json<JoinRequest>,
@@ -240,10 +288,35 @@ async fn join(
         Region::Unset
     };
 
-    let mut sfu = sfu.lock();
+    let congestion_control_feedback = match request.congestion_control_feedback {
+        None => rtp::CongestionControlFeedback::default(),
+        Some(congestion_control_feedback) => {
+            rtp::CongestionControlFeedback::from_str(&congestion_control_feedback)
+                .map_err(|err| (StatusCode::NOT_ACCEPTABLE, err.to_string()))?
+        }
+    };
+
+    let srtp_protection_profile = match request.srtp_protection_profile {
+        None => rtp::SrtpProtectionProfile::default(),
+        Some(srtp_protection_profile) => {
+            rtp::SrtpProtectionProfile::from_str(&srtp_protection_profile)
+                .map_err(|err| (StatusCode::NOT_ACCEPTABLE, err.to_string()))?
+        }
+    };
+
+    let video_metadata_source = match request.video_metadata_source {
+        None => call::VideoMetadataSource::default(),
+        Some(video_metadata_source) => call::VideoMetadataSource::from_str(&video_metadata_source)
+            .map_err(|err| (StatusCode::NOT_ACCEPTABLE, err.to_string()))?,
+    };
+
+    let receive_mixed_audio = request.receive_mixed_audio.unwrap_or(false);
+    let is_admin = request.is_admin.unwrap_or(false);
+
     match sfu.get_or_create_call_and_add_client(
         call_id,
         &user_id,
+        is_admin,
         resolution_request_id,
         request.endpoint_id,
         demux_id,
@@ -253,6 +326,10 @@ async fn join(
         client_dhe_public_key,
         client_hkdf_extra_info,
         region,
+        congestion_control_feedback,
+        srtp_protection_profile,
+        video_metadata_source,
+        receive_mixed_audio,
     ) {
         Ok(server_dhe_public_key) => {
             let media_server = config::ServerMediaAddress::from(config);
@@ -267,6 +344,7 @@ async fn join(
                     .collect(),
                 server_port: media_server.ports.udp,
                 server_port_tcp: media_server.ports.tcp,
+                server_port_tls: media_server.ports.tls,
                 server_ice_ufrag,
                 server_ice_pwd,
                 server_dhe_public_key,
@@ -289,10 +367,132 @@ async fn join(
     }
 }
 
+/// Handles a request for a client that has already joined to switch to new ICE credentials,
+/// such as after changing networks.
+async fn restart_ice(
+    State(sfu): State<Arc<Sfu>>,
+    Path((call_id, demux_id)): Path<(String, u32)>,
+    Json(request): Json<IceRestartRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    trace!("restart_ice(): {} {}", call_id, demux_id);
+
+    let call_id =
+        call_id_from_hex(&call_id).map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+    let demux_id = demux_id
+        .try_into()
+        .map_err(|err: call::Error| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+    let server_ice_ufrag = ice::random_ufrag();
+    let server_ice_pwd = ice::random_pwd();
+
+    match sfu.restart_ice(
+        call_id,
+        demux_id,
+        server_ice_ufrag.clone(),
+        server_ice_pwd.clone(),
+        request.client_ice_ufrag,
+    ) {
+        Ok(()) => Ok(Json(IceRestartResponse {
+            server_ice_ufrag,
+            server_ice_pwd,
+        })),
+        Err(err @ sfu::SfuError::MissingConnection(..)) => {
+            Err((StatusCode::NOT_FOUND, err.to_string()))
+        }
+        Err(err) => {
+            error!("client failed to restart ICE {}", err);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to restart ICE {}", err),
+            ))
+        }
+    }
+}
+
+/// Handles a request for a client that has already joined to switch to new SRTP keys
+/// from a new DHE exchange.
+async fn rekey(
+    State(sfu): State<Arc<Sfu>>,
+    Path((call_id, demux_id)): Path<(String, u32)>,
+    Json(request): Json<RekeyRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    trace!("rekey(): {} {}", call_id, demux_id);
+
+    let call_id =
+        call_id_from_hex(&call_id).map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+    let demux_id = demux_id
+        .try_into()
+        .map_err(|err: call::Error| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+    let client_dhe_public_key = <[u8; 32]>::from_hex(request.client_dhe_public_key)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+    let client_hkdf_extra_info = match request.hkdf_extra_info {
+        None => vec![],
+        Some(hkdf_extra_info) => Vec::<u8>::from_hex(hkdf_extra_info)
+            .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?,
+    };
+
+    match sfu.rekey(
+        call_id,
+        demux_id,
+        client_dhe_public_key,
+        client_hkdf_extra_info,
+    ) {
+        Ok(server_dhe_public_key) => Ok(Json(RekeyResponse {
+            server_dhe_public_key: server_dhe_public_key.encode_hex(),
+        })),
+        Err(err @ sfu::SfuError::MissingConnection(..)) => {
+            Err((StatusCode::NOT_FOUND, err.to_string()))
+        }
+        Err(err) => {
+            error!("client failed to rekey {}", err);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to rekey {}", err),
+            ))
+        }
+    }
+}
+
+/// Handles a request to replace the senders whose video every client in the call should see
+/// first. The caller is responsible for making sure this comes from an admin of the call.
+async fn set_spotlight(
+    State(sfu): State<Arc<Sfu>>,
+    Path(call_id): Path<String>,
+    Json(request): Json<SpotlightRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    trace!("set_spotlight(): {}", call_id);
+
+    let call_id =
+        call_id_from_hex(&call_id).map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+    let demux_ids = request
+        .demux_ids
+        .into_iter()
+        .map(|demux_id| demux_id.try_into())
+        .collect::<Result<Vec<sfu::DemuxId>, call::Error>>()
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+    match sfu.set_spotlight(call_id, &demux_ids) {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(err @ sfu::SfuError::MissingCall(..)) => Err((StatusCode::NOT_FOUND, err.to_string())),
+        Err(err) => {
+            error!("failed to set spotlight {}", err);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to set spotlight {}", err),
+            ))
+        }
+    }
+}
+
 /// The overall signaling api combined as a Router for the server and testing.
 pub fn signaling_api(
     config: &'static config::Config,
-    sfu: Arc<Mutex<Sfu>>,
+    sfu: Arc<Sfu>,
     is_healthy: Arc<AtomicBool>,
     cpu_idle_pct: Arc<AtomicU8>,
 ) -> Router {
@@ -316,6 +516,21 @@ pub fn signaling_api(
     let join_route = Router::new()
         .route("/v1/call/:call_id/client/:demux_id", post(join))
         .layer(Extension(config))
+        .with_state(sfu.clone());
+
+    let ice_restart_route = Router::new()
+        .route(
+            "/v1/call/:call_id/client/:demux_id/ice-restart",
+            post(restart_ice),
+        )
+        .with_state(sfu.clone());
+
+    let rekey_route = Router::new()
+        .route("/v1/call/:call_id/client/:demux_id/rekey", post(rekey))
+        .with_state(sfu.clone());
+
+    let spotlight_route = Router::new()
+        .route("/v1/call/:call_id/spotlight", put(set_spotlight))
         .with_state(sfu);
 
     Router::new()
@@ -323,11 +538,14 @@ pub fn signaling_api(
         .merge(info_route)
         .merge(clients_route)
         .merge(join_route)
+        .merge(ice_restart_route)
+        .merge(rekey_route)
+        .merge(spotlight_route)
 }
 
 pub async fn start(
     config: &'static config::Config,
-    sfu: Arc<Mutex<Sfu>>,
+    sfu: Arc<Sfu>,
     ender_rx: Receiver<()>,
     is_healthy: Arc<AtomicBool>,
 ) -> Result<()> {
@@ -420,14 +638,12 @@ mod signaling_server_tests {
         config
     });
 
-    fn new_sfu(now: Instant, config: &'static config::Config) -> Arc<Mutex<Sfu>> {
-        Arc::new(Mutex::new(
-            Sfu::new(now, config).expect("Sfu::new should work"),
-        ))
+    fn new_sfu(now: Instant, config: &'static config::Config) -> Arc<Sfu> {
+        Arc::new(Sfu::new(now, config).expect("Sfu::new should work"))
     }
 
     fn add_client_to_sfu(
-        sfu: Arc<Mutex<Sfu>>,
+        sfu: Arc<Sfu>,
         call_id: &str,
         endpoint_id: &str,
         demux_id: DemuxId,
@@ -439,10 +655,10 @@ mod signaling_server_tests {
             parse_user_id_and_resolution_request_id_from_endpoint_id(endpoint_id).unwrap();
 
         let _ = sfu
-            .lock()
             .get_or_create_call_and_add_client(
                 call_id,
                 &user_id,
+                false,
                 resolution_request_id,
                 endpoint_id.to_string(),
                 demux_id,
@@ -452,28 +668,27 @@ mod signaling_server_tests {
                 client_dhe_pub_key,
                 vec![],
                 Region::Unset,
+                rtp::CongestionControlFeedback::default(),
+                rtp::SrtpProtectionProfile::default(),
+                call::VideoMetadataSource::default(),
+                false,
             )
             .unwrap();
     }
 
-    fn remove_client_from_sfu(sfu: Arc<Mutex<Sfu>>, call_id: &str, demux_id: DemuxId) {
+    fn remove_client_from_sfu(sfu: Arc<Sfu>, call_id: &str, demux_id: DemuxId) {
         let call_id = call_id_from_hex(call_id).unwrap();
 
-        sfu.lock()
-            .remove_client_from_call(Instant::now(), call_id, demux_id);
+        sfu.remove_client_from_call(Instant::now(), call_id, demux_id);
     }
 
-    fn check_call_exists_in_sfu(sfu: Arc<Mutex<Sfu>>, call_id: &str) -> bool {
-        sfu.lock()
-            .get_call_signaling_info(call_id_from_hex(call_id).unwrap())
+    fn check_call_exists_in_sfu(sfu: Arc<Sfu>, call_id: &str) -> bool {
+        sfu.get_call_signaling_info(call_id_from_hex(call_id).unwrap())
             .is_some()
     }
 
-    fn get_client_count_in_call_from_sfu(sfu: Arc<Mutex<Sfu>>, call_id: &str) -> usize {
-        if let Some(signaling) = sfu
-            .lock()
-            .get_call_signaling_info(call_id_from_hex(call_id).unwrap())
-        {
+    fn get_client_count_in_call_from_sfu(sfu: Arc<Sfu>, call_id: &str) -> usize {
+        if let Some(signaling) = sfu.get_call_signaling_info(call_id_from_hex(call_id).unwrap()) {
             signaling.size
         } else {
             0
@@ -709,6 +924,11 @@ mod signaling_server_tests {
                             client_dhe_public_key: CLIENT_DHE_PUB_KEY.encode_hex(),
                             hkdf_extra_info: None,
                             region: None,
+                            is_admin: None,
+                            congestion_control_feedback: None,
+                            srtp_protection_profile: None,
+                            video_metadata_source: None,
+                            receive_mixed_audio: None,
                         })
                         .unwrap(),
                     ))
@@ -732,6 +952,11 @@ mod signaling_server_tests {
                             client_dhe_public_key: CLIENT_DHE_PUB_KEY.encode_hex(),
                             hkdf_extra_info: None,
                             region: None,
+                            is_admin: None,
+                            congestion_control_feedback: None,
+                            srtp_protection_profile: None,
+                            video_metadata_source: None,
+                            receive_mixed_audio: None,
                         })
                         .unwrap(),
                     ))
@@ -755,6 +980,11 @@ mod signaling_server_tests {
                             client_dhe_public_key: CLIENT_DHE_PUB_KEY.encode_hex(),
                             hkdf_extra_info: None,
                             region: None,
+                            is_admin: None,
+                            congestion_control_feedback: None,
+                            srtp_protection_profile: None,
+                            video_metadata_source: None,
+                            receive_mixed_audio: None,
                         })
                         .unwrap(),
                     ))
@@ -778,6 +1008,11 @@ mod signaling_server_tests {
                             client_dhe_public_key: "INVALID".to_string(),
                             hkdf_extra_info: None,
                             region: None,
+                            is_admin: None,
+                            congestion_control_feedback: None,
+                            srtp_protection_profile: None,
+                            video_metadata_source: None,
+                            receive_mixed_audio: None,
                         })
                         .unwrap(),
                     ))
@@ -801,6 +1036,11 @@ mod signaling_server_tests {
                             client_dhe_public_key: CLIENT_DHE_PUB_KEY.encode_hex(),
                             hkdf_extra_info: Some("G".to_string()),
                             region: None,
+                            is_admin: None,
+                            congestion_control_feedback: None,
+                            srtp_protection_profile: None,
+                            video_metadata_source: None,
+                            receive_mixed_audio: None,
                         })
                         .unwrap(),
                     ))
@@ -811,6 +1051,34 @@ mod signaling_server_tests {
 
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 
+        // Join with an unsupported SRTP protection profile
+        let response = api
+            .clone()
+            .oneshot(
+                Request::post(&format!("/v1/call/{}/client/{}", CALL_ID, 16))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::to_vec(&JoinRequest {
+                            endpoint_id: ENDPOINT_ID_1.to_string(),
+                            client_ice_ufrag: UFRAG.to_string(),
+                            client_dhe_public_key: CLIENT_DHE_PUB_KEY.encode_hex(),
+                            hkdf_extra_info: None,
+                            region: None,
+                            is_admin: None,
+                            congestion_control_feedback: None,
+                            srtp_protection_profile: Some("NULL".to_string()),
+                            video_metadata_source: None,
+                            receive_mixed_audio: None,
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+
         // Join with good parameters.
         let response = api
             .clone()
@@ -824,6 +1092,11 @@ mod signaling_server_tests {
                             client_dhe_public_key: CLIENT_DHE_PUB_KEY.encode_hex(),
                             hkdf_extra_info: None,
                             region: None,
+                            is_admin: None,
+                            congestion_control_feedback: None,
+                            srtp_protection_profile: None,
+                            video_metadata_source: None,
+                            receive_mixed_audio: None,
                         })
                         .unwrap(),
                     ))
@@ -863,6 +1136,11 @@ mod signaling_server_tests {
                             client_dhe_public_key: CLIENT_DHE_PUB_KEY.encode_hex(),
                             hkdf_extra_info: None,
                             region: None,
+                            is_admin: None,
+                            congestion_control_feedback: None,
+                            srtp_protection_profile: None,
+                            video_metadata_source: None,
+                            receive_mixed_audio: None,
                         })
                         .unwrap(),
                     ))
@@ -874,4 +1152,275 @@ mod signaling_server_tests {
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
         assert_eq!(get_client_count_in_call_from_sfu(sfu.clone(), CALL_ID), 1);
     }
+
+    #[tokio::test]
+    async fn test_restart_ice() {
+        let config = &DEFAULT_CONFIG;
+        let sfu = new_sfu(Instant::now(), config);
+        let is_healthy = Arc::new(AtomicBool::new(true));
+        let cpu_idle_pct = Arc::new(AtomicU8::new(100));
+
+        let api = signaling_api(config, sfu.clone(), is_healthy, cpu_idle_pct);
+
+        let restart_ice_request = || {
+            Body::from(
+                serde_json::to_vec(&IceRestartRequest {
+                    client_ice_ufrag: "new-ufrag".to_string(),
+                })
+                .unwrap(),
+            )
+        };
+
+        // Restart before joining.
+        let response = api
+            .clone()
+            .oneshot(
+                Request::post(&format!("/v1/call/{}/client/{}/ice-restart", CALL_ID, 16))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(restart_ice_request())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = api
+            .clone()
+            .oneshot(
+                Request::post(&format!("/v1/call/{}/client/{}", CALL_ID, 16))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::to_vec(&JoinRequest {
+                            endpoint_id: ENDPOINT_ID_1.to_string(),
+                            client_ice_ufrag: UFRAG.to_string(),
+                            client_dhe_public_key: CLIENT_DHE_PUB_KEY.encode_hex(),
+                            hkdf_extra_info: None,
+                            region: None,
+                            is_admin: None,
+                            congestion_control_feedback: None,
+                            srtp_protection_profile: None,
+                            video_metadata_source: None,
+                            receive_mixed_audio: None,
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let join_response: JoinResponse = serde_json::from_slice(&body).unwrap();
+
+        // Restart after joining.
+        let response = api
+            .clone()
+            .oneshot(
+                Request::post(&format!("/v1/call/{}/client/{}/ice-restart", CALL_ID, 16))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(restart_ice_request())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let response: IceRestartResponse = serde_json::from_slice(&body).unwrap();
+        assert_ne!(join_response.server_ice_ufrag, response.server_ice_ufrag);
+        assert_ne!(join_response.server_ice_pwd, response.server_ice_pwd);
+
+        // Still the same client.
+        assert_eq!(get_client_count_in_call_from_sfu(sfu.clone(), CALL_ID), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rekey() {
+        let config = &DEFAULT_CONFIG;
+        let sfu = new_sfu(Instant::now(), config);
+        let is_healthy = Arc::new(AtomicBool::new(true));
+        let cpu_idle_pct = Arc::new(AtomicU8::new(100));
+
+        let api = signaling_api(config, sfu.clone(), is_healthy, cpu_idle_pct);
+
+        let rekey_request = |client_dhe_public_key: String| {
+            Body::from(
+                serde_json::to_vec(&RekeyRequest {
+                    client_dhe_public_key,
+                    hkdf_extra_info: None,
+                })
+                .unwrap(),
+            )
+        };
+
+        // Rekey before joining.
+        let response = api
+            .clone()
+            .oneshot(
+                Request::post(&format!("/v1/call/{}/client/{}/rekey", CALL_ID, 16))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(rekey_request(CLIENT_DHE_PUB_KEY.encode_hex()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = api
+            .clone()
+            .oneshot(
+                Request::post(&format!("/v1/call/{}/client/{}", CALL_ID, 16))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::to_vec(&JoinRequest {
+                            endpoint_id: ENDPOINT_ID_1.to_string(),
+                            client_ice_ufrag: UFRAG.to_string(),
+                            client_dhe_public_key: CLIENT_DHE_PUB_KEY.encode_hex(),
+                            hkdf_extra_info: None,
+                            region: None,
+                            is_admin: None,
+                            congestion_control_feedback: None,
+                            srtp_protection_profile: None,
+                            video_metadata_source: None,
+                            receive_mixed_audio: None,
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let join_response: JoinResponse = serde_json::from_slice(&body).unwrap();
+
+        // Rekey with an invalid public key.
+        let response = api
+            .clone()
+            .oneshot(
+                Request::post(&format!("/v1/call/{}/client/{}/rekey", CALL_ID, 16))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(rekey_request("invalid".to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Rekey after joining.
+        let response = api
+            .clone()
+            .oneshot(
+                Request::post(&format!("/v1/call/{}/client/{}/rekey", CALL_ID, 16))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(rekey_request(CLIENT_DHE_PUB_KEY.encode_hex()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let response: RekeyResponse = serde_json::from_slice(&body).unwrap();
+        assert_ne!(
+            join_response.server_dhe_public_key,
+            response.server_dhe_public_key
+        );
+
+        // Still the same client.
+        assert_eq!(get_client_count_in_call_from_sfu(sfu.clone(), CALL_ID), 1);
+    }
+
+    #[tokio::test]
+    async fn test_set_spotlight() {
+        let config = &DEFAULT_CONFIG;
+        let sfu = new_sfu(Instant::now(), config);
+        let is_healthy = Arc::new(AtomicBool::new(true));
+        let cpu_idle_pct = Arc::new(AtomicU8::new(100));
+
+        let api = signaling_api(config, sfu.clone(), is_healthy, cpu_idle_pct);
+
+        let spotlight_request = |demux_ids: Vec<u32>| {
+            Body::from(serde_json::to_vec(&SpotlightRequest { demux_ids }).unwrap())
+        };
+
+        // Spotlight before anyone joins.
+        let response = api
+            .clone()
+            .oneshot(
+                Request::put(&format!("/v1/call/{}/spotlight", CALL_ID))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(spotlight_request(vec![16]))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = api
+            .clone()
+            .oneshot(
+                Request::post(&format!("/v1/call/{}/client/{}", CALL_ID, 16))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::to_vec(&JoinRequest {
+                            endpoint_id: ENDPOINT_ID_1.to_string(),
+                            client_ice_ufrag: UFRAG.to_string(),
+                            client_dhe_public_key: CLIENT_DHE_PUB_KEY.encode_hex(),
+                            hkdf_extra_info: None,
+                            region: None,
+                            is_admin: None,
+                            congestion_control_feedback: None,
+                            srtp_protection_profile: None,
+                            video_metadata_source: None,
+                            receive_mixed_audio: None,
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Spotlight with an invalid demux ID.
+        let response = api
+            .clone()
+            .oneshot(
+                Request::put(&format!("/v1/call/{}/spotlight", CALL_ID))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(spotlight_request(vec![17]))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Spotlight after joining. Unknown demux IDs are ignored.
+        let response = api
+            .clone()
+            .oneshot(
+                Request::put(&format!("/v1/call/{}/spotlight", CALL_ID))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(spotlight_request(vec![32, 16]))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let calls = sfu.get_calls_snapshot();
+        assert_eq!(
+            calls[0].lock().spotlight(),
+            &[DemuxId::try_from(16).unwrap()]
+        );
+    }
 }
@@ -15,6 +15,7 @@ use byteorder::{ReadBytesExt, BE};
 use calling_common::{DataSize, Duration, Instant, TwoGenerationCache, Writable, Writer, U24};
 use log::*;
 
+use crate::pacer::ProbeClusterId;
 pub use crate::rtp::{expand_seqnum, FullSequenceNumber, TruncatedSequenceNumber};
 
 /// A remote instant, internally represented as a duration since a remote-chosen epoch.
@@ -61,12 +62,29 @@ pub struct Ack {
     pub departure: Instant,
     pub arrival: RemoteInstant,
     pub feedback_arrival: Instant,
+    pub probe_cluster_id: Option<ProbeClusterId>,
+}
+
+/// A sent packet that transport-cc feedback reported as not received.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Loss {
+    pub size: DataSize,
+    pub departure: Instant,
+    pub feedback_arrival: Instant,
 }
 
 pub struct Sender {
     next_send_seqnum: FullSequenceNumber,
     max_received_seqnum: FullSequenceNumber,
-    size_by_seqnum: TwoGenerationCache<FullSequenceNumber, (DataSize, Instant)>,
+    sent_by_seqnum: TwoGenerationCache<FullSequenceNumber, SentPacket>,
+}
+
+struct SentPacket {
+    size: DataSize,
+    departure: Instant,
+    probe_cluster_id: Option<ProbeClusterId>,
+    // Kept around after being reported lost in case a later feedback reports it as received.
+    reported_lost: bool,
 }
 
 // The state for sending transport-cc, which keeps track of packets sent and
@@ -82,7 +100,7 @@ impl Sender {
             // A feedback message might take longer than that.
             // So we'll go with the WebRTC way.  However, 60 seconds seems a bit long, so let's go with 10 seconds.
             // TODO: Consider making this configurable
-            size_by_seqnum: TwoGenerationCache::new(Duration::from_secs(10), now),
+            sent_by_seqnum: TwoGenerationCache::new(Duration::from_secs(10), now),
         }
     }
 
@@ -92,37 +110,85 @@ impl Sender {
         seqnum
     }
 
-    pub fn remember_sent(&mut self, seqnum: FullSequenceNumber, size: DataSize, now: Instant) {
+    pub fn remember_sent(
+        &mut self,
+        seqnum: FullSequenceNumber,
+        size: DataSize,
+        probe_cluster_id: Option<ProbeClusterId>,
+        now: Instant,
+    ) {
         let departure = now;
-        self.size_by_seqnum.insert(seqnum, (size, departure), now);
+        self.sent_by_seqnum.insert(
+            seqnum,
+            SentPacket {
+                size,
+                departure,
+                probe_cluster_id,
+                reported_lost: false,
+            },
+            now,
+        );
     }
 
+    /// Returns the acks, the losses, and how many of the acks are for packets that were
+    /// reported lost by earlier feedback (they arrived late rather than not at all).
     pub fn process_feedback_and_correlate_acks(
         &mut self,
         feedback: impl Iterator<Item = impl AsRef<[u8]>>,
         feedback_arrival: Instant,
-    ) -> Vec<Ack> {
-        let mut acks = Vec::new();
+    ) -> (Vec<Ack>, Vec<Loss>, usize) {
+        let mut statuses = Vec::new();
         for feedback in feedback {
-            if let Some((_feedback_seqnum, arrivals)) =
-                read_feedback(feedback.as_ref(), &mut self.max_received_seqnum)
+            if let Some((_feedback_seqnum, feedback_statuses)) =
+                read_feedback_statuses(feedback.as_ref(), &mut self.max_received_seqnum)
             {
-                for (seqnum, arrival) in arrivals {
-                    if let Some((size, departure)) = self.size_by_seqnum.remove(&seqnum) {
-                        acks.push(Ack {
-                            // seqnum,
-                            size,
-                            departure,
-                            arrival,
-                            feedback_arrival,
-                        });
-                    }
-                }
+                statuses.extend(feedback_statuses);
             } else {
                 warn!("Failed to parse TCC feedback");
             }
         }
-        acks
+        self.correlate_statuses(statuses, feedback_arrival)
+    }
+
+    /// Correlates the statuses (None means not received) of previously sent packets
+    /// from any kind of feedback.  See process_feedback_and_correlate_acks for what's returned.
+    pub fn correlate_statuses(
+        &mut self,
+        statuses: impl IntoIterator<Item = (FullSequenceNumber, Option<RemoteInstant>)>,
+        feedback_arrival: Instant,
+    ) -> (Vec<Ack>, Vec<Loss>, usize) {
+        let mut acks = Vec::new();
+        let mut losses = Vec::new();
+        let mut late_ack_count = 0;
+        for (seqnum, arrival) in statuses {
+            if let Some(arrival) = arrival {
+                if let Some(sent) = self.sent_by_seqnum.remove(&seqnum) {
+                    if sent.reported_lost {
+                        late_ack_count += 1;
+                    }
+                    acks.push(Ack {
+                        // seqnum,
+                        size: sent.size,
+                        departure: sent.departure,
+                        arrival,
+                        feedback_arrival,
+                        probe_cluster_id: sent.probe_cluster_id,
+                    });
+                }
+            } else if let Some(mut sent) = self.sent_by_seqnum.remove(&seqnum) {
+                // Feedback can repeat the status of a packet, but it's only lost once.
+                if !sent.reported_lost {
+                    losses.push(Loss {
+                        size: sent.size,
+                        departure: sent.departure,
+                        feedback_arrival,
+                    });
+                    sent.reported_lost = true;
+                }
+                self.sent_by_seqnum.insert(seqnum, sent, feedback_arrival);
+            }
+        }
+        (acks, losses, late_ack_count)
     }
 }
 
@@ -293,9 +359,23 @@ fn duration_from_ticks(ticks: u64, micros_per_tick: u16) -> Duration {
 }
 
 pub fn read_feedback(
-    mut payload: &[u8],
+    payload: &[u8],
     max_seqnum: &mut FullSequenceNumber,
 ) -> Option<(u8, Vec<(FullSequenceNumber, RemoteInstant)>)> {
+    let (feedback_seqnum, statuses) = read_feedback_statuses(payload, max_seqnum)?;
+    let arrivals = statuses
+        .into_iter()
+        .filter_map(|(seqnum, arrival)| Some((seqnum, arrival?)))
+        .collect();
+    Some((feedback_seqnum, arrivals))
+}
+
+// Like read_feedback, but also includes the seqnums that were not received (with no arrival).
+#[allow(clippy::type_complexity)]
+fn read_feedback_statuses(
+    mut payload: &[u8],
+    max_seqnum: &mut FullSequenceNumber,
+) -> Option<(u8, Vec<(FullSequenceNumber, Option<RemoteInstant>)>)> {
     let _ssrc = payload.read_u32::<BE>().ok()?;
     let base_seqnum = payload.read_u16::<BE>().ok()?;
     let base_seqnum: FullSequenceNumber = expand_seqnum(base_seqnum, max_seqnum);
@@ -311,7 +391,7 @@ pub fn read_feedback(
         status_chunks.push(status_chunk);
         status_chunks_sum_count += status_chunk.len();
     }
-    let mut arrivals = Vec::new();
+    let mut statuses = Vec::new();
     let mut arrivals_sum_delta_ticks: i32 = 0;
     for (seqnum, status) in (base_seqnum..(base_seqnum + status_count as u64))
         .zip(status_chunks.iter().copied().flatten())
@@ -332,10 +412,12 @@ pub fn read_feedback(
                 * u64::from(reference_time_ticks) as i64)
                 + (MICROS_PER_DELTA_TICK as i64 * arrivals_sum_delta_ticks as i64))
                 as u64;
-            arrivals.push((seqnum, RemoteInstant::from_micros(arrival_micros)));
+            statuses.push((seqnum, Some(RemoteInstant::from_micros(arrival_micros))));
+        } else {
+            statuses.push((seqnum, None));
         }
     }
-    Some((feedback_seqnum, arrivals))
+    Some((feedback_seqnum, statuses))
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
@@ -617,11 +699,11 @@ mod test {
         let bytes = DataSize::from_bytes;
 
         let mut sender = Sender::new(now);
-        sender.remember_sent(1, bytes(1201), at(10));
-        sender.remember_sent(2, bytes(1202), at(20));
-        sender.remember_sent(3, bytes(1203), at(30));
-        sender.remember_sent(4, bytes(1204), at(40));
-        sender.remember_sent(5, bytes(1205), at(50));
+        sender.remember_sent(1, bytes(1201), None, at(10));
+        sender.remember_sent(2, bytes(1202), None, at(20));
+        sender.remember_sent(3, bytes(1203), Some(7), at(30));
+        sender.remember_sent(4, bytes(1204), None, at(40));
+        sender.remember_sent(5, bytes(1205), None, at(50));
 
         let mut next_feedback_seqnum = 2;
         let feedback = collect_feedback(write_feedback(
@@ -631,39 +713,51 @@ mod test {
             vec![(1, at(15)), (2, at(38)), (3, at(37)), (5, at(59))].into_iter(),
         ));
         assert_eq!(
-            vec![
-                Ack {
-                    size: bytes(1201),
-                    departure: at(10),
-                    arrival: RemoteInstant::from_millis(0),
-                    feedback_arrival: at(50),
-                },
-                Ack {
-                    size: bytes(1202),
-                    departure: at(20),
-                    arrival: RemoteInstant::from_millis(23),
-                    feedback_arrival: at(50),
-                },
-                Ack {
-                    size: bytes(1203),
-                    departure: at(30),
-                    arrival: RemoteInstant::from_millis(22),
-                    feedback_arrival: at(50),
-                },
-                Ack {
-                    size: bytes(1205),
-                    departure: at(50),
-                    arrival: RemoteInstant::from_millis(44),
+            (
+                vec![
+                    Ack {
+                        size: bytes(1201),
+                        departure: at(10),
+                        arrival: RemoteInstant::from_millis(0),
+                        feedback_arrival: at(50),
+                        probe_cluster_id: None,
+                    },
+                    Ack {
+                        size: bytes(1202),
+                        departure: at(20),
+                        arrival: RemoteInstant::from_millis(23),
+                        feedback_arrival: at(50),
+                        probe_cluster_id: None,
+                    },
+                    Ack {
+                        size: bytes(1203),
+                        departure: at(30),
+                        arrival: RemoteInstant::from_millis(22),
+                        feedback_arrival: at(50),
+                        probe_cluster_id: Some(7),
+                    },
+                    Ack {
+                        size: bytes(1205),
+                        departure: at(50),
+                        arrival: RemoteInstant::from_millis(44),
+                        feedback_arrival: at(50),
+                        probe_cluster_id: None,
+                    }
+                ],
+                vec![Loss {
+                    size: bytes(1204),
+                    departure: at(40),
                     feedback_arrival: at(50),
-                }
-            ],
+                }],
+                0
+            ),
             sender.process_feedback_and_correlate_acks(feedback.iter(), at(50))
         );
         assert_eq!(3, next_feedback_seqnum);
 
         // Way past the expiration time for seqnum 4.
-        sender.remember_sent(6, bytes(1206), at(20000));
-        sender.remember_sent(7, bytes(1207), at(30000));
+        sender.remember_sent(6, bytes(1206), None, at(20000));
+        sender.remember_sent(7, bytes(1207), None, at(30000));
         let feedback = collect_feedback(write_feedback(
             1000,
             &mut next_feedback_seqnum,
@@ -674,11 +768,72 @@ mod test {
             0,
             sender
                 .process_feedback_and_correlate_acks(feedback.iter(), at(20000))
+                .0
                 .len()
         );
         assert_eq!(4, next_feedback_seqnum);
     }
 
+    #[test]
+    fn test_tcc_sender_late_ack() {
+        let now = Instant::now();
+        let at = |millis| now + Duration::from_millis(millis);
+        let bytes = DataSize::from_bytes;
+
+        let mut sender = Sender::new(now);
+        sender.remember_sent(1, bytes(1201), None, at(10));
+        sender.remember_sent(2, bytes(1202), None, at(20));
+        sender.remember_sent(3, bytes(1203), None, at(30));
+
+        let mut next_feedback_seqnum = 1;
+        let feedback = collect_feedback(write_feedback(
+            1000,
+            &mut next_feedback_seqnum,
+            at(15),
+            vec![(1, at(15)), (3, at(35))].into_iter(),
+        ));
+        let (acks, losses, late_ack_count) =
+            sender.process_feedback_and_correlate_acks(feedback.iter(), at(50));
+        assert_eq!(2, acks.len());
+        assert_eq!(
+            vec![Loss {
+                size: bytes(1202),
+                departure: at(20),
+                feedback_arrival: at(50),
+            }],
+            losses
+        );
+        assert_eq!(0, late_ack_count);
+
+        // Reporting it as not received again doesn't count it as lost again.
+        assert_eq!(
+            (vec![], vec![], 0),
+            sender.correlate_statuses(vec![(2, None)], at(60))
+        );
+
+        // When it does arrive, it's acked, and counted as late.
+        let feedback = collect_feedback(write_feedback(
+            1000,
+            &mut next_feedback_seqnum,
+            at(15),
+            vec![(2, at(65))].into_iter(),
+        ));
+        assert_eq!(
+            (
+                vec![Ack {
+                    size: bytes(1202),
+                    departure: at(20),
+                    arrival: RemoteInstant::from_millis(50),
+                    feedback_arrival: at(70),
+                    probe_cluster_id: None,
+                }],
+                vec![],
+                1
+            ),
+            sender.process_feedback_and_correlate_acks(feedback.iter(), at(70))
+        );
+    }
+
     #[test]
     fn test_write_feedback_seqnum_overflow() {
         let now = Instant::now();
@@ -20,7 +20,7 @@ use axum::{
     extract::{MatchedPath, State},
     middleware::{self, Next},
     response::IntoResponse,
-    routing::get,
+    routing::{get, post, put},
     Extension, Router,
 };
 use http::{header, Method, Request, StatusCode};
@@ -46,6 +46,7 @@ impl From<FrontendError> for StatusCode {
         match err {
             FrontendError::CallNotFound => StatusCode::NOT_FOUND,
             FrontendError::NoPermissionToCreateCall => StatusCode::FORBIDDEN,
+            FrontendError::ClientNotFound => StatusCode::NOT_FOUND,
             FrontendError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -356,6 +357,24 @@ fn app(frontend: Arc<Frontend>) -> Router {
             "/v2/conference/:room_id/participants",
             get(v2::get_participants_by_room_id).put(v2::join_by_room_id),
         )
+        .route(
+            "/v2/conference/participants/ice-restart",
+            post(v2::restart_ice),
+        )
+        .route(
+            "/v2/conference/:room_id/participants/ice-restart",
+            post(v2::restart_ice_by_room_id),
+        )
+        .route("/v2/conference/participants/rekey", post(v2::rekey))
+        .route(
+            "/v2/conference/:room_id/participants/rekey",
+            post(v2::rekey_by_room_id),
+        )
+        .route("/v2/conference/spotlight", put(v2::set_spotlight))
+        .route(
+            "/v2/conference/:room_id/spotlight",
+            put(v2::set_spotlight_by_room_id),
+        )
         .layer(
             ServiceBuilder::new()
                 .layer(middleware::from_fn_with_state(frontend.clone(), metrics))
@@ -22,9 +22,10 @@ use zkgroup::call_links::CallLinkAuthCredentialPresentation;
 use crate::{
     api::call_links::{verify_auth_credential_against_zkparams, RoomId},
     authenticator::UserAuthorization,
+    backend,
     frontend::{Frontend, JoinRequestWrapper, UserId},
     metrics::Timer,
-    storage::CallLinkRestrictions,
+    storage::{CallLinkRestrictions, CallRecord},
 };
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -53,6 +54,15 @@ pub struct JoinRequest {
     pub ice_ufrag: String,
     pub dhe_public_key: String,
     pub hkdf_extra_info: Option<String>,
+    /// These are passed on to the backend as is.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub congestion_control_feedback: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub srtp_protection_profile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video_metadata_source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receive_mixed_audio: Option<bool>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -62,6 +72,8 @@ pub struct JoinResponse {
     pub port: u16,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub port_tcp: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port_tls: Option<u16>,
     pub ip: String, // TODO remove once all clients use 'ips' field instead
     pub ips: Vec<String>,
     pub ice_ufrag: String,
@@ -72,6 +84,44 @@ pub struct JoinResponse {
     pub era_id: String,
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IceRestartRequest {
+    pub demux_id: u32,
+    pub ice_ufrag: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IceRestartResponse {
+    pub ice_ufrag: String,
+    pub ice_pwd: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RekeyRequest {
+    pub demux_id: u32,
+    pub dhe_public_key: String,
+    pub hkdf_extra_info: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RekeyResponse {
+    pub dhe_public_key: String,
+}
+
+#[serde_as]
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SpotlightRequest {
+    #[serde_as(as = "serde_with::base64::Base64")]
+    pub admin_passkey: Vec<u8>,
+    /// Most important first. An empty list clears the spotlight.
+    pub demux_ids: Vec<u32>,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ErrorResponse<'a> {
     pub reason: &'a str,
@@ -308,6 +358,10 @@ pub async fn join(
                 region,
                 restrictions,
                 is_admin,
+                congestion_control_feedback: request.congestion_control_feedback,
+                srtp_protection_profile: request.srtp_protection_profile,
+                video_metadata_source: request.video_metadata_source,
+                receive_mixed_audio: request.receive_mixed_audio,
             },
         )
         .await?;
@@ -317,6 +371,7 @@ pub async fn join(
         demux_id: response.demux_id,
         port: response.port,
         port_tcp: response.port_tcp,
+        port_tls: response.port_tls,
         ip: response.ip,
         ips: response.ips,
         ice_ufrag: response.ice_ufrag,
@@ -328,6 +383,247 @@ pub async fn join(
     .into_response())
 }
 
+/// Looks up the call and the requesting user's ID for requests about one of the user's own
+/// clients, using either group or call link authorization.
+async fn get_call_and_user_id(
+    frontend: &Frontend,
+    group_auth: Option<Extension<UserAuthorization>>,
+    call_links_auth: Option<Extension<Arc<CallLinkAuthCredentialPresentation>>>,
+    room_id: Option<TypedHeader<RoomId>>,
+) -> Result<(CallRecord, UserId), axum::response::Response> {
+    match (group_auth, call_links_auth, room_id) {
+        (Some(Extension(user_authorization)), None, None) => Ok((
+            frontend
+                .get_call_record(&user_authorization.room_id)
+                .await
+                .map_err(|err| StatusCode::from(err).into_response())?,
+            user_authorization.user_id,
+        )),
+        (None, Some(Extension(auth_credential)), Some(TypedHeader(room_id))) => {
+            let room_id = room_id.into();
+
+            match frontend.storage.get_call_link_and_record(&room_id).await {
+                Ok((Some(state), call)) => {
+                    verify_auth_credential_against_zkparams(&auth_credential, &state, frontend)
+                        .map_err(IntoResponse::into_response)?;
+                    let user_id = auth_credential.get_user_id();
+                    // Encode as hex for compatability with existing user ids
+                    let user_id = bincode::serialize(&user_id).unwrap().encode_hex();
+                    Ok((
+                        call.ok_or_else(|| StatusCode::NOT_FOUND.into_response())?,
+                        user_id,
+                    ))
+                }
+                Ok((None, _)) => Err(not_found("invalid")),
+                Err(err) => {
+                    error!("get_call_and_user_id: {err}");
+                    Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+                }
+            }
+        }
+        // wrong auth type for call link
+        (_, None, Some(_)) => Err(StatusCode::UNAUTHORIZED.into_response()),
+        _ => Err(StatusCode::BAD_REQUEST.into_response()),
+    }
+}
+
+/// Handler for the POST /conference/:room_id/participants/ice-restart route.
+pub async fn restart_ice_by_room_id(
+    frontend: State<Arc<Frontend>>,
+    maybe_auth_credential: Option<Extension<Arc<CallLinkAuthCredentialPresentation>>>,
+    Path(room_id): Path<RoomId>,
+    original_uri: OriginalUri,
+    request: Json<IceRestartRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    restart_ice(
+        frontend,
+        None,
+        maybe_auth_credential,
+        Some(axum::TypedHeader(room_id)),
+        original_uri,
+        request,
+    )
+    .await
+}
+
+/// Handler for the POST /conference/participants/ice-restart route.
+///
+/// Gives a client that has already joined new ICE credentials.
+pub async fn restart_ice(
+    State(frontend): State<Arc<Frontend>>,
+    group_auth: Option<Extension<UserAuthorization>>,
+    call_links_auth: Option<Extension<Arc<CallLinkAuthCredentialPresentation>>>,
+    room_id: Option<TypedHeader<RoomId>>,
+    OriginalUri(original_uri): OriginalUri,
+    Json(request): Json<IceRestartRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    trace!("restart_ice: ");
+    // Do some simple request verification.
+    if request.ice_ufrag.is_empty() {
+        warn!("restart_ice: ice_ufrag is empty");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let (call, user_id) =
+        match get_call_and_user_id(&frontend, group_auth, call_links_auth, room_id).await {
+            Ok(call_and_user_id) => call_and_user_id,
+            Err(response) => return Ok(response),
+        };
+
+    if let Some(redirect_uri) = frontend.get_redirect_uri(&call.backend_region, &original_uri) {
+        return temporary_redirect(&redirect_uri);
+    }
+
+    let response = frontend
+        .restart_client_ice(
+            &user_id,
+            &call,
+            request.demux_id,
+            backend::IceRestartRequest {
+                ice_ufrag: request.ice_ufrag,
+            },
+        )
+        .await?;
+
+    Ok(Json(IceRestartResponse {
+        ice_ufrag: response.ice_ufrag,
+        ice_pwd: response.ice_pwd,
+    })
+    .into_response())
+}
+
+/// Handler for the POST /conference/:room_id/participants/rekey route.
+pub async fn rekey_by_room_id(
+    frontend: State<Arc<Frontend>>,
+    maybe_auth_credential: Option<Extension<Arc<CallLinkAuthCredentialPresentation>>>,
+    Path(room_id): Path<RoomId>,
+    original_uri: OriginalUri,
+    request: Json<RekeyRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    rekey(
+        frontend,
+        None,
+        maybe_auth_credential,
+        Some(axum::TypedHeader(room_id)),
+        original_uri,
+        request,
+    )
+    .await
+}
+
+/// Handler for the POST /conference/participants/rekey route.
+///
+/// Gives a client that has already joined new SRTP keys from a new DHE exchange.
+pub async fn rekey(
+    State(frontend): State<Arc<Frontend>>,
+    group_auth: Option<Extension<UserAuthorization>>,
+    call_links_auth: Option<Extension<Arc<CallLinkAuthCredentialPresentation>>>,
+    room_id: Option<TypedHeader<RoomId>>,
+    OriginalUri(original_uri): OriginalUri,
+    Json(request): Json<RekeyRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    trace!("rekey: ");
+    // Do some simple request verification.
+    if request.dhe_public_key.is_empty() {
+        warn!("rekey: dhe_public_key is empty");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let (call, user_id) =
+        match get_call_and_user_id(&frontend, group_auth, call_links_auth, room_id).await {
+            Ok(call_and_user_id) => call_and_user_id,
+            Err(response) => return Ok(response),
+        };
+
+    if let Some(redirect_uri) = frontend.get_redirect_uri(&call.backend_region, &original_uri) {
+        return temporary_redirect(&redirect_uri);
+    }
+
+    let dhe_public_key = frontend
+        .rekey_client(
+            &user_id,
+            &call,
+            request.demux_id,
+            backend::RekeyRequest {
+                dhe_public_key: request.dhe_public_key,
+                hkdf_extra_info: request.hkdf_extra_info,
+            },
+        )
+        .await?;
+
+    Ok(Json(RekeyResponse { dhe_public_key }).into_response())
+}
+
+/// Handler for the PUT /conference/:room_id/spotlight route.
+pub async fn set_spotlight_by_room_id(
+    frontend: State<Arc<Frontend>>,
+    maybe_auth_credential: Option<Extension<Arc<CallLinkAuthCredentialPresentation>>>,
+    Path(room_id): Path<RoomId>,
+    original_uri: OriginalUri,
+    request: Json<SpotlightRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    set_spotlight(
+        frontend,
+        None,
+        maybe_auth_credential,
+        Some(axum::TypedHeader(room_id)),
+        original_uri,
+        request,
+    )
+    .await
+}
+
+/// Handler for the PUT /conference/spotlight route.
+///
+/// Replaces the senders whose video every client in the call sees first. Only call links
+/// have admins, so only an admin of a call link may do this.
+pub async fn set_spotlight(
+    State(frontend): State<Arc<Frontend>>,
+    group_auth: Option<Extension<UserAuthorization>>,
+    call_links_auth: Option<Extension<Arc<CallLinkAuthCredentialPresentation>>>,
+    room_id: Option<TypedHeader<RoomId>>,
+    OriginalUri(original_uri): OriginalUri,
+    Json(request): Json<SpotlightRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    trace!("set_spotlight: ");
+
+    let call = match (group_auth, call_links_auth, room_id) {
+        (Some(_), None, None) => return Err(StatusCode::FORBIDDEN), // group calls have no admins
+        (None, Some(Extension(auth_credential)), Some(TypedHeader(room_id))) => {
+            let room_id = room_id.into();
+
+            match frontend.storage.get_call_link_and_record(&room_id).await {
+                Ok((Some(state), call)) => {
+                    verify_auth_credential_against_zkparams(&auth_credential, &state, &frontend)?;
+
+                    if state.revoked || state.expiration < SystemTime::now() {
+                        return Ok(not_found("expired"));
+                    }
+                    if !bool::from(state.admin_passkey.ct_eq(&request.admin_passkey)) {
+                        return Err(StatusCode::FORBIDDEN);
+                    }
+                    call.ok_or(StatusCode::NOT_FOUND)?
+                }
+                Ok((None, _)) => return Ok(not_found("invalid")),
+                Err(err) => {
+                    error!("set_spotlight_by_room_id: {err}");
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                }
+            }
+        }
+        (_, None, Some(_)) => return Err(StatusCode::UNAUTHORIZED), // wrong auth type for call link
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    if let Some(redirect_uri) = frontend.get_redirect_uri(&call.backend_region, &original_uri) {
+        return temporary_redirect(&redirect_uri);
+    }
+
+    frontend.set_spotlight(&call, request.demux_ids).await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
 #[cfg(test)]
 mod api_server_v2_tests {
     use super::*;
@@ -460,6 +756,10 @@ mod api_server_v2_tests {
             ice_ufrag: CLIENT_ICE_UFRAG.to_string(),
             dhe_public_key: CLIENT_DHE_PUBLIC_KEY.to_string(),
             hkdf_extra_info: None,
+            congestion_control_feedback: None,
+            srtp_protection_profile: None,
+            video_metadata_source: None,
+            receive_mixed_audio: None,
         }
     }
 
@@ -469,6 +769,10 @@ mod api_server_v2_tests {
                 ice_ufrag: CLIENT_ICE_UFRAG.to_string(),
                 dhe_public_key: CLIENT_DHE_PUBLIC_KEY.to_string(),
                 hkdf_extra_info: None,
+                congestion_control_feedback: None,
+                srtp_protection_profile: None,
+                video_metadata_source: None,
+                receive_mixed_audio: None,
                 admin_passkey: Some(passkey.into()),
             })
             .unwrap()
@@ -478,6 +782,10 @@ mod api_server_v2_tests {
                 ice_ufrag: CLIENT_ICE_UFRAG.to_string(),
                 dhe_public_key: CLIENT_DHE_PUBLIC_KEY.to_string(),
                 hkdf_extra_info: None,
+                congestion_control_feedback: None,
+                srtp_protection_profile: None,
+                video_metadata_source: None,
+                receive_mixed_audio: None,
             })
             .unwrap()
         }
@@ -825,6 +1133,10 @@ mod api_server_v2_tests {
                     hkdf_extra_info: None,
                     region: LOCAL_REGION.to_string(),
                     is_admin: false,
+                    congestion_control_feedback: None,
+                    srtp_protection_profile: None,
+                    video_metadata_source: None,
+                    receive_mixed_audio: None,
                 }),
             )
             .once()
@@ -835,6 +1147,7 @@ mod api_server_v2_tests {
                     ips: Some(vec!["127.0.0.1".to_string()]),
                     port: 8080,
                     port_tcp: Some(8080),
+                    port_tls: None,
                     ice_ufrag: BACKEND_ICE_UFRAG.to_string(),
                     ice_pwd: BACKEND_ICE_PWD.to_string(),
                     dhe_public_key: Some(BACKEND_DHE_PUBLIC_KEY.to_string()),
@@ -926,6 +1239,10 @@ mod api_server_v2_tests {
                     hkdf_extra_info: None,
                     region: LOCAL_REGION.to_string(),
                     is_admin: false,
+                    congestion_control_feedback: None,
+                    srtp_protection_profile: None,
+                    video_metadata_source: None,
+                    receive_mixed_audio: None,
                 }),
             )
             .once()
@@ -936,6 +1253,7 @@ mod api_server_v2_tests {
                     ips: Some(vec!["127.0.0.1".to_string()]),
                     port: 8080,
                     port_tcp: Some(8080),
+                    port_tls: None,
                     ice_ufrag: BACKEND_ICE_UFRAG.to_string(),
                     ice_pwd: BACKEND_ICE_PWD.to_string(),
                     dhe_public_key: Some(BACKEND_DHE_PUBLIC_KEY.to_string()),
@@ -982,9 +1300,9 @@ mod api_server_v2_tests {
         assert_eq!(&join_response.era_id, ERA_ID_1);
     }
 
-    /// Invoke the "PUT /v2/conference/participants" to join in the case where there is a call and backend is older and does not return ips.
+    /// Invoke the "PUT /v2/conference/participants" to join with media options, which are passed on to the backend.
     #[tokio::test]
-    async fn test_join_with_call_old_backend() {
+    async fn test_join_with_media_options() {
         let config = &CONFIG;
 
         // Create mocked dependencies with expectations.
@@ -1027,6 +1345,10 @@ mod api_server_v2_tests {
                     hkdf_extra_info: None,
                     region: LOCAL_REGION.to_string(),
                     is_admin: false,
+                    congestion_control_feedback: Some("rfc8888".to_string()),
+                    srtp_protection_profile: Some("AEAD_AES_256_GCM".to_string()),
+                    video_metadata_source: Some("dependency-descriptor".to_string()),
+                    receive_mixed_audio: Some(true),
                 }),
             )
             .once()
@@ -1034,9 +1356,10 @@ mod api_server_v2_tests {
             .returning(|_, _, _, _| {
                 Ok(backend::JoinResponse {
                     ip: "127.0.0.1".to_string(),
-                    ips: None,
+                    ips: Some(vec!["127.0.0.1".to_string()]),
                     port: 8080,
-                    port_tcp: None,
+                    port_tcp: Some(8080),
+                    port_tls: None,
                     ice_ufrag: BACKEND_ICE_UFRAG.to_string(),
                     ice_pwd: BACKEND_ICE_PWD.to_string(),
                     dhe_public_key: Some(BACKEND_DHE_PUBLIC_KEY.to_string()),
@@ -1049,7 +1372,13 @@ mod api_server_v2_tests {
         let app = app(frontend);
 
         // Create the request.
-        let join_request = create_join_request();
+        let join_request = JoinRequest {
+            congestion_control_feedback: Some("rfc8888".to_string()),
+            srtp_protection_profile: Some("AEAD_AES_256_GCM".to_string()),
+            video_metadata_source: Some("dependency-descriptor".to_string()),
+            receive_mixed_audio: Some(true),
+            ..create_join_request()
+        };
 
         let request = Request::builder()
             .method(http::Method::PUT)
@@ -1070,69 +1399,164 @@ mod api_server_v2_tests {
         let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
         let join_response: JoinResponse = serde_json::from_slice(&body).unwrap();
         assert_eq!(join_response.demux_id, DEMUX_ID_2);
-        assert_eq!(join_response.port, 8080);
-        assert_eq!(join_response.ip, "127.0.0.1".to_string());
-        assert_eq!(join_response.ips, vec!["127.0.0.1".to_string()]);
-        assert_eq!(join_response.ice_ufrag, BACKEND_ICE_UFRAG.to_string());
-        assert_eq!(join_response.ice_pwd, BACKEND_ICE_PWD.to_string());
-        assert_eq!(
-            join_response.dhe_public_key,
-            BACKEND_DHE_PUBLIC_KEY.to_string()
-        );
-        assert_eq!(&join_response.call_creator, USER_ID_1);
-        assert_eq!(&join_response.era_id, ERA_ID_1);
     }
 
-    /// Invoke the "PUT /v2/conference/participants" to join in the case where the call is
-    /// in a different region.
+    /// Invoke the "PUT /v2/conference/participants" to join in the case where there is a call and backend is older and does not return ips.
     #[tokio::test]
-    async fn test_join_with_call_in_different_region() {
+    async fn test_join_with_call_old_backend() {
         let config = &CONFIG;
 
         // Create mocked dependencies with expectations.
-        let mut storage = Box::new(MockStorage::new());
+        let storage = create_mocked_storage_for_join(&config.region, USER_ID_2);
         let mut backend = Box::new(MockBackend::new());
+        let mut id_generator = Box::new(MockIdGenerator::new());
+
+        // Create additional expectations.
         backend
             .expect_select_ip()
             .once()
             // Result<String, BackendError>
             .returning(|| Ok("127.0.0.1".to_string()));
-        let mut id_generator = Box::new(MockIdGenerator::new());
         id_generator
             .expect_get_random_era_id()
             .with(eq(16))
             .once()
             .returning(|_| ERA_ID_1.to_string());
-
-        let mut expected_call_record = create_call_record(GROUP_ID_1, &config.region);
-        expected_call_record.creator = USER_ID_2.to_string();
-        let resulting_call_record = create_call_record(GROUP_ID_1, ALT_REGION);
-        storage
-            .expect_get_or_add_call_record()
-            // call: CallRecord
-            .with(eq(expected_call_record))
+        id_generator
+            .expect_get_random_demux_id_and_endpoint_id()
+            // user_id: &str
+            .with(eq(USER_ID_2))
             .once()
-            // Result<CallRecord>
-            .return_once(move |_| Ok(resulting_call_record));
-
-        let frontend = create_frontend_with_id_generator(config, storage, backend, id_generator);
-
-        // Create an axum application.
-        let app = app(frontend);
+            // Result<(DemuxId, String), FrontendError>
+            .returning(|_| Ok((DEMUX_ID_2.try_into().unwrap(), ENDPOINT_ID_2.to_string())));
 
-        // Create the request.
-        let join_request = create_join_request();
+        let expected_demux_id: DemuxId = DEMUX_ID_2.try_into().unwrap();
 
-        let request = Request::builder()
-            .method(http::Method::PUT)
-            .uri("/v2/conference/participants")
-            .header(header::USER_AGENT, "test/user/agent")
-            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
-            .header(
-                header::AUTHORIZATION,
-                create_authorization_header_for_user(USER_ID_2),
-            )
-            .body(Body::from(serde_json::to_vec(&join_request).unwrap()))
+        backend
+            .expect_join()
+            // backend_address: &BackendAddress, call_id: &str, demux_id: DemuxId, join_request: &JoinRequest,
+            .with(
+                eq(backend::Address::try_from("127.0.0.1").unwrap()),
+                eq(ERA_ID_1),
+                eq(expected_demux_id),
+                eq(backend::JoinRequest {
+                    client_id: ENDPOINT_ID_2.to_string(),
+                    ice_ufrag: CLIENT_ICE_UFRAG.to_string(),
+                    dhe_public_key: Some(CLIENT_DHE_PUBLIC_KEY.to_string()),
+                    hkdf_extra_info: None,
+                    region: LOCAL_REGION.to_string(),
+                    is_admin: false,
+                    congestion_control_feedback: None,
+                    srtp_protection_profile: None,
+                    video_metadata_source: None,
+                    receive_mixed_audio: None,
+                }),
+            )
+            .once()
+            // Result<JoinResponse, BackendError>
+            .returning(|_, _, _, _| {
+                Ok(backend::JoinResponse {
+                    ip: "127.0.0.1".to_string(),
+                    ips: None,
+                    port: 8080,
+                    port_tcp: None,
+                    port_tls: None,
+                    ice_ufrag: BACKEND_ICE_UFRAG.to_string(),
+                    ice_pwd: BACKEND_ICE_PWD.to_string(),
+                    dhe_public_key: Some(BACKEND_DHE_PUBLIC_KEY.to_string()),
+                })
+            });
+
+        let frontend = create_frontend_with_id_generator(config, storage, backend, id_generator);
+
+        // Create an axum application.
+        let app = app(frontend);
+
+        // Create the request.
+        let join_request = create_join_request();
+
+        let request = Request::builder()
+            .method(http::Method::PUT)
+            .uri("/v2/conference/participants")
+            .header(header::USER_AGENT, "test/user/agent")
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(
+                header::AUTHORIZATION,
+                create_authorization_header_for_user(USER_ID_2),
+            )
+            .body(Body::from(serde_json::to_vec(&join_request).unwrap()))
+            .unwrap();
+
+        // Submit the request.
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let join_response: JoinResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(join_response.demux_id, DEMUX_ID_2);
+        assert_eq!(join_response.port, 8080);
+        assert_eq!(join_response.ip, "127.0.0.1".to_string());
+        assert_eq!(join_response.ips, vec!["127.0.0.1".to_string()]);
+        assert_eq!(join_response.ice_ufrag, BACKEND_ICE_UFRAG.to_string());
+        assert_eq!(join_response.ice_pwd, BACKEND_ICE_PWD.to_string());
+        assert_eq!(
+            join_response.dhe_public_key,
+            BACKEND_DHE_PUBLIC_KEY.to_string()
+        );
+        assert_eq!(&join_response.call_creator, USER_ID_1);
+        assert_eq!(&join_response.era_id, ERA_ID_1);
+    }
+
+    /// Invoke the "PUT /v2/conference/participants" to join in the case where the call is
+    /// in a different region.
+    #[tokio::test]
+    async fn test_join_with_call_in_different_region() {
+        let config = &CONFIG;
+
+        // Create mocked dependencies with expectations.
+        let mut storage = Box::new(MockStorage::new());
+        let mut backend = Box::new(MockBackend::new());
+        backend
+            .expect_select_ip()
+            .once()
+            // Result<String, BackendError>
+            .returning(|| Ok("127.0.0.1".to_string()));
+        let mut id_generator = Box::new(MockIdGenerator::new());
+        id_generator
+            .expect_get_random_era_id()
+            .with(eq(16))
+            .once()
+            .returning(|_| ERA_ID_1.to_string());
+
+        let mut expected_call_record = create_call_record(GROUP_ID_1, &config.region);
+        expected_call_record.creator = USER_ID_2.to_string();
+        let resulting_call_record = create_call_record(GROUP_ID_1, ALT_REGION);
+        storage
+            .expect_get_or_add_call_record()
+            // call: CallRecord
+            .with(eq(expected_call_record))
+            .once()
+            // Result<CallRecord>
+            .return_once(move |_| Ok(resulting_call_record));
+
+        let frontend = create_frontend_with_id_generator(config, storage, backend, id_generator);
+
+        // Create an axum application.
+        let app = app(frontend);
+
+        // Create the request.
+        let join_request = create_join_request();
+
+        let request = Request::builder()
+            .method(http::Method::PUT)
+            .uri("/v2/conference/participants")
+            .header(header::USER_AGENT, "test/user/agent")
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(
+                header::AUTHORIZATION,
+                create_authorization_header_for_user(USER_ID_2),
+            )
+            .body(Body::from(serde_json::to_vec(&join_request).unwrap()))
             .unwrap();
 
         // Submit the request.
@@ -1169,6 +1593,10 @@ mod api_server_v2_tests {
             ice_ufrag: CLIENT_ICE_UFRAG.to_string(),
             dhe_public_key: "".to_string(),
             hkdf_extra_info: None,
+            congestion_control_feedback: None,
+            srtp_protection_profile: None,
+            video_metadata_source: None,
+            receive_mixed_audio: None,
         };
 
         let request = Request::builder()
@@ -1382,6 +1810,324 @@ mod api_server_v2_tests {
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
+    /// Invoke the "POST /v2/conference/participants/ice-restart" for the user's own client.
+    #[tokio::test]
+    async fn test_restart_ice() {
+        let config = &CONFIG;
+
+        // Create mocked dependencies with expectations.
+        let storage = create_mocked_storage_with_call_for_region(config.region.to_string());
+        let mut backend = create_mocked_backend_two_calls();
+
+        let expected_demux_id: DemuxId = DEMUX_ID_1.try_into().unwrap();
+
+        backend
+            .expect_restart_ice()
+            // backend_address: &BackendAddress, call_id: &str, demux_id: DemuxId, ice_restart_request: &IceRestartRequest,
+            .with(
+                eq(backend::Address::try_from("127.0.0.1").unwrap()),
+                eq(ERA_ID_1),
+                eq(expected_demux_id),
+                eq(backend::IceRestartRequest {
+                    ice_ufrag: CLIENT_ICE_UFRAG.to_string(),
+                }),
+            )
+            .once()
+            // Result<IceRestartResponse, BackendError>
+            .returning(|_, _, _, _| {
+                Ok(backend::IceRestartResponse {
+                    ice_ufrag: BACKEND_ICE_UFRAG.to_string(),
+                    ice_pwd: BACKEND_ICE_PWD.to_string(),
+                })
+            });
+
+        let frontend = create_frontend(config, storage, backend);
+
+        // Create an axum application.
+        let app = app(frontend);
+
+        // Create the request.
+        let ice_restart_request = IceRestartRequest {
+            demux_id: DEMUX_ID_1,
+            ice_ufrag: CLIENT_ICE_UFRAG.to_string(),
+        };
+
+        let request = Request::builder()
+            .method(http::Method::POST)
+            .uri("/v2/conference/participants/ice-restart")
+            .header(header::USER_AGENT, "test/user/agent")
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(
+                header::AUTHORIZATION,
+                create_authorization_header_for_user(USER_ID_1),
+            )
+            .body(Body::from(
+                serde_json::to_vec(&ice_restart_request).unwrap(),
+            ))
+            .unwrap();
+
+        // Submit the request.
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let ice_restart_response: IceRestartResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(ice_restart_response.ice_ufrag, BACKEND_ICE_UFRAG);
+        assert_eq!(ice_restart_response.ice_pwd, BACKEND_ICE_PWD);
+    }
+
+    /// Invoke the "POST /v2/conference/participants/ice-restart" for another user's client.
+    #[tokio::test]
+    async fn test_restart_ice_other_users_client() {
+        let config = &CONFIG;
+
+        // Create mocked dependencies with expectations.
+        let storage = create_mocked_storage_with_call_for_region(config.region.to_string());
+        let backend = create_mocked_backend_two_calls();
+
+        let frontend = create_frontend(config, storage, backend);
+
+        // Create an axum application.
+        let app = app(frontend);
+
+        // Create the request.
+        let ice_restart_request = IceRestartRequest {
+            demux_id: DEMUX_ID_1,
+            ice_ufrag: CLIENT_ICE_UFRAG.to_string(),
+        };
+
+        let request = Request::builder()
+            .method(http::Method::POST)
+            .uri("/v2/conference/participants/ice-restart")
+            .header(header::USER_AGENT, "test/user/agent")
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(
+                header::AUTHORIZATION,
+                create_authorization_header_for_user(USER_ID_2),
+            )
+            .body(Body::from(
+                serde_json::to_vec(&ice_restart_request).unwrap(),
+            ))
+            .unwrap();
+
+        // Submit the request.
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// Invoke the "POST /v2/conference/participants/rekey" for the user's own client.
+    #[tokio::test]
+    async fn test_rekey() {
+        let config = &CONFIG;
+
+        // Create mocked dependencies with expectations.
+        let storage = create_mocked_storage_with_call_for_region(config.region.to_string());
+        let mut backend = create_mocked_backend_two_calls();
+
+        let expected_demux_id: DemuxId = DEMUX_ID_1.try_into().unwrap();
+
+        backend
+            .expect_rekey()
+            // backend_address: &BackendAddress, call_id: &str, demux_id: DemuxId, rekey_request: &RekeyRequest,
+            .with(
+                eq(backend::Address::try_from("127.0.0.1").unwrap()),
+                eq(ERA_ID_1),
+                eq(expected_demux_id),
+                eq(backend::RekeyRequest {
+                    dhe_public_key: CLIENT_DHE_PUBLIC_KEY.to_string(),
+                    hkdf_extra_info: None,
+                }),
+            )
+            .once()
+            // Result<RekeyResponse, BackendError>
+            .returning(|_, _, _, _| {
+                Ok(backend::RekeyResponse {
+                    dhe_public_key: BACKEND_DHE_PUBLIC_KEY.to_string(),
+                })
+            });
+
+        let frontend = create_frontend(config, storage, backend);
+
+        // Create an axum application.
+        let app = app(frontend);
+
+        // Create the request.
+        let rekey_request = RekeyRequest {
+            demux_id: DEMUX_ID_1,
+            dhe_public_key: CLIENT_DHE_PUBLIC_KEY.to_string(),
+            hkdf_extra_info: None,
+        };
+
+        let request = Request::builder()
+            .method(http::Method::POST)
+            .uri("/v2/conference/participants/rekey")
+            .header(header::USER_AGENT, "test/user/agent")
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(
+                header::AUTHORIZATION,
+                create_authorization_header_for_user(USER_ID_1),
+            )
+            .body(Body::from(serde_json::to_vec(&rekey_request).unwrap()))
+            .unwrap();
+
+        // Submit the request.
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let rekey_response: RekeyResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            rekey_response.dhe_public_key,
+            BACKEND_DHE_PUBLIC_KEY.to_string()
+        );
+    }
+
+    /// Invoke the "POST /v2/conference/participants/rekey" for another user's client.
+    #[tokio::test]
+    async fn test_rekey_other_users_client() {
+        let config = &CONFIG;
+
+        // Create mocked dependencies with expectations.
+        let storage = create_mocked_storage_with_call_for_region(config.region.to_string());
+        let backend = create_mocked_backend_two_calls();
+
+        let frontend = create_frontend(config, storage, backend);
+
+        // Create an axum application.
+        let app = app(frontend);
+
+        // Create the request.
+        let rekey_request = RekeyRequest {
+            demux_id: DEMUX_ID_1,
+            dhe_public_key: CLIENT_DHE_PUBLIC_KEY.to_string(),
+            hkdf_extra_info: None,
+        };
+
+        let request = Request::builder()
+            .method(http::Method::POST)
+            .uri("/v2/conference/participants/rekey")
+            .header(header::USER_AGENT, "test/user/agent")
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(
+                header::AUTHORIZATION,
+                create_authorization_header_for_user(USER_ID_2),
+            )
+            .body(Body::from(serde_json::to_vec(&rekey_request).unwrap()))
+            .unwrap();
+
+        // Submit the request.
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// Invoke the "PUT /v2/conference/:room_id/spotlight" as an admin of the call link.
+    #[tokio::test]
+    async fn test_call_link_set_spotlight_as_admin() {
+        let config = &CONFIG;
+
+        // Create mocked dependencies with expectations.
+        let mut storage = Box::new(MockStorage::new());
+        storage
+            .expect_get_call_link_and_record()
+            .with(eq(RoomId::from(ROOM_ID)))
+            .once()
+            .return_once(|_| {
+                Ok((
+                    Some(default_call_link_state()),
+                    Some(create_call_record(ROOM_ID, LOCAL_REGION)),
+                ))
+            });
+        let mut backend = Box::new(MockBackend::new());
+
+        backend
+            .expect_set_spotlight()
+            // backend_address: &BackendAddress, call_id: &str, spotlight_request: &SpotlightRequest,
+            .with(
+                eq(backend::Address::try_from("127.0.0.1").unwrap()),
+                eq(ERA_ID_1),
+                eq(backend::SpotlightRequest {
+                    demux_ids: vec![DEMUX_ID_1],
+                }),
+            )
+            .once()
+            // Result<(), BackendError>
+            .returning(|_, _, _| Ok(()));
+
+        let frontend = create_frontend(config, storage, backend);
+
+        // Create an axum application.
+        let app = app(frontend.clone());
+
+        // Create the request.
+        let spotlight_request = SpotlightRequest {
+            admin_passkey: ADMIN_PASSKEY.into(),
+            demux_ids: vec![DEMUX_ID_1],
+        };
+
+        let request = Request::builder()
+            .method(http::Method::PUT)
+            .uri(format!("/v2/conference/{}/spotlight", ROOM_ID))
+            .header(header::USER_AGENT, "test/user/agent")
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(
+                header::AUTHORIZATION,
+                create_call_links_authorization_header_for_user(&frontend, CALL_LINKS_USER_ID_1),
+            )
+            .body(Body::from(serde_json::to_vec(&spotlight_request).unwrap()))
+            .unwrap();
+
+        // Submit the request.
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    /// Invoke the "PUT /v2/conference/:room_id/spotlight" with the wrong admin passkey.
+    #[tokio::test]
+    async fn test_call_link_set_spotlight_wrong_admin() {
+        let config = &CONFIG;
+
+        // Create mocked dependencies with expectations.
+        let mut storage = Box::new(MockStorage::new());
+        storage
+            .expect_get_call_link_and_record()
+            .with(eq(RoomId::from(ROOM_ID)))
+            .once()
+            .return_once(|_| {
+                Ok((
+                    Some(default_call_link_state()),
+                    Some(create_call_record(ROOM_ID, LOCAL_REGION)),
+                ))
+            });
+        let backend = create_mocked_backend_unused();
+
+        let frontend = create_frontend(config, storage, backend);
+
+        // Create an axum application.
+        let app = app(frontend.clone());
+
+        // Create the request.
+        let spotlight_request = SpotlightRequest {
+            admin_passkey: b"joshua".to_vec(),
+            demux_ids: vec![DEMUX_ID_1],
+        };
+
+        let request = Request::builder()
+            .method(http::Method::PUT)
+            .uri(format!("/v2/conference/{}/spotlight", ROOM_ID))
+            .header(header::USER_AGENT, "test/user/agent")
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(
+                header::AUTHORIZATION,
+                create_call_links_authorization_header_for_user(&frontend, CALL_LINKS_USER_ID_1),
+            )
+            .body(Body::from(serde_json::to_vec(&spotlight_request).unwrap()))
+            .unwrap();
+
+        // Submit the request.
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
     /// Invoke the "GET /v2/conference/:room_id/participants" in the case where there is no call.
     #[tokio::test]
     async fn test_call_link_get_with_no_call() {
@@ -1980,6 +2726,10 @@ mod api_server_v2_tests {
                     hkdf_extra_info: None,
                     region: LOCAL_REGION.to_string(),
                     is_admin: false,
+                    congestion_control_feedback: None,
+                    srtp_protection_profile: None,
+                    video_metadata_source: None,
+                    receive_mixed_audio: None,
                 }),
             )
             .once()
@@ -1990,6 +2740,7 @@ mod api_server_v2_tests {
                     ips: Some(vec!["127.0.0.1".to_string()]),
                     port: 8080,
                     port_tcp: Some(8080),
+                    port_tls: None,
                     ice_ufrag: BACKEND_ICE_UFRAG.to_string(),
                     ice_pwd: BACKEND_ICE_PWD.to_string(),
                     dhe_public_key: Some(BACKEND_DHE_PUBLIC_KEY.to_string()),
@@ -2080,6 +2831,10 @@ mod api_server_v2_tests {
                     hkdf_extra_info: None,
                     region: LOCAL_REGION.to_string(),
                     is_admin: false,
+                    congestion_control_feedback: None,
+                    srtp_protection_profile: None,
+                    video_metadata_source: None,
+                    receive_mixed_audio: None,
                 }),
             )
             .once()
@@ -2090,6 +2845,7 @@ mod api_server_v2_tests {
                     ips: Some(vec!["127.0.0.1".to_string()]),
                     port: 8080,
                     port_tcp: Some(8080),
+                    port_tls: None,
                     ice_ufrag: BACKEND_ICE_UFRAG.to_string(),
                     ice_pwd: BACKEND_ICE_PWD.to_string(),
                     dhe_public_key: Some(BACKEND_DHE_PUBLIC_KEY.to_string()),
@@ -2281,6 +3037,10 @@ mod api_server_v2_tests {
                     hkdf_extra_info: None,
                     region: LOCAL_REGION.to_string(),
                     is_admin: true,
+                    congestion_control_feedback: None,
+                    srtp_protection_profile: None,
+                    video_metadata_source: None,
+                    receive_mixed_audio: None,
                 }),
             )
             .once()
@@ -2291,6 +3051,7 @@ mod api_server_v2_tests {
                     ips: Some(vec!["127.0.0.1".to_string()]),
                     port: 8080,
                     port_tcp: Some(8080),
+                    port_tls: None,
                     ice_ufrag: BACKEND_ICE_UFRAG.to_string(),
                     ice_pwd: BACKEND_ICE_PWD.to_string(),
                     dhe_public_key: Some(BACKEND_DHE_PUBLIC_KEY.to_string()),
@@ -2383,6 +3144,10 @@ mod api_server_v2_tests {
                     hkdf_extra_info: None,
                     region: LOCAL_REGION.to_string(),
                     is_admin: false,
+                    congestion_control_feedback: None,
+                    srtp_protection_profile: None,
+                    video_metadata_source: None,
+                    receive_mixed_audio: None,
                 }),
             )
             .once()
@@ -2393,6 +3158,7 @@ mod api_server_v2_tests {
                     ips: Some(vec!["127.0.0.1".to_string()]),
                     port: 8080,
                     port_tcp: Some(8080),
+                    port_tls: None,
                     ice_ufrag: BACKEND_ICE_UFRAG.to_string(),
                     ice_pwd: BACKEND_ICE_PWD.to_string(),
                     dhe_public_key: Some(BACKEND_DHE_PUBLIC_KEY.to_string()),
@@ -2694,6 +3460,10 @@ mod api_server_v2_tests {
             ice_ufrag: CLIENT_ICE_UFRAG.to_string(),
             dhe_public_key: "".to_string(),
             hkdf_extra_info: None,
+            congestion_control_feedback: None,
+            srtp_protection_profile: None,
+            video_metadata_source: None,
+            receive_mixed_audio: None,
         };
         let join_request = serde_json::to_vec(&join_request).unwrap();
 
@@ -3542,6 +4312,10 @@ mod api_server_v2_tests {
                     hkdf_extra_info: None,
                     region: LOCAL_REGION.to_string(),
                     is_admin: false,
+                    congestion_control_feedback: None,
+                    srtp_protection_profile: None,
+                    video_metadata_source: None,
+                    receive_mixed_audio: None,
                 }),
             )
             .once()
@@ -3552,6 +4326,7 @@ mod api_server_v2_tests {
                     ips: Some(vec!["127.0.0.1".to_string()]),
                     port: 8080,
                     port_tcp: Some(8080),
+                    port_tls: None,
                     ice_ufrag: BACKEND_ICE_UFRAG.to_string(),
                     ice_pwd: BACKEND_ICE_PWD.to_string(),
                     dhe_public_key: Some(BACKEND_DHE_PUBLIC_KEY.to_string()),
@@ -3642,6 +4417,10 @@ mod api_server_v2_tests {
                     hkdf_extra_info: None,
                     region: LOCAL_REGION.to_string(),
                     is_admin: false,
+                    congestion_control_feedback: None,
+                    srtp_protection_profile: None,
+                    video_metadata_source: None,
+                    receive_mixed_audio: None,
                 }),
             )
             .once()
@@ -3652,6 +4431,7 @@ mod api_server_v2_tests {
                     ips: Some(vec!["127.0.0.1".to_string()]),
                     port: 8080,
                     port_tcp: Some(8080),
+                    port_tls: None,
                     ice_ufrag: BACKEND_ICE_UFRAG.to_string(),
                     ice_pwd: BACKEND_ICE_PWD.to_string(),
                     dhe_public_key: Some(BACKEND_DHE_PUBLIC_KEY.to_string()),
@@ -3840,6 +4620,10 @@ mod api_server_v2_tests {
                     hkdf_extra_info: None,
                     region: LOCAL_REGION.to_string(),
                     is_admin: true,
+                    congestion_control_feedback: None,
+                    srtp_protection_profile: None,
+                    video_metadata_source: None,
+                    receive_mixed_audio: None,
                 }),
             )
             .once()
@@ -3850,6 +4634,7 @@ mod api_server_v2_tests {
                     ips: Some(vec!["127.0.0.1".to_string()]),
                     port: 8080,
                     port_tcp: Some(8080),
+                    port_tls: None,
                     ice_ufrag: BACKEND_ICE_UFRAG.to_string(),
                     ice_pwd: BACKEND_ICE_PWD.to_string(),
                     dhe_public_key: Some(BACKEND_DHE_PUBLIC_KEY.to_string()),
@@ -3941,6 +4726,10 @@ mod api_server_v2_tests {
                     hkdf_extra_info: None,
                     region: LOCAL_REGION.to_string(),
                     is_admin: false,
+                    congestion_control_feedback: None,
+                    srtp_protection_profile: None,
+                    video_metadata_source: None,
+                    receive_mixed_audio: None,
                 }),
             )
             .once()
@@ -3951,6 +4740,7 @@ mod api_server_v2_tests {
                     ips: Some(vec!["127.0.0.1".to_string()]),
                     port: 8080,
                     port_tcp: Some(8080),
+                    port_tls: None,
                     ice_ufrag: BACKEND_ICE_UFRAG.to_string(),
                     ice_pwd: BACKEND_ICE_PWD.to_string(),
                     dhe_public_key: Some(BACKEND_DHE_PUBLIC_KEY.to_string()),
@@ -4246,6 +5036,10 @@ mod api_server_v2_tests {
             ice_ufrag: CLIENT_ICE_UFRAG.to_string(),
             dhe_public_key: "".to_string(),
             hkdf_extra_info: None,
+            congestion_control_feedback: None,
+            srtp_protection_profile: None,
+            video_metadata_source: None,
+            receive_mixed_audio: None,
         };
         let join_request = serde_json::to_vec(&join_request).unwrap();
 
@@ -80,6 +80,23 @@ pub struct JoinRequest {
     pub region: String,
     #[serde(rename = "isAdmin")]
     pub is_admin: bool,
+    #[serde(
+        rename = "congestionControlFeedback",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub congestion_control_feedback: Option<String>,
+    #[serde(
+        rename = "srtpProtectionProfile",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub srtp_protection_profile: Option<String>,
+    #[serde(
+        rename = "videoMetadataSource",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub video_metadata_source: Option<String>,
+    #[serde(rename = "receiveMixedAudio", skip_serializing_if = "Option::is_none")]
+    pub receive_mixed_audio: Option<bool>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -92,6 +109,8 @@ pub struct JoinResponse {
     pub port: u16,
     #[serde(rename = "serverPortTcp", default)]
     pub port_tcp: Option<u16>,
+    #[serde(rename = "serverPortTls", default)]
+    pub port_tls: Option<u16>,
     #[serde(rename = "serverIceUfrag")]
     pub ice_ufrag: String,
     #[serde(rename = "serverIcePwd")]
@@ -100,6 +119,40 @@ pub struct JoinResponse {
     pub dhe_public_key: Option<String>,
 }
 
+#[derive(Serialize, Debug, PartialEq)]
+pub struct IceRestartRequest {
+    #[serde(rename = "clientIceUfrag")]
+    pub ice_ufrag: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct IceRestartResponse {
+    #[serde(rename = "serverIceUfrag")]
+    pub ice_ufrag: String,
+    #[serde(rename = "serverIcePwd")]
+    pub ice_pwd: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct RekeyRequest {
+    #[serde(rename = "clientDhePublicKey")]
+    pub dhe_public_key: String,
+    #[serde(rename = "hkdfExtraInfo")]
+    pub hkdf_extra_info: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RekeyResponse {
+    #[serde(rename = "serverDhePublicKey")]
+    pub dhe_public_key: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SpotlightRequest {
+    #[serde(rename = "demuxIds")]
+    pub demux_ids: Vec<u32>,
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum BackendError {
     #[error("No such call exists")]
@@ -127,6 +180,26 @@ pub trait Backend: Sync + Send {
         demux_id: DemuxId,
         join_request: &JoinRequest,
     ) -> Result<JoinResponse, BackendError>;
+    async fn restart_ice(
+        &self,
+        backend_address: &Address,
+        call_id: &str,
+        demux_id: DemuxId,
+        ice_restart_request: &IceRestartRequest,
+    ) -> Result<IceRestartResponse, BackendError>;
+    async fn rekey(
+        &self,
+        backend_address: &Address,
+        call_id: &str,
+        demux_id: DemuxId,
+        rekey_request: &RekeyRequest,
+    ) -> Result<RekeyResponse, BackendError>;
+    async fn set_spotlight(
+        &self,
+        backend_address: &Address,
+        call_id: &str,
+        spotlight_request: &SpotlightRequest,
+    ) -> Result<(), BackendError>;
 }
 
 pub struct BackendHttpClient {
@@ -315,4 +388,148 @@ impl Backend for BackendHttpClient {
             )))),
         }
     }
+
+    async fn restart_ice(
+        &self,
+        backend_address: &Address,
+        call_id: &str,
+        demux_id: DemuxId,
+        ice_restart_request: &IceRestartRequest,
+    ) -> Result<IceRestartResponse, BackendError> {
+        let uri_string = format!(
+            "http://{}:{}/v1/call/{}/client/{}/ice-restart",
+            backend_address.ip(),
+            backend_address.port(),
+            call_id,
+            demux_id.as_u32(),
+        );
+
+        let request_body = serde_json::to_vec(ice_restart_request)
+            .context("failed to convert ice restart request to body")?;
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(uri_string)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(request_body))
+            .context("failed to form the ice restart request")?;
+
+        let response = timeout(DEFAULT_TIMEOUT, self.http_client.request(request))
+            .await?
+            .context(format!(
+                "failed to make backend request `ice-restart` to `{}`",
+                backend_address.ip()
+            ))?;
+
+        match response.status() {
+            StatusCode::OK => {
+                let body = hyper::body::aggregate(response)
+                    .await
+                    .context("failed to aggregate body for ice restart response")?;
+
+                let ice_restart_response = serde_json::from_reader(body.reader())
+                    .context("failed to convert body to ice restart response")?;
+
+                Ok(ice_restart_response)
+            }
+            // The client isn't in the call (any more).
+            StatusCode::NOT_FOUND => Err(BackendError::CallNotFound),
+            _ => Err(BackendError::UnexpectedError(anyhow!(format!(
+                "failed `ice-restart` with unexpected status {}",
+                response.status()
+            )))),
+        }
+    }
+
+    async fn rekey(
+        &self,
+        backend_address: &Address,
+        call_id: &str,
+        demux_id: DemuxId,
+        rekey_request: &RekeyRequest,
+    ) -> Result<RekeyResponse, BackendError> {
+        let uri_string = format!(
+            "http://{}:{}/v1/call/{}/client/{}/rekey",
+            backend_address.ip(),
+            backend_address.port(),
+            call_id,
+            demux_id.as_u32(),
+        );
+
+        let request_body =
+            serde_json::to_vec(rekey_request).context("failed to convert rekey request to body")?;
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(uri_string)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(request_body))
+            .context("failed to form the rekey request")?;
+
+        let response = timeout(DEFAULT_TIMEOUT, self.http_client.request(request))
+            .await?
+            .context(format!(
+                "failed to make backend request `rekey` to `{}`",
+                backend_address.ip()
+            ))?;
+
+        match response.status() {
+            StatusCode::OK => {
+                let body = hyper::body::aggregate(response)
+                    .await
+                    .context("failed to aggregate body for rekey response")?;
+
+                let rekey_response = serde_json::from_reader(body.reader())
+                    .context("failed to convert body to rekey response")?;
+
+                Ok(rekey_response)
+            }
+            // The client isn't in the call (any more).
+            StatusCode::NOT_FOUND => Err(BackendError::CallNotFound),
+            _ => Err(BackendError::UnexpectedError(anyhow!(format!(
+                "failed `rekey` with unexpected status {}",
+                response.status()
+            )))),
+        }
+    }
+
+    async fn set_spotlight(
+        &self,
+        backend_address: &Address,
+        call_id: &str,
+        spotlight_request: &SpotlightRequest,
+    ) -> Result<(), BackendError> {
+        let uri_string = format!(
+            "http://{}:{}/v1/call/{}/spotlight",
+            backend_address.ip(),
+            backend_address.port(),
+            call_id,
+        );
+
+        let request_body = serde_json::to_vec(spotlight_request)
+            .context("failed to convert spotlight request to body")?;
+
+        let request = Request::builder()
+            .method(Method::PUT)
+            .uri(uri_string)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(request_body))
+            .context("failed to form the spotlight request")?;
+
+        let response = timeout(DEFAULT_TIMEOUT, self.http_client.request(request))
+            .await?
+            .context(format!(
+                "failed to make backend request `set_spotlight` to `{}`",
+                backend_address.ip()
+            ))?;
+
+        match response.status() {
+            StatusCode::NO_CONTENT => Ok(()),
+            StatusCode::NOT_FOUND => Err(BackendError::CallNotFound),
+            _ => Err(BackendError::UnexpectedError(anyhow!(format!(
+                "failed `set_spotlight` with unexpected status {}",
+                response.status()
+            )))),
+        }
+    }
 }
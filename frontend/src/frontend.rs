@@ -128,12 +128,17 @@ pub struct JoinRequestWrapper {
     pub region: String,
     pub restrictions: CallLinkRestrictions,
     pub is_admin: bool,
+    pub congestion_control_feedback: Option<String>,
+    pub srtp_protection_profile: Option<String>,
+    pub video_metadata_source: Option<String>,
+    pub receive_mixed_audio: Option<bool>,
 }
 
 pub struct JoinResponseWrapper {
     pub demux_id: u32,
     pub port: u16,
     pub port_tcp: Option<u16>,
+    pub port_tls: Option<u16>,
     pub ip: String,
     pub ips: Vec<String>,
     pub ice_ufrag: String,
@@ -147,6 +152,8 @@ pub enum FrontendError {
     CallNotFound,
     #[error("NoPermissionToCreateCall")]
     NoPermissionToCreateCall,
+    #[error("ClientNotFound")]
+    ClientNotFound,
     #[error("InternalError")]
     InternalError,
 }
@@ -365,6 +372,10 @@ impl Frontend {
                     hkdf_extra_info: join_request.hkdf_extra_info,
                     region: join_request.region,
                     is_admin: join_request.is_admin,
+                    congestion_control_feedback: join_request.congestion_control_feedback,
+                    srtp_protection_profile: join_request.srtp_protection_profile,
+                    video_metadata_source: join_request.video_metadata_source,
+                    receive_mixed_audio: join_request.receive_mixed_audio,
                 },
             )
             .await
@@ -387,6 +398,7 @@ impl Frontend {
             demux_id: demux_id.as_u32(),
             port: backend_join_response.port,
             port_tcp: backend_join_response.port_tcp,
+            port_tls: backend_join_response.port_tls,
             ip: backend_join_response.ip,
             ips,
             ice_ufrag: backend_join_response.ice_ufrag,
@@ -395,6 +407,127 @@ impl Frontend {
         })
     }
 
+    /// Finds the client in the call with the given demux ID, if it belongs to the given user.
+    async fn find_own_client(
+        &self,
+        user_id: &str,
+        call: &CallRecord,
+        demux_id: u32,
+    ) -> Result<DemuxId, FrontendError> {
+        self.get_client_ids_in_call(call)
+            .await?
+            .iter()
+            .find_map(|client_id| {
+                let client_demux_id = Frontend::get_demux_id_from_endpoint_id(client_id).ok()?;
+                let client_user_id =
+                    Frontend::get_opaque_user_id_from_endpoint_id(client_id).ok()?;
+                (client_demux_id.as_u32() == demux_id && client_user_id == user_id)
+                    .then_some(client_demux_id)
+            })
+            .ok_or(FrontendError::ClientNotFound)
+    }
+
+    /// Gives a client that has already joined the call new ICE credentials, such as after it
+    /// changes networks. Returns the backend's new ICE ufrag and pwd. Only the user the client
+    /// belongs to may do this.
+    pub async fn restart_client_ice(
+        &self,
+        user_id: &str,
+        call: &CallRecord,
+        demux_id: u32,
+        ice_restart_request: backend::IceRestartRequest,
+    ) -> Result<backend::IceRestartResponse, FrontendError> {
+        let demux_id = self.find_own_client(user_id, call, demux_id).await?;
+
+        // Get the direct address to the Calling Backend.
+        let backend_address = backend::Address::try_from(&call.backend_ip).map_err(|err| {
+            error!("restart_client_ice: failed to parse backend_ip: {}", err);
+            FrontendError::InternalError
+        })?;
+
+        match self
+            .backend
+            .restart_ice(
+                &backend_address,
+                &call.era_id,
+                demux_id,
+                &ice_restart_request,
+            )
+            .await
+        {
+            Ok(ice_restart_response) => Ok(ice_restart_response),
+            // The client left after we looked for it.
+            Err(BackendError::CallNotFound) => Err(FrontendError::ClientNotFound),
+            Err(err) => {
+                Frontend::log_error("restart_client_ice", err.into());
+                Err(FrontendError::InternalError)
+            }
+        }
+    }
+
+    /// Gives a client that has already joined the call new SRTP keys from a new DHE exchange.
+    /// Returns the backend's DHE public key. Only the user the client belongs to may do this.
+    pub async fn rekey_client(
+        &self,
+        user_id: &str,
+        call: &CallRecord,
+        demux_id: u32,
+        rekey_request: backend::RekeyRequest,
+    ) -> Result<String, FrontendError> {
+        let demux_id = self.find_own_client(user_id, call, demux_id).await?;
+
+        // Get the direct address to the Calling Backend.
+        let backend_address = backend::Address::try_from(&call.backend_ip).map_err(|err| {
+            error!("rekey_client: failed to parse backend_ip: {}", err);
+            FrontendError::InternalError
+        })?;
+
+        match self
+            .backend
+            .rekey(&backend_address, &call.era_id, demux_id, &rekey_request)
+            .await
+        {
+            Ok(rekey_response) => Ok(rekey_response.dhe_public_key),
+            // The client left after we looked for it.
+            Err(BackendError::CallNotFound) => Err(FrontendError::ClientNotFound),
+            Err(err) => {
+                Frontend::log_error("rekey_client", err.into());
+                Err(FrontendError::InternalError)
+            }
+        }
+    }
+
+    /// Replaces the senders whose video every client in the call sees first. The caller is
+    /// responsible for making sure the request comes from an admin of the call.
+    pub async fn set_spotlight(
+        &self,
+        call: &CallRecord,
+        demux_ids: Vec<u32>,
+    ) -> Result<(), FrontendError> {
+        // Get the direct address to the Calling Backend.
+        let backend_address = backend::Address::try_from(&call.backend_ip).map_err(|err| {
+            error!("set_spotlight: failed to parse backend_ip: {}", err);
+            FrontendError::InternalError
+        })?;
+
+        match self
+            .backend
+            .set_spotlight(
+                &backend_address,
+                &call.era_id,
+                &backend::SpotlightRequest { demux_ids },
+            )
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(BackendError::CallNotFound) => Err(FrontendError::CallNotFound),
+            Err(err) => {
+                Frontend::log_error("set_spotlight", err.into());
+                Err(FrontendError::InternalError)
+            }
+        }
+    }
+
     pub async fn remove_call_record(
         &self,
         room_id: &RoomId,